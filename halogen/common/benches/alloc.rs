@@ -0,0 +1,139 @@
+//! Compare the segregated-fit `FreeListAllocator` against the first-fit
+//! `LinkedListAllocator` it replaced.
+//!
+//! Run with `cargo bench --bench alloc --features bench`.
+
+use std::{alloc::Layout, hint::black_box, time::Instant};
+
+use halogen_common::mem::{
+    alloc::{FreeListAllocator, LinkedListAllocator},
+    KIB, MIB,
+};
+
+const BLOCK_SIZE: usize = 64;
+const ARENA_SIZE: usize = 4 * MIB;
+
+/// The operations shared by both allocators.
+trait Heap {
+    const NAME: &'static str;
+
+    fn alloc(&mut self, layout: Layout) -> *mut u8;
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout);
+}
+
+impl Heap for FreeListAllocator<'_, BLOCK_SIZE> {
+    const NAME: &'static str = "free list";
+
+    fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        FreeListAllocator::alloc(self, layout)
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        FreeListAllocator::dealloc(self, ptr, layout)
+    }
+}
+
+impl Heap for LinkedListAllocator<'_, BLOCK_SIZE> {
+    const NAME: &'static str = "linked list";
+
+    fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        LinkedListAllocator::alloc(self, layout)
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        LinkedListAllocator::dealloc(self, ptr, layout)
+    }
+}
+
+/// A xorshift generator, so both allocators see the same workload.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 as usize
+    }
+}
+
+/// Allocate and free blocks of one size in FIFO order.
+fn fixed<H: Heap>(heap: &mut H, ops: usize) -> usize {
+    let layout = Layout::from_size_align(BLOCK_SIZE, 8).unwrap();
+    let mut live = std::collections::VecDeque::new();
+    let mut failed = 0;
+
+    for _ in 0..ops {
+        if live.len() == 256 {
+            unsafe { heap.dealloc(live.pop_front().unwrap(), layout) };
+        }
+        match heap.alloc(layout) {
+            ptr if ptr.is_null() => failed += 1,
+            ptr => live.push_back(black_box(ptr)),
+        }
+    }
+
+    for ptr in live {
+        unsafe { heap.dealloc(ptr, layout) };
+    }
+
+    failed
+}
+
+/// Allocate blocks of random sizes and free random live blocks, leaving the
+/// heap fragmented with many free blocks.
+fn fragmented<H: Heap>(heap: &mut H, ops: usize) -> usize {
+    let mut rng = Rng(0x2545F4914F6CDD1D);
+    let mut live: Vec<(*mut u8, Layout)> = Vec::new();
+    let mut failed = 0;
+
+    for _ in 0..ops {
+        if live.len() < 1024 || rng.next() % 2 == 0 {
+            let layout = Layout::from_size_align(8 + rng.next() % (2 * KIB), 8).unwrap();
+            match heap.alloc(layout) {
+                ptr if ptr.is_null() => failed += 1,
+                ptr => live.push((black_box(ptr), layout)),
+            }
+        } else {
+            let (ptr, layout) = live.swap_remove(rng.next() % live.len());
+            unsafe { heap.dealloc(ptr, layout) };
+        }
+    }
+
+    for (ptr, layout) in live {
+        unsafe { heap.dealloc(ptr, layout) };
+    }
+
+    failed
+}
+
+fn run<H: Heap>(
+    workload: &str,
+    ops: usize,
+    new: impl Fn(&'static mut [u8]) -> H,
+    bench: fn(&mut H, usize) -> usize,
+) {
+    let arena = Box::leak(vec![0u8; ARENA_SIZE].into_boxed_slice());
+    let mut heap = new(arena);
+
+    let start = Instant::now();
+    let failed = bench(&mut heap, ops);
+    let elapsed = start.elapsed();
+
+    println!(
+        "{:<12} {:<12} {:>8} ops {:>10.1?} {:>8.1} ns/op {:>6} failed",
+        workload,
+        H::NAME,
+        ops,
+        elapsed,
+        elapsed.as_nanos() as f64 / ops as f64,
+        failed
+    );
+}
+
+fn main() {
+    run("fixed", 200_000, FreeListAllocator::new, fixed);
+    run("fixed", 200_000, LinkedListAllocator::new, fixed);
+    run("fragmented", 50_000, FreeListAllocator::new, fragmented);
+    run("fragmented", 50_000, LinkedListAllocator::new, fragmented);
+}
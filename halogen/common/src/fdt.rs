@@ -0,0 +1,397 @@
+//! A reader for the flattened device-tree (FDT) that firmware passes to the
+//! kernel at boot. Only looking up properties is supported: the structure block
+//! is walked token by token, and nothing is allocated or copied.
+//!
+//! See the [devicetree specification](https://www.devicetree.org/specifications/)
+//! for the format.
+
+const MAGIC: u32 = 0xD00D_FEED;
+const HEADER_SIZE: usize = 40;
+
+const TOKEN_BEGIN_NODE: u32 = 1;
+const TOKEN_END_NODE: u32 = 2;
+const TOKEN_PROP: u32 = 3;
+const TOKEN_NOP: u32 = 4;
+const TOKEN_END: u32 = 9;
+
+/// Read a big-endian word at `offset`.
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Read a NUL-terminated string at `offset`.
+fn read_str(data: &[u8], offset: usize) -> Option<&str> {
+    let bytes = data.get(offset..)?;
+    let len = bytes.iter().position(|&b| b == 0)?;
+    core::str::from_utf8(&bytes[..len]).ok()
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Fdt<'a> {
+    structs: &'a [u8],
+    strings: &'a [u8],
+}
+
+impl<'a> Fdt<'a> {
+    /// Check the header of a device-tree blob. Returns `None` if it is not a
+    /// device-tree or its blocks are out of bounds.
+    pub fn new(data: &'a [u8]) -> Option<Fdt<'a>> {
+        if read_u32(data, 0)? != MAGIC || (read_u32(data, 4)? as usize) > data.len() {
+            return None;
+        }
+
+        let block = |offset, size| {
+            let start = read_u32(data, offset)? as usize;
+            let size = read_u32(data, size)? as usize;
+            data.get(start..start + size)
+        };
+
+        Some(Fdt {
+            structs: block(8, 36)?,
+            strings: block(12, 32)?,
+        })
+    }
+
+    /// Read the device-tree at `ptr`, using the size in its header.
+    ///
+    /// # Safety
+    ///
+    /// - If `ptr` is not null, it must be readable for at least the header, and
+    ///   for the size in the header if the magic number matches.
+    pub unsafe fn from_ptr(ptr: *const u8) -> Option<Fdt<'a>> {
+        if ptr.is_null() {
+            return None;
+        }
+
+        let header = core::slice::from_raw_parts(ptr, HEADER_SIZE);
+        if read_u32(header, 0)? != MAGIC {
+            return None;
+        }
+
+        let size = read_u32(header, 4)? as usize;
+        Fdt::new(core::slice::from_raw_parts(ptr, size))
+    }
+
+    /// Iterate over the properties of every node, in the order of the tree.
+    pub fn properties(&self) -> Properties<'a> {
+        Properties {
+            fdt: *self,
+            offset: 0,
+            node: "",
+        }
+    }
+
+    /// Find the first property named `name` in any node.
+    pub fn property(&self, name: &str) -> Option<Property<'a>> {
+        self.properties().find(|prop| prop.name == name)
+    }
+}
+
+/// A property of a device-tree node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Property<'a> {
+    /// Name of the node that has the property, e.g. `cpu@0`.
+    pub node: &'a str,
+    pub name: &'a str,
+    pub value: &'a [u8],
+}
+
+impl<'a> Property<'a> {
+    /// Interpret the value as a string.
+    pub fn as_str(&self) -> Option<&'a str> {
+        read_str(self.value, 0)
+    }
+
+    /// Interpret the value as a list of strings.
+    pub fn strings(&self) -> impl Iterator<Item = &'a str> {
+        self.value
+            .split(|&b| b == 0)
+            .filter(|s| !s.is_empty())
+            .filter_map(|s| core::str::from_utf8(s).ok())
+    }
+}
+
+/// Iterator over the properties of a device-tree. Stops at the end of the tree
+/// or at the first malformed token.
+pub struct Properties<'a> {
+    fdt: Fdt<'a>,
+    offset: usize,
+    /// The node of the properties that follow. Properties come before the
+    /// sub-nodes, so the parent is never needed again.
+    node: &'a str,
+}
+
+impl<'a> Iterator for Properties<'a> {
+    type Item = Property<'a>;
+
+    fn next(&mut self) -> Option<Property<'a>> {
+        let structs = self.fdt.structs;
+
+        loop {
+            let token = read_u32(structs, self.offset)?;
+            self.offset += 4;
+
+            match token {
+                TOKEN_BEGIN_NODE => {
+                    self.node = read_str(structs, self.offset)?;
+                    self.offset = (self.offset + self.node.len() + 1 + 3) & !3;
+                }
+                TOKEN_PROP => {
+                    let len = read_u32(structs, self.offset)? as usize;
+                    let name = read_str(
+                        self.fdt.strings,
+                        read_u32(structs, self.offset + 4)? as usize,
+                    )?;
+                    let value = structs.get(self.offset + 8..self.offset + 8 + len)?;
+                    self.offset = (self.offset + 8 + len + 3) & !3;
+
+                    return Some(Property {
+                        node: self.node,
+                        name,
+                        value,
+                    });
+                }
+                TOKEN_END_NODE | TOKEN_NOP => {}
+                TOKEN_END => return None,
+                // An unknown token means the tree is malformed.
+                _ => return None,
+            }
+        }
+    }
+}
+
+/// Returns true if a RISC-V ISA string such as `rv64imafdc_zicsr_svnapot`
+/// includes an extension. Single-letter extensions follow the base, and the
+/// others are separated by underscores. Case is ignored.
+pub fn isa_has_extension(isa: &str, ext: &str) -> bool {
+    let mut parts = isa.split('_');
+    let letters = match parts.next() {
+        Some(base) if base.len() >= 4 => &base.as_bytes()[4..],
+        _ => return false,
+    };
+
+    match ext.as_bytes() {
+        [letter] => letters.iter().any(|b| b.eq_ignore_ascii_case(letter)),
+        _ => parts.any(|part| part.eq_ignore_ascii_case(ext)),
+    }
+}
+
+/// Returns true if every hart in the device-tree supports an extension. Both
+/// the `riscv,isa-extensions` list and the older `riscv,isa` string are
+/// checked, so each must include it where present.
+pub fn has_extension(fdt: &Fdt, ext: &str) -> bool {
+    let mut found = false;
+
+    for prop in fdt.properties() {
+        let has = match prop.name {
+            "riscv,isa-extensions" => prop.strings().any(|s| s.eq_ignore_ascii_case(ext)),
+            "riscv,isa" => matches!(prop.as_str(), Some(isa) if isa_has_extension(isa, ext)),
+            _ => continue,
+        };
+
+        if !has {
+            return false;
+        }
+        found = true;
+    }
+
+    found
+}
+
+/// Get the kernel command line from the `bootargs` property of `/chosen`.
+pub fn bootargs<'a>(fdt: &Fdt<'a>) -> Option<&'a str> {
+    fdt.properties()
+        .find(|prop| prop.node == "chosen" && prop.name == "bootargs")?
+        .as_str()
+}
+
+/// Find the value of a `key=value` argument on a command line. Arguments are
+/// separated by whitespace, and the last one with the key wins.
+pub fn boot_arg<'a>(args: &'a str, key: &str) -> Option<&'a str> {
+    args.split_whitespace()
+        .rev()
+        .filter_map(|arg| arg.split_once('='))
+        .find(|&(k, _)| k == key)
+        .map(|(_, value)| value)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Builds a device-tree blob for testing.
+    #[derive(Default)]
+    struct Builder {
+        structs: Vec<u8>,
+        strings: Vec<u8>,
+    }
+
+    impl Builder {
+        fn token(&mut self, token: u32) -> &mut Self {
+            self.structs.extend(token.to_be_bytes());
+            self
+        }
+
+        fn pad(&mut self) {
+            while self.structs.len() % 4 != 0 {
+                self.structs.push(0);
+            }
+        }
+
+        fn begin(&mut self, name: &str) -> &mut Self {
+            self.token(TOKEN_BEGIN_NODE);
+            self.structs.extend(name.as_bytes());
+            self.structs.push(0);
+            self.pad();
+            self
+        }
+
+        fn end(&mut self) -> &mut Self {
+            self.token(TOKEN_END_NODE)
+        }
+
+        fn prop(&mut self, name: &str, value: &[u8]) -> &mut Self {
+            let name_offset = self.strings.len() as u32;
+            self.strings.extend(name.as_bytes());
+            self.strings.push(0);
+
+            self.token(TOKEN_PROP);
+            self.structs.extend((value.len() as u32).to_be_bytes());
+            self.structs.extend(name_offset.to_be_bytes());
+            self.structs.extend(value);
+            self.pad();
+            self
+        }
+
+        fn build(&mut self) -> Vec<u8> {
+            self.token(TOKEN_END);
+
+            let off_structs = HEADER_SIZE;
+            let off_strings = off_structs + self.structs.len();
+            let total = off_strings + self.strings.len();
+            let header = [
+                MAGIC,
+                total as u32,
+                off_structs as u32,
+                off_strings as u32,
+                HEADER_SIZE as u32,
+                17,
+                16,
+                0,
+                self.strings.len() as u32,
+                self.structs.len() as u32,
+            ];
+
+            let mut data: Vec<u8> = header.iter().flat_map(|w| w.to_be_bytes()).collect();
+            data.extend(&self.structs);
+            data.extend(&self.strings);
+            data
+        }
+    }
+
+    fn cpus(isa: &[&str]) -> Vec<u8> {
+        let mut builder = Builder::default();
+        builder
+            .begin("")
+            .prop("model", b"riscv-virtio,qemu\0")
+            .begin("cpus");
+        for (n, isa) in isa.iter().enumerate() {
+            builder
+                .begin(&format!("cpu@{}", n))
+                .prop("riscv,isa", format!("{}\0", isa).as_bytes())
+                .end();
+        }
+        builder.end().end().build()
+    }
+
+    #[test]
+    fn properties() {
+        let data = cpus(&["rv64imafdc"]);
+        let fdt = Fdt::new(&data).unwrap();
+
+        let props: Vec<_> = fdt.properties().map(|p| (p.node, p.name)).collect();
+        assert_eq!(vec![("", "model"), ("cpu@0", "riscv,isa")], props);
+        assert_eq!(
+            Some("riscv-virtio,qemu"),
+            fdt.property("model").unwrap().as_str()
+        );
+        assert!(fdt.property("missing").is_none());
+
+        let fdt = unsafe { Fdt::from_ptr(data.as_ptr()) }.unwrap();
+        assert_eq!(
+            Some("rv64imafdc"),
+            fdt.property("riscv,isa").unwrap().as_str()
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        let mut data = cpus(&["rv64imafdc"]);
+        assert!(Fdt::new(&data[..HEADER_SIZE]).is_none());
+        assert!(unsafe { Fdt::from_ptr(core::ptr::null()) }.is_none());
+
+        data[0] = 0;
+        assert!(Fdt::new(&data).is_none());
+    }
+
+    #[test]
+    fn isa_string() {
+        let isa = "rv64imafdch_zicsr_zifencei_svnapot";
+        assert!(isa_has_extension(isa, "svnapot"));
+        assert!(isa_has_extension(isa, "Zicsr"));
+        assert!(isa_has_extension(isa, "h"));
+        assert!(!isa_has_extension(isa, "v"));
+        assert!(!isa_has_extension(isa, "svpbmt"));
+        assert!(!isa_has_extension("rv64", "i"));
+    }
+
+    #[test]
+    fn every_hart() {
+        let both = cpus(&["rv64imac_svnapot", "rv64imac_svnapot"]);
+        assert!(has_extension(&Fdt::new(&both).unwrap(), "svnapot"));
+
+        let one = cpus(&["rv64imac_svnapot", "rv64imac"]);
+        assert!(!has_extension(&Fdt::new(&one).unwrap(), "svnapot"));
+
+        let list = Builder::default()
+            .begin("")
+            .begin("cpu@0")
+            .prop("riscv,isa-extensions", b"i\0m\0svnapot\0")
+            .end()
+            .end()
+            .build();
+        assert!(has_extension(&Fdt::new(&list).unwrap(), "svnapot"));
+        assert!(!has_extension(&Fdt::new(&list).unwrap(), "svpbmt"));
+    }
+
+    #[test]
+    fn chosen_bootargs() {
+        let data = Builder::default()
+            .begin("")
+            .prop("bootargs", b"not=chosen\0")
+            .begin("chosen")
+            .prop("stdout-path", b"/soc/serial\0")
+            .prop("bootargs", b"console=ttyS0 sched=fair\0")
+            .end()
+            .end()
+            .build();
+        let args = bootargs(&Fdt::new(&data).unwrap()).unwrap();
+        assert_eq!("console=ttyS0 sched=fair", args);
+        assert_eq!(Some("fair"), boot_arg(args, "sched"));
+
+        assert!(bootargs(&Fdt::new(&cpus(&["rv64imac"])).unwrap()).is_none());
+    }
+
+    #[test]
+    fn command_line() {
+        assert_eq!(Some("fifo"), boot_arg("sched=fifo", "sched"));
+        assert_eq!(
+            Some("mlfq"),
+            boot_arg(" sched=fifo  quiet sched=mlfq ", "sched")
+        );
+        assert_eq!(Some(""), boot_arg("sched=", "sched"));
+        assert_eq!(None, boot_arg("schedule=fifo sched", "sched"));
+        assert_eq!(None, boot_arg("", "sched"));
+    }
+}
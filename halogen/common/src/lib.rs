@@ -11,6 +11,8 @@
 #[cfg(all(feature = "alloc", not(test)))]
 extern crate alloc;
 
+/// Reading the device-tree passed by firmware.
+pub mod fdt;
 /// Math and bit-manip helper functions.
 pub mod math;
 /// Data structures and algorithms for memory-management.
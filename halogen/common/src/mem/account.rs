@@ -0,0 +1,220 @@
+//! Accounting of the memory used on behalf of a single process. Usage is
+//! tracked in bytes for each kind of resource and checked against a limit
+//! before it is charged, so that a process that runs out of its budget fails
+//! on its own instead of exhausting memory for everyone.
+
+/// A kind of memory that is charged to a process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resource {
+    /// Frames mapped into the address-space.
+    Resident,
+    /// Frames used for the page tables of the address-space.
+    PageTables,
+    /// Kernel heap allocated on behalf of the process.
+    Heap,
+}
+
+/// Bytes of each resource in use.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    pub resident: usize,
+    pub page_tables: usize,
+    pub heap: usize,
+}
+
+impl MemoryUsage {
+    /// Get the bytes used of a resource.
+    pub fn get(&self, resource: Resource) -> usize {
+        match resource {
+            Resource::Resident => self.resident,
+            Resource::PageTables => self.page_tables,
+            Resource::Heap => self.heap,
+        }
+    }
+
+    /// Get a mutable reference to the bytes used of a resource.
+    fn get_mut(&mut self, resource: Resource) -> &mut usize {
+        match resource {
+            Resource::Resident => &mut self.resident,
+            Resource::PageTables => &mut self.page_tables,
+            Resource::Heap => &mut self.heap,
+        }
+    }
+
+    /// Total bytes used of all resources.
+    pub fn total(&self) -> usize {
+        self.resident + self.page_tables + self.heap
+    }
+}
+
+impl core::fmt::Display for MemoryUsage {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "resident={}K page_tables={}K heap={}K",
+            self.resident / 1024,
+            self.page_tables / 1024,
+            self.heap / 1024
+        )
+    }
+}
+
+/// Maximum bytes of each resource. A limit of `usize::MAX` is unlimited.
+pub type MemoryLimits = MemoryUsage;
+
+impl MemoryLimits {
+    /// No limits on any resource.
+    pub const fn unlimited() -> MemoryLimits {
+        MemoryUsage {
+            resident: usize::MAX,
+            page_tables: usize::MAX,
+            heap: usize::MAX,
+        }
+    }
+}
+
+/// Memory usage of a process along with its limits.
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryAccount {
+    usage: MemoryUsage,
+    limits: MemoryLimits,
+}
+
+impl Default for MemoryAccount {
+    fn default() -> MemoryAccount {
+        MemoryAccount::new(MemoryLimits::unlimited())
+    }
+}
+
+impl MemoryAccount {
+    /// Create an account with nothing charged.
+    pub const fn new(limits: MemoryLimits) -> MemoryAccount {
+        MemoryAccount {
+            usage: MemoryUsage {
+                resident: 0,
+                page_tables: 0,
+                heap: 0,
+            },
+            limits,
+        }
+    }
+
+    /// Get the bytes in use of each resource.
+    pub fn usage(&self) -> MemoryUsage {
+        self.usage
+    }
+
+    /// Get the limits of each resource.
+    pub fn limits(&self) -> MemoryLimits {
+        self.limits
+    }
+
+    /// Replace the limits. Usage already over a new limit is not reclaimed, but
+    /// further charges of that resource will fail.
+    pub fn set_limits(&mut self, limits: MemoryLimits) {
+        self.limits = limits;
+    }
+
+    /// Get the bytes of a resource that can still be charged, which is zero if
+    /// the usage is at or over the limit, e.g. after the limit was lowered.
+    pub fn available(&self, resource: Resource) -> usize {
+        self.limits
+            .get(resource)
+            .saturating_sub(self.usage.get(resource))
+    }
+
+    /// Returns true if `bytes` more of a resource can be charged without
+    /// exceeding its limit.
+    pub fn can_charge(&self, resource: Resource, bytes: usize) -> bool {
+        bytes <= self.available(resource)
+    }
+
+    /// Charge `bytes` of a resource if it would not exceed the limit. Returns
+    /// false, charging nothing, otherwise.
+    pub fn try_charge(&mut self, resource: Resource, bytes: usize) -> bool {
+        if self.can_charge(resource, bytes) {
+            *self.usage.get_mut(resource) += bytes;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Return `bytes` of a resource.
+    pub fn uncharge(&mut self, resource: Resource, bytes: usize) {
+        let usage = self.usage.get_mut(resource);
+        debug_assert!(
+            *usage >= bytes,
+            "uncharged more {:?} than charged",
+            resource
+        );
+        *usage = usage.saturating_sub(bytes);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unlimited() {
+        let mut account = MemoryAccount::default();
+
+        assert!(account.try_charge(Resource::Resident, 1 << 40));
+        assert!(account.try_charge(Resource::Heap, 64));
+        assert_eq!(1 << 40, account.usage().resident);
+        assert_eq!(64, account.usage().heap);
+        assert!(!account.can_charge(Resource::Resident, usize::MAX));
+    }
+
+    #[test]
+    fn limited() {
+        let mut account = MemoryAccount::new(MemoryLimits {
+            resident: 4096,
+            page_tables: 8192,
+            heap: usize::MAX,
+        });
+
+        assert!(account.try_charge(Resource::Resident, 4096));
+        assert!(!account.try_charge(Resource::Resident, 1));
+        assert_eq!(4096, account.usage().resident);
+
+        assert!(account.try_charge(Resource::PageTables, 4096));
+        assert!(account.can_charge(Resource::PageTables, 4096));
+        assert!(!account.can_charge(Resource::PageTables, 4097));
+
+        account.uncharge(Resource::Resident, 4096);
+        assert!(account.try_charge(Resource::Resident, 1));
+    }
+
+    #[test]
+    fn zero_limit() {
+        let mut account = MemoryAccount::new(MemoryLimits {
+            resident: 0,
+            page_tables: 0,
+            heap: 0,
+        });
+
+        assert!(account.try_charge(Resource::PageTables, 0));
+        assert!(!account.try_charge(Resource::PageTables, 4096));
+        assert_eq!(0, account.usage().total());
+        assert_eq!(0, account.available(Resource::Heap));
+    }
+
+    #[test]
+    fn lower_limit() {
+        let mut account = MemoryAccount::default();
+
+        assert!(account.try_charge(Resource::Heap, 1024));
+        account.set_limits(MemoryLimits {
+            heap: 512,
+            ..MemoryLimits::unlimited()
+        });
+
+        // Usage over the new limit leaves nothing available.
+        assert_eq!(0, account.available(Resource::Heap));
+        assert!(!account.try_charge(Resource::Heap, 1));
+        account.uncharge(Resource::Heap, 1024);
+        assert!(account.try_charge(Resource::Heap, 512));
+    }
+}
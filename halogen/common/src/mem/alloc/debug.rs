@@ -0,0 +1,370 @@
+//! A `FreeListAllocator` wrapper that catches misuse of the heap as close to
+//! the bug as possible. Each allocation is laid out as:
+//!
+//! ```text
+//! [reserved][padding][guard][red zone][allocation][red zone]
+//! ```
+//!
+//! The reserved words are overwritten by the free-list links of the inner
+//! allocator when the block is freed, which keeps the guard intact so that
+//! double frees are still recognized. Padding keeps over-aligned allocations
+//! aligned.
+//!
+//! The guard records the requested size, whether the block is live, and the
+//! call site that allocated it. Red zones are filled with a known pattern and
+//! checked when the block is freed to catch overruns, and freed memory is
+//! filled with a poison pattern so that use-after-free reads are recognizable.
+
+use core::alloc::Layout;
+
+use super::{AllocatorStats, FreeListAllocator};
+use crate::align_up;
+
+/// Number of return addresses recorded for the call site of an allocation.
+pub const TRACE_DEPTH: usize = 4;
+/// Size of each red zone in bytes.
+pub const RED_ZONE_SIZE: usize = 16;
+/// Pattern filling the red zones.
+pub const RED_ZONE_BYTE: u8 = 0xFD;
+/// Pattern filling new allocations.
+pub const UNINIT_BYTE: u8 = 0xA5;
+/// Pattern filling freed allocations.
+pub const POISON_BYTE: u8 = 0x6B;
+
+const LIVE_MAGIC: usize = 0xA110_CA7E_DB10_C000;
+const FREED_MAGIC: usize = 0xF4EE_DB10_C000_DEAD;
+
+/// Metadata preceding each allocation.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct Guard {
+    magic: usize,
+    size: usize,
+    trace: [usize; TRACE_DEPTH],
+}
+
+const RESERVED_SIZE: usize = 2 * core::mem::size_of::<usize>();
+const GUARD_SIZE: usize = core::mem::size_of::<Guard>();
+const PREFIX_SIZE: usize = RESERVED_SIZE + GUARD_SIZE + RED_ZONE_SIZE;
+
+/// The ways a block can be found corrupt.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Corruption {
+    /// The red zone before the allocation was written.
+    Underflow,
+    /// The red zone after the allocation was written.
+    Overflow,
+    /// The block was already freed.
+    DoubleFree,
+    /// The pointer was not issued by this allocator or its guard was
+    /// overwritten.
+    InvalidFree,
+    /// The block was freed with a different size than it was allocated with.
+    SizeMismatch,
+}
+
+/// A corrupt block found on `dealloc`.
+#[derive(Clone, Copy, Debug)]
+pub struct BadBlock {
+    pub corruption: Corruption,
+    /// The pointer that was freed.
+    pub ptr: *const u8,
+    /// The size recorded when the block was allocated.
+    pub size: usize,
+    /// Return addresses of the call site that allocated the block, innermost
+    /// first. Unused entries are zero.
+    pub trace: [usize; TRACE_DEPTH],
+}
+
+impl core::fmt::Display for BadBlock {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{:?} of {:p} ({} bytes) allocated at",
+            self.corruption, self.ptr, self.size
+        )?;
+        for &addr in self.trace.iter().take_while(|&&addr| addr != 0) {
+            write!(f, " {:#x}", addr)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct DebugAllocator<'a, const B: usize> {
+    inner: FreeListAllocator<'a, B>,
+}
+
+impl<'a, const B: usize> DebugAllocator<'a, B> {
+    /// Initialize a debug allocator with a region of memory to manage.
+    pub fn new(arena: &'a mut [u8]) -> DebugAllocator<'a, B> {
+        DebugAllocator {
+            inner: FreeListAllocator::new(arena),
+        }
+    }
+
+    /// The layout requested from the inner allocator for an allocation.
+    fn padded(layout: Layout) -> Option<Layout> {
+        let size = layout
+            .size()
+            .checked_add(Self::prefix(layout) + RED_ZONE_SIZE)?;
+        Layout::from_size_align(size, layout.align()).ok()
+    }
+
+    /// Offset from the block returned by the inner allocator to the
+    /// allocation, which keeps the allocation aligned.
+    fn prefix(layout: Layout) -> usize {
+        align_up!(PREFIX_SIZE, layout.align())
+    }
+
+    /// Get a pointer to the guard of an allocation.
+    fn guard(ptr: *mut u8) -> *mut Guard {
+        (ptr as usize - RED_ZONE_SIZE - GUARD_SIZE) as *mut Guard
+    }
+
+    /// Allocate a block with the provided layout surrounded by red zones,
+    /// recording `trace` as its call site. Returns `core::ptr::null_mut()` if
+    /// no suitable allocation is found.
+    pub fn alloc(&mut self, layout: Layout, trace: [usize; TRACE_DEPTH]) -> *mut u8 {
+        let padded = match DebugAllocator::<B>::padded(layout) {
+            Some(padded) => padded,
+            None => return core::ptr::null_mut(),
+        };
+
+        let base = self.inner.alloc(padded);
+        if base.is_null() {
+            return base;
+        }
+
+        unsafe {
+            let ptr = base.add(Self::prefix(layout));
+            Self::guard(ptr).write_unaligned(Guard {
+                magic: LIVE_MAGIC,
+                size: layout.size(),
+                trace,
+            });
+
+            ptr.sub(RED_ZONE_SIZE)
+                .write_bytes(RED_ZONE_BYTE, RED_ZONE_SIZE);
+            ptr.write_bytes(UNINIT_BYTE, layout.size());
+            ptr.add(layout.size())
+                .write_bytes(RED_ZONE_BYTE, RED_ZONE_SIZE);
+
+            ptr
+        }
+    }
+
+    /// Allocate some memory and zero it.
+    pub fn alloc_zeroed(&mut self, layout: Layout, trace: [usize; TRACE_DEPTH]) -> *mut u8 {
+        let ptr = self.alloc(layout, trace);
+        if !ptr.is_null() {
+            unsafe { ptr.write_bytes(0, layout.size()) };
+        }
+        ptr
+    }
+
+    /// Check the guard and red zones of a block, then poison and free it.
+    /// Returns the bad block if it is found corrupt, in which case it is not
+    /// freed.
+    ///
+    /// # Safety
+    ///
+    /// - `ptr` should have been returned by `alloc` with the same `layout`.
+    pub unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) -> Result<(), BadBlock> {
+        let mut bad = BadBlock {
+            corruption: Corruption::InvalidFree,
+            ptr,
+            size: layout.size(),
+            trace: [0; TRACE_DEPTH],
+        };
+
+        let padded = DebugAllocator::<B>::padded(layout).ok_or(bad)?;
+        let prefix = Self::prefix(layout);
+        if (ptr as usize) < prefix {
+            return Err(bad);
+        }
+
+        let base = ptr.sub(prefix);
+        if !self.inner.contains(base, padded) {
+            return Err(bad);
+        }
+
+        let guard = Self::guard(ptr).read_unaligned();
+        bad.size = guard.size;
+        bad.trace = guard.trace;
+
+        match guard.magic {
+            LIVE_MAGIC => {}
+            FREED_MAGIC => {
+                bad.corruption = Corruption::DoubleFree;
+                return Err(bad);
+            }
+            _ => {
+                bad.trace = [0; TRACE_DEPTH];
+                return Err(bad);
+            }
+        }
+
+        if guard.size != layout.size() {
+            bad.corruption = Corruption::SizeMismatch;
+            return Err(bad);
+        }
+
+        let zone_ok = |zone: *const u8| (0..RED_ZONE_SIZE).all(|i| *zone.add(i) == RED_ZONE_BYTE);
+
+        if !zone_ok(ptr.sub(RED_ZONE_SIZE)) {
+            bad.corruption = Corruption::Underflow;
+            return Err(bad);
+        }
+        if !zone_ok(ptr.add(layout.size())) {
+            bad.corruption = Corruption::Overflow;
+            return Err(bad);
+        }
+
+        Self::guard(ptr).write_unaligned(Guard {
+            magic: FREED_MAGIC,
+            ..guard
+        });
+        ptr.write_bytes(POISON_BYTE, layout.size());
+
+        self.inner.dealloc(base, padded);
+        Ok(())
+    }
+
+    /// Returns `true` if the allocator contains the pointer.
+    pub fn contains(&self, ptr: *const u8, layout: Layout) -> bool {
+        self.inner.contains(ptr, layout)
+    }
+
+    pub fn stats(&self) -> AllocatorStats {
+        self.inner.stats()
+    }
+
+    /// Returns an false if the heap is suspected corrupted
+    pub fn integrity_ok(&self) -> bool {
+        self.inner.integrity_ok()
+    }
+}
+
+impl<'a, const B: usize> core::fmt::Display for DebugAllocator<'a, B> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::fmt::Display::fmt(&self.inner, f)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mem::KIB;
+
+    const TRACE: [usize; TRACE_DEPTH] = [0x8020_1234, 0x8020_5678, 0, 0];
+
+    #[test]
+    fn round_trip() {
+        let mut buf = vec![0; 4 * KIB];
+        let mut allocator: DebugAllocator<64> = DebugAllocator::new(buf.as_mut_slice());
+
+        let layout = Layout::from_size_align(100, 8).unwrap();
+
+        unsafe {
+            let ptr = allocator.alloc(layout, TRACE);
+            assert!(!ptr.is_null());
+            assert!((0..layout.size()).all(|i| *ptr.add(i) == UNINIT_BYTE));
+
+            ptr.write_bytes(1, layout.size());
+            allocator.dealloc(ptr, layout).unwrap();
+
+            assert!((0..layout.size()).all(|i| *ptr.add(i) == POISON_BYTE));
+        }
+
+        assert_eq!(0, allocator.stats().blocks_used);
+        assert!(allocator.integrity_ok());
+    }
+
+    #[test]
+    fn aligned() {
+        let mut buf = vec![0; 32 * KIB];
+        let mut allocator: DebugAllocator<64> = DebugAllocator::new(buf.as_mut_slice());
+        let layout = Layout::from_size_align(100, 4 * KIB).unwrap();
+
+        unsafe {
+            let ptr = allocator.alloc(layout, TRACE);
+            assert_eq!(0, ptr as usize % layout.align());
+            allocator.dealloc(ptr, layout).unwrap();
+        }
+
+        assert!(allocator.integrity_ok());
+    }
+
+    #[test]
+    fn overflow() {
+        let mut buf = vec![0; 4 * KIB];
+        let mut allocator: DebugAllocator<64> = DebugAllocator::new(buf.as_mut_slice());
+        let layout = Layout::from_size_align(32, 8).unwrap();
+
+        unsafe {
+            let ptr = allocator.alloc(layout, TRACE);
+            *ptr.add(layout.size()) = 0;
+
+            let bad = allocator.dealloc(ptr, layout).unwrap_err();
+            assert_eq!(Corruption::Overflow, bad.corruption);
+            assert_eq!(layout.size(), bad.size);
+            assert_eq!(TRACE, bad.trace);
+        }
+    }
+
+    #[test]
+    fn underflow() {
+        let mut buf = vec![0; 4 * KIB];
+        let mut allocator: DebugAllocator<64> = DebugAllocator::new(buf.as_mut_slice());
+        let layout = Layout::from_size_align(32, 8).unwrap();
+
+        unsafe {
+            let ptr = allocator.alloc(layout, TRACE);
+            *ptr.sub(1) = 0;
+
+            let bad = allocator.dealloc(ptr, layout).unwrap_err();
+            assert_eq!(Corruption::Underflow, bad.corruption);
+            assert_eq!(TRACE, bad.trace);
+        }
+    }
+
+    #[test]
+    fn double_free() {
+        let mut buf = vec![0; 4 * KIB];
+        let mut allocator: DebugAllocator<64> = DebugAllocator::new(buf.as_mut_slice());
+        let layout = Layout::from_size_align(32, 8).unwrap();
+
+        unsafe {
+            let ptr = allocator.alloc(layout, TRACE);
+            allocator.dealloc(ptr, layout).unwrap();
+
+            let bad = allocator.dealloc(ptr, layout).unwrap_err();
+            assert_eq!(Corruption::DoubleFree, bad.corruption);
+            assert_eq!(TRACE, bad.trace);
+        }
+
+        assert!(allocator.integrity_ok());
+    }
+
+    #[test]
+    fn invalid_free() {
+        let mut buf = vec![0; 4 * KIB];
+        let mut allocator: DebugAllocator<64> = DebugAllocator::new(buf.as_mut_slice());
+        let layout = Layout::from_size_align(32, 8).unwrap();
+
+        unsafe {
+            let ptr = allocator.alloc(layout, TRACE);
+
+            let bad = allocator.dealloc(ptr.add(8), layout).unwrap_err();
+            assert_eq!(Corruption::InvalidFree, bad.corruption);
+
+            let bad = allocator
+                .dealloc(ptr, Layout::from_size_align(16, 8).unwrap())
+                .unwrap_err();
+            assert_eq!(Corruption::SizeMismatch, bad.corruption);
+
+            allocator.dealloc(ptr, layout).unwrap();
+        }
+    }
+}
@@ -4,9 +4,13 @@
 
 use crate::{
     align_down,
-    mem::{Address, PhysicalAddress, VirtualAddress},
+    mem::{Address, PhysicalAddress, Segment, VirtualAddress},
 };
 
+/// Number of separate ranges of frames that can be donated to an allocator.
+/// Adjacent frames are merged into one range.
+pub const MAX_DONATED_RANGES: usize = 8;
+
 #[derive(Debug, Clone)]
 #[repr(C, align(4096))]
 struct FreeFrame(*mut FreeFrame);
@@ -19,6 +23,10 @@ pub struct FrameAllocator<'a, const B: usize> {
     arena: Option<&'a mut [[u8; B]]>,
     virt_offset: isize,
     brk: usize,
+    /// Ranges of frames outside the arena given to the allocator with
+    /// `donate`. Only the first `donated_len` are used.
+    donated: [Segment<PhysicalAddress>; MAX_DONATED_RANGES],
+    donated_len: usize,
 }
 
 unsafe impl<'a, const B: usize> Sync for FrameAllocator<'a, B> {}
@@ -32,6 +40,8 @@ impl<'a, const B: usize> FrameAllocator<'a, B> {
             arena: None,
             virt_offset: 0,
             brk: 0,
+            donated: [Segment::new(PhysicalAddress(0), PhysicalAddress(0)); MAX_DONATED_RANGES],
+            donated_len: 0,
         }
     }
 
@@ -52,6 +62,7 @@ impl<'a, const B: usize> FrameAllocator<'a, B> {
         self.virt_offset = virt_offset;
         self.arena = Some(arena);
         self.brk = 0;
+        self.donated_len = 0;
     }
 
     /// Create a frame allocator for a specific arena
@@ -68,6 +79,8 @@ impl<'a, const B: usize> FrameAllocator<'a, B> {
             brk: 0,
             virt_offset,
             arena: Some(arena),
+            donated: [Segment::new(PhysicalAddress(0), PhysicalAddress(0)); MAX_DONATED_RANGES],
+            donated_len: 0,
         }
     }
 
@@ -91,17 +104,24 @@ impl<'a, const B: usize> FrameAllocator<'a, B> {
         ))
     }
 
-    /// Return true if the allocator contains a physical address.
+    /// Return true if the allocator contains a physical address, either in its
+    /// arena or in a frame donated to it.
     pub fn contains(&self, addr: PhysicalAddress) -> bool {
         match &self.arena {
             Some(a) => {
                 a.as_ptr_range()
                     .contains(&(self.phys_to_virt(addr).as_ptr() as *const [u8; B]))
+                    || self.donated().iter().any(|range| range.contains(addr))
             }
             None => false,
         }
     }
 
+    /// Get the ranges of frames donated to the allocator.
+    fn donated(&self) -> &[Segment<PhysicalAddress>] {
+        &self.donated[..self.donated_len]
+    }
+
     #[inline]
     fn virt_to_phys(&self, addr: VirtualAddress) -> PhysicalAddress {
         addr.add_offset(-self.virt_offset).as_phys()
@@ -135,6 +155,32 @@ impl<'a, const B: usize> FrameAllocator<'a, B> {
         }
     }
 
+    /// Allocate `count` physically contiguous frames, the first aligned to
+    /// `align`. Only the part of the arena that has never been issued is
+    /// searched, since freed frames are not kept in order. Frames skipped to
+    /// reach the alignment are put on the free list. The frames are freed one
+    /// at a time.
+    pub fn alloc_contiguous(&mut self, count: usize, align: usize) -> Option<PhysicalAddress> {
+        let arena = self.arena.as_ref()?;
+        let base = self.virt_to_phys(VirtualAddress::from_ptr(arena.as_ptr()));
+        let len = arena.len();
+
+        let frame = |n: usize| base + n * B;
+        let start = (self.brk..len).find(|&n| frame(n).is_aligned_to(align))?;
+        if len - start < count {
+            return None;
+        }
+
+        unsafe {
+            for n in self.brk..start {
+                self.push(frame(n));
+            }
+        }
+        self.brk = start + count;
+
+        Some(frame(start))
+    }
+
     /// Free a frame.
     ///
     /// # Safety
@@ -142,6 +188,61 @@ impl<'a, const B: usize> FrameAllocator<'a, B> {
     /// - Must be called with a valid physical frame.
     pub unsafe fn free(&mut self, frame: PhysicalAddress) {
         assert!(self.contains(frame));
+        self.push(frame);
+    }
+
+    /// Returns true if `donate` would take a frame, i.e. it is next to a
+    /// donated frame or fewer than `MAX_DONATED_RANGES` ranges are donated.
+    pub fn can_donate(&self, frame: PhysicalAddress) -> bool {
+        let frame = PhysicalAddress(align_down!(usize::from(frame), B));
+        self.donated_len < MAX_DONATED_RANGES
+            || self
+                .donated()
+                .iter()
+                .any(|range| range.end == frame || range.start == frame + B)
+    }
+
+    /// Give the allocator a frame outside its arena, e.g. memory that was only
+    /// needed during boot. It is issued and freed like the frames of the arena
+    /// from then on. Returns false, leaving the frame out of the allocator, if
+    /// `can_donate` is false.
+    ///
+    /// # Safety
+    ///
+    /// - The frame must be unused and accessible at the same offset as the
+    ///   arena.
+    pub unsafe fn donate(&mut self, frame: PhysicalAddress) -> bool {
+        let frame = PhysicalAddress(align_down!(usize::from(frame), B));
+        let len = self.donated_len;
+        let below = self.donated[..len]
+            .iter()
+            .position(|range| range.end == frame);
+        let above = self.donated[..len]
+            .iter()
+            .position(|range| range.start == frame + B);
+
+        match (below, above) {
+            // The frame bridges two ranges, so they become one.
+            (Some(below), Some(above)) => {
+                self.donated[below].end = self.donated[above].end;
+                self.donated[above] = self.donated[len - 1];
+                self.donated_len -= 1;
+            }
+            (Some(below), None) => self.donated[below].end = frame + B,
+            (None, Some(above)) => self.donated[above].start = frame,
+            (None, None) if len < MAX_DONATED_RANGES => {
+                self.donated[len] = Segment::from_size(frame, B);
+                self.donated_len += 1;
+            }
+            (None, None) => return false,
+        }
+
+        self.push(frame);
+        true
+    }
+
+    /// Put a frame at the head of the free list.
+    unsafe fn push(&mut self, frame: PhysicalAddress) {
         let frame: usize = align_down!(usize::from(frame), B);
         let new_head = frame.add_offset(self.virt_offset()).as_mut_ptr() as *mut FreeFrame;
 
@@ -283,4 +384,117 @@ mod test {
         assert_eq!(1, used);
         assert_eq!(start + 4096, boundary.into());
     }
+
+    #[repr(C, align(4096))]
+    struct Frame([u8; 4096]);
+
+    #[test]
+    fn donate() {
+        let mut buf = (0..4).map(|_| Frame([0; 4096])).collect::<Vec<_>>();
+        let (donated, arena) = buf.split_at_mut(2);
+        let donated = PhysicalAddress::from_ptr(donated[1].0.as_ptr());
+        let arena = unsafe {
+            core::slice::from_raw_parts_mut(arena.as_mut_ptr() as *mut [u8; 4096], arena.len())
+        };
+        let mut allocator: FrameAllocator<4096> = unsafe { FrameAllocator::new(arena, 0) };
+
+        assert!(!allocator.contains(donated));
+        assert!(unsafe { allocator.donate(donated) });
+        assert!(allocator.contains(donated));
+
+        // The donated frame is issued first, then the arena.
+        assert_eq!(donated, allocator.alloc().unwrap());
+        allocator.alloc().unwrap();
+        allocator.alloc().unwrap();
+        assert!(allocator.alloc().is_none());
+
+        unsafe { allocator.free(donated) };
+        assert_eq!(donated, allocator.alloc().unwrap());
+    }
+
+    #[test]
+    fn contiguous() {
+        let mut buf = (0..16).map(|_| Frame([0; 4096])).collect::<Vec<_>>();
+        let start = buf.as_ptr() as usize;
+        let arena = unsafe {
+            core::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut [u8; 4096], buf.len())
+        };
+        let mut allocator: FrameAllocator<4096> = unsafe { FrameAllocator::new(arena, 0) };
+
+        // The frames skipped to align the run are issued later.
+        let first = allocator.alloc().unwrap();
+        let align = 4 * 4096;
+        let run = allocator.alloc_contiguous(4, align).unwrap();
+        assert!(run.is_aligned_to(align));
+        assert!(usize::from(run) > usize::from(first));
+
+        let skipped = (usize::from(run) - start) / 4096 - 1;
+        for _ in 0..skipped {
+            let frame = usize::from(allocator.alloc().unwrap());
+            assert!(frame > usize::from(first) && frame < usize::from(run));
+        }
+        assert_eq!(usize::from(run) + 4 * 4096, allocator.alloc().unwrap().into());
+
+        // There is no room left for another run of the whole arena.
+        assert!(allocator.alloc_contiguous(16, 4096).is_none());
+    }
+
+    #[test]
+    fn donated_ranges() {
+        let mut buf = (0..6).map(|_| Frame([0; 4096])).collect::<Vec<_>>();
+        let (below, arena) = buf.split_at_mut(4);
+        let frame = |n: usize| PhysicalAddress::from_ptr(below[n].0.as_ptr());
+        let frames = [frame(0), frame(1), frame(2), frame(3)];
+        let arena = unsafe {
+            core::slice::from_raw_parts_mut(arena.as_mut_ptr() as *mut [u8; 4096], arena.len())
+        };
+        let mut allocator: FrameAllocator<4096> = unsafe { FrameAllocator::new(arena, 0) };
+
+        // Frames between a donated frame and the arena are not contained.
+        assert!(unsafe { allocator.donate(frames[0]) });
+        assert!(allocator.contains(frames[0]));
+        assert!(!allocator.contains(frames[1]));
+        assert!(!allocator.contains(frames[3]));
+
+        // Adjacent donations extend a range rather than taking another.
+        assert!(unsafe { allocator.donate(frames[1]) });
+        assert!(unsafe { allocator.donate(frames[3]) });
+        assert!(allocator.contains(frames[1]));
+        assert!(!allocator.contains(frames[2]));
+        assert_eq!(2, allocator.donated().len());
+
+        // A frame that bridges two ranges merges them.
+        assert!(unsafe { allocator.donate(frames[2]) });
+        assert!(allocator.contains(frames[2]));
+        assert_eq!(1, allocator.donated().len());
+        assert_eq!(frames[0], allocator.donated()[0].start);
+        assert_eq!(frames[3] + 4096, allocator.donated()[0].end);
+    }
+
+    #[test]
+    fn donated_capacity() {
+        let mut buf = (0..(2 * MAX_DONATED_RANGES + 2))
+            .map(|_| Frame([0; 4096]))
+            .collect::<Vec<_>>();
+        let (below, arena) = buf.split_at_mut(2 * MAX_DONATED_RANGES);
+        let frame = |n: usize| PhysicalAddress::from_ptr(below[n].0.as_ptr());
+        let frames = (0..2 * MAX_DONATED_RANGES).map(frame).collect::<Vec<_>>();
+        let arena = unsafe {
+            core::slice::from_raw_parts_mut(arena.as_mut_ptr() as *mut [u8; 4096], arena.len())
+        };
+        let mut allocator: FrameAllocator<4096> = unsafe { FrameAllocator::new(arena, 0) };
+
+        // Every other frame takes a range of its own.
+        for n in (0..2 * MAX_DONATED_RANGES).step_by(2) {
+            assert!(allocator.can_donate(frames[n]));
+            assert!(unsafe { allocator.donate(frames[n]) });
+        }
+        assert_eq!(MAX_DONATED_RANGES, allocator.donated().len());
+
+        // Only frames next to a range fit now, and bridging frees a range.
+        assert!(allocator.can_donate(frames[1]));
+        assert!(unsafe { allocator.donate(frames[1]) });
+        assert_eq!(MAX_DONATED_RANGES - 1, allocator.donated().len());
+        assert!(allocator.can_donate(frames[2 * MAX_DONATED_RANGES - 1]));
+    }
 }
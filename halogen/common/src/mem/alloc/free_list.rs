@@ -1,296 +1,380 @@
-//! This module provides a linked-list based implementation of the Rust
-//! `GlobalAllocator` interface, i.e. an allocator that has control over some
-//! memory and can allocate chunks given a `Layout`.
+//! This module provides a two-level segregated-fit (TLSF) implementation of
+//! the Rust `GlobalAllocator` interface, i.e. an allocator that has control
+//! over some memory and can allocate chunks given a `Layout`.
 //!
-//! This is not the most elegent implementation of a linked-list. It must be
-//! must be `std`/`alloc`-free, so can't use any containers that do dynamic
-//! borrow checking. In other words, there's a lot of 'unsafe' to get around
-//! interior mutability using raw pointers.
+//! Free blocks are kept in one of many lists, each holding blocks of a narrow
+//! range of sizes. The first level splits sizes by powers of two and the second
+//! level splits each power of two into `SL_COUNT` linear steps. A bitmap for
+//! each level records which lists are non-empty, so finding a list that is
+//! guaranteed to fit a request takes a couple of bit-scans regardless of how
+//! fragmented the heap is. Freed blocks are merged with their free physical
+//! neighbors immediately, so no two free blocks are ever adjacent.
 //!
-//! With the requirement of only interfacing using `alloc` and `dealloc`, this
-//! can still be done safely without reference-counting or cells
+//! Every block starts with a header linking it to the block physically before
+//! it. Free blocks also store the links of their list in the first bytes of
+//! the otherwise unused allocation:
+//!
+//! ```text
+//! used: [prev_phys | size | check][allocation ...........]
+//! free: [prev_phys | size | check][next_free | prev_free ..]
+//! ```
 
-use core::{alloc::Layout, fmt::Debug, slice::from_raw_parts_mut};
+use core::{alloc::Layout, slice::from_raw_parts_mut};
 
 use super::AllocatorStats;
+use crate::align_up;
 
 const MAGIC: usize = 0x1234567890abcdef;
 
-/// Stores metadata about an allocation block.
-#[derive(Clone, Copy, Debug)]
-pub enum BlockHeader {
-    Free(usize, *mut BlockHeader, *mut BlockHeader),
-    Used(usize, usize, usize),
+/// Log2 of the granularity of block sizes.
+const ALIGN_LOG2: usize = 3;
+/// Granularity of block sizes.
+const ALIGN: usize = 1 << ALIGN_LOG2;
+/// Log2 of the number of second-level lists per first-level class.
+const SL_LOG2: usize = 4;
+/// Number of second-level lists per first-level class.
+const SL_COUNT: usize = 1 << SL_LOG2;
+/// Blocks smaller than this are all kept in the first first-level class, which
+/// is divided linearly.
+const SMALL_BLOCK: usize = 1 << (SL_LOG2 + ALIGN_LOG2);
+/// Log2 of the largest block that can be managed.
+const FL_MAX_LOG2: usize = 40;
+/// Number of first-level classes.
+const FL_COUNT: usize = FL_MAX_LOG2 - (SL_LOG2 + ALIGN_LOG2) + 1;
+
+/// Bit in `Block::size` marking a free block.
+const FREE: usize = 1;
+/// Bits of `Block::size` that are flags.
+const FLAGS: usize = ALIGN - 1;
+
+/// Header at the start of every block.
+#[repr(C)]
+struct Block {
+    /// The block physically before this one, or null for the first block.
+    prev_phys: *mut Block,
+    /// Size of the usable region, combined with the flags.
+    size: usize,
+    /// Checksum of the address, size and flags.
+    check: usize,
 }
 
-unsafe impl Sync for BlockHeader {}
-unsafe impl Send for BlockHeader {}
+/// Links of a free block, stored in its usable region.
+#[repr(C)]
+struct FreeLinks {
+    next: *mut Block,
+    prev: *mut Block,
+}
 
-const HEADER_SIZE: usize = core::mem::size_of::<BlockHeader>();
+const HEADER_SIZE: usize = core::mem::size_of::<Block>();
 
-impl BlockHeader {
-    /// Create a new block from a size
-    fn new(size: usize) -> BlockHeader {
-        BlockHeader::Free(size, core::ptr::null_mut(), core::ptr::null_mut())
-    }
-
-    /// Get a pointer to the block.
+impl Block {
+    /// Size of the usable region of the block.
     #[inline]
-    fn as_ptr(&self) -> *const BlockHeader {
-        core::ptr::addr_of!(*self)
+    fn size(&self) -> usize {
+        self.size & !FLAGS
     }
 
-    /// Get a mutable pointer to the block.
     #[inline]
-    fn as_mut_ptr(&mut self) -> *mut BlockHeader {
-        core::ptr::addr_of_mut!(*self)
+    fn is_free(&self) -> bool {
+        self.size & FREE != 0
     }
 
-    /// Create a mutable reference to the header whose *base* is at a pointer.
     #[inline]
-    fn from_ptr<T>(ptr: *mut T) -> &'static mut BlockHeader {
-        unsafe { (ptr as *mut BlockHeader).as_mut().expect("null pointer") }
+    fn calc_check(&self) -> usize {
+        MAGIC ^ (self as *const Block as usize).wrapping_mul(self.size | 1)
     }
 
-    /// Create a mutable reference to the header whose allocation is at a
-    /// pointer.
+    /// Set the size and flags and update the checksum.
     #[inline]
-    fn from_caller_ptr<T>(ptr: *mut T) -> &'static mut BlockHeader {
-        unsafe {
-            (ptr as *mut BlockHeader)
-                .sub(1)
-                .as_mut()
-                .expect("null pointer")
-        }
+    fn set(&mut self, size: usize, free: bool) {
+        self.size = size | if free { FREE } else { 0 };
+        self.check = self.calc_check();
     }
 
-    /// Returns the size of the block.
+    /// Check that the checksum is still valid. A value of `false` may indicate
+    /// out-of-bounds writes have corrupted the heap.
     #[inline]
-    fn size(&self) -> usize {
-        match self {
-            BlockHeader::Free(size, _, _) | BlockHeader::Used(size, _, _) => *size,
-        }
+    fn is_valid(&self) -> bool {
+        self.check == self.calc_check()
     }
 
-    /// Returns the size of the block.
+    /// Get a pointer to the usable region of a block.
     #[inline]
-    fn set_size(&mut self, size: usize) {
-        match self {
-            BlockHeader::Free(this_size, _, _) | BlockHeader::Used(this_size, _, _) => {
-                *this_size = size
-            }
-        }
+    fn allocation(&mut self) -> *mut u8 {
+        (self as *mut Block as usize + HEADER_SIZE) as *mut u8
     }
 
-    /// Return a pointer to the first byte outside this block.
+    /// Get the header of the block whose usable region is at a pointer.
     #[inline]
-    fn end(&self) -> *const u8 {
-        (self.as_ptr() as usize + self.size() + HEADER_SIZE) as *const u8
-    }
-
-    /// Get a mutable reference to the next block.
-    fn next(self) -> Option<&'static mut BlockHeader> {
-        unsafe { self.next_ptr().as_mut() }
+    fn from_allocation(ptr: *mut u8) -> *mut Block {
+        (ptr as usize - HEADER_SIZE) as *mut Block
     }
 
-    /// Get a mutable reference to the previous block.
+    /// Get a pointer to the block physically after this one. It may be past
+    /// the end of the arena.
     #[inline]
-    fn prev(self) -> Option<&'static mut BlockHeader> {
-        unsafe { self.prev_ptr().as_mut() }
+    fn next_phys(&mut self) -> *mut Block {
+        (self.allocation() as usize + self.size()) as *mut Block
     }
 
-    /// Get a pointer to the usable region of a block.
+    /// Get the list links of a free block.
     #[inline]
-    fn allocation<T>(&mut self) -> *mut T {
-        (self.as_ptr() as usize + HEADER_SIZE) as *mut T
+    fn links(&mut self) -> &mut FreeLinks {
+        unsafe { &mut *(self.allocation() as *mut FreeLinks) }
     }
+}
 
-    /// Get the pointer to the next block.
-    #[inline]
-    unsafe fn next_ptr(self) -> *mut BlockHeader {
-        match self {
-            BlockHeader::Free(_, _, next) => next,
-            _ => panic!("cannot get next for non-free block"),
-        }
+/// Get the first- and second-level indices of the list a block of `size`
+/// belongs in.
+#[inline]
+fn mapping_insert(size: usize) -> (usize, usize) {
+    if size < SMALL_BLOCK {
+        (0, size >> ALIGN_LOG2)
+    } else {
+        let log2 = usize::BITS as usize - 1 - size.leading_zeros() as usize;
+        let sl = (size >> (log2 - SL_LOG2)) ^ SL_COUNT;
+        (log2 - (SL_LOG2 + ALIGN_LOG2) + 1, sl)
     }
+}
 
-    /// Get the pointer to the previous block.
-    #[inline]
-    unsafe fn prev_ptr(self) -> *mut BlockHeader {
-        match self {
-            BlockHeader::Free(_, prev, _) => prev,
-            _ => panic!("cannot get previous for non-free block"),
-        }
+/// Get the indices of the first list whose blocks are all at least `size`.
+/// Returns `None` if the size is too large to be managed.
+#[inline]
+fn mapping_search(size: usize) -> Option<(usize, usize)> {
+    let size = if size < SMALL_BLOCK {
+        size
+    } else {
+        let log2 = usize::BITS as usize - 1 - size.leading_zeros() as usize;
+        size.checked_add((1 << (log2 - SL_LOG2)) - 1)?
+    };
+
+    match mapping_insert(size) {
+        (fl, _) if fl >= FL_COUNT => None,
+        indices => Some(indices),
     }
+}
 
-    /// Set the pointer to the next block.
-    #[inline]
-    unsafe fn set_next(&mut self, next: *mut BlockHeader) {
-        match self {
-            BlockHeader::Free(_, _, this_next) => *this_next = next,
-            _ => panic!("cannot set next for non-free block"),
-        }
-    }
+pub struct FreeListAllocator<'a, const B: usize> {
+    arena: &'a mut [u8],
+    /// The managed part of the arena, which is aligned to `ALIGN`.
+    start: usize,
+    end: usize,
+    fl_bitmap: usize,
+    sl_bitmap: [usize; FL_COUNT],
+    heads: [[*mut Block; SL_COUNT]; FL_COUNT],
+    issued_blocks: usize,
+}
 
-    /// Set the pointer to the previous block.
-    #[inline]
-    unsafe fn set_prev(&mut self, prev: *mut BlockHeader) {
-        match self {
-            BlockHeader::Free(_, this_prev, _) => *this_prev = prev,
-            _ => panic!("cannot set next for non-free block"),
+unsafe impl<'a, const B: usize> Sync for FreeListAllocator<'a, B> {}
+unsafe impl<'a, const B: usize> Send for FreeListAllocator<'a, B> {}
+
+impl<'a, const B: usize> FreeListAllocator<'a, B> {
+    /// Smallest usable region of a block. It must be able to hold the list
+    /// links when the block is freed.
+    const MIN_SIZE: usize = align_up!(
+        if B > core::mem::size_of::<FreeLinks>() {
+            B
+        } else {
+            core::mem::size_of::<FreeLinks>()
+        },
+        ALIGN
+    );
+
+    /// Initialize a free-list allocator with a region of memory to manage.
+    pub fn new(arena: &'a mut [u8]) -> FreeListAllocator<'a, B> {
+        let start = align_up!(arena.as_ptr() as usize, ALIGN);
+        let end = (arena.as_ptr() as usize + arena.len()) & !(ALIGN - 1);
+
+        assert!(end > start && end - start > HEADER_SIZE + Self::MIN_SIZE);
+        assert!(end - start - HEADER_SIZE < 1 << (FL_MAX_LOG2 + 1));
+
+        let mut allocator = FreeListAllocator {
+            arena,
+            start,
+            end,
+            fl_bitmap: 0,
+            sl_bitmap: [0; FL_COUNT],
+            heads: [[core::ptr::null_mut(); SL_COUNT]; FL_COUNT],
+            issued_blocks: 0,
+        };
+
+        unsafe {
+            let block = &mut *(start as *mut Block);
+            block.prev_phys = core::ptr::null_mut();
+            block.set(end - start - HEADER_SIZE, true);
+            allocator.insert(block);
         }
-    }
 
-    #[inline]
-    fn calc_check(&self) -> usize {
-        MAGIC ^ ((self.as_ptr() as usize).overflowing_mul(self.size())).0
+        allocator
     }
 
-    /// Set the block metadata to a checksum and record the new size. Call when
-    /// issuing a block to a caller.
+    /// Returns true if a block pointer is inside the managed region.
     #[inline]
-    fn make_used(&mut self, size: usize) {
-        match self {
-            BlockHeader::Free(_, _, _) => {
-                self.set_size(size); // Set this for the checksum calculation.
-                let check = self.calc_check();
-                *self = BlockHeader::Used(size, check, check);
-            }
-            _ => panic!("cannot mark a non-free block as used"),
-        }
+    fn in_arena(&self, block: *mut Block) -> bool {
+        (self.start..self.end).contains(&(block as usize))
     }
 
-    /// Set the block metadata to a checksum and record the new size. Call when
-    /// issuing a block to a caller.
-    #[inline]
-    fn make_free(&mut self) {
-        match self {
-            BlockHeader::Used(size, _, _) => {
-                *self = BlockHeader::Free(*size, core::ptr::null_mut(), core::ptr::null_mut());
-            }
-            _ => panic!("cannot mark a non-free block as used"),
+    /// Add a free block to the list for its size.
+    unsafe fn insert(&mut self, block: &mut Block) {
+        let (fl, sl) = mapping_insert(block.size());
+        let head = self.heads[fl][sl];
+
+        *block.links() = FreeLinks {
+            next: head,
+            prev: core::ptr::null_mut(),
+        };
+        if let Some(head) = head.as_mut() {
+            head.links().prev = block;
         }
+
+        self.heads[fl][sl] = block;
+        self.fl_bitmap |= 1 << fl;
+        self.sl_bitmap[fl] |= 1 << sl;
     }
 
-    /// Check that the checksum is still valid. A value of `false` may
-    /// indicate out-of-bounds writes have corrupted the list.
-    #[inline]
-    fn is_used_and_valid(&self) -> bool {
-        match self {
-            BlockHeader::Used(_, ck1, ck2) => {
-                let check = self.calc_check();
-                check == *ck1 && check == *ck2
+    /// Remove a free block from the list for its size.
+    unsafe fn remove(&mut self, block: &mut Block) {
+        let (fl, sl) = mapping_insert(block.size());
+        let FreeLinks { next, prev } = *block.links();
+
+        if let Some(next) = next.as_mut() {
+            next.links().prev = prev;
+        }
+        match prev.as_mut() {
+            Some(prev) => prev.links().next = next,
+            None => {
+                self.heads[fl][sl] = next;
+                if next.is_null() {
+                    self.sl_bitmap[fl] &= !(1 << sl);
+                    if self.sl_bitmap[fl] == 0 {
+                        self.fl_bitmap &= !(1 << fl);
+                    }
+                }
             }
-            _ => false,
         }
     }
 
-    /// Try to consolidate a block with its neighbors and return true if
-    /// successful.
-    fn try_coalesce(&mut self) -> bool {
-        unsafe {
-            // There is a next block.
-            if let Some(old_next) = self.next() {
-                // The blocks are adjacent.
-                if self.end() as usize == self.next_ptr() as usize {
-                    // Consume the other block and adjust the list.
-                    self.set_size(self.size() + old_next.size() + HEADER_SIZE);
-                    self.set_next(old_next.next_ptr());
-                    if let Some(new_next) = self.next() {
-                        new_next.set_prev(self);
-                    }
-                    true
-                } else {
-                    false
+    /// Find a free block of at least `size` bytes in constant time.
+    fn find_suitable(&self, size: usize) -> Option<*mut Block> {
+        let (fl, sl) = mapping_search(size)?;
+
+        let (fl, sl_map) = match self.sl_bitmap[fl] & (!0 << sl) {
+            0 => {
+                let fl_map = self.fl_bitmap & (!0usize).checked_shl(fl as u32 + 1).unwrap_or(0);
+                if fl_map == 0 {
+                    return None;
                 }
-            } else {
-                false
+                let fl = fl_map.trailing_zeros() as usize;
+                (fl, self.sl_bitmap[fl])
             }
-        }
+            sl_map => (fl, sl_map),
+        };
+
+        Some(self.heads[fl][sl_map.trailing_zeros() as usize])
     }
 
-    /// Returns true if `self` sits before `other` in memory.
-    #[inline]
-    fn should_precede(&self, other: &BlockHeader) -> bool {
-        (self.as_ptr() as usize) < (other.as_ptr() as usize)
+    /// Get the size of the block needed for an allocation of `size` bytes.
+    fn block_size(size: usize) -> Option<usize> {
+        size.checked_add(ALIGN - 1)?;
+        Some(align_up!(size.max(Self::MIN_SIZE), ALIGN))
     }
 
-    /// Attempt to coalesce two blocks, linking them by pointer if they are not
-    /// adjacent.
-    ///
-    /// Before:
-    ///
-    /// ```text
-    /// ... <--> [self] <--> [self.next] <--> ...
-    /// ```
-    ///
-    /// After:
-    ///
-    /// ```text
-    /// ... <--> [self] <--> [new] <--> [self.next] <--> ...
-    /// ```
-    ///
-    /// # Safety
-    ///
-    /// - `new` must not be in the list already.
-    unsafe fn push(&mut self, new: &mut BlockHeader) {
-        new.set_prev(self.as_mut_ptr());
+    /// If a block is large enough, split off the space past `size` bytes into
+    /// a new free block, merging it with the next block if that is free.
+    unsafe fn split(&mut self, block: &mut Block, size: usize) {
+        if block.size() - size < HEADER_SIZE + Self::MIN_SIZE {
+            return;
+        }
 
-        if let Some(next) = self.next() {
-            next.set_prev(new.as_mut_ptr());
-            new.set_next(next.as_mut_ptr());
-        } else {
-            new.set_next(core::ptr::null_mut());
+        let rest = &mut *((block.allocation() as usize + size) as *mut Block);
+        rest.prev_phys = block;
+        rest.set(block.size() - size - HEADER_SIZE, true);
+
+        let next = rest.next_phys();
+        if self.in_arena(next) {
+            (*next).prev_phys = rest;
         }
-        self.set_next(new.as_mut_ptr());
 
-        loop {
-            if !self.try_coalesce() {
-                break;
-            }
+        block.set(size, block.is_free());
+
+        if let Some(next) = self.next_free(rest) {
+            self.remove(next);
+            self.absorb_next(rest);
         }
+        self.insert(rest);
     }
-}
 
-#[derive(Debug)]
-pub struct FreeListAllocator<'a, const B: usize> {
-    head: *mut BlockHeader,
-    arena: &'a mut [u8],
-    issued_blocks: usize,
-}
+    /// Split the space before the first address in a free block that is
+    /// aligned to `align` off into a new free block. Returns the block that
+    /// starts at the aligned address. Neither block is in a list.
+    unsafe fn split_front(&mut self, block: &mut Block, align: usize) -> &'a mut Block {
+        let start = block.allocation() as usize;
+        let mut aligned = align_up!(start, align);
+        if aligned == start {
+            return &mut *(block as *mut Block);
+        }
 
-unsafe impl<'a, const B: usize> Sync for FreeListAllocator<'a, B> {}
-unsafe impl<'a, const B: usize> Send for FreeListAllocator<'a, B> {}
+        // The leading space must be large enough to be a block of its own.
+        while aligned - start < HEADER_SIZE + Self::MIN_SIZE {
+            aligned += align;
+        }
 
-impl<'a, const B: usize> FreeListAllocator<'a, B> {
-    /// Initialize a free-list allocator with a region of memory to manage.
-    pub fn new(arena: &'a mut [u8]) -> FreeListAllocator<B> {
-        assert!(arena.len() > HEADER_SIZE + B);
+        let front = aligned - start;
+        let new = &mut *Block::from_allocation(aligned as *mut u8);
+        new.prev_phys = block;
+        new.set(block.size() - front, true);
+
+        let next = new.next_phys();
+        if self.in_arena(next) {
+            (*next).prev_phys = new;
+        }
 
-        let head = BlockHeader::from_ptr(arena.as_mut_ptr());
-        *head = BlockHeader::new(arena.len() - HEADER_SIZE);
+        block.set(front - HEADER_SIZE, true);
+        self.insert(block);
 
-        FreeListAllocator {
-            head: arena.as_ptr() as *mut BlockHeader,
-            arena,
-            issued_blocks: 0,
+        new
+    }
+
+    /// Returns the free block physically after a block, if there is one.
+    unsafe fn next_free(&self, block: &mut Block) -> Option<&'a mut Block> {
+        let next = block.next_phys();
+        if self.in_arena(next) && (*next).is_free() {
+            Some(&mut *next)
+        } else {
+            None
         }
     }
 
-    /// Get a mutable reference to the head of the linked-list of free blocks.
-    #[inline]
-    fn head(&self) -> Option<&'static mut BlockHeader> {
-        unsafe { self.head.as_mut() }
+    /// Merge a block with the free block physically after it, which must not
+    /// be in a list.
+    unsafe fn absorb_next(&mut self, block: &mut Block) {
+        let next = &mut *block.next_phys();
+        block.set(block.size() + HEADER_SIZE + next.size(), block.is_free());
+
+        let after = block.next_phys();
+        if self.in_arena(after) {
+            (*after).prev_phys = block;
+        }
     }
 
     pub fn stats(&self) -> AllocatorStats {
+        let mut bytes_free = 0;
+        let mut blocks_free = 0;
+
+        for &head in self.heads.iter().flatten() {
+            let mut block = head;
+            while let Some(free) = unsafe { block.as_mut() } {
+                bytes_free += free.size();
+                blocks_free += 1;
+                block = free.links().next;
+            }
+        }
+
         let bytes_total = self.arena.len();
         let blocks_used = self.issued_blocks;
-        let bytes_free = self.into_iter().map(|block| block.size()).sum();
-        let bytes_used = bytes_total - (blocks_used * HEADER_SIZE) - bytes_free;
-        let bytes_overhead = bytes_total - bytes_free - bytes_used;
-        let blocks_free = self.into_iter().count();
         let blocks_total = blocks_free + blocks_used;
+        let bytes_overhead = bytes_total - (self.end - self.start) + blocks_total * HEADER_SIZE;
+        let bytes_used = bytes_total - bytes_free - bytes_overhead;
 
         AllocatorStats {
             bytes_free,
@@ -312,94 +396,39 @@ impl<'a, const B: usize> FreeListAllocator<'a, B> {
                 .contains(&((ptr as usize + layout.size() - 1) as *const u8))
     }
 
-    /// Try to allocate `size` bytes from this block. If possible, adjust the
-    /// list and return a pointer to the allocation.
-    fn alloc_from_block(
-        &mut self,
-        block: &'static mut BlockHeader,
-        size: usize,
-    ) -> Option<*mut u8> {
-        // Not enough space
-        if block.size() < size {
-            None
-        }
-        // Enough space, but not enough left over to make a smaller block.
-        else if block.size() - size < HEADER_SIZE + B {
-            // Connect the previous block or head to the used block's next block.
-            unsafe {
-                match block.prev() {
-                    Some(prev) => prev.set_next(block.next_ptr()),
-                    None => self.head = block.next_ptr(),
-                }
-            }
-
-            // If there is a next, update its previous to the used block's previous.
-            if let Some(next) = block.next() {
-                unsafe {
-                    next.set_prev(block.prev_ptr());
-                }
-            }
-
-            // Mark the block as used and return a pointer to the usable region.
-            block.make_used(size);
-            Some(block.allocation())
-        }
-        // Split the block (there is space for a new block).
-        else {
-            unsafe {
-                let new_block = block.allocation::<u8>().add(size) as *mut BlockHeader;
-
-                match block.prev() {
-                    Some(prev) => prev.set_next(new_block),
-                    None => self.head = new_block,
-                }
+    /// Allocate a block with the provided layout. Guaranteed to be at least `B`
+    /// and `layout.size()` in size, and aligned to `layout.align()`. Returns
+    /// `core::ptr::null_mut()` if no suitable allocation is found.
+    pub fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        let size = match Self::block_size(layout.size()) {
+            Some(size) => size,
+            None => return core::ptr::null_mut(),
+        };
 
-                if let Some(next) = block.next() {
-                    next.set_prev(new_block);
-                }
+        // Over-aligned allocations need room to split the leading space off.
+        let search = match layout.align() {
+            align if align <= ALIGN => Some(size),
+            align => size.checked_add(align + HEADER_SIZE + Self::MIN_SIZE),
+        };
 
-                *new_block = BlockHeader::Free(
-                    block.size() - HEADER_SIZE - size,
-                    block.prev_ptr(),
-                    block.next_ptr(),
-                );
+        let block = match search.and_then(|search| self.find_suitable(search)) {
+            Some(block) => unsafe { &mut *block },
+            None => return core::ptr::null_mut(),
+        };
 
-                block.make_used(size);
-                Some(block.allocation())
-            }
-        }
-    }
+        let block = unsafe {
+            self.remove(block);
+            let block = match layout.align() {
+                align if align <= ALIGN => block,
+                align => self.split_front(block, align),
+            };
+            self.split(block, size);
+            block
+        };
 
-    /// Allocate a block with the provided layout. Guaranteed to be at least `B`
-    /// and `layout.size()` in size. Returns `core::ptr::null_mut()` if no
-    /// suitable allocation is found.
-    pub fn alloc(&mut self, layout: Layout) -> *mut u8 {
-        let size = layout.size().max(B);
-        match self
-            .into_iter()
-            .find(|block| block.size() >= size)
-            .and_then(|block| self.alloc_from_block(block, size))
-        {
-            Some(ptr) => {
-                self.issued_blocks += 1;
-                ptr
-            }
-            None => core::ptr::null_mut(),
-        }
-    }
-
-    /// Place a new block at the head of this, adjust the successors.
-    pub fn set_head(&mut self, block: &mut BlockHeader) {
-        unsafe {
-            // If there is a head, set it's previous to the new head.
-            if let Some(head) = self.head() {
-                head.set_prev(block.as_mut_ptr());
-            }
-            // Point the new head to the old head (or lack of head).
-            block.set_next(self.head);
-            // Finally, set the head.
-            self.head = block.as_mut_ptr();
-        }
+        block.set(block.size(), false);
+        self.issued_blocks += 1;
+        block.allocation()
     }
 
     /// Allocate some memory and zero it.
@@ -417,111 +446,175 @@ impl<'a, const B: usize> FreeListAllocator<'a, B> {
         alloc
     }
 
+    /// Resize an allocation to `new_size` bytes, keeping its contents. The
+    /// block grows into the free block after it or shrinks in place when
+    /// possible; otherwise the contents are moved to a new allocation. Returns
+    /// `core::ptr::null_mut()` and leaves the allocation untouched if there is
+    /// no space.
+    ///
+    /// # Safety
+    ///
+    /// - `ptr` must have been returned by `alloc` with `layout` and not freed
+    ///   since.
+    pub unsafe fn realloc(&mut self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        assert!(self.contains(ptr, layout) && ptr as usize & (ALIGN - 1) == 0);
+
+        let block = &mut *Block::from_allocation(ptr);
+        assert!(block.is_valid() && !block.is_free());
+
+        let size = match Self::block_size(new_size) {
+            Some(size) => size,
+            None => return core::ptr::null_mut(),
+        };
+
+        if size > block.size() {
+            if let Some(next) = self.next_free(block) {
+                if block.size() + HEADER_SIZE + next.size() >= size {
+                    self.remove(next);
+                    self.absorb_next(block);
+                }
+            }
+        }
+
+        if size <= block.size() {
+            self.split(block, size);
+            return ptr;
+        }
+
+        // Move the contents to a new block.
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let new_ptr = self.alloc(new_layout);
+        if !new_ptr.is_null() {
+            core::ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+            self.dealloc(ptr, layout);
+        }
+
+        new_ptr
+    }
+
     /// Deallocate a block that was previously allocated from this allocator.
-    /// Will usually panic on invalid frees. May panic if the list is corrupted.
+    /// Will usually panic on invalid frees. May panic if the heap is
+    /// corrupted.
     ///
     /// # Safety
     ///
-    /// * Only call
+    /// - `ptr` must have been returned by `alloc` and not freed since.
     pub unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
-        let block = BlockHeader::from_caller_ptr(ptr);
+        // Panic if pointer is out-of-bounds or could not have been issued.
+        assert!(self.contains(ptr, layout) && ptr as usize & (ALIGN - 1) == 0);
+        assert!(ptr as usize >= self.start + HEADER_SIZE);
+
+        let mut block = &mut *Block::from_allocation(ptr);
 
-        // Panic if pointer is out-of-bounds.
-        assert!(self.contains(ptr, layout));
-        // Panic if there is suspected corruption.
-        assert!(block.is_used_and_valid());
+        // Panic if there is suspected corruption or a double free.
+        assert!(block.is_valid() && !block.is_free());
 
         self.issued_blocks -= 1;
-        block.make_free();
+        block.set(block.size(), true);
 
-        match self
-            .into_iter()
-            .filter(|other| other.should_precede(block))
-            .last()
-        {
-            Some(pred) => pred.push(block),
-            None => self.set_head(block),
+        if let Some(next) = self.next_free(block) {
+            self.remove(next);
+            self.absorb_next(block);
         }
+        if let Some(prev) = block.prev_phys.as_mut() {
+            if prev.is_free() {
+                self.remove(prev);
+                self.absorb_next(prev);
+                block = prev;
+            }
+        }
+
+        self.insert(block);
     }
 
     /// Returns an false if the heap is suspected corrupted
     pub fn integrity_ok(&self) -> bool {
         unsafe {
-            if !self.head.is_null() && !(*self.head).prev_ptr().is_null() {
-                return false;
-            }
-
-            for block in self.into_iter() {
-                // The block is within the allocator's memory.
-                if !self.contains(
-                    block.as_ptr() as *const u8,
-                    Layout::from_size_align(HEADER_SIZE + block.size(), 1).unwrap(),
-                ) {
+            // Walk the blocks in physical order.
+            let mut prev: *mut Block = core::ptr::null_mut();
+            let mut block = self.start as *mut Block;
+            let mut free_blocks = 0;
+
+            while self.in_arena(block) {
+                let this = &mut *block;
+                if !this.is_valid() || this.prev_phys != prev {
                     return false;
                 }
-                if !block.next_ptr().is_null() {
-                    if (block.next_ptr() as usize)
-                        < block.as_ptr() as usize + HEADER_SIZE + block.size()
-                    {
-                        return false;
-                    }
-                    if block.next().unwrap().prev_ptr() as usize != block.as_ptr() as usize {
+                if this.is_free() {
+                    // Free blocks are always merged with free neighbors.
+                    if prev.as_ref().is_some_and(|prev| prev.is_free()) {
                         return false;
                     }
+                    free_blocks += 1;
                 }
-                if !block.prev_ptr().is_null() {
-                    if block.prev_ptr() as usize >= block.as_ptr() as usize {
+                prev = block;
+                block = this.next_phys();
+            }
+
+            // The last block must end exactly at the end of the arena.
+            if block as usize != self.end {
+                return false;
+            }
+
+            // Every listed block is free, in the right list, and linked both
+            // ways.
+            let mut listed_blocks = 0;
+            for fl in 0..FL_COUNT {
+                for sl in 0..SL_COUNT {
+                    let head = self.heads[fl][sl];
+                    let has_blocks = self.sl_bitmap[fl] & (1 << sl) != 0;
+                    if has_blocks == head.is_null() {
                         return false;
                     }
-                    if block.prev().unwrap().next_ptr() as usize != block.as_ptr() as usize {
-                        return false;
+
+                    let mut prev: *mut Block = core::ptr::null_mut();
+                    let mut block = head;
+                    while let Some(free) = block.as_mut() {
+                        if !self.in_arena(block)
+                            || !free.is_valid()
+                            || !free.is_free()
+                            || mapping_insert(free.size()) != (fl, sl)
+                            || free.links().prev != prev
+                        {
+                            return false;
+                        }
+                        listed_blocks += 1;
+                        prev = block;
+                        block = free.links().next;
                     }
                 }
+
+                if (self.fl_bitmap & (1 << fl) != 0) != (self.sl_bitmap[fl] != 0) {
+                    return false;
+                }
             }
+
+            listed_blocks == free_blocks
         }
-        true
     }
 }
 
-impl<'a, const B: usize> core::fmt::Display for FreeListAllocator<'a, B> {
+impl<'a, const B: usize> core::fmt::Debug for FreeListAllocator<'a, B> {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        writeln!(f, "Statistics: {:?}", self.stats())?;
-        for block in self.into_iter() {
-            writeln!(f, "{:p}: {}", block, block.size())?;
-        }
-        Ok(())
+        f.debug_struct("FreeListAllocator")
+            .field("arena", &self.arena.as_ptr_range())
+            .field("issued_blocks", &self.issued_blocks)
+            .field("fl_bitmap", &format_args!("{:#b}", self.fl_bitmap))
+            .finish()
     }
 }
 
-/// Iterator over all the free blocks in a `FreeListAllocator`
-pub struct FreeListIterator {
-    curr: *mut BlockHeader,
-}
-
-impl Iterator for FreeListIterator {
-    type Item = &'static mut BlockHeader;
-
-    fn next(&mut self) -> Option<&'static mut BlockHeader> {
-        if self.curr.is_null() {
-            None
-        } else {
-            unsafe {
-                let curr = self.curr.as_mut()?;
-                let next = curr.next_ptr();
-
-                self.curr = next;
-                Some(curr)
+impl<'a, const B: usize> core::fmt::Display for FreeListAllocator<'a, B> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        writeln!(f, "Statistics: {:?}", self.stats())?;
+        for &head in self.heads.iter().flatten() {
+            let mut block = head;
+            while let Some(free) = unsafe { block.as_mut() } {
+                writeln!(f, "{:p}: {}", block, free.size())?;
+                block = free.links().next;
             }
         }
-    }
-}
-
-impl<'a, const B: usize> IntoIterator for &FreeListAllocator<'a, B> {
-    type IntoIter = FreeListIterator;
-    type Item = &'static mut BlockHeader;
-
-    fn into_iter(self) -> Self::IntoIter {
-        FreeListIterator { curr: self.head }
+        Ok(())
     }
 }
 
@@ -532,45 +625,66 @@ mod test {
     use super::*;
     use crate::mem::KIB;
 
+    #[test]
+    fn mapping() {
+        assert_eq!((0, 0), mapping_insert(0));
+        assert_eq!((0, 1), mapping_insert(8));
+        assert_eq!((0, 15), mapping_insert(SMALL_BLOCK - 8));
+        assert_eq!((1, 0), mapping_insert(SMALL_BLOCK));
+        assert_eq!((1, 1), mapping_insert(SMALL_BLOCK + 8));
+        assert_eq!((2, 0), mapping_insert(2 * SMALL_BLOCK));
+        assert_eq!((2, 15), mapping_insert(4 * SMALL_BLOCK - 1));
+
+        // Searches round up to the next list so any block in it fits.
+        assert_eq!(Some((1, 1)), mapping_search(SMALL_BLOCK + 1));
+        assert_eq!(Some((2, 0)), mapping_search(2 * SMALL_BLOCK));
+        assert_eq!(Some((3, 0)), mapping_search(4 * SMALL_BLOCK - 1));
+        assert_eq!(None, mapping_search(usize::MAX));
+    }
+
     #[test]
     fn simple() {
-        unsafe {
-            let mut buf = vec![0; 4 * KIB];
-            let mut allocator: FreeListAllocator<64> = FreeListAllocator::new(buf.as_mut_slice());
+        let mut buf = vec![0u64; 512];
+        let arena = unsafe { from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, 4 * KIB) };
+        let mut allocator: FreeListAllocator<64> = FreeListAllocator::new(arena);
 
-            let free_before = allocator.stats().bytes_free;
+        let free_before = allocator.stats().bytes_free;
 
-            let l1 = Layout::new::<[u8; KIB / 2]>();
-            let p1 = allocator.alloc(l1) as *const u8;
+        let l1 = Layout::new::<[u8; KIB / 2]>();
+        let p1 = allocator.alloc(l1);
+        assert!(!p1.is_null());
+        assert!(allocator.contains(p1, l1));
+        assert!(allocator.integrity_ok());
 
-            assert!(!p1.is_null());
-            assert!(allocator.contains(p1, l1));
-            assert!(allocator.integrity_ok());
+        let l2 = Layout::new::<[u8; KIB]>();
+        let p2 = allocator.alloc(l2);
+        assert!(!p2.is_null());
+        assert!(allocator.contains(p2, l2));
+        assert_eq!(p2 as usize, p1 as usize + l1.size() + HEADER_SIZE);
+        assert!(allocator.integrity_ok());
 
-            let l2 = Layout::new::<[u8; KIB]>();
-            let p2 = allocator.alloc(l2) as *const u8;
+        assert_eq!(
+            free_before - l1.size() - l2.size() - 2 * HEADER_SIZE,
+            allocator.stats().bytes_free
+        );
 
+        unsafe {
+            allocator.dealloc(p1, l1);
+            assert!(allocator.integrity_ok());
+            allocator.dealloc(p2, l2);
             assert!(allocator.integrity_ok());
-            assert!(!p2.is_null());
-            assert!(allocator.contains(p2, l2));
-            assert_eq!(p2 as usize, p1 as usize + HEADER_SIZE + l1.size());
-            assert_eq!(
-                l2.size(),
-                BlockHeader::from_caller_ptr(p2 as *mut u8).size()
-            );
-            assert_eq!(allocator.head as usize, p2.add(l2.size()) as usize);
-            assert_ne!(p1, p2);
-            assert_eq!(
-                free_before - l1.size() - l2.size() - (2 * HEADER_SIZE),
-                allocator.stats().bytes_free
-            );
         }
+
+        // Everything merges back into one block.
+        assert_eq!(free_before, allocator.stats().bytes_free);
+        assert_eq!(1, allocator.stats().blocks_free);
     }
 
     #[test]
     fn too_large() {
-        let mut buf = vec![0; 128];
-        let mut allocator: FreeListAllocator<64> = FreeListAllocator::new(buf.as_mut_slice());
+        let mut buf = vec![0u64; 16];
+        let mut allocator: FreeListAllocator<64> =
+            FreeListAllocator::new(unsafe { from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, 128) });
 
         assert!(allocator
             .alloc(Layout::from_size_align(128 - HEADER_SIZE + 1, 1).unwrap())
@@ -578,49 +692,38 @@ mod test {
         assert!(allocator.integrity_ok());
     }
 
-    #[test]
-    fn perfect_fit() {
-        let mut buf = vec![0; 128];
-        let mut allocator: FreeListAllocator<64> = FreeListAllocator::new(buf.as_mut_slice());
-
-        assert!(!allocator
-            .alloc(Layout::from_size_align(128 - HEADER_SIZE, 1).unwrap())
-            .is_null());
-        assert!(allocator.integrity_ok());
-    }
-
     #[test]
     fn out_of_space() {
-        let mut buf = vec![0; 128];
-        let mut allocator: FreeListAllocator<64> = FreeListAllocator::new(buf.as_mut_slice());
-
-        assert!(!allocator
-            .alloc(Layout::from_size_align(128 - HEADER_SIZE, 1).unwrap())
-            .is_null());
-
-        assert!(allocator
-            .alloc(Layout::from_size_align(1, 1).unwrap())
-            .is_null());
+        let mut buf = vec![0u64; 64];
+        let mut allocator: FreeListAllocator<64> =
+            FreeListAllocator::new(unsafe { from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, 512) });
 
-        let mut buf = vec![0; 128];
-        let mut allocator: FreeListAllocator<64> = FreeListAllocator::new(buf.as_mut_slice());
-
-        assert!(!allocator
-            .alloc(Layout::from_size_align(128 / 2, 1).unwrap())
-            .is_null());
+        let layout = Layout::from_size_align(64, 1).unwrap();
+        let mut ptrs = Vec::new();
+        loop {
+            let ptr = allocator.alloc(layout);
+            if ptr.is_null() {
+                break;
+            }
+            ptrs.push(ptr);
+        }
 
-        assert!(allocator
-            .alloc(Layout::from_size_align(128 / 2 + 1, 1).unwrap())
-            .is_null());
+        assert_eq!(512 / (64 + HEADER_SIZE), ptrs.len());
+        assert!(allocator.integrity_ok());
 
+        for ptr in ptrs {
+            unsafe { allocator.dealloc(ptr, layout) };
+        }
+        assert_eq!(1, allocator.stats().blocks_free);
         assert!(allocator.integrity_ok());
     }
 
     #[test]
     #[should_panic]
     fn invalid_free() {
-        let mut buf = vec![0; 128];
-        let mut allocator: FreeListAllocator<64> = FreeListAllocator::new(buf.as_mut_slice());
+        let mut buf = vec![0u64; 16];
+        let mut allocator: FreeListAllocator<64> =
+            FreeListAllocator::new(unsafe { from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, 128) });
 
         let layout = Layout::from_size_align(64, 1).unwrap();
         let ptr = allocator.alloc(layout);
@@ -631,248 +734,195 @@ mod test {
     }
 
     #[test]
-    fn allocate_between() {
-        let mut buf = vec![0; 8 * KIB];
-        let mut allocator: FreeListAllocator<64> = FreeListAllocator::new(buf.as_mut_slice());
-        let arr_size = 1000;
-
-        let layout = Layout::from_size_align(arr_size, 1).unwrap();
-
-        assert!(!allocator.alloc_zeroed(layout).is_null());
-
-        let middle_block = allocator.alloc_zeroed(layout);
-        assert!(!middle_block.is_null());
+    #[should_panic]
+    fn double_free() {
+        let mut buf = vec![0u64; 64];
+        let mut allocator: FreeListAllocator<64> =
+            FreeListAllocator::new(unsafe { from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, 512) });
 
-        assert!(!allocator.alloc_zeroed(layout).is_null());
+        let layout = Layout::from_size_align(64, 1).unwrap();
+        let ptr = allocator.alloc(layout);
+        let _guard = allocator.alloc(layout);
 
         unsafe {
-            allocator.dealloc(middle_block, layout);
+            allocator.dealloc(ptr, layout);
+            allocator.dealloc(ptr, layout);
         }
-        assert_eq!(middle_block as usize - HEADER_SIZE, allocator.head as usize);
-
-        assert!(allocator.integrity_ok());
-
-        let larger_layout = Layout::from_size_align(arr_size + 1, 1).unwrap();
-        assert!(!allocator.alloc_zeroed(larger_layout).is_null());
-
-        assert!(allocator.integrity_ok());
     }
 
     #[test]
-    fn stress_1() {
-        unsafe {
-            let mut buf = vec![0; 8 * KIB];
-            let mut allocator: FreeListAllocator<64> = FreeListAllocator::new(buf.as_mut_slice());
-
-            assert!(allocator.integrity_ok());
-
-            let orig_head = allocator.head;
-            let free_before = allocator.stats().bytes_free;
-
-            let l1 = Layout::new::<[u8; KIB]>();
-            let p1 = allocator.alloc(l1) as *const u8;
-
-            assert!(!p1.is_null());
-            assert!(allocator.arena.as_ptr_range().contains(&p1));
-            assert!(allocator.integrity_ok());
+    fn coalesce() {
+        let mut buf = vec![0u64; 1024];
+        let mut allocator: FreeListAllocator<64> = FreeListAllocator::new(unsafe {
+            from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, 8 * KIB)
+        });
+
+        let layout = Layout::from_size_align(1000, 1).unwrap();
+        let ptrs = [
+            allocator.alloc(layout),
+            allocator.alloc(layout),
+            allocator.alloc(layout),
+            allocator.alloc(layout),
+        ];
 
-            let l2 = Layout::new::<[u8; 32]>();
-            let p2 = allocator.alloc(l2) as *const u8;
-
-            assert!(!p2.is_null());
-            assert!(allocator.arena.as_ptr_range().contains(&p2));
-            assert_eq!(p2 as usize, p1 as usize + HEADER_SIZE + l1.size());
-            assert_eq!(64, BlockHeader::from_caller_ptr(p2 as *mut u8).size());
-            assert_eq!(allocator.head as usize, p2.add(64) as usize);
-            assert!(allocator.integrity_ok());
-
-            let free_during = allocator.stats().bytes_free;
-
-            let old_head = allocator.head;
-            allocator.dealloc(p1 as *mut u8, l1);
-
-            assert_eq!(allocator.head, (p1 as *mut BlockHeader).sub(1));
-            assert_eq!(old_head, allocator.head().unwrap().next_ptr());
-            assert!(allocator.head().unwrap().prev().is_none());
-            assert_eq!(free_during + l1.size(), allocator.stats().bytes_free);
-            assert_eq!(
-                l1.size(),
-                BlockHeader::from_caller_ptr(p1 as *mut u8).size()
-            );
+        unsafe {
+            // Free the middle blocks in an order that exercises merging with
+            // the next and previous neighbors.
+            allocator.dealloc(ptrs[1], layout);
+            allocator.dealloc(ptrs[2], layout);
+            assert_eq!(2, allocator.stats().blocks_free);
             assert!(allocator.integrity_ok());
 
-            allocator.dealloc(p2 as *mut u8, l2);
+            // The merged hole fits something larger than either block.
+            let larger = Layout::from_size_align(1900, 1).unwrap();
+            let ptr = allocator.alloc(larger);
+            assert_eq!(ptrs[1], ptr);
+            allocator.dealloc(ptr, larger);
 
-            assert_eq!(allocator.head, orig_head);
-            assert!(allocator.head().unwrap().next().is_none());
-            assert_eq!(free_before, allocator.stats().bytes_free);
-            assert!(allocator.integrity_ok());
+            allocator.dealloc(ptrs[0], layout);
+            allocator.dealloc(ptrs[3], layout);
         }
+
+        assert_eq!(1, allocator.stats().blocks_free);
+        assert!(allocator.integrity_ok());
     }
 
     #[test]
-    fn stress_2() {
-        for _ in 0..100 {
-            let mut buf = vec![0; 20 * KIB];
-            let mut allocator: FreeListAllocator<64> = FreeListAllocator::new(buf.as_mut_slice());
-
-            let trials = 10;
-
-            for _ in 0..trials {
-                let sizes = [16, 64, 512];
-
-                let usage_before = allocator.stats().bytes_used;
-                let free_before = allocator.stats().bytes_free;
-
-                let ptrs = sizes
-                    .iter()
-                    .map(|&size| {
-                        let layout = Layout::from_size_align(size, 1).unwrap();
-                        let ptr = allocator.alloc_zeroed(layout);
-                        (layout, ptr)
-                    })
-                    .collect::<Vec<_>>();
-
-                for (i1, (l1, p1)) in ptrs.iter().enumerate() {
-                    for (i2, (l2, p2)) in ptrs.iter().enumerate() {
-                        let p1 = *p1 as usize;
-                        let p2 = *p2 as usize;
-
-                        assert!(
-                            i1 == i2
-                                || p1.abs_diff(p2)
-                                    >= (if p1 < p2 { l1.size() } else { l2.size() }) + HEADER_SIZE
-                        );
-                    }
-
-                    let slice = unsafe { from_raw_parts_mut(*p1, l1.size()) };
+    fn stress() {
+        let mut buf = vec![0u64; 64 * KIB / 8];
+        let mut allocator: FreeListAllocator<64> = FreeListAllocator::new(unsafe {
+            from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, 64 * KIB)
+        });
+        let free_before = allocator.stats().bytes_free;
+
+        // A simple xorshift generator keeps the test deterministic.
+        let mut state = 0x2545F4914F6CDD1Du64;
+        let mut rand = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as usize
+        };
 
-                    slice.iter_mut().for_each(|byte| {
-                        assert_eq!(0, *byte);
-                        *byte = 7;
-                    });
+        let mut live: Vec<(*mut u8, Layout, u8)> = Vec::new();
+        for i in 0..10_000 {
+            if !live.is_empty() && rand() % 5 == 0 {
+                // Resize a random block, checking that its contents survive.
+                let j = rand() % live.len();
+                let (ptr, layout, fill) = live[j];
+                let new_size = 1 + rand() % 700;
+                let ptr = unsafe { allocator.realloc(ptr, layout, new_size) };
+                if !ptr.is_null() {
+                    let slice = unsafe { from_raw_parts_mut(ptr, layout.size().min(new_size)) };
+                    assert!(slice.iter().all(|&byte| byte == fill));
+                    unsafe { ptr.write_bytes(fill, new_size) };
+                    let layout = Layout::from_size_align(new_size, layout.align()).unwrap();
+                    live[j] = (ptr, layout, fill);
                 }
+            } else if live.is_empty() || rand() % 3 != 0 {
+                let align = 1 << (rand() % 8);
+                let layout = Layout::from_size_align(1 + rand() % 700, align).unwrap();
+                let ptr = allocator.alloc(layout);
+                if !ptr.is_null() {
+                    assert_eq!(0, ptr as usize % layout.align());
+                    let fill = i as u8;
+                    unsafe { ptr.write_bytes(fill, layout.size()) };
+                    live.push((ptr, layout, fill));
+                }
+            } else {
+                let (ptr, layout, fill) = live.swap_remove(rand() % live.len());
+                let slice = unsafe { from_raw_parts_mut(ptr, layout.size()) };
+                assert!(slice.iter().all(|&byte| byte == fill));
+                unsafe { allocator.dealloc(ptr, layout) };
+            }
 
-                ptrs.iter()
-                    .for_each(|(layout, alloc)| unsafe { allocator.dealloc(*alloc, *layout) });
-
-                assert_eq!(usage_before, allocator.stats().bytes_used);
-                assert_eq!(free_before, allocator.stats().bytes_free);
+            if i % 100 == 0 {
+                assert!(allocator.integrity_ok());
             }
         }
-    }
-
-    #[test]
-    fn stress_3() {
-        let mut buf = vec![0; 8 * KIB];
-        let mut allocator: FreeListAllocator<64> = FreeListAllocator::new(buf.as_mut_slice());
-        let arr_size = 1000;
-
-        assert!(allocator.integrity_ok());
-
-        let (l1, arr1) = unsafe {
-            let layout = Layout::from_size_align(arr_size, 1).unwrap();
-            (
-                layout,
-                from_raw_parts_mut(allocator.alloc_zeroed(layout), arr_size),
-            )
-        };
-
-        assert!(allocator.integrity_ok());
-
-        arr1.iter_mut().for_each(|byte| *byte = 1);
-
-        assert!(allocator.integrity_ok());
-
-        let (l2, arr2) = unsafe {
-            let layout = Layout::from_size_align(arr_size, 1).unwrap();
-            (
-                layout,
-                from_raw_parts_mut(allocator.alloc_zeroed(layout), arr_size),
-            )
-        };
-
-        assert!(allocator.integrity_ok());
-
-        arr2.iter_mut().for_each(|byte| *byte = 2);
-        arr1.iter().for_each(|byte| assert_eq!(1, *byte));
-
-        assert!(allocator.integrity_ok());
-
-        let (l3, arr3) = unsafe {
-            let layout = Layout::from_size_align(arr_size, 1).unwrap();
-            (
-                layout,
-                from_raw_parts_mut(allocator.alloc_zeroed(layout), arr_size),
-            )
-        };
-
-        assert!(allocator.integrity_ok());
 
-        arr3.iter_mut().for_each(|byte| *byte = 3);
-
-        assert!(allocator.integrity_ok());
-        arr1.iter().for_each(|byte| assert_eq!(1, *byte));
-        arr2.iter().for_each(|byte| assert_eq!(2, *byte));
-
-        arr1.iter_mut().for_each(|byte| *byte = 1);
-        arr2.iter_mut().for_each(|byte| *byte = 2);
+        for (ptr, layout, _) in live {
+            unsafe { allocator.dealloc(ptr, layout) };
+        }
 
         assert!(allocator.integrity_ok());
-        arr3.iter().for_each(|byte| assert_eq!(3, *byte));
+        assert_eq!(free_before, allocator.stats().bytes_free);
+        assert_eq!(1, allocator.stats().blocks_free);
+    }
 
-        unsafe {
-            allocator.dealloc(arr2.as_mut_ptr(), l2);
+    #[test]
+    fn aligned() {
+        let mut buf = vec![0u64; 64 * KIB / 8];
+        let mut allocator: FreeListAllocator<64> = FreeListAllocator::new(unsafe {
+            from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, 64 * KIB)
+        });
+        let free_before = allocator.stats().bytes_free;
+
+        let mut ptrs = Vec::new();
+        for align in [16, 64, 256, 4 * KIB, 8 * KIB] {
+            let small = Layout::from_size_align(24, 8).unwrap();
+            ptrs.push((allocator.alloc(small), small));
+
+            let layout = Layout::from_size_align(100, align).unwrap();
+            let ptr = allocator.alloc(layout);
+            assert!(!ptr.is_null());
+            assert_eq!(0, ptr as usize % align);
+            assert!(allocator.integrity_ok());
+            ptrs.push((ptr, layout));
         }
 
-        assert!(allocator.integrity_ok());
-        arr1.iter().for_each(|byte| assert_eq!(1, *byte));
-        arr3.iter().for_each(|byte| assert_eq!(3, *byte));
-
-        let (l4, arr4) = unsafe {
-            let layout = Layout::from_size_align(arr_size + 1, 1).unwrap();
-            (
-                layout,
-                from_raw_parts_mut(allocator.alloc_zeroed(layout), arr_size + 1),
-            )
-        };
+        // The leading space of page-aligned blocks is not wasted.
+        assert!(allocator.stats().bytes_free > free_before - 4 * KIB);
 
-        assert!(allocator.integrity_ok());
-        assert!(
-            arr4.as_ptr() > arr3.as_ptr()
-                && arr4.as_ptr() > arr2.as_ptr()
-                && arr4.as_ptr() > arr1.as_ptr()
-        );
-        arr1.iter().for_each(|byte| assert_eq!(1, *byte));
-        arr3.iter().for_each(|byte| assert_eq!(3, *byte));
-
-        arr4.iter_mut().for_each(|byte| *byte = 4);
+        for (ptr, layout) in ptrs {
+            unsafe { allocator.dealloc(ptr, layout) };
+            assert!(allocator.integrity_ok());
+        }
+        assert_eq!(free_before, allocator.stats().bytes_free);
+    }
 
-        assert!(allocator.integrity_ok());
-        arr1.iter().for_each(|byte| assert_eq!(1, *byte));
-        arr3.iter().for_each(|byte| assert_eq!(3, *byte));
+    #[test]
+    fn realloc() {
+        let mut buf = vec![0u64; 1024];
+        let mut allocator: FreeListAllocator<64> = FreeListAllocator::new(unsafe {
+            from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, 8 * KIB)
+        });
+        let free_before = allocator.stats().bytes_free;
+
+        let layout = Layout::from_size_align(100, 8).unwrap();
+        let ptr = allocator.alloc(layout);
+        unsafe { ptr.write_bytes(7, layout.size()) };
 
         unsafe {
-            allocator.dealloc(arr4.as_mut_ptr(), l4);
-        }
-
-        println!("{}", allocator);
+            // Grow into the free space after the block.
+            let grown = allocator.realloc(ptr, layout, 1000);
+            assert_eq!(ptr, grown);
+            assert!(allocator.integrity_ok());
 
-        assert!(allocator.integrity_ok());
-        arr1.iter().for_each(|byte| assert_eq!(1, *byte));
-        arr3.iter().for_each(|byte| assert_eq!(3, *byte));
+            // Shrink in place, returning the tail to the free list.
+            let layout = Layout::from_size_align(1000, 8).unwrap();
+            let shrunk = allocator.realloc(ptr, layout, 200);
+            assert_eq!(ptr, shrunk);
+            assert!(allocator.integrity_ok());
 
-        unsafe {
-            allocator.dealloc(arr1.as_mut_ptr(), l1);
-        }
+            // A block after it forces the next growth to move.
+            let layout = Layout::from_size_align(200, 8).unwrap();
+            let blocker = allocator.alloc(layout);
+            let moved = allocator.realloc(ptr, layout, 2000);
+            assert_ne!(ptr, moved);
+            assert!(from_raw_parts_mut(moved, 100).iter().all(|&byte| byte == 7));
+            assert!(allocator.integrity_ok());
 
-        assert!(allocator.integrity_ok());
-        arr3.iter().for_each(|byte| assert_eq!(3, *byte));
+            // Too large to fit anywhere.
+            let layout = Layout::from_size_align(2000, 8).unwrap();
+            assert!(allocator.realloc(moved, layout, 16 * KIB).is_null());
 
-        unsafe {
-            allocator.dealloc(arr3.as_mut_ptr(), l3);
+            allocator.dealloc(moved, layout);
+            allocator.dealloc(blocker, Layout::from_size_align(200, 8).unwrap());
         }
 
         assert!(allocator.integrity_ok());
+        assert_eq!(free_before, allocator.stats().bytes_free);
+        assert_eq!(1, allocator.stats().blocks_free);
     }
 }
@@ -4,6 +4,19 @@ pub use frame::*;
 mod free_list;
 pub use free_list::*;
 
+#[cfg(any(test, feature = "bench"))]
+mod linked_list;
+#[cfg(any(test, feature = "bench"))]
+pub use linked_list::*;
+
+#[cfg(feature = "debug-heap")]
+mod debug;
+#[cfg(feature = "debug-heap")]
+pub use debug::*;
+
+mod track;
+pub use track::*;
+
 #[cfg(feature = "alloc")]
 mod segment;
 #[cfg(feature = "alloc")]
@@ -1,80 +1,256 @@
+//! The `SegmentAllocator` hands out ranges of addresses from a segment. Free
+//! ranges are kept in two trees: one ordered by start address, so a freed range
+//! can be merged with its neighbors, and one ordered by size, so the smallest
+//! free range that fits a request is found in logarithmic time.
+
 #[cfg(not(test))]
-use alloc::vec::Vec;
+use alloc::collections::{BTreeMap, BTreeSet};
+#[cfg(test)]
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::mem::{Address, Segment};
+
+/// The ways freeing an address can fail.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FreeError {
+    /// The address is outside the segment managed by the allocator.
+    OutOfRange,
+    /// The address is inside an allocation, but not at its start.
+    NotStart,
+    /// The address is not allocated; it may have been freed already.
+    DoubleFree,
+}
 
-use crate::{
-    align_up,
-    mem::{Address, Segment},
-};
+/// Round `n` up to a multiple of `align`, a power of two, or return `None` if
+/// that overflows.
+fn checked_align_up(n: usize, align: usize) -> Option<usize> {
+    Some(n.checked_add(align - 1)? & !(align - 1))
+}
 
 #[derive(Clone, Debug)]
 pub struct SegmentAllocator<T: Address> {
     segment: Segment<T>,
-    allocations: Vec<Segment<T>>,
     align: usize,
+    /// Free ranges by start address, mapped to their end.
+    free: BTreeMap<usize, usize>,
+    /// Free ranges by size, then start address.
+    by_size: BTreeSet<(usize, usize)>,
+    /// Live allocations by start address, mapped to their size.
+    allocations: BTreeMap<usize, usize>,
     _phantom: core::marker::PhantomData<T>,
 }
 
 impl<T: Address> SegmentAllocator<T> {
+    /// Create an allocator for the addresses in `segment`. Every allocation is
+    /// a multiple of `align` in size and aligned to at least `align`.
     pub fn new(segment: Segment<T>, align: usize) -> SegmentAllocator<T> {
-        SegmentAllocator {
-            segment: segment.align_up(align),
+        let segment = segment.align_up(align);
+
+        let mut allocator = SegmentAllocator {
+            segment,
             align,
-            allocations: Vec::new(),
+            free: BTreeMap::new(),
+            by_size: BTreeSet::new(),
+            allocations: BTreeMap::new(),
             _phantom: core::marker::PhantomData,
+        };
+
+        if segment.start.into() < segment.end.into() {
+            allocator.insert_free(segment.start.into(), segment.end.into());
         }
+
+        allocator
+    }
+
+    fn insert_free(&mut self, start: usize, end: usize) {
+        self.free.insert(start, end);
+        self.by_size.insert((end - start, start));
     }
 
+    fn remove_free(&mut self, start: usize, end: usize) {
+        self.free.remove(&start);
+        self.by_size.remove(&(end - start, start));
+    }
+
+    /// Allocate `size` bytes aligned to the allocator's alignment.
     pub fn alloc(&mut self, size: usize) -> Option<T> {
-        let size = align_up!(size, self.align);
+        self.alloc_aligned(size, self.align)
+    }
 
-        // Fast path: check if the segment fits at the end
-        let tail = match self.allocations.last() {
-            Some(last) => last.end,
-            None => self.segment.start,
-        };
+    /// Allocate `size` bytes aligned to `align`, which must be a power of two.
+    /// The smallest free range that fits is used. Returns `None` if nothing
+    /// fits, including when `size` is too large to round up.
+    pub fn alloc_aligned(&mut self, size: usize, align: usize) -> Option<T> {
+        let align = align.max(self.align);
+        let size = checked_align_up(size.max(1), self.align)?;
 
-        if self.segment.contains(tail + size) {
-            let segment = Segment::from_size(tail, size);
-            self.allocations.push(segment);
-            return Some(segment.start);
+        // Ranges are ordered by size, so the first that fits is the best fit.
+        // Unless the alignment is larger than the allocator's, that is always
+        // the first range at least `size` in length. Otherwise, free ranges
+        // start on a multiple of `self.align`, so aligning one wastes at most
+        // `align - self.align` bytes, and the first range at least that much
+        // longer than `size` always fits. The scan is bounded by the number of
+        // free ranges whose length is within `align` of `size`.
+        let (start, end, base) = self
+            .by_size
+            .range((size, 0)..)
+            .filter_map(|&(len, start)| Some((start, start + len, checked_align_up(start, align)?)))
+            .find(|&(_, end, base)| matches!(base.checked_add(size), Some(top) if top <= end))?;
+
+        self.remove_free(start, end);
+        if start < base {
+            self.insert_free(start, base);
+        }
+        if base + size < end {
+            self.insert_free(base + size, end);
         }
 
-        // Find the index and start address if the new segment can fit between existing
-        // segments
-        match self
-            .allocations
-            .windows(2)
-            .enumerate()
-            .find(|(_, w)| w[1].start - w[0].end >= size)
-            .map(|(i, w)| (i + 1, w[0].end))
-        {
-            // It fits between existing allocations
-            Some((i, addr)) => {
-                let segment = Segment::from_size(addr, size);
-                self.allocations.insert(i, segment);
-                Some(segment.start)
-            }
+        self.allocations.insert(base, size);
+        Some(T::from(base))
+    }
 
-            // Put the new allocation at the end
+    /// Free the allocation starting at `addr`, merging it with any free
+    /// neighbors. Returns the size of the allocation.
+    pub fn free(&mut self, addr: T) -> Result<usize, FreeError> {
+        if !self.segment.contains(addr) {
+            return Err(FreeError::OutOfRange);
+        }
+
+        let addr: usize = addr.into();
+        let size = match self.allocations.remove(&addr) {
+            Some(size) => size,
             None => {
-                let start = match self.allocations.last() {
-                    Some(last) => last.end,
-                    None => self.segment.start,
-                };
-
-                let segment = Segment::from_size(start, size);
-
-                if self.segment.encapsulates(segment) {
-                    self.allocations.push(segment);
-                    Some(segment.start)
-                } else {
-                    None
+                return match self.allocations.range(..addr).next_back() {
+                    Some((&start, &size)) if addr < start + size => Err(FreeError::NotStart),
+                    _ => Err(FreeError::DoubleFree),
                 }
             }
+        };
+
+        let mut start = addr;
+        let mut end = addr + size;
+
+        if let Some((&prev, &prev_end)) = self.free.range(..start).next_back() {
+            if prev_end == start {
+                self.remove_free(prev, prev_end);
+                start = prev;
+            }
+        }
+        if let Some(&next_end) = self.free.get(&end) {
+            self.remove_free(end, next_end);
+            end = next_end;
+        }
+
+        self.insert_free(start, end);
+        Ok(size)
+    }
+
+    /// Get the allocation starting at `addr`, if there is one.
+    pub fn allocation(&self, addr: T) -> Option<Segment<T>> {
+        self.allocations
+            .get(&addr.into())
+            .map(|&size| Segment::from_size(addr, size))
+    }
+
+    /// Number of free bytes, which may not be contiguous.
+    pub fn free_bytes(&self) -> usize {
+        self.by_size.iter().map(|&(size, _)| size).sum()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PAGE: usize = 0x1000;
+
+    fn allocator() -> SegmentAllocator<usize> {
+        SegmentAllocator::new(Segment::new(0x10000, 0x20000), PAGE)
+    }
+
+    #[test]
+    fn reuse() {
+        let mut allocator = allocator();
+
+        let a = allocator.alloc(PAGE).unwrap();
+        let b = allocator.alloc(3 * PAGE).unwrap();
+        assert_eq!(PAGE, b - a);
+        assert_eq!(Ok(PAGE), allocator.free(a));
+
+        // The freed page is the best fit.
+        assert_eq!(Some(a), allocator.alloc(1));
+        assert_eq!(Ok(3 * PAGE), allocator.free(b));
+        assert_eq!(Ok(PAGE), allocator.free(a));
+        assert_eq!(0x10000, allocator.free_bytes());
+    }
+
+    #[test]
+    fn best_fit() {
+        let mut allocator = allocator();
+
+        let ptrs: Vec<usize> = (0..6).map(|_| allocator.alloc(2 * PAGE).unwrap()).collect();
+        allocator.free(ptrs[1]).unwrap();
+        allocator.free(ptrs[3]).unwrap();
+        allocator.free(ptrs[4]).unwrap();
+
+        // The two-page hole is used rather than the four-page hole or the tail.
+        assert_eq!(Some(ptrs[1]), allocator.alloc(PAGE));
+        assert_eq!(Some(ptrs[3]), allocator.alloc(3 * PAGE));
+    }
+
+    #[test]
+    fn coalesce() {
+        let mut allocator = allocator();
+
+        let ptrs: Vec<usize> = (0..16).map(|_| allocator.alloc(PAGE).unwrap()).collect();
+        assert_eq!(None, allocator.alloc(PAGE));
+
+        for &ptr in ptrs.iter().step_by(2).chain(ptrs.iter().skip(1).step_by(2)) {
+            allocator.free(ptr).unwrap();
         }
+
+        assert_eq!(Some(0x10000), allocator.alloc(16 * PAGE));
     }
 
-    pub fn free(&mut self, addr: T) {
-        self.allocations.retain(|segment| !segment.contains(addr));
+    #[test]
+    fn aligned() {
+        let mut allocator = allocator();
+
+        allocator.alloc(PAGE).unwrap();
+        let ptr = allocator.alloc_aligned(PAGE, 0x8000).unwrap();
+        assert_eq!(0x18000, ptr);
+
+        // The space skipped for alignment is still free.
+        assert_eq!(Some(0x11000), allocator.alloc(PAGE));
+        assert_eq!(None, allocator.alloc_aligned(PAGE, 0x10000));
+    }
+
+    #[test]
+    fn too_large() {
+        let mut allocator = allocator();
+
+        assert_eq!(None, allocator.alloc(usize::MAX));
+        assert_eq!(None, allocator.alloc(usize::MAX - PAGE));
+        assert_eq!(None, allocator.alloc_aligned(usize::MAX, 0x8000));
+
+        // An alignment past the end of the address-space fits nowhere.
+        let mut allocator =
+            SegmentAllocator::new(Segment::new(usize::MAX - 0xFFFF, usize::MAX), PAGE);
+        assert_eq!(None, allocator.alloc_aligned(PAGE, 1 << 63));
+        assert!(allocator.alloc(PAGE).is_some());
+    }
+
+    #[test]
+    fn invalid_free() {
+        let mut allocator = allocator();
+
+        let ptr = allocator.alloc(2 * PAGE).unwrap();
+        assert_eq!(Err(FreeError::OutOfRange), allocator.free(0x30000));
+        assert_eq!(Err(FreeError::NotStart), allocator.free(ptr + PAGE));
+        assert_eq!(Err(FreeError::DoubleFree), allocator.free(ptr + 2 * PAGE));
+
+        assert_eq!(Ok(2 * PAGE), allocator.free(ptr));
+        assert_eq!(Err(FreeError::DoubleFree), allocator.free(ptr));
+        assert_eq!(0x10000, allocator.free_bytes());
     }
 }
@@ -0,0 +1,431 @@
+//! Bookkeeping for heap allocation tracking. Each live allocation is recorded
+//! with its layout, the address it was allocated from and a `Tag` naming the
+//! subsystem that owns it. The records live in a fixed-size table so that
+//! tracking never allocates from the heap it is tracking.
+
+use core::alloc::Layout;
+
+/// The subsystem an allocation is made on behalf of.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Tag {
+    Untagged,
+    Scheduler,
+    Process,
+    Loader,
+    Paging,
+    SharedMemory,
+    Driver,
+}
+
+impl Tag {
+    pub const ALL: [Tag; Tag::COUNT] = [
+        Tag::Untagged,
+        Tag::Scheduler,
+        Tag::Process,
+        Tag::Loader,
+        Tag::Paging,
+        Tag::SharedMemory,
+        Tag::Driver,
+    ];
+    pub const COUNT: usize = 7;
+
+    /// Get a tag from its index, falling back to `Untagged`.
+    pub fn from_index(index: usize) -> Tag {
+        Tag::ALL.get(index).copied().unwrap_or(Tag::Untagged)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Tag::Untagged => "untagged",
+            Tag::Scheduler => "scheduler",
+            Tag::Process => "process",
+            Tag::Loader => "loader",
+            Tag::Paging => "paging",
+            Tag::SharedMemory => "shared memory",
+            Tag::Driver => "driver",
+        }
+    }
+}
+
+/// A live allocation.
+#[derive(Clone, Copy, Debug)]
+pub struct Allocation {
+    pub ptr: usize,
+    pub layout: Layout,
+    /// Return address of the code that made the allocation.
+    pub caller: usize,
+    pub tag: Tag,
+    /// Order in which the allocation was made.
+    pub seq: u64,
+}
+
+/// Number and size of the live allocations with a tag.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TagUsage {
+    pub count: usize,
+    pub bytes: usize,
+}
+
+/// Change in `TagUsage` between two snapshots.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TagDelta {
+    pub count: isize,
+    pub bytes: isize,
+}
+
+/// Usage of the heap grouped by tag at a point in time.
+#[derive(Clone, Copy, Debug)]
+pub struct Snapshot {
+    seq: u64,
+    usage: [TagUsage; Tag::COUNT],
+    /// Allocations that could not be recorded because the table was full.
+    pub dropped: usize,
+}
+
+impl Snapshot {
+    pub fn usage(&self, tag: Tag) -> TagUsage {
+        self.usage[tag as usize]
+    }
+
+    /// Usage summed over every tag.
+    pub fn total(&self) -> TagUsage {
+        self.usage.iter().fold(TagUsage::default(), |sum, usage| {
+            TagUsage {
+                count: sum.count + usage.count,
+                bytes: sum.bytes + usage.bytes,
+            }
+        })
+    }
+
+    /// Get the change in usage of each tag since an earlier snapshot.
+    pub fn diff(&self, earlier: &Snapshot) -> SnapshotDiff {
+        let mut deltas = [TagDelta::default(); Tag::COUNT];
+
+        for (delta, (now, then)) in deltas
+            .iter_mut()
+            .zip(self.usage.iter().zip(earlier.usage.iter()))
+        {
+            *delta = TagDelta {
+                count: now.count as isize - then.count as isize,
+                bytes: now.bytes as isize - then.bytes as isize,
+            };
+        }
+
+        SnapshotDiff { deltas }
+    }
+}
+
+impl core::fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for tag in Tag::ALL {
+            let usage = self.usage(tag);
+            if usage.count > 0 {
+                writeln!(
+                    f,
+                    "{:<14} {:>6} allocations {:>10} bytes",
+                    tag.name(),
+                    usage.count,
+                    usage.bytes
+                )?;
+            }
+        }
+
+        let total = self.total();
+        write!(
+            f,
+            "{:<14} {:>6} allocations {:>10} bytes",
+            "total", total.count, total.bytes
+        )?;
+        if self.dropped > 0 {
+            write!(f, " ({} untracked)", self.dropped)?;
+        }
+        Ok(())
+    }
+}
+
+/// Change in usage of each tag between two snapshots.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SnapshotDiff {
+    deltas: [TagDelta; Tag::COUNT],
+}
+
+impl SnapshotDiff {
+    pub fn delta(&self, tag: Tag) -> TagDelta {
+        self.deltas[tag as usize]
+    }
+
+    /// Returns `true` if no tag's usage changed.
+    pub fn is_empty(&self) -> bool {
+        self.deltas
+            .iter()
+            .all(|delta| *delta == TagDelta::default())
+    }
+}
+
+impl core::fmt::Display for SnapshotDiff {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if self.is_empty() {
+            return write!(f, "no change");
+        }
+
+        for tag in Tag::ALL {
+            let delta = self.delta(tag);
+            if delta != TagDelta::default() {
+                writeln!(
+                    f,
+                    "{:<14} {:>+6} allocations {:>+10} bytes",
+                    tag.name(),
+                    delta.count,
+                    delta.bytes
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A table of up to `N` live allocations, keyed by address.
+pub struct AllocationTracker<const N: usize> {
+    /// Open-addressed table using linear probing.
+    entries: [Option<Allocation>; N],
+    len: usize,
+    seq: u64,
+    usage: [TagUsage; Tag::COUNT],
+    dropped: usize,
+}
+
+impl<const N: usize> AllocationTracker<N> {
+    pub const fn new() -> AllocationTracker<N> {
+        AllocationTracker {
+            entries: [None; N],
+            len: 0,
+            seq: 0,
+            usage: [TagUsage { count: 0, bytes: 0 }; Tag::COUNT],
+            dropped: 0,
+        }
+    }
+
+    /// The slot an address is placed in if there are no collisions.
+    fn home(ptr: usize) -> usize {
+        (ptr >> 3).wrapping_mul(0x9E37_79B9_7F4A_7C15) % N
+    }
+
+    fn find(&self, ptr: usize) -> Option<usize> {
+        let mut slot = Self::home(ptr);
+        for _ in 0..N {
+            match self.entries[slot] {
+                Some(entry) if entry.ptr == ptr => return Some(slot),
+                Some(_) => slot = (slot + 1) % N,
+                None => return None,
+            }
+        }
+        None
+    }
+
+    /// Record a new allocation. Returns `false` if the table is full, in which
+    /// case the allocation is counted as dropped.
+    pub fn insert(&mut self, ptr: usize, layout: Layout, caller: usize, tag: Tag) -> bool {
+        // Keep one slot empty so that probes always terminate.
+        if self.len + 1 >= N {
+            self.dropped += 1;
+            return false;
+        }
+
+        let mut slot = Self::home(ptr);
+        while self.entries[slot].is_some() {
+            slot = (slot + 1) % N;
+        }
+
+        self.seq += 1;
+        self.entries[slot] = Some(Allocation {
+            ptr,
+            layout,
+            caller,
+            tag,
+            seq: self.seq,
+        });
+        self.len += 1;
+
+        let usage = &mut self.usage[tag as usize];
+        usage.count += 1;
+        usage.bytes += layout.size();
+
+        true
+    }
+
+    /// Remove the record of an allocation that was freed.
+    pub fn remove(&mut self, ptr: usize) -> Option<Allocation> {
+        let mut hole = self.find(ptr)?;
+        let removed = self.entries[hole].take()?;
+        self.len -= 1;
+
+        let usage = &mut self.usage[removed.tag as usize];
+        usage.count -= 1;
+        usage.bytes -= removed.layout.size();
+
+        // Shift later entries of the probe sequence back into the hole so that
+        // lookups never stop early.
+        let mut slot = hole;
+        loop {
+            slot = (slot + 1) % N;
+            let entry = match self.entries[slot] {
+                Some(entry) => entry,
+                None => break,
+            };
+
+            let home = Self::home(entry.ptr);
+            let stays = if hole <= slot {
+                hole < home && home <= slot
+            } else {
+                hole < home || home <= slot
+            };
+
+            if !stays {
+                self.entries[hole] = self.entries[slot].take();
+                hole = slot;
+            }
+        }
+
+        Some(removed)
+    }
+
+    pub fn get(&self, ptr: usize) -> Option<&Allocation> {
+        self.find(ptr).and_then(|slot| self.entries[slot].as_ref())
+    }
+
+    /// Number of live allocations recorded.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Allocation> {
+        self.entries.iter().flatten()
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            seq: self.seq,
+            usage: self.usage,
+            dropped: self.dropped,
+        }
+    }
+
+    /// Iterate over the live allocations made after a snapshot was taken.
+    pub fn since<'a>(&'a self, snapshot: &Snapshot) -> impl Iterator<Item = &'a Allocation> {
+        let seq = snapshot.seq;
+        self.iter().filter(move |alloc| alloc.seq > seq)
+    }
+}
+
+impl<const N: usize> core::fmt::Debug for AllocationTracker<N> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("AllocationTracker")
+            .field("len", &self.len)
+            .field("capacity", &N)
+            .field("dropped", &self.dropped)
+            .finish()
+    }
+}
+
+impl<const N: usize> Default for AllocationTracker<N> {
+    fn default() -> AllocationTracker<N> {
+        AllocationTracker::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn layout(size: usize) -> Layout {
+        Layout::from_size_align(size, 8).unwrap()
+    }
+
+    #[test]
+    fn insert_remove() {
+        let mut tracker: AllocationTracker<16> = AllocationTracker::new();
+
+        // Addresses that collide in the table.
+        let ptrs: Vec<usize> = (0..10).map(|i| 0x1000 + i * 16 * 8).collect();
+        for &ptr in &ptrs {
+            assert!(tracker.insert(ptr, layout(32), 0x8000_0000, Tag::Loader));
+        }
+        assert_eq!(10, tracker.len());
+
+        for &ptr in ptrs.iter().step_by(2) {
+            assert_eq!(ptr, tracker.remove(ptr).unwrap().ptr);
+        }
+        for &ptr in ptrs.iter().skip(1).step_by(2) {
+            assert_eq!(ptr, tracker.get(ptr).unwrap().ptr);
+        }
+        for &ptr in ptrs.iter().step_by(2) {
+            assert!(tracker.get(ptr).is_none());
+        }
+
+        assert_eq!(
+            TagUsage {
+                count: 5,
+                bytes: 5 * 32
+            },
+            tracker.snapshot().usage(Tag::Loader)
+        );
+    }
+
+    #[test]
+    fn full() {
+        let mut tracker: AllocationTracker<4> = AllocationTracker::new();
+
+        for ptr in 0..3 {
+            assert!(tracker.insert(ptr * 8, layout(8), 0, Tag::Untagged));
+        }
+        assert!(!tracker.insert(24, layout(8), 0, Tag::Untagged));
+        assert_eq!(1, tracker.snapshot().dropped);
+
+        // Untracked frees are ignored.
+        assert!(tracker.remove(24).is_none());
+        assert_eq!(3, tracker.len());
+    }
+
+    #[test]
+    fn diff() {
+        let mut tracker: AllocationTracker<64> = AllocationTracker::new();
+
+        tracker.insert(0x100, layout(64), 0, Tag::Scheduler);
+        let before = tracker.snapshot();
+
+        tracker.insert(0x200, layout(16), 0xabc, Tag::Process);
+        tracker.insert(0x300, layout(128), 0, Tag::Scheduler);
+        tracker.remove(0x100);
+
+        let diff = tracker.snapshot().diff(&before);
+        assert!(!diff.is_empty());
+        assert_eq!(
+            TagDelta {
+                count: 1,
+                bytes: 16
+            },
+            diff.delta(Tag::Process)
+        );
+        assert_eq!(
+            TagDelta {
+                count: 0,
+                bytes: 64
+            },
+            diff.delta(Tag::Scheduler)
+        );
+
+        let new: Vec<usize> = tracker.since(&before).map(|alloc| alloc.ptr).collect();
+        assert_eq!(2, new.len());
+        assert!(new.contains(&0x200) && new.contains(&0x300));
+
+        tracker.remove(0x200);
+        tracker.remove(0x300);
+        tracker.insert(0x100, layout(64), 0, Tag::Scheduler);
+        assert!(tracker.snapshot().diff(&before).is_empty());
+    }
+}
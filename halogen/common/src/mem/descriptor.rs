@@ -0,0 +1,507 @@
+//! This module provides a table of descriptors, one per physical frame, that
+//! records what each frame is being used for after it leaves the frame
+//! allocator. The table is indexed by page frame number (PFN) relative to the
+//! base of physical memory, so lookups are a single subtraction and shift.
+//!
+//! The table is backed by a caller-provided slice so that it can live in a
+//! static before any heap exists.
+
+use crate::mem::{Address, PhysicalAddress, Segment};
+
+/// What a physical frame is being used for.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameKind {
+    /// Not issued by the frame allocator.
+    Free,
+    /// Firmware or the kernel image; never returned to the allocator.
+    Reserved,
+    /// General kernel use.
+    Kernel,
+    /// A page table at any level.
+    PageTable,
+    /// Backs part of the kernel heap.
+    Heap,
+    /// Backs part of a kernel or user stack.
+    Stack,
+    /// Mapped into a user address-space.
+    User,
+}
+
+/// Metadata for a single physical frame. Kept to 8 bytes so that the table for
+/// 256 MiB of 4 KiB frames fits in 512 KiB.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct FrameDescriptor {
+    refcount: u16,
+    owner: u16,
+    kind: FrameKind,
+    order: u8,
+    flags: u8,
+}
+
+impl FrameDescriptor {
+    /// The frame must never be reclaimed (e.g. swapped out or migrated).
+    pub const PINNED: u8 = 0b0000_0001;
+    /// The contents of the frame are known to be zero.
+    pub const ZEROED: u8 = 0b0000_0010;
+
+    /// Create a descriptor for a free frame.
+    pub const fn new() -> FrameDescriptor {
+        FrameDescriptor {
+            refcount: 0,
+            owner: 0,
+            kind: FrameKind::Free,
+            order: 0,
+            flags: 0,
+        }
+    }
+
+    /// The number of references (i.e. mappings or owners) to the frame.
+    #[inline]
+    pub fn refcount(&self) -> usize {
+        self.refcount as usize
+    }
+
+    /// Tag of the owner of the frame, the ASID of the address-space it was
+    /// issued for.
+    #[inline]
+    pub fn owner(&self) -> u16 {
+        self.owner
+    }
+
+    /// What the frame is being used for.
+    #[inline]
+    pub fn kind(&self) -> FrameKind {
+        self.kind
+    }
+
+    /// The base-2 logarithm of the number of frames in the block this frame
+    /// heads.
+    #[inline]
+    pub fn order(&self) -> usize {
+        self.order as usize
+    }
+
+    /// Get the flag bits of the descriptor.
+    #[inline]
+    pub fn flags(&self) -> u8 {
+        self.flags
+    }
+
+    /// Returns true if all the bits in `flags` are set.
+    #[inline]
+    pub fn has_flags(&self, flags: u8) -> bool {
+        self.flags & flags == flags
+    }
+
+    /// Set the bits in `flags`.
+    #[inline]
+    pub fn set_flags(&mut self, flags: u8) {
+        self.flags |= flags;
+    }
+
+    /// Clear the bits in `flags`.
+    #[inline]
+    pub fn clear_flags(&mut self, flags: u8) {
+        self.flags &= !flags;
+    }
+
+    /// Returns true if the frame has not been issued.
+    #[inline]
+    pub fn is_free(&self) -> bool {
+        matches!(self.kind, FrameKind::Free)
+    }
+}
+
+impl Default for FrameDescriptor {
+    fn default() -> FrameDescriptor {
+        FrameDescriptor::new()
+    }
+}
+
+/// Counts of frames by kind.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FrameStats {
+    pub total: usize,
+    pub free: usize,
+    pub reserved: usize,
+    pub kernel: usize,
+    pub page_table: usize,
+    pub heap: usize,
+    pub stack: usize,
+    pub user: usize,
+    pub shared: usize,
+}
+
+impl core::fmt::Display for FrameStats {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        writeln!(f, "{:12} {:>8}", "Total:", self.total)?;
+        writeln!(f, "{:12} {:>8}", "Free:", self.free)?;
+        writeln!(f, "{:12} {:>8}", "Reserved:", self.reserved)?;
+        writeln!(f, "{:12} {:>8}", "Kernel:", self.kernel)?;
+        writeln!(f, "{:12} {:>8}", "PageTables:", self.page_table)?;
+        writeln!(f, "{:12} {:>8}", "Heap:", self.heap)?;
+        writeln!(f, "{:12} {:>8}", "Stack:", self.stack)?;
+        writeln!(f, "{:12} {:>8}", "User:", self.user)?;
+        write!(f, "{:12} {:>8}", "Shared:", self.shared)
+    }
+}
+
+/// Descriptor array indexed by PFN over a contiguous range of physical memory
+/// made up of frames of size `B`.
+pub struct FrameTable<'a, const B: usize> {
+    base: PhysicalAddress,
+    descriptors: Option<&'a mut [FrameDescriptor]>,
+}
+
+unsafe impl<'a, const B: usize> Sync for FrameTable<'a, B> {}
+unsafe impl<'a, const B: usize> Send for FrameTable<'a, B> {}
+
+impl<'a, const B: usize> FrameTable<'a, B> {
+    pub const fn new_uninit() -> FrameTable<'a, B> {
+        FrameTable {
+            base: PhysicalAddress(0),
+            descriptors: None,
+        }
+    }
+
+    /// Create a table that describes `descriptors.len()` frames starting at
+    /// `base`. All frames start out free.
+    pub fn new(base: PhysicalAddress, descriptors: &'a mut [FrameDescriptor]) -> FrameTable<'a, B> {
+        let mut table = FrameTable::new_uninit();
+        table.init(base, descriptors);
+        table
+    }
+
+    /// Describe `descriptors.len()` frames starting at `base`. All frames start
+    /// out free.
+    pub fn init(&mut self, base: PhysicalAddress, descriptors: &'a mut [FrameDescriptor]) {
+        debug_assert!(base.is_aligned_to(B));
+
+        descriptors
+            .iter_mut()
+            .for_each(|desc| *desc = FrameDescriptor::new());
+
+        self.base = base;
+        self.descriptors = Some(descriptors);
+    }
+
+    /// Move the backing slice of descriptors by `offset` bytes, e.g. when the
+    /// table is accessed through a new mapping.
+    ///
+    /// # Safety
+    ///
+    /// - The descriptors must be accessible at the new location.
+    pub unsafe fn rebase(&mut self, offset: isize) {
+        if let Some(descriptors) = self.descriptors.take() {
+            let len = descriptors.len();
+            let ptr = (descriptors.as_mut_ptr() as usize).wrapping_add(offset as usize);
+            self.descriptors = Some(core::slice::from_raw_parts_mut(
+                ptr as *mut FrameDescriptor,
+                len,
+            ));
+        }
+    }
+
+    /// The number of frames described.
+    pub fn len(&self) -> usize {
+        match &self.descriptors {
+            Some(descriptors) => descriptors.len(),
+            None => 0,
+        }
+    }
+
+    /// Returns true if the table describes no frames.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get the frame number of the frame containing an address.
+    #[inline]
+    pub fn pfn(&self, addr: PhysicalAddress) -> Option<usize> {
+        if addr < self.base {
+            return None;
+        }
+        let pfn = (addr - self.base) / B;
+        if pfn < self.len() {
+            Some(pfn)
+        } else {
+            None
+        }
+    }
+
+    /// Get the base address of a frame from its number.
+    #[inline]
+    pub fn addr(&self, pfn: usize) -> PhysicalAddress {
+        self.base + pfn * B
+    }
+
+    /// Get the descriptor of the frame containing an address.
+    pub fn get(&self, addr: PhysicalAddress) -> Option<&FrameDescriptor> {
+        let pfn = self.pfn(addr)?;
+        self.descriptors.as_ref()?.get(pfn)
+    }
+
+    /// Get a mutable reference to the descriptor of the frame containing an
+    /// address.
+    pub fn get_mut(&mut self, addr: PhysicalAddress) -> Option<&mut FrameDescriptor> {
+        let pfn = self.pfn(addr)?;
+        self.descriptors.as_mut()?.get_mut(pfn)
+    }
+
+    /// Mark every frame in a segment as permanently in use.
+    pub fn reserve(&mut self, segment: Segment<PhysicalAddress>) {
+        for addr in segment.iter().step_by(B) {
+            if let Some(desc) = self.get_mut(PhysicalAddress(addr)) {
+                *desc = FrameDescriptor {
+                    refcount: 1,
+                    owner: 0,
+                    kind: FrameKind::Reserved,
+                    order: 0,
+                    flags: FrameDescriptor::PINNED,
+                };
+            }
+        }
+    }
+
+    /// Mark a reserved frame as free again, e.g. once boot-only memory is
+    /// released. Returns false if the frame was not reserved.
+    pub fn unreserve(&mut self, addr: PhysicalAddress) -> bool {
+        match self.get_mut(addr) {
+            Some(desc) if matches!(desc.kind, FrameKind::Reserved) => {
+                *desc = FrameDescriptor::new();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Record that a block of `2^order` frames has been issued. The first frame
+    /// holds the only reference.
+    ///
+    /// Panics if a frame in the block was already issued.
+    pub fn issue(&mut self, addr: PhysicalAddress, kind: FrameKind, owner: u16, order: usize) {
+        for n in 0..(1 << order) {
+            let desc = self
+                .get_mut(addr + n * B)
+                .unwrap_or_else(|| panic!("no descriptor for frame {:?}", addr + n * B));
+
+            assert!(desc.is_free(), "frame {:?} issued twice", addr + n * B);
+
+            *desc = FrameDescriptor {
+                refcount: if n == 0 { 1 } else { 0 },
+                owner,
+                kind,
+                order: if n == 0 { order as u8 } else { 0 },
+                flags: 0,
+            };
+        }
+    }
+
+    /// Add a reference to an issued frame and return the new count.
+    pub fn get_ref(&mut self, addr: PhysicalAddress) -> usize {
+        let desc = self
+            .get_mut(addr)
+            .unwrap_or_else(|| panic!("no descriptor for frame {:?}", addr));
+
+        assert!(!desc.is_free(), "reference to free frame {:?}", addr);
+
+        desc.refcount = desc
+            .refcount
+            .checked_add(1)
+            .unwrap_or_else(|| panic!("reference count overflow for frame {:?}", addr));
+        desc.refcount()
+    }
+
+    /// Drop a reference to an issued frame and return the remaining count. When
+    /// the last reference is dropped, the block the frame heads is marked free
+    /// and it should be returned to the allocator.
+    pub fn put_ref(&mut self, addr: PhysicalAddress) -> usize {
+        let desc = self
+            .get_mut(addr)
+            .unwrap_or_else(|| panic!("no descriptor for frame {:?}", addr));
+
+        assert!(
+            !desc.is_free() && desc.refcount > 0,
+            "double free of frame {:?}",
+            addr
+        );
+        assert!(
+            !matches!(desc.kind, FrameKind::Reserved),
+            "free of reserved frame {:?}",
+            addr
+        );
+
+        desc.refcount -= 1;
+
+        if desc.refcount == 0 {
+            let order = desc.order();
+            for n in 0..(1 << order) {
+                if let Some(desc) = self.get_mut(addr + n * B) {
+                    *desc = FrameDescriptor::new();
+                }
+            }
+            0
+        } else {
+            desc.refcount()
+        }
+    }
+
+    /// Count the frames of each kind.
+    pub fn stats(&self) -> FrameStats {
+        let mut stats = FrameStats::default();
+
+        for desc in self.descriptors.iter().flat_map(|d| d.iter()) {
+            stats.total += 1;
+            match desc.kind {
+                FrameKind::Free => stats.free += 1,
+                FrameKind::Reserved => stats.reserved += 1,
+                FrameKind::Kernel => stats.kernel += 1,
+                FrameKind::PageTable => stats.page_table += 1,
+                FrameKind::Heap => stats.heap += 1,
+                FrameKind::Stack => stats.stack += 1,
+                FrameKind::User => stats.user += 1,
+            }
+            if desc.refcount > 1 {
+                stats.shared += 1;
+            }
+        }
+
+        stats
+    }
+
+    /// Iterate over the base addresses and descriptors of all issued frames
+    /// with an owner tag.
+    pub fn owned_by(
+        &self,
+        owner: u16,
+    ) -> impl Iterator<Item = (PhysicalAddress, &FrameDescriptor)> + '_ {
+        self.descriptors
+            .iter()
+            .flat_map(|d| d.iter())
+            .enumerate()
+            .filter(move |(_, desc)| !desc.is_free() && desc.owner == owner)
+            .map(|(pfn, desc)| (self.addr(pfn), desc))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const BASE: PhysicalAddress = PhysicalAddress(0x8000_0000);
+
+    #[test]
+    fn size() {
+        assert_eq!(8, core::mem::size_of::<FrameDescriptor>());
+    }
+
+    #[test]
+    fn lookup() {
+        let mut buf = [FrameDescriptor::new(); 16];
+        let table: FrameTable<4096> = FrameTable::new(BASE, &mut buf);
+
+        assert_eq!(Some(0), table.pfn(BASE));
+        assert_eq!(Some(0), table.pfn(BASE + 4095));
+        assert_eq!(Some(15), table.pfn(BASE + 15 * 4096));
+        assert_eq!(None, table.pfn(BASE + 16 * 4096));
+        assert_eq!(None, table.pfn(BASE - 1));
+        assert_eq!(BASE + 3 * 4096, table.addr(3));
+    }
+
+    #[test]
+    fn issue_and_free() {
+        let mut buf = [FrameDescriptor::new(); 16];
+        let mut table: FrameTable<4096> = FrameTable::new(BASE, &mut buf);
+        let frame = BASE + 2 * 4096;
+
+        table.issue(frame, FrameKind::User, 3, 0);
+
+        let desc = table.get(frame).unwrap();
+        assert_eq!(FrameKind::User, desc.kind());
+        assert_eq!(3, desc.owner());
+        assert_eq!(1, desc.refcount());
+
+        assert_eq!(2, table.get_ref(frame));
+        assert_eq!(1, table.stats().shared);
+        assert_eq!(1, table.put_ref(frame));
+        assert_eq!(0, table.put_ref(frame));
+
+        assert!(table.get(frame).unwrap().is_free());
+        assert_eq!(16, table.stats().free);
+    }
+
+    #[test]
+    fn issue_block() {
+        let mut buf = [FrameDescriptor::new(); 16];
+        let mut table: FrameTable<4096> = FrameTable::new(BASE, &mut buf);
+
+        table.issue(BASE + 4 * 4096, FrameKind::Heap, 0, 2);
+
+        assert_eq!(2, table.get(BASE + 4 * 4096).unwrap().order());
+        assert_eq!(4, table.stats().heap);
+        assert!(table.get(BASE + 8 * 4096).unwrap().is_free());
+
+        assert_eq!(0, table.put_ref(BASE + 4 * 4096));
+        assert_eq!(0, table.stats().heap);
+    }
+
+    #[test]
+    fn reserve_and_owners() {
+        let mut buf = [FrameDescriptor::new(); 16];
+        let mut table: FrameTable<4096> = FrameTable::new(BASE, &mut buf);
+
+        table.reserve(Segment::from_size(BASE, 4 * 4096));
+        table.issue(BASE + 5 * 4096, FrameKind::User, 7, 0);
+        table.issue(BASE + 9 * 4096, FrameKind::User, 7, 0);
+        table.issue(BASE + 10 * 4096, FrameKind::User, 8, 0);
+
+        let stats = table.stats();
+        assert_eq!(4, stats.reserved);
+        assert_eq!(3, stats.user);
+        assert_eq!(9, stats.free);
+
+        let owned = table.owned_by(7).map(|(addr, _)| addr).collect::<Vec<_>>();
+        assert_eq!(vec![BASE + 5 * 4096, BASE + 9 * 4096], owned);
+    }
+
+    #[test]
+    fn unreserve() {
+        let mut buf = [FrameDescriptor::new(); 16];
+        let mut table: FrameTable<4096> = FrameTable::new(BASE, &mut buf);
+
+        table.reserve(Segment::from_size(BASE, 4 * 4096));
+        table.issue(BASE + 5 * 4096, FrameKind::User, 7, 0);
+
+        assert!(table.unreserve(BASE + 4096));
+        assert!(!table.unreserve(BASE + 4096));
+        assert!(!table.unreserve(BASE + 5 * 4096));
+        assert_eq!(3, table.stats().reserved);
+
+        // A released frame can be issued like any other.
+        table.issue(BASE + 4096, FrameKind::Kernel, 0, 0);
+        assert_eq!(0, table.put_ref(BASE + 4096));
+    }
+
+    #[test]
+    #[should_panic]
+    fn double_free() {
+        let mut buf = [FrameDescriptor::new(); 16];
+        let mut table: FrameTable<4096> = FrameTable::new(BASE, &mut buf);
+
+        table.issue(BASE, FrameKind::Kernel, 0, 0);
+        table.put_ref(BASE);
+        table.put_ref(BASE);
+    }
+
+    #[test]
+    #[should_panic]
+    fn free_reserved() {
+        let mut buf = [FrameDescriptor::new(); 16];
+        let mut table: FrameTable<4096> = FrameTable::new(BASE, &mut buf);
+
+        table.reserve(Segment::from_size(BASE, 4096));
+        table.put_ref(BASE);
+    }
+}
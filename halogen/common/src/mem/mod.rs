@@ -2,9 +2,23 @@
 mod addr;
 pub use addr::*;
 
+/// Per-process memory usage and limits.
+mod account;
+pub use account::*;
+
+/// Per-frame metadata.
+mod descriptor;
+pub use descriptor::*;
+
 /// Data structures to perform memory-allocation.
 pub mod alloc;
 
+/// Slots of a swap area.
+#[cfg(feature = "alloc")]
+mod swap;
+#[cfg(feature = "alloc")]
+pub use swap::*;
+
 pub const WORD: usize = 4;
 pub const DWORD: usize = 8;
 pub const KIB: usize = 1024;
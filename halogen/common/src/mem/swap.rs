@@ -0,0 +1,156 @@
+//! Slots of a swap area. Each slot holds one page that has been swapped out.
+//! The `SwapMap` records which slots are in use with one bit per slot and
+//! hands out free slots next-fit, so consecutive swap-outs tend to land in
+//! consecutive slots.
+
+#[cfg(not(test))]
+use alloc::{vec, vec::Vec};
+
+const BITS: usize = u64::BITS as usize;
+
+#[derive(Clone, Debug)]
+pub struct SwapMap {
+    /// One bit per slot, set if the slot is in use.
+    words: Vec<u64>,
+    slots: usize,
+    used: usize,
+    /// Where to start looking for the next free slot.
+    next: usize,
+}
+
+impl SwapMap {
+    /// Create a map of `slots` free slots.
+    pub fn new(slots: usize) -> SwapMap {
+        SwapMap {
+            words: vec![0; slots.div_ceil(BITS)],
+            slots,
+            used: 0,
+            next: 0,
+        }
+    }
+
+    /// Take a free slot.
+    pub fn alloc(&mut self) -> Option<usize> {
+        if self.is_full() {
+            return None;
+        }
+
+        let start = self.next / BITS;
+        let count = self.words.len();
+
+        for n in 0..=count {
+            let i = (start + n) % count;
+            // Bits past the last slot are never free.
+            let mut free = !self.words[i];
+            if i == count - 1 && !self.slots.is_multiple_of(BITS) {
+                free &= (1 << (self.slots % BITS)) - 1;
+            }
+            // Don't go back past the hint in the first word.
+            if n == 0 {
+                free &= u64::MAX << (self.next % BITS);
+            }
+
+            if free != 0 {
+                let bit = free.trailing_zeros() as usize;
+                self.words[i] |= 1 << bit;
+                self.used += 1;
+
+                let slot = i * BITS + bit;
+                self.next = (slot + 1) % self.slots;
+                return Some(slot);
+            }
+        }
+
+        None
+    }
+
+    /// Return a slot to the map. Returns false if the slot was not in use.
+    pub fn free(&mut self, slot: usize) -> bool {
+        if !self.is_used(slot) {
+            return false;
+        }
+
+        self.words[slot / BITS] &= !(1 << (slot % BITS));
+        self.used -= 1;
+        true
+    }
+
+    /// Returns true if the slot is in use.
+    pub fn is_used(&self, slot: usize) -> bool {
+        slot < self.slots && self.words[slot / BITS] & (1 << (slot % BITS)) != 0
+    }
+
+    /// Total number of slots.
+    pub fn len(&self) -> usize {
+        self.slots
+    }
+
+    /// Returns true if the map has no slots at all.
+    pub fn is_empty(&self) -> bool {
+        self.slots == 0
+    }
+
+    /// Number of slots in use.
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    /// Returns true if every slot is in use.
+    pub fn is_full(&self) -> bool {
+        self.used == self.slots
+    }
+}
+
+impl core::fmt::Display for SwapMap {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}/{} slots used", self.used, self.slots)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn next_fit() {
+        let mut map = SwapMap::new(100);
+
+        assert_eq!(Some(0), map.alloc());
+        assert_eq!(Some(1), map.alloc());
+        assert_eq!(Some(2), map.alloc());
+        assert!(map.free(1));
+
+        // Freed slots are not reused until the map wraps around.
+        assert_eq!(Some(3), map.alloc());
+        assert_eq!(3, map.used());
+    }
+
+    #[test]
+    fn full() {
+        let mut map = SwapMap::new(70);
+
+        for slot in 0..70 {
+            assert_eq!(Some(slot), map.alloc());
+        }
+        assert!(map.is_full());
+        assert_eq!(None, map.alloc());
+
+        assert!(map.free(65));
+        assert!(map.free(3));
+        assert_eq!(Some(3), map.alloc());
+        assert_eq!(Some(65), map.alloc());
+        assert_eq!(None, map.alloc());
+    }
+
+    #[test]
+    fn invalid_free() {
+        let mut map = SwapMap::new(8);
+
+        assert!(!map.free(0));
+        assert!(!map.free(8));
+        let slot = map.alloc().unwrap();
+        assert!(map.free(slot));
+        assert!(!map.free(slot));
+        assert_eq!(0, map.used());
+    }
+}
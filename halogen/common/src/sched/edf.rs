@@ -0,0 +1,507 @@
+#[cfg(not(test))]
+use alloc::collections::BTreeMap;
+#[cfg(test)]
+use std::collections::BTreeMap;
+
+/// Utilisation of a whole CPU, in parts per million.
+pub const FULL_UTILIZATION_PPM: usize = 1_000_000;
+
+/// Timing of a periodic real-time task, in microseconds. Each period, the task
+/// is released and must get `runtime_us` of CPU time within `deadline_us`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RealTimeParams {
+    pub runtime_us: usize,
+    pub period_us: usize,
+    pub deadline_us: usize,
+}
+
+impl RealTimeParams {
+    /// Returns true if `0 < runtime <= deadline <= period`.
+    pub fn is_valid(&self) -> bool {
+        0 < self.runtime_us
+            && self.runtime_us <= self.deadline_us
+            && self.deadline_us <= self.period_us
+    }
+
+    /// Get the share of a CPU the task needs, in parts per million, rounded up.
+    /// This is the density `runtime / deadline`: the utilisation for tasks
+    /// whose deadline is their period, and more for tighter deadlines.
+    pub fn density_ppm(&self) -> usize {
+        (self.runtime_us * FULL_UTILIZATION_PPM).div_ceil(self.deadline_us)
+    }
+}
+
+/// The ways admitting a real-time task can fail.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AdmissionError {
+    /// The parameters are not `0 < runtime <= deadline <= period`.
+    Invalid,
+    /// The task would raise the total density over 100%, so deadlines could be
+    /// missed.
+    Overloaded,
+    /// The task is already admitted.
+    Exists,
+}
+
+/// A task known to the EDF scheduler.
+#[derive(Clone, Copy, Debug)]
+struct RtTask {
+    params: RealTimeParams,
+    /// Release time of the current instance.
+    release: usize,
+    /// Absolute deadline of the current instance.
+    deadline: usize,
+    /// Runtime left in the current instance. The task is throttled at 0 until
+    /// its next release.
+    remaining: usize,
+    /// The current instance missed its deadline.
+    late: bool,
+    /// The task waits for an event, so it can't run.
+    blocked: bool,
+    /// Instances that missed their deadline.
+    missed: usize,
+}
+
+impl RtTask {
+    fn next_release(&self) -> usize {
+        self.release + self.params.period_us
+    }
+}
+
+/// Earliest-deadline-first scheduling class for periodic real-time tasks. The
+/// ready task with the earliest absolute deadline runs. Each task is admitted
+/// with its `RealTimeParams` only if the total density stays at most 100%, in
+/// which case EDF meets every deadline.
+///
+/// A task gets `runtime_us` per period and is throttled once it used it, so an
+/// overrunning task can't make others miss their deadlines. A task that is done
+/// early in a period calls `finish` to wait for the next.
+///
+/// Unlike a `TaskScheduler`, decisions depend on the time, which the caller
+/// passes in microseconds.
+#[derive(Clone, Default)]
+pub struct EdfScheduler {
+    tasks: BTreeMap<usize, RtTask>,
+    current: Option<usize>,
+    density_ppm: usize,
+}
+
+impl EdfScheduler {
+    /// Admit a task, released at `now`.
+    pub fn admit(
+        &mut self,
+        id: usize,
+        params: RealTimeParams,
+        now: usize,
+    ) -> Result<(), AdmissionError> {
+        if !params.is_valid() {
+            return Err(AdmissionError::Invalid);
+        }
+        if self.tasks.contains_key(&id) {
+            return Err(AdmissionError::Exists);
+        }
+
+        let density = self.density_ppm + params.density_ppm();
+        if density > FULL_UTILIZATION_PPM {
+            return Err(AdmissionError::Overloaded);
+        }
+
+        self.density_ppm = density;
+        self.tasks.insert(
+            id,
+            RtTask {
+                params,
+                release: now,
+                deadline: now + params.deadline_us,
+                remaining: params.runtime_us,
+                late: false,
+                blocked: false,
+                missed: 0,
+            },
+        );
+        Ok(())
+    }
+
+    /// Remove a task. Returns false if it was not admitted.
+    pub fn remove(&mut self, id: usize) -> bool {
+        if self.current == Some(id) {
+            self.current = None;
+        }
+
+        match self.tasks.remove(&id) {
+            Some(task) => {
+                self.density_ppm -= task.params.density_ppm();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns true if the task is admitted.
+    pub fn contains(&self, id: usize) -> bool {
+        self.tasks.contains_key(&id)
+    }
+
+    /// Get the total density of the admitted tasks, in parts per million.
+    pub fn density_ppm(&self) -> usize {
+        self.density_ppm
+    }
+
+    /// Get the running task.
+    pub fn current(&self) -> Option<usize> {
+        self.current
+    }
+
+    /// Mark a task as running without a scheduling decision, e.g. a running
+    /// thread that just joined the class.
+    pub fn start(&mut self, id: usize) {
+        if self.tasks.contains_key(&id) {
+            self.current = Some(id);
+        }
+    }
+
+    /// Get the runtime left to a task in its current instance.
+    pub fn remaining(&self, id: usize) -> Option<usize> {
+        self.tasks.get(&id).map(|task| task.remaining)
+    }
+
+    /// Get the number of instances of a task that missed their deadline.
+    pub fn missed(&self, id: usize) -> Option<usize> {
+        self.tasks.get(&id).map(|task| task.missed)
+    }
+
+    /// Take a task out of scheduling until it is woken, e.g. while it waits
+    /// for an event. Its deadlines don't count as missed meanwhile.
+    pub fn block(&mut self, id: usize) {
+        if let Some(task) = self.tasks.get_mut(&id) {
+            task.blocked = true;
+            if self.current == Some(id) {
+                self.current = None;
+            }
+        }
+    }
+
+    /// Return a blocked task to scheduling at `now`. The periods that passed
+    /// while it was blocked are skipped: the instance released in the current
+    /// period runs if its deadline is still ahead, otherwise the task waits for
+    /// the next release. None of the skipped deadlines count as missed.
+    pub fn wake(&mut self, id: usize, now: usize) {
+        let task = match self.tasks.get_mut(&id) {
+            Some(task) if task.blocked => task,
+            _ => return,
+        };
+        task.blocked = false;
+
+        if task.next_release() <= now {
+            let periods = (now - task.release) / task.params.period_us;
+            task.release += periods * task.params.period_us;
+            task.deadline = task.release + task.params.deadline_us;
+            task.remaining = task.params.runtime_us;
+            task.late = false;
+        }
+        if task.deadline <= now && !task.late {
+            task.remaining = 0;
+        }
+    }
+
+    /// Release every task whose next period started by `now`, and count the
+    /// instances that are still unfinished at their deadline as missed.
+    fn release(&mut self, now: usize) {
+        for task in self.tasks.values_mut() {
+            if task.remaining > 0 && task.deadline <= now && !task.late && !task.blocked {
+                task.missed += 1;
+                task.late = true;
+            }
+
+            if task.next_release() > now {
+                continue;
+            }
+
+            // Skip to the last period that started by `now`. The instances
+            // released before it never ran, and since deadlines are within the
+            // period, each of them missed its deadline.
+            let periods = (now - task.release) / task.params.period_us;
+            task.release += periods * task.params.period_us;
+            task.deadline = task.release + task.params.deadline_us;
+            task.remaining = task.params.runtime_us;
+            task.late = task.deadline <= now && !task.blocked;
+            if !task.blocked {
+                task.missed += periods - 1 + task.late as usize;
+            }
+        }
+    }
+
+    /// Get the ready task with the earliest deadline.
+    fn earliest(&self) -> Option<(usize, usize)> {
+        self.tasks
+            .iter()
+            .filter(|(_, task)| task.remaining > 0 && !task.blocked)
+            .map(|(&id, task)| (task.deadline, id))
+            .min()
+    }
+
+    /// Choose the task to run at `now`, or `None` if every task is throttled.
+    pub fn next(&mut self, now: usize) -> Option<usize> {
+        self.release(now);
+        self.current = self.earliest().map(|(_, id)| id);
+        self.current
+    }
+
+    /// Returns true if at `now` a ready task should preempt the running one:
+    /// if it has an earlier deadline, or if no task of the class is running.
+    pub fn preempt(&mut self, now: usize) -> bool {
+        self.release(now);
+
+        let current = self
+            .current
+            .and_then(|id| self.tasks.get(&id))
+            .filter(|task| task.remaining > 0)
+            .map(|task| task.deadline);
+
+        match (current, self.earliest()) {
+            (Some(current), Some((deadline, _))) => deadline < current,
+            (None, Some(_)) => true,
+            (_, None) => false,
+        }
+    }
+
+    /// Report that a task ran for `runtime_us`.
+    pub fn account(&mut self, id: usize, runtime_us: usize) {
+        if let Some(task) = self.tasks.get_mut(&id) {
+            task.remaining = task.remaining.saturating_sub(runtime_us);
+        }
+    }
+
+    /// Finish the current instance of a task early; it waits for its next
+    /// release.
+    pub fn finish(&mut self, id: usize) {
+        if let Some(task) = self.tasks.get_mut(&id) {
+            task.remaining = 0;
+        }
+    }
+
+    /// Get the time of the next release of a throttled task, which may preempt
+    /// whatever runs then.
+    pub fn next_release(&self) -> Option<usize> {
+        self.tasks
+            .values()
+            .filter(|task| task.remaining == 0 && !task.blocked)
+            .map(RtTask::next_release)
+            .min()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn params(runtime_us: usize, period_us: usize, deadline_us: usize) -> RealTimeParams {
+        RealTimeParams {
+            runtime_us,
+            period_us,
+            deadline_us,
+        }
+    }
+
+    /// Simulate `duration` microseconds one at a time, where each task uses all
+    /// of its runtime, and return the total number of missed deadlines.
+    fn simulate(tasks: &[RealTimeParams], duration: usize) -> usize {
+        let mut sched = EdfScheduler::default();
+        for (id, &p) in tasks.iter().enumerate() {
+            sched.admit(id, p, 0).unwrap();
+        }
+
+        for now in 0..duration {
+            if let Some(id) = sched.next(now) {
+                sched.account(id, 1);
+            }
+        }
+
+        // Count instances that ended unfinished at the end too.
+        sched.release(duration);
+        (0..tasks.len()).map(|id| sched.missed(id).unwrap()).sum()
+    }
+
+    #[test]
+    fn admission() {
+        let mut sched = EdfScheduler::default();
+        assert_eq!(
+            Err(AdmissionError::Invalid),
+            sched.admit(1, params(0, 10, 10), 0)
+        );
+        assert_eq!(
+            Err(AdmissionError::Invalid),
+            sched.admit(1, params(5, 10, 4), 0)
+        );
+        assert_eq!(
+            Err(AdmissionError::Invalid),
+            sched.admit(1, params(5, 10, 11), 0)
+        );
+
+        sched.admit(1, params(5, 10, 10), 0).unwrap();
+        sched.admit(2, params(1, 4, 4), 0).unwrap();
+        assert_eq!(750_000, sched.density_ppm());
+        assert_eq!(
+            Err(AdmissionError::Exists),
+            sched.admit(2, params(1, 4, 4), 0)
+        );
+
+        // A tighter deadline needs more of the CPU.
+        assert_eq!(
+            Err(AdmissionError::Overloaded),
+            sched.admit(3, params(1, 4, 2), 0)
+        );
+        sched.admit(3, params(1, 4, 4), 0).unwrap();
+        assert_eq!(FULL_UTILIZATION_PPM, sched.density_ppm());
+        assert_eq!(
+            Err(AdmissionError::Overloaded),
+            sched.admit(4, params(1, 1000, 1000), 0)
+        );
+
+        assert!(sched.remove(3));
+        assert!(!sched.remove(3));
+        sched.admit(4, params(1, 1000, 1000), 0).unwrap();
+    }
+
+    #[test]
+    fn earliest_deadline_first() {
+        let mut sched = EdfScheduler::default();
+        sched.admit(1, params(2, 10, 10), 0).unwrap();
+        sched.admit(2, params(2, 10, 5), 0).unwrap();
+
+        assert_eq!(Some(2), sched.next(0));
+        sched.account(2, 2);
+        assert_eq!(Some(1), sched.next(2));
+        sched.account(1, 2);
+
+        // Both are throttled until their next release.
+        assert_eq!(None, sched.next(4));
+        assert_eq!(Some(10), sched.next_release());
+        assert_eq!(Some(2), sched.next(10));
+    }
+
+    #[test]
+    fn preempt() {
+        let mut sched = EdfScheduler::default();
+        sched.admit(1, params(5, 20, 20), 0).unwrap();
+        assert!(sched.preempt(0));
+        assert_eq!(Some(1), sched.next(0));
+        assert!(!sched.preempt(1));
+
+        sched.admit(2, params(1, 10, 3), 1).unwrap();
+        assert!(sched.preempt(1));
+        assert_eq!(Some(2), sched.next(1));
+
+        // A throttled task doesn't preempt.
+        sched.finish(2);
+        sched.finish(1);
+        assert!(!sched.preempt(2));
+        assert_eq!(Some(11), sched.next_release());
+    }
+
+    #[test]
+    fn throttle_overrun() {
+        let mut sched = EdfScheduler::default();
+        sched.admit(1, params(2, 10, 10), 0).unwrap();
+        assert_eq!(Some(1), sched.next(0));
+        sched.account(1, 5);
+
+        assert_eq!(Some(0), sched.remaining(1));
+        assert_eq!(None, sched.next(5));
+        assert_eq!(Some(0), sched.missed(1));
+    }
+
+    #[test]
+    fn missed_deadline() {
+        let mut sched = EdfScheduler::default();
+        sched.admit(1, params(2, 10, 5), 0).unwrap();
+
+        // Never run, so each instance misses.
+        assert_eq!(Some(1), sched.next(30));
+        assert_eq!(Some(3), sched.missed(1));
+    }
+
+    #[test]
+    fn long_gap() {
+        let mut sched = EdfScheduler::default();
+        sched.admit(1, params(2, 10, 5), 0).unwrap();
+
+        // Every instance released in the gap is counted, including the
+        // current one, which is already past its deadline.
+        assert_eq!(Some(1), sched.next(1_000_000_007));
+        assert_eq!(Some(100_000_001), sched.missed(1));
+        assert_eq!(Some(2), sched.remaining(1));
+
+        // The next release is a full period later.
+        assert_eq!(Some(1), sched.next(1_000_000_010));
+        assert_eq!(Some(100_000_001), sched.missed(1));
+    }
+
+    #[test]
+    fn block() {
+        let mut sched = EdfScheduler::default();
+        sched.admit(1, params(2, 10, 5), 0).unwrap();
+        sched.admit(2, params(2, 10, 10), 0).unwrap();
+
+        assert_eq!(Some(1), sched.next(0));
+        sched.block(1);
+        assert_eq!(None, sched.current());
+        assert_eq!(Some(2), sched.next(0));
+
+        // Blocked through its deadline, which doesn't count as missed.
+        assert!(!sched.preempt(20));
+        sched.wake(1, 20);
+        assert!(sched.preempt(20));
+        assert_eq!(Some(1), sched.next(20));
+        assert_eq!(Some(0), sched.missed(1));
+    }
+
+    #[test]
+    fn sleep_through_periods() {
+        let mut sched = EdfScheduler::default();
+        sched.admit(1, params(10, 100, 100), 0).unwrap();
+        assert_eq!(Some(1), sched.next(0));
+        sched.account(1, 5);
+        sched.block(1);
+
+        // The periods slept through are skipped, and the current one runs.
+        sched.wake(1, 1050);
+        assert_eq!(Some(1), sched.next(1050));
+        assert_eq!(Some(0), sched.missed(1));
+        assert_eq!(Some(10), sched.remaining(1));
+    }
+
+    #[test]
+    fn wake_after_deadline() {
+        let mut sched = EdfScheduler::default();
+        sched.admit(1, params(2, 10, 5), 0).unwrap();
+        sched.block(1);
+
+        // Released while blocked, and woken after that instance's deadline.
+        assert_eq!(None, sched.next(20));
+        sched.wake(1, 27);
+        assert_eq!(None, sched.next(27));
+        assert_eq!(Some(0), sched.missed(1));
+
+        // The next instance is released as usual.
+        assert_eq!(Some(1), sched.next(30));
+        assert_eq!(Some(0), sched.missed(1));
+    }
+
+    #[test]
+    fn feasible_sets_meet_deadlines() {
+        let sets: &[&[RealTimeParams]] = &[
+            &[params(1, 4, 4), params(2, 6, 6), params(3, 8, 8)],
+            &[params(1, 5, 5), params(1, 4, 4), params(11, 20, 20)],
+            &[params(2, 5, 5), params(4, 7, 7)],
+            &[params(1, 10, 4), params(3, 10, 6), params(5, 20, 20)],
+            &[params(10, 10, 10)],
+        ];
+
+        for tasks in sets {
+            let density: usize = tasks.iter().map(RealTimeParams::density_ppm).sum();
+            assert!(density <= FULL_UTILIZATION_PPM);
+            assert_eq!(0, simulate(tasks, 10_000), "{:?}", tasks);
+        }
+    }
+}
@@ -0,0 +1,462 @@
+#[cfg(not(test))]
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    vec::Vec,
+};
+#[cfg(test)]
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::TaskScheduler;
+
+/// Weight of a task with nice value 0.
+pub const NICE_0_WEIGHT: usize = 1024;
+
+/// Weights for nice values -20 to 19. Each step is about 1.25 times the next,
+/// so a task gets about 10% more CPU time than one a nice value above it.
+const NICE_WEIGHTS: [usize; 40] = [
+    88761, 71755, 56483, 46273, 36291, 29154, 23254, 18705, 14949, 11916, 9548, 7620, 6100, 4904,
+    3906, 3121, 2501, 1991, 1586, 1277, 1024, 820, 655, 526, 423, 335, 272, 215, 172, 137, 110, 87,
+    70, 56, 45, 36, 29, 23, 18, 15,
+];
+
+/// Virtual runtime the running task may lead the next by before it is
+/// preempted, in microseconds.
+pub const PREEMPT_GRANULARITY_US: usize = 1000;
+
+/// Virtual runtime a task that slept may trail the minimum by when it wakes,
+/// in microseconds.
+pub const SLEEPER_CREDIT_US: usize = 3000;
+
+/// Get the weight for a priority, where a priority of `p` has nice value `-p`.
+pub fn priority_weight(priority: isize) -> usize {
+    let nice = priority.saturating_neg().clamp(-20, 19);
+    NICE_WEIGHTS[(nice + 20) as usize]
+}
+
+/// A task known to the fair scheduler.
+#[derive(Clone, Copy, Debug)]
+struct Task {
+    weight: usize,
+    /// Runtime in microseconds, scaled by `NICE_0_WEIGHT / weight`.
+    vruntime: usize,
+}
+
+/// Fair-share scheduler in the style of Linux's CFS. Each task accumulates
+/// virtual runtime, i.e. the time it ran scaled by its weight, and the task
+/// with the least always runs next. So a task that ran less than its share,
+/// e.g. because it yielded early, runs before one that used all of its time.
+///
+/// Priorities map to nice values: a priority of `p` is nice `-p`, clamped to
+/// -20 to 19. The executor reports time with `account`.
+///
+/// A blocked task keeps its virtual runtime. When it wakes, it is moved up to
+/// `SLEEPER_CREDIT_US` behind the minimum, so it runs soon, but can't claim
+/// all the time it slept.
+#[derive(Clone, Default)]
+pub struct FairScheduler {
+    tasks: BTreeMap<usize, Task>,
+    /// Ready tasks ordered by virtual runtime, then by handle.
+    ready: BTreeSet<(usize, usize)>,
+    current: Option<usize>,
+    /// Never decreases, and new tasks start at it, so they don't get all the
+    /// time that passed before they were added.
+    min_vruntime: usize,
+    /// Task that yielded, which runs only if nothing else is ready.
+    skip: Option<usize>,
+}
+
+impl FairScheduler {
+    /// Get the virtual runtime of a task.
+    pub fn vruntime(&self, job: usize) -> Option<usize> {
+        self.tasks.get(&job).map(|task| task.vruntime)
+    }
+
+    /// Get the ready task with the least virtual runtime.
+    fn leftmost(&self) -> Option<(usize, usize)> {
+        self.ready.iter().next().copied()
+    }
+
+    /// Put a task in the tree of ready tasks.
+    fn enqueue(&mut self, job: usize) {
+        if let Some(task) = self.tasks.get(&job) {
+            self.ready.insert((task.vruntime, job));
+        }
+    }
+
+    /// Remove a task from the tree of ready tasks.
+    fn dequeue(&mut self, job: usize) {
+        if let Some(task) = self.tasks.get(&job) {
+            self.ready.remove(&(task.vruntime, job));
+        }
+    }
+
+    /// Advance the minimum virtual runtime to that of the running or leftmost
+    /// task, whichever is less.
+    fn update_min_vruntime(&mut self) {
+        let current = self
+            .current
+            .and_then(|job| self.tasks.get(&job))
+            .map(|task| task.vruntime);
+        let leftmost = self.leftmost().map(|(vruntime, _)| vruntime);
+
+        let min = match (current, leftmost) {
+            (Some(a), Some(b)) => a.min(b),
+            (Some(min), None) | (None, Some(min)) => min,
+            (None, None) => return,
+        };
+        self.min_vruntime = self.min_vruntime.max(min);
+    }
+}
+
+impl TaskScheduler for FairScheduler {
+    type Handle = usize;
+
+    fn add_with_priority(&mut self, id: Self::Handle, priority: isize) {
+        self.tasks.insert(
+            id,
+            Task {
+                weight: priority_weight(priority),
+                vruntime: self.min_vruntime,
+            },
+        );
+        self.enqueue(id);
+    }
+
+    fn next(&mut self) -> Option<Self::Handle> {
+        if let Some(job) = self.current.take() {
+            self.enqueue(job);
+        }
+
+        let skip = self.skip.take();
+        let next = self
+            .ready
+            .iter()
+            .find(|&&(_, job)| Some(job) != skip)
+            .or_else(|| self.ready.iter().next())
+            .copied();
+
+        if let Some(entry) = next {
+            self.ready.remove(&entry);
+        }
+        self.current = next.map(|(_, job)| job);
+        self.update_min_vruntime();
+
+        self.current
+    }
+
+    fn complete(&mut self, job: Self::Handle) {
+        if self.current == Some(job) {
+            self.current = None;
+        } else {
+            self.dequeue(job);
+        }
+        if self.skip == Some(job) {
+            self.skip = None;
+        }
+        self.tasks.remove(&job);
+    }
+
+    fn block(&mut self, job: Self::Handle) {
+        // Like `complete`, but the task is kept for `wake`.
+        if self.current == Some(job) {
+            self.current = None;
+        } else {
+            self.dequeue(job);
+        }
+        if self.skip == Some(job) {
+            self.skip = None;
+        }
+    }
+
+    fn wake(&mut self, job: Self::Handle, priority: isize) {
+        let queued = self.current == Some(job)
+            || self
+                .tasks
+                .get(&job)
+                .is_some_and(|task| self.ready.contains(&(task.vruntime, job)));
+        if queued {
+            return;
+        }
+
+        match self.tasks.get_mut(&job) {
+            Some(task) => {
+                let floor = self.min_vruntime.saturating_sub(SLEEPER_CREDIT_US);
+                task.vruntime = task.vruntime.max(floor);
+                self.enqueue(job);
+            }
+            // Blocked before the scheduler was swapped in.
+            None => self.add_with_priority(job, priority),
+        }
+    }
+
+    fn current(&self) -> Option<Self::Handle> {
+        self.current
+    }
+
+    fn set_priority(&mut self, job: Self::Handle, priority: isize) {
+        if let Some(task) = self.tasks.get_mut(&job) {
+            task.weight = priority_weight(priority);
+        }
+    }
+
+    fn yld(&mut self, job: Self::Handle) {
+        // The running task stays current until the next decision.
+        self.skip = Some(job);
+    }
+
+    fn account(&mut self, job: Self::Handle, runtime_us: usize) {
+        if let Some(task) = self.tasks.get_mut(&job) {
+            // Neither the running task nor a blocked one is in the tree.
+            let queued = self.ready.remove(&(task.vruntime, job));
+            task.vruntime += runtime_us * NICE_0_WEIGHT / task.weight;
+            if queued {
+                self.ready.insert((task.vruntime, job));
+            }
+        }
+        self.update_min_vruntime();
+    }
+
+    fn preempt(&self) -> bool {
+        let current = self.current.and_then(|job| self.tasks.get(&job));
+        match (current, self.leftmost()) {
+            (Some(task), Some((vruntime, _))) => task.vruntime > vruntime + PREEMPT_GRANULARITY_US,
+            _ => false,
+        }
+    }
+
+    fn drain(&mut self) -> Vec<Self::Handle> {
+        self.tasks.clear();
+        self.skip = None;
+        core::mem::take(&mut self.ready)
+            .into_iter()
+            .map(|(_, job)| job)
+            .chain(self.current.take())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Run `count` slices, where each task runs for the time returned by
+    /// `slice`, and return the number of slices each task got.
+    fn run(
+        sched: &mut FairScheduler,
+        count: usize,
+        slice: impl Fn(usize) -> usize,
+    ) -> BTreeMap<usize, usize> {
+        let mut slices = BTreeMap::new();
+        for _ in 0..count {
+            let job = sched.next().unwrap();
+            sched.account(job, slice(job));
+            *slices.entry(job).or_insert(0) += 1;
+        }
+        slices
+    }
+
+    #[test]
+    fn weights() {
+        assert_eq!(NICE_0_WEIGHT, priority_weight(0));
+        assert_eq!(88761, priority_weight(20));
+        assert_eq!(88761, priority_weight(100));
+        assert_eq!(15, priority_weight(-19));
+        assert_eq!(15, priority_weight(-100));
+        assert_eq!(88761, priority_weight(isize::MAX));
+        assert_eq!(15, priority_weight(isize::MIN));
+        assert!(priority_weight(1) > priority_weight(0));
+    }
+
+    #[test]
+    fn least_runtime_first() {
+        let mut sched = FairScheduler::default();
+        sched.add_new(1);
+        sched.add_new(2);
+
+        assert_eq!(Some(1), sched.next());
+        sched.account(1, 500);
+        assert_eq!(Some(2), sched.next());
+        sched.account(2, 100);
+
+        // Task 2 is still behind.
+        assert_eq!(Some(2), sched.next());
+        sched.account(2, 300);
+        assert_eq!(Some(2), sched.next());
+        sched.account(2, 200);
+        assert_eq!(Some(1), sched.next());
+    }
+
+    #[test]
+    fn equal_share() {
+        let mut sched = FairScheduler::default();
+        for job in 0..4 {
+            sched.add_new(job);
+        }
+
+        let slices = run(&mut sched, 400, |_| 1000);
+        assert!(slices.values().all(|&n| n == 100));
+    }
+
+    #[test]
+    fn weighted_share() {
+        let mut sched = FairScheduler::default();
+        sched.add_with_priority(1, 0);
+        sched.add_with_priority(2, 5);
+
+        // Nice -5 has about three times the weight of nice 0.
+        let slices = run(&mut sched, 1000, |_| 1000);
+        let ratio = slices[&2] as f64 / slices[&1] as f64;
+        assert!((ratio - 3121.0 / 1024.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn early_yield_runs_more() {
+        let mut sched = FairScheduler::default();
+        sched.add_new(1);
+        sched.add_new(2);
+
+        // Task 1 uses a tenth of its slice, so it is picked more often, but
+        // both get the same time.
+        let slices = run(&mut sched, 1100, |job| if job == 1 { 100 } else { 1000 });
+        assert_eq!(1000, slices[&1]);
+        assert_eq!(100, slices[&2]);
+        assert_eq!(sched.vruntime(1), sched.vruntime(2));
+    }
+
+    #[test]
+    fn new_task_starts_at_min() {
+        let mut sched = FairScheduler::default();
+        sched.add_new(1);
+        run(&mut sched, 10, |_| 1000);
+
+        // The new task doesn't get the 10 ms that passed before it.
+        sched.add_new(2);
+        assert_eq!(sched.vruntime(1), sched.vruntime(2));
+
+        let slices = run(&mut sched, 10, |_| 1000);
+        assert_eq!(5, slices[&1]);
+        assert_eq!(5, slices[&2]);
+    }
+
+    #[test]
+    fn yld() {
+        let mut sched = FairScheduler::default();
+        sched.add_new(1);
+        sched.add_new(2);
+
+        assert_eq!(Some(1), sched.next());
+        sched.yld(1);
+        assert_eq!(Some(2), sched.next());
+
+        // Alone, a task that yields runs again.
+        sched.complete(2);
+        sched.yld(1);
+        assert_eq!(Some(1), sched.next());
+    }
+
+    #[test]
+    fn preempt() {
+        let mut sched = FairScheduler::default();
+        sched.add_new(1);
+        sched.add_new(2);
+
+        assert_eq!(Some(1), sched.next());
+        sched.account(1, PREEMPT_GRANULARITY_US);
+        assert!(!sched.preempt());
+        sched.account(1, 1);
+        assert!(sched.preempt());
+    }
+
+    #[test]
+    fn drain() {
+        let mut sched = FairScheduler::default();
+        sched.add_new(1);
+        sched.add_new(2);
+        sched.add_new(3);
+
+        assert_eq!(Some(1), sched.next());
+        sched.account(1, 100);
+        assert_eq!(Some(2), sched.next());
+        sched.account(2, 50);
+        assert_eq!(vec![3, 1, 2], sched.drain());
+        assert_eq!(None, sched.vruntime(1));
+        assert_eq!(None, sched.next());
+    }
+
+    #[test]
+    fn complete() {
+        let mut sched = FairScheduler::default();
+        sched.add_new(1);
+        sched.add_new(2);
+
+        assert_eq!(Some(1), sched.next());
+        sched.complete(2);
+        sched.complete(1);
+        assert_eq!(None, sched.current());
+        assert_eq!(None, sched.next());
+        assert_eq!(None, sched.vruntime(1));
+    }
+
+    #[test]
+    fn block_keeps_vruntime() {
+        let mut sched = FairScheduler::default();
+        sched.add_new(1);
+        sched.add_new(2);
+
+        assert_eq!(Some(1), sched.next());
+        sched.account(1, 500);
+        sched.block(1);
+        assert_eq!(Some(500), sched.vruntime(1));
+        assert_eq!(Some(2), sched.next());
+        sched.account(2, 1000);
+
+        // Task 1 slept briefly, so it picks up where it left off and runs
+        // next, but only until it catches up.
+        sched.wake(1, 0);
+        assert_eq!(Some(500), sched.vruntime(1));
+        assert_eq!(Some(1), sched.next());
+        sched.account(1, 500);
+        sched.account(1, 1);
+        assert_eq!(Some(2), sched.next());
+    }
+
+    #[test]
+    fn long_sleeper_credit() {
+        let mut sched = FairScheduler::default();
+        sched.add_new(1);
+        sched.add_new(2);
+
+        assert_eq!(Some(1), sched.next());
+        sched.block(1);
+        run(&mut sched, 100, |_| 1000);
+
+        // Task 1 slept for 100 ms, but only gets the credit back.
+        sched.wake(1, 0);
+        assert_eq!(Some(100_000 - SLEEPER_CREDIT_US), sched.vruntime(1));
+        let slices = run(&mut sched, 5, |_| 1000);
+        assert_eq!(4, slices[&1]);
+
+        // Waking a task that isn't blocked does nothing.
+        let vruntime = sched.vruntime(1);
+        sched.wake(1, 0);
+        assert_eq!(vruntime, sched.vruntime(1));
+        assert_eq!(2, sched.drain().len());
+    }
+
+    #[test]
+    fn blocked_not_accounted_into_tree() {
+        let mut sched = FairScheduler::default();
+        sched.add_new(1);
+
+        assert_eq!(Some(1), sched.next());
+        sched.block(1);
+        sched.account(1, 1000);
+        assert_eq!(None, sched.next());
+
+        // Blocked tasks aren't drained, and are added anew when they wake.
+        assert!(sched.drain().is_empty());
+        sched.wake(1, 0);
+        assert_eq!(Some(0), sched.vruntime(1));
+        assert_eq!(Some(1), sched.next());
+    }
+}
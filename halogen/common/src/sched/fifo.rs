@@ -1,36 +1,48 @@
 #[cfg(not(test))]
-use alloc::collections::VecDeque;
+use alloc::{collections::VecDeque, vec::Vec};
 #[cfg(test)]
 use std::collections::VecDeque;
 
 use super::TaskScheduler;
 
-/// First-in-first-out task scheduler. Jobs are referred to by ID.
+/// First-in-first-out task scheduler. Jobs are referred to by ID. The running
+/// job keeps running until it yields or completes, since the executor asks for
+/// the next job at the end of every time slice without giving it back.
 #[derive(Default, Clone)]
 pub struct FifoScheduler {
     queue: VecDeque<usize>,
     current: Option<usize>,
+    /// The running job yielded, so it goes to the back of the queue.
+    yielded: bool,
 }
 
 impl TaskScheduler for FifoScheduler {
     type Handle = usize;
 
     fn add_with_priority(&mut self, id: Self::Handle, _priority: isize) {
-        self.queue.push_front(id);
+        self.queue.push_back(id);
     }
 
-    fn set_priority(&self, _id: Self::Handle, _priority: isize) {
-        unimplemented!()
-    }
+    fn set_priority(&mut self, _id: Self::Handle, _priority: isize) {}
 
     fn next(&mut self) -> Option<Self::Handle> {
+        if let Some(job) = self.current {
+            if !self.yielded {
+                return Some(job);
+            }
+            self.queue.push_back(job);
+        }
+
         let next = self.queue.pop_front();
         self.current = next;
+        self.yielded = false;
         next
     }
 
     fn complete(&mut self, job: Self::Handle) {
-        self.current = None;
+        if self.current == Some(job) {
+            self.current = None;
+        }
         self.queue.retain(|&j| j != job);
     }
 
@@ -39,7 +51,81 @@ impl TaskScheduler for FifoScheduler {
     }
 
     fn yld(&mut self, job: Self::Handle) {
-        self.queue.retain(|&j| j != job);
-        self.queue.push_back(job);
+        if self.current == Some(job) {
+            self.yielded = true;
+        } else if self.queue.contains(&job) {
+            self.queue.retain(|&j| j != job);
+            self.queue.push_back(job);
+        }
+    }
+
+    fn drain(&mut self) -> Vec<Self::Handle> {
+        self.yielded = false;
+        self.queue.drain(..).chain(self.current.take()).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn first_in_first_out() {
+        let mut sched = FifoScheduler::default();
+        sched.add_new(1);
+        sched.add_new(2);
+
+        // The running job isn't preempted.
+        assert_eq!(Some(1), sched.next());
+        assert_eq!(Some(1), sched.next());
+        sched.complete(1);
+        assert_eq!(Some(2), sched.next());
+        sched.complete(2);
+        assert_eq!(None, sched.next());
+    }
+
+    #[test]
+    fn yld() {
+        let mut sched = FifoScheduler::default();
+        sched.add_new(1);
+        sched.add_new(2);
+
+        assert_eq!(Some(1), sched.next());
+        sched.yld(1);
+        assert_eq!(Some(1), sched.current());
+        assert_eq!(Some(2), sched.next());
+        sched.yld(2);
+        assert_eq!(Some(1), sched.next());
+    }
+
+    #[test]
+    fn complete_queued() {
+        let mut sched = FifoScheduler::default();
+        sched.add_new(1);
+        sched.add_new(2);
+        sched.add_new(3);
+
+        // A queued job leaves without disturbing the running one.
+        assert_eq!(Some(1), sched.next());
+        sched.complete(2);
+        assert_eq!(Some(1), sched.current());
+        sched.yld(1);
+        assert_eq!(Some(3), sched.next());
+        assert_eq!(Some(3), sched.next());
+        sched.complete(3);
+        assert_eq!(Some(1), sched.next());
+    }
+
+    #[test]
+    fn drain() {
+        let mut sched = FifoScheduler::default();
+        for job in 1..=3 {
+            sched.add_new(job);
+        }
+
+        assert_eq!(Some(1), sched.next());
+        assert_eq!(vec![2, 3, 1], sched.drain());
+        assert_eq!(None, sched.current());
+        assert_eq!(None, sched.next());
     }
 }
@@ -0,0 +1,330 @@
+#[cfg(not(test))]
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    vec::Vec,
+};
+#[cfg(test)]
+use std::collections::{BTreeMap, VecDeque};
+
+use super::TaskScheduler;
+
+/// Time slices of the levels used by default, from the top level down.
+pub const DEFAULT_MLFQ_SLICES_US: [usize; 4] = [10_000, 20_000, 40_000, 80_000];
+/// Runtime between boosts used by default.
+pub const DEFAULT_BOOST_INTERVAL_US: usize = 1_000_000;
+
+/// A task known to the MLFQ scheduler.
+#[derive(Clone, Copy, Debug, Default)]
+struct Task {
+    level: usize,
+    /// Time used of the current slice.
+    used: usize,
+    /// The task yielded during the current slice.
+    yielded: bool,
+}
+
+/// Multi-level feedback queue scheduler. Tasks move between levels based on
+/// how they use their time, so interactive tasks stay at the top while compute
+/// tasks sink:
+///
+/// - New tasks start at the top level, which has the shortest slice.
+/// - A task that uses its whole slice is demoted one level.
+/// - A task that yields before its slice ends is promoted one level.
+/// - After `boost_interval_us` of runtime, every task returns to the top, so
+///   the bottom levels don't starve.
+///
+/// Each level is a round-robin queue with its own slice, and a ready task in a
+/// higher level preempts the running one. Priorities are ignored, since the
+/// level of a task follows its behavior.
+#[derive(Clone)]
+pub struct MlfqScheduler {
+    queues: Vec<VecDeque<usize>>,
+    slices_us: Vec<usize>,
+    tasks: BTreeMap<usize, Task>,
+    current: Option<usize>,
+    boost_interval_us: usize,
+    since_boost_us: usize,
+}
+
+impl Default for MlfqScheduler {
+    fn default() -> MlfqScheduler {
+        MlfqScheduler::new(&DEFAULT_MLFQ_SLICES_US, DEFAULT_BOOST_INTERVAL_US)
+    }
+}
+
+impl MlfqScheduler {
+    /// Create a scheduler with a level for each slice, from the top down, which
+    /// boosts every task after `boost_interval_us` of runtime.
+    pub fn new(slices_us: &[usize], boost_interval_us: usize) -> MlfqScheduler {
+        assert!(!slices_us.is_empty());
+
+        MlfqScheduler {
+            queues: slices_us.iter().map(|_| VecDeque::new()).collect(),
+            slices_us: slices_us.to_vec(),
+            tasks: BTreeMap::new(),
+            current: None,
+            boost_interval_us,
+            since_boost_us: 0,
+        }
+    }
+
+    /// Get the level of a task, where 0 is the top.
+    pub fn level(&self, job: usize) -> Option<usize> {
+        self.tasks.get(&job).map(|task| task.level)
+    }
+
+    /// Get the highest level with a ready task.
+    fn highest_ready(&self) -> Option<usize> {
+        self.queues.iter().position(|queue| !queue.is_empty())
+    }
+
+    /// Move a task that stops running to the level it earned, and put it at
+    /// the back of that queue.
+    fn requeue(&mut self, job: usize) {
+        let bottom = self.queues.len() - 1;
+        if let Some(task) = self.tasks.get_mut(&job) {
+            if task.used >= self.slices_us[task.level] {
+                task.level = (task.level + 1).min(bottom);
+            } else if task.yielded {
+                task.level = task.level.saturating_sub(1);
+            }
+
+            task.used = 0;
+            task.yielded = false;
+            self.queues[task.level].push_back(job);
+        }
+    }
+
+    /// Move every task to the top level.
+    fn boost(&mut self) {
+        self.since_boost_us = 0;
+
+        for task in self.tasks.values_mut() {
+            task.level = 0;
+        }
+        for level in 1..self.queues.len() {
+            let mut queue = core::mem::take(&mut self.queues[level]);
+            self.queues[0].append(&mut queue);
+        }
+    }
+}
+
+impl TaskScheduler for MlfqScheduler {
+    type Handle = usize;
+
+    fn add_with_priority(&mut self, id: Self::Handle, _priority: isize) {
+        self.tasks.insert(id, Task::default());
+        self.queues[0].push_back(id);
+    }
+
+    fn next(&mut self) -> Option<Self::Handle> {
+        if let Some(job) = self.current.take() {
+            self.requeue(job);
+        }
+
+        if self.since_boost_us >= self.boost_interval_us {
+            self.boost();
+        }
+
+        let next = self
+            .highest_ready()
+            .and_then(|level| self.queues[level].pop_front());
+        self.current = next;
+
+        next
+    }
+
+    fn complete(&mut self, job: Self::Handle) {
+        if self.current == Some(job) {
+            self.current = None;
+        } else if let Some(task) = self.tasks.get(&job) {
+            self.queues[task.level].retain(|&j| j != job);
+        }
+        self.tasks.remove(&job);
+    }
+
+    fn current(&self) -> Option<Self::Handle> {
+        self.current
+    }
+
+    fn set_priority(&mut self, _job: Self::Handle, _priority: isize) {}
+
+    fn yld(&mut self, job: Self::Handle) {
+        // The running task stays current until the next decision.
+        if let Some(task) = self.tasks.get_mut(&job) {
+            task.yielded = true;
+        }
+    }
+
+    fn account(&mut self, job: Self::Handle, runtime_us: usize) {
+        if let Some(task) = self.tasks.get_mut(&job) {
+            task.used += runtime_us;
+        }
+        self.since_boost_us += runtime_us;
+    }
+
+    fn preempt(&self) -> bool {
+        match (
+            self.current.and_then(|job| self.tasks.get(&job)),
+            self.highest_ready(),
+        ) {
+            (Some(task), Some(level)) => level < task.level,
+            _ => false,
+        }
+    }
+
+    fn time_slice_us(&self, job: Self::Handle) -> Option<usize> {
+        self.tasks.get(&job).map(|task| self.slices_us[task.level])
+    }
+
+    fn drain(&mut self) -> Vec<Self::Handle> {
+        self.tasks.clear();
+        self.since_boost_us = 0;
+        self.queues
+            .iter_mut()
+            .flat_map(|queue| queue.drain(..))
+            .chain(self.current.take())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SLICES: [usize; 3] = [10, 20, 40];
+
+    /// Run the next task for `runtime_us`, yielding if `yld`.
+    fn run(sched: &mut MlfqScheduler, runtime_us: usize, yld: bool) -> usize {
+        let job = sched.next().unwrap();
+        sched.account(job, runtime_us);
+        if yld {
+            sched.yld(job);
+        }
+        job
+    }
+
+    #[test]
+    fn slice_per_level() {
+        let mut sched = MlfqScheduler::new(&SLICES, 1000);
+        sched.add_new(1);
+
+        for &slice in SLICES.iter() {
+            assert_eq!(Some(1), sched.next());
+            assert_eq!(Some(slice), sched.time_slice_us(1));
+            sched.account(1, slice);
+        }
+
+        // The bottom level keeps its task.
+        assert_eq!(Some(1), sched.next());
+        assert_eq!(Some(2), sched.level(1));
+        assert_eq!(None, sched.time_slice_us(2));
+    }
+
+    #[test]
+    fn demote_and_promote() {
+        let mut sched = MlfqScheduler::new(&SLICES, 1000);
+        sched.add_new(1);
+
+        run(&mut sched, 10, false);
+        run(&mut sched, 20, false);
+        assert_eq!(Some(1), sched.next());
+        assert_eq!(Some(2), sched.level(1));
+
+        // Yielding early moves it back up, one level at a time.
+        sched.account(1, 5);
+        sched.yld(1);
+        assert_eq!(Some(1), sched.next());
+        assert_eq!(Some(1), sched.level(1));
+
+        // Preempted before the slice ends without yielding: stays.
+        sched.account(1, 5);
+        assert_eq!(Some(1), sched.next());
+        assert_eq!(Some(1), sched.level(1));
+    }
+
+    #[test]
+    fn interactive_stays_on_top() {
+        let mut sched = MlfqScheduler::new(&SLICES, 1000);
+        sched.add_new(1);
+        sched.add_new(2);
+
+        // Task 1 computes, and task 2 yields right away.
+        let mut order = Vec::new();
+        for _ in 0..8 {
+            let job = sched.next().unwrap();
+            sched.account(job, if job == 1 { 40 } else { 1 });
+            if job == 2 {
+                sched.yld(job);
+            }
+            order.push(job);
+        }
+
+        assert_eq!(Some(1), sched.level(1));
+        assert_eq!(Some(0), sched.level(2));
+        assert_eq!(vec![1, 2, 2, 2, 2, 2, 2, 2], order);
+    }
+
+    #[test]
+    fn preempt() {
+        let mut sched = MlfqScheduler::new(&SLICES, 1000);
+        sched.add_new(1);
+        run(&mut sched, 10, false);
+        assert_eq!(Some(1), sched.next());
+        assert!(!sched.preempt());
+
+        sched.add_new(2);
+        assert!(sched.preempt());
+        assert_eq!(Some(2), sched.next());
+        assert!(!sched.preempt());
+    }
+
+    #[test]
+    fn boost() {
+        let mut sched = MlfqScheduler::new(&SLICES, 150);
+        sched.add_new(1);
+        sched.add_new(2);
+
+        // Task 1 sinks to the bottom.
+        for _ in 0..4 {
+            run(&mut sched, 40, false);
+        }
+        assert_eq!(Some(2), sched.level(1));
+
+        // 160 us have run, so both are boosted.
+        assert!(sched.next().is_some());
+        assert_eq!(Some(0), sched.level(1));
+        assert_eq!(Some(0), sched.level(2));
+    }
+
+    #[test]
+    fn drain() {
+        let mut sched = MlfqScheduler::new(&SLICES, 1000);
+        sched.add_new(1);
+        sched.add_new(2);
+        sched.add_new(3);
+
+        // Task 1 sinks a level.
+        run(&mut sched, 10, false);
+        assert_eq!(Some(2), sched.next());
+        assert_eq!(vec![3, 1, 2], sched.drain());
+        assert_eq!(None, sched.level(1));
+        assert_eq!(None, sched.next());
+    }
+
+    #[test]
+    fn complete() {
+        let mut sched = MlfqScheduler::new(&SLICES, 1000);
+        sched.add_new(1);
+        sched.add_new(2);
+        sched.add_new(3);
+
+        assert_eq!(Some(1), sched.next());
+        sched.complete(2);
+        sched.complete(1);
+        assert_eq!(None, sched.current());
+        assert_eq!(Some(3), sched.next());
+        assert_eq!(Some(3), sched.next());
+        assert_eq!(None, sched.level(1));
+    }
+}
@@ -1,10 +1,33 @@
+/// Earliest-deadline-first real-time scheduling class.
+mod edf;
+/// Fair-share scheduler by virtual runtime.
+mod fair;
 /// FIFO scheduler.
 mod fifo;
+/// Multi-level feedback queue scheduler.
+mod mlfq;
+/// Fixed-priority scheduler with aging.
+mod priority;
+/// Schedulers by name.
+mod registry;
 /// Round-robin scheduler.
 mod round_robin;
 /// Scheduler interface.
 mod scheduler;
+/// Deadline-ordered queue of timers.
+mod timer_queue;
 
+pub use edf::{AdmissionError, EdfScheduler, RealTimeParams, FULL_UTILIZATION_PPM};
+pub use fair::{
+    priority_weight, FairScheduler, NICE_0_WEIGHT, PREEMPT_GRANULARITY_US, SLEEPER_CREDIT_US,
+};
 pub use fifo::FifoScheduler;
+pub use mlfq::{MlfqScheduler, DEFAULT_BOOST_INTERVAL_US, DEFAULT_MLFQ_SLICES_US};
+pub use priority::{PriorityScheduler, DEFAULT_AGING_INTERVAL, DEFAULT_PRIORITY_LEVELS};
+pub use registry::{
+    create_scheduler, scheduler_name, BoxedScheduler, SchedulerConstructor,
+    DEFAULT_SCHEDULER, SCHEDULERS,
+};
 pub use round_robin::RoundRobinScheduler;
 pub use scheduler::TaskScheduler;
+pub use timer_queue::{TimerId, TimerQueue};
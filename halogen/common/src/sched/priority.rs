@@ -0,0 +1,353 @@
+#[cfg(not(test))]
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    vec::Vec,
+};
+#[cfg(test)]
+use std::collections::{BTreeMap, VecDeque};
+
+use super::TaskScheduler;
+
+/// Number of priority levels used by default.
+pub const DEFAULT_PRIORITY_LEVELS: usize = 8;
+/// Number of scheduling decisions a task waits by default before it is aged.
+pub const DEFAULT_AGING_INTERVAL: usize = 16;
+
+/// A task known to the priority scheduler.
+#[derive(Clone, Copy, Debug)]
+struct Task {
+    /// The priority set for the task.
+    base: usize,
+    /// The level of the task while it waits, which may be raised by aging.
+    level: usize,
+    /// Scheduling decisions since the task last ran or was aged.
+    waited: usize,
+}
+
+/// Fixed-priority preemptive scheduler with a round-robin queue per level. The
+/// ready task with the highest priority always runs, and a higher priority
+/// task preempts the running one.
+///
+/// To prevent starvation, a task that waits for `aging_interval` scheduling
+/// decisions is raised one level. Once it runs, it drops back to its own
+/// priority.
+///
+/// Priorities are clamped to `0..levels`, where 0 is the lowest.
+#[derive(Clone)]
+pub struct PriorityScheduler {
+    queues: Vec<VecDeque<usize>>,
+    tasks: BTreeMap<usize, Task>,
+    current: Option<usize>,
+    aging_interval: usize,
+}
+
+impl Default for PriorityScheduler {
+    fn default() -> PriorityScheduler {
+        PriorityScheduler::new(DEFAULT_PRIORITY_LEVELS, DEFAULT_AGING_INTERVAL)
+    }
+}
+
+impl PriorityScheduler {
+    /// Create a scheduler with `levels` priorities, which ages waiting tasks
+    /// every `aging_interval` scheduling decisions.
+    pub fn new(levels: usize, aging_interval: usize) -> PriorityScheduler {
+        assert!(levels > 0 && aging_interval > 0);
+
+        PriorityScheduler {
+            queues: (0..levels).map(|_| VecDeque::new()).collect(),
+            tasks: BTreeMap::new(),
+            current: None,
+            aging_interval,
+        }
+    }
+
+    /// Get the priority set for a task.
+    pub fn priority(&self, job: usize) -> Option<usize> {
+        self.tasks.get(&job).map(|task| task.base)
+    }
+
+    /// Get the level a task waits at, which is raised by aging.
+    pub fn level(&self, job: usize) -> Option<usize> {
+        self.tasks.get(&job).map(|task| task.level)
+    }
+
+    fn clamp(&self, priority: isize) -> usize {
+        priority.clamp(0, self.queues.len() as isize - 1) as usize
+    }
+
+    /// Get the highest level with a ready task.
+    fn highest_ready(&self) -> Option<usize> {
+        self.queues.iter().rposition(|queue| !queue.is_empty())
+    }
+
+    /// Remove a task from the queue of its level.
+    fn dequeue(&mut self, job: usize) {
+        if let Some(task) = self.tasks.get(&job) {
+            self.queues[task.level].retain(|&j| j != job);
+        }
+    }
+
+    /// Put a task at the back of the queue of its own priority.
+    fn enqueue(&mut self, job: usize) {
+        if let Some(task) = self.tasks.get_mut(&job) {
+            task.level = task.base;
+            task.waited = 0;
+            self.queues[task.base].push_back(job);
+        }
+    }
+
+    /// Count a scheduling decision against every waiting task, and raise each
+    /// that has waited long enough.
+    fn age(&mut self) {
+        // From the top down, so a task is raised at most once.
+        for level in (0..self.queues.len() - 1).rev() {
+            let mut n = 0;
+            while n < self.queues[level].len() {
+                let job = self.queues[level][n];
+                let task = self.tasks.get_mut(&job).expect("queued task is unknown");
+
+                task.waited += 1;
+                if task.waited >= self.aging_interval {
+                    task.waited = 0;
+                    task.level = level + 1;
+                    self.queues[level].remove(n);
+                    self.queues[level + 1].push_back(job);
+                } else {
+                    n += 1;
+                }
+            }
+        }
+    }
+}
+
+impl TaskScheduler for PriorityScheduler {
+    type Handle = usize;
+
+    fn add_with_priority(&mut self, id: Self::Handle, priority: isize) {
+        let level = self.clamp(priority);
+        self.tasks.insert(
+            id,
+            Task {
+                base: level,
+                level,
+                waited: 0,
+            },
+        );
+        self.queues[level].push_back(id);
+    }
+
+    fn next(&mut self) -> Option<Self::Handle> {
+        if let Some(job) = self.current.take() {
+            self.enqueue(job);
+        }
+
+        self.age();
+
+        let next = self
+            .highest_ready()
+            .and_then(|level| self.queues[level].pop_front());
+        self.current = next;
+
+        next
+    }
+
+    fn complete(&mut self, job: Self::Handle) {
+        if self.current == Some(job) {
+            self.current = None;
+        } else {
+            self.dequeue(job);
+        }
+        self.tasks.remove(&job);
+    }
+
+    fn current(&self) -> Option<Self::Handle> {
+        self.current
+    }
+
+    fn set_priority(&mut self, job: Self::Handle, priority: isize) {
+        let level = self.clamp(priority);
+        let queued = self.current != Some(job);
+
+        if queued {
+            self.dequeue(job);
+        }
+        if let Some(task) = self.tasks.get_mut(&job) {
+            task.base = level;
+        }
+        if queued {
+            self.enqueue(job);
+        }
+    }
+
+    fn yld(&mut self, job: Self::Handle) {
+        // The running task stays current until the next decision, which puts
+        // it at the back of its queue.
+        if self.current != Some(job) {
+            self.dequeue(job);
+            self.enqueue(job);
+        }
+    }
+
+    fn preempt(&self) -> bool {
+        match (
+            self.current.and_then(|job| self.tasks.get(&job)),
+            self.highest_ready(),
+        ) {
+            (Some(task), Some(level)) => level > task.base,
+            _ => false,
+        }
+    }
+
+    fn drain(&mut self) -> Vec<Self::Handle> {
+        self.tasks.clear();
+        self.queues
+            .iter_mut()
+            .rev()
+            .flat_map(|queue| queue.drain(..))
+            .chain(self.current.take())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Run `count` scheduling decisions and return the chosen tasks.
+    fn run(sched: &mut PriorityScheduler, count: usize) -> Vec<usize> {
+        (0..count).filter_map(|_| sched.next()).collect()
+    }
+
+    #[test]
+    fn highest_priority_first() {
+        let mut sched = PriorityScheduler::new(4, 100);
+        sched.add_with_priority(1, 0);
+        sched.add_with_priority(2, 3);
+        sched.add_with_priority(3, 1);
+
+        assert_eq!(Some(2), sched.next());
+        sched.complete(2);
+        assert_eq!(Some(3), sched.next());
+        sched.complete(3);
+        assert_eq!(Some(1), sched.next());
+        sched.complete(1);
+        assert_eq!(None, sched.next());
+    }
+
+    #[test]
+    fn round_robin_per_level() {
+        let mut sched = PriorityScheduler::new(4, 100);
+        sched.add_with_priority(1, 2);
+        sched.add_with_priority(2, 2);
+        sched.add_with_priority(3, 2);
+        sched.add_with_priority(4, 0);
+
+        assert_eq!(vec![1, 2, 3, 1, 2, 3], run(&mut sched, 6));
+
+        sched.yld(1);
+        assert_eq!(vec![2, 1, 3], run(&mut sched, 3));
+
+        sched.yld(3);
+        assert_eq!(Some(3), sched.current());
+        assert_eq!(vec![2, 1, 3], run(&mut sched, 3));
+    }
+
+    #[test]
+    fn clamp_priority() {
+        let mut sched = PriorityScheduler::new(4, 100);
+        sched.add_with_priority(1, -5);
+        sched.add_with_priority(2, 50);
+
+        assert_eq!(Some(0), sched.priority(1));
+        assert_eq!(Some(3), sched.priority(2));
+        assert_eq!(None, sched.priority(3));
+    }
+
+    #[test]
+    fn set_priority() {
+        let mut sched = PriorityScheduler::new(4, 100);
+        sched.add_with_priority(1, 1);
+        sched.add_with_priority(2, 2);
+
+        assert_eq!(vec![2, 2], run(&mut sched, 2));
+
+        // A waiting task moves to its new level.
+        sched.set_priority(1, 3);
+        assert_eq!(vec![1, 1], run(&mut sched, 2));
+
+        // The running task keeps running until the next decision.
+        sched.set_priority(1, 0);
+        assert_eq!(Some(1), sched.current());
+        assert_eq!(vec![2, 2], run(&mut sched, 2));
+    }
+
+    #[test]
+    fn preempt() {
+        let mut sched = PriorityScheduler::new(4, 100);
+        sched.add_with_priority(1, 1);
+        assert_eq!(Some(1), sched.next());
+        assert!(!sched.preempt());
+
+        sched.add_with_priority(2, 1);
+        assert!(!sched.preempt());
+
+        sched.add_with_priority(3, 2);
+        assert!(sched.preempt());
+        assert_eq!(Some(3), sched.next());
+        assert!(!sched.preempt());
+    }
+
+    #[test]
+    fn aging() {
+        let mut sched = PriorityScheduler::new(3, 4);
+        sched.add_with_priority(1, 2);
+        sched.add_with_priority(2, 0);
+
+        // The low task climbs one level every 4 decisions, runs once it reaches
+        // the top, then drops back.
+        assert_eq!(vec![1, 1, 1, 1, 1, 1, 1, 1, 2], run(&mut sched, 9));
+        assert_eq!(Some(2), sched.level(2));
+        assert_eq!(Some(1), sched.next());
+        assert_eq!(Some(0), sched.level(2));
+    }
+
+    #[test]
+    fn aging_without_starvation() {
+        let mut sched = PriorityScheduler::new(DEFAULT_PRIORITY_LEVELS, 2);
+        for job in 0..4 {
+            sched.add_with_priority(job, DEFAULT_PRIORITY_LEVELS as isize - 1);
+        }
+        sched.add_with_priority(100, 0);
+
+        let order = run(&mut sched, 100);
+        assert!(order.contains(&100));
+    }
+
+    #[test]
+    fn drain() {
+        let mut sched = PriorityScheduler::new(4, 100);
+        sched.add_with_priority(1, 1);
+        sched.add_with_priority(2, 3);
+        sched.add_with_priority(3, 1);
+        sched.add_with_priority(4, 2);
+
+        assert_eq!(Some(2), sched.next());
+        assert_eq!(vec![4, 1, 3, 2], sched.drain());
+        assert_eq!(None, sched.priority(2));
+        assert_eq!(None, sched.next());
+    }
+
+    #[test]
+    fn complete() {
+        let mut sched = PriorityScheduler::new(4, 100);
+        sched.add_with_priority(1, 1);
+        sched.add_with_priority(2, 1);
+        sched.add_with_priority(3, 1);
+
+        assert_eq!(Some(1), sched.next());
+        sched.complete(2);
+        sched.complete(1);
+        assert_eq!(None, sched.current());
+        assert_eq!(vec![3, 3], run(&mut sched, 2));
+    }
+}
@@ -0,0 +1,81 @@
+#[cfg(not(test))]
+use alloc::boxed::Box;
+
+use super::{
+    FairScheduler, FifoScheduler, MlfqScheduler, PriorityScheduler, RoundRobinScheduler,
+    TaskScheduler,
+};
+
+/// A scheduler of tasks referred to by ID.
+pub type BoxedScheduler = Box<dyn TaskScheduler<Handle = usize>>;
+/// Creates a scheduler in its default configuration.
+pub type SchedulerConstructor = fn() -> BoxedScheduler;
+
+/// Name of the scheduler used if none is chosen.
+pub const DEFAULT_SCHEDULER: &str = "round-robin";
+
+/// Every scheduler, by name, with a constructor for its default configuration.
+pub const SCHEDULERS: [(&str, SchedulerConstructor); 5] = [
+    ("fair", || Box::new(FairScheduler::default())),
+    ("fifo", || Box::new(FifoScheduler::default())),
+    ("mlfq", || Box::new(MlfqScheduler::default())),
+    ("priority", || Box::new(PriorityScheduler::default())),
+    ("round-robin", || Box::new(RoundRobinScheduler::default())),
+];
+
+/// Create the scheduler with a name, or `None` if there is no such scheduler.
+pub fn create_scheduler(name: &str) -> Option<BoxedScheduler> {
+    SCHEDULERS
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, create)| create())
+}
+
+/// Get the name of a registered scheduler as it is stored in the registry, or
+/// `None` if there is no such scheduler.
+pub fn scheduler_name(name: &str) -> Option<&'static str> {
+    SCHEDULERS.iter().map(|(n, _)| *n).find(|n| *n == name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn create() {
+        assert!(create_scheduler(DEFAULT_SCHEDULER).is_some());
+        assert!(create_scheduler("lottery").is_none());
+        assert_eq!(Some(DEFAULT_SCHEDULER), scheduler_name(DEFAULT_SCHEDULER));
+        assert!(scheduler_name("lottery").is_none());
+        assert!(scheduler_name("fai").is_none());
+
+        for (name, _) in SCHEDULERS.iter() {
+            let mut sched = create_scheduler(name).unwrap();
+            sched.add_new(1);
+            assert_eq!(Some(1), sched.next(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn swap_keeps_order() {
+        let mut from = create_scheduler("round-robin").unwrap();
+        for job in 1..=4 {
+            from.add_new(job);
+        }
+        assert_eq!(Some(1), from.next());
+        assert_eq!(Some(2), from.next());
+
+        // Reinsert the drained tasks, like the executor does.
+        let mut to = create_scheduler("fifo").unwrap();
+        for job in from.drain() {
+            to.add_new(job);
+        }
+        assert_eq!(None, from.next());
+
+        for job in [3, 4, 1, 2] {
+            assert_eq!(Some(job), to.next());
+            to.complete(job);
+        }
+        assert_eq!(None, to.next());
+    }
+}
@@ -1,5 +1,5 @@
 #[cfg(not(test))]
-use alloc::collections::VecDeque;
+use alloc::{collections::VecDeque, vec::Vec};
 #[cfg(test)]
 use std::collections::VecDeque;
 
@@ -19,9 +19,7 @@ impl TaskScheduler for RoundRobinScheduler {
         self.queue.push_back(id);
     }
 
-    fn set_priority(&self, _: Self::Handle, _: isize) {
-        unimplemented!()
-    }
+    fn set_priority(&mut self, _id: Self::Handle, _priority: isize) {}
 
     fn next(&mut self) -> Option<Self::Handle> {
         if let Some(job) = self.current {
@@ -35,7 +33,9 @@ impl TaskScheduler for RoundRobinScheduler {
     }
 
     fn complete(&mut self, job: Self::Handle) {
-        self.current = None;
+        if self.current == Some(job) {
+            self.current = None;
+        }
         self.queue.retain(|&j| j != job);
     }
 
@@ -47,4 +47,8 @@ impl TaskScheduler for RoundRobinScheduler {
         self.queue.retain(|&j| j != job);
         self.queue.push_back(job);
     }
+
+    fn drain(&mut self) -> Vec<Self::Handle> {
+        self.queue.drain(..).chain(self.current.take()).collect()
+    }
 }
@@ -1,3 +1,6 @@
+#[cfg(not(test))]
+use alloc::vec::Vec;
+
 /// Interface for task schedulers. This is currently unfinished; the interface
 /// will likely shift when more complicated schedulers are added.
 pub trait TaskScheduler: Sync + Send {
@@ -12,15 +15,47 @@ pub trait TaskScheduler: Sync + Send {
     /// Complete a task and remove it from the pool.
     fn complete(&mut self, job: Self::Handle);
 
+    /// Take a task out of the pool while it waits for an event. Schedulers
+    /// that keep state across a wait, e.g. runtime, override this and `wake`;
+    /// by default, the task is completed.
+    fn block(&mut self, job: Self::Handle) {
+        self.complete(job);
+    }
+
+    /// Return a blocked task to the pool. By default, it is added anew.
+    fn wake(&mut self, job: Self::Handle, priority: isize) {
+        self.add_with_priority(job, priority);
+    }
+
     /// Get the handle of the currently running task.
     fn current(&self) -> Option<Self::Handle>;
 
-    /// Set the priority for a task.
-    fn set_priority(&self, job: Self::Handle, priority: isize);
+    /// Set the priority for a task. Schedulers without priorities ignore it.
+    fn set_priority(&mut self, job: Self::Handle, priority: isize);
 
     /// Yield the currently running task's remaining time.
     fn yld(&mut self, job: Self::Handle);
 
+    /// Report that a task ran for `runtime_us` microseconds. Schedulers that
+    /// don't track runtime ignore it.
+    fn account(&mut self, _job: Self::Handle, _runtime_us: usize) {}
+
+    /// Returns true if a ready task should preempt the running one before its
+    /// time is up.
+    fn preempt(&self) -> bool {
+        false
+    }
+
+    /// Get the length of the time slice of a task in microseconds. If `None`,
+    /// the executor uses its default quanta.
+    fn time_slice_us(&self, _job: Self::Handle) -> Option<usize> {
+        None
+    }
+
+    /// Remove every task, and return the ready tasks in the order they would
+    /// run followed by the running task, if any.
+    fn drain(&mut self) -> Vec<Self::Handle>;
+
     /// Add a new task with the lowest priority.
     fn add_new(&mut self, id: Self::Handle) {
         self.add_with_priority(id, 0);
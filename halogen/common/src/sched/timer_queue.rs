@@ -0,0 +1,117 @@
+#[cfg(not(test))]
+use alloc::collections::BTreeMap;
+#[cfg(test)]
+use std::collections::BTreeMap;
+
+/// Identifies a timer in a `TimerQueue`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TimerId(usize);
+
+/// Queue of timers ordered by deadline. Timers with the same deadline expire
+/// in the order they were inserted.
+#[derive(Clone)]
+pub struct TimerQueue<T> {
+    timers: BTreeMap<(usize, TimerId), T>,
+    /// Deadline of each timer, to find it by ID.
+    deadlines: BTreeMap<TimerId, usize>,
+    next_id: usize,
+}
+
+impl<T> Default for TimerQueue<T> {
+    fn default() -> TimerQueue<T> {
+        TimerQueue {
+            timers: BTreeMap::new(),
+            deadlines: BTreeMap::new(),
+            next_id: 0,
+        }
+    }
+}
+
+impl<T> TimerQueue<T> {
+    /// Add a timer that expires at `deadline`.
+    pub fn insert(&mut self, deadline: usize, item: T) -> TimerId {
+        let id = TimerId(self.next_id);
+        self.next_id += 1;
+
+        self.timers.insert((deadline, id), item);
+        self.deadlines.insert(id, deadline);
+        id
+    }
+
+    /// Remove a timer before it expires. Returns `None` if there is no such
+    /// timer, e.g. because it expired.
+    pub fn cancel(&mut self, id: TimerId) -> Option<T> {
+        let deadline = self.deadlines.remove(&id)?;
+        self.timers.remove(&(deadline, id))
+    }
+
+    /// Get the earliest deadline.
+    pub fn next_deadline(&self) -> Option<usize> {
+        self.timers.keys().next().map(|&(deadline, _)| deadline)
+    }
+
+    /// Remove and return the earliest timer if it expired by `now`.
+    pub fn pop_expired(&mut self, now: usize) -> Option<(TimerId, T)> {
+        let &(deadline, id) = self.timers.keys().next()?;
+        if deadline > now {
+            return None;
+        }
+
+        self.deadlines.remove(&id);
+        self.timers.remove(&(deadline, id)).map(|item| (id, item))
+    }
+
+    /// Get the number of timers.
+    pub fn len(&self) -> usize {
+        self.timers.len()
+    }
+
+    /// Returns true if there are no timers.
+    pub fn is_empty(&self) -> bool {
+        self.timers.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Pop every timer expired by `now`.
+    fn expire(queue: &mut TimerQueue<char>, now: usize) -> Vec<char> {
+        std::iter::from_fn(|| queue.pop_expired(now))
+            .map(|(_, item)| item)
+            .collect()
+    }
+
+    #[test]
+    fn deadline_order() {
+        let mut queue = TimerQueue::default();
+        queue.insert(30, 'c');
+        queue.insert(10, 'a');
+        queue.insert(20, 'b');
+        queue.insert(10, 'd');
+
+        assert_eq!(Some(10), queue.next_deadline());
+        assert_eq!(Vec::<char>::new(), expire(&mut queue, 9));
+        assert_eq!(vec!['a', 'd'], expire(&mut queue, 10));
+        assert_eq!(Some(20), queue.next_deadline());
+        assert_eq!(vec!['b', 'c'], expire(&mut queue, 100));
+        assert!(queue.is_empty());
+        assert_eq!(None, queue.next_deadline());
+    }
+
+    #[test]
+    fn cancel() {
+        let mut queue = TimerQueue::default();
+        let a = queue.insert(10, 'a');
+        let b = queue.insert(20, 'b');
+
+        assert_eq!(Some('a'), queue.cancel(a));
+        assert_eq!(None, queue.cancel(a));
+        assert_eq!(Some(20), queue.next_deadline());
+        assert_eq!(1, queue.len());
+
+        assert_eq!(vec!['b'], expire(&mut queue, 20));
+        assert_eq!(None, queue.cancel(b));
+    }
+}
@@ -3,6 +3,8 @@ use core::sync::atomic::AtomicUsize;
 
 pub use context::{Context, Privilege};
 
+use crate::mem::regions::kernel_space_start;
+
 pub const TIMER_FREQ_HZ: usize = 10_000_000;
 
 // TODO: The hart ID should be passed into/saved by `kmain()` and `HART_ID`
@@ -57,12 +59,56 @@ macro_rules! read_csr {
     }
 }
 
-/// Disable interrupts for a block statement.
+/// Collect the return addresses of the caller's frames, innermost first, by
+/// walking the frame-pointer chain. Entries past the outermost frame are left
+/// zero. Requires the kernel to be built with frame pointers.
+#[inline(always)]
+pub fn backtrace<const N: usize>() -> [usize; N] {
+    let mut trace = [0; N];
+    let mut fp: usize = crate::read_reg!(s0);
+
+    for addr in trace.iter_mut() {
+        if fp < usize::from(kernel_space_start()) || fp % 8 != 0 {
+            break;
+        }
+
+        // The return address and the caller's frame pointer sit just below the
+        // frame pointer.
+        let (ra, prev_fp) = unsafe {
+            let frame = fp as *const usize;
+            (*frame.sub(1), *frame.sub(2))
+        };
+
+        *addr = ra;
+
+        // Stacks grow down, so the caller's frame must be above this one.
+        if prev_fp <= fp {
+            break;
+        }
+        fp = prev_fp;
+    }
+
+    trace
+}
+
+/// Disable interrupts for a block statement. They are restored when the block
+/// is left, including by `return` or `?`, so critical sections can nest.
+///
+/// The locks taken in critical sections are also taken by trap handlers, e.g.
+/// the executor's on every timer tick, and the heap's and a task's waker's by
+/// device interrupts. If an interrupt arrived while one was held, the handler
+/// would spin on it forever, so every critical section masks interrupts.
+///
+/// Keep them short, since nothing is handled until they end. Syscalls and
+/// page-faults are already handled with interrupts masked, so swapping pages
+/// in for them delays nothing more. In a thread, do slow work such as loading
+/// an executable outside the critical section.
 #[macro_export]
 macro_rules! critical_section {
     ($blk:block) => {
         #[allow(redundant_semicolons)]
         {
+            let _guard = $crate::irq::InterruptGuard::disable();
             #[allow(unused_unsafe)]
             let _cs = unsafe { riscv::interrupt::CriticalSection::new() };
             $blk
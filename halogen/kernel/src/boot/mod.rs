@@ -1,17 +1,24 @@
-use halogen_common::mem::{Address, PhysicalAddress, Segment};
+use halogen_common::{
+    fdt::{boot_arg, bootargs, has_extension, Fdt},
+    mem::{Address, PhysicalAddress, Segment},
+};
 
 use crate::{
     io::console::early_println,
     mem::{
-        paging::{get_root_satp, map, Permissions, Privilege, Scope, PAGING_ENABLED},
+        paging::{
+            get_root_satp, map, probe_mode, set_svnapot, Mode, Permissions, Privilege, Scope,
+            KERNEL_ASID, PAGING_ENABLED,
+        },
         phys,
         regions::{
-            FREE_SIZE, KERNEL_SPACE_START, PHYSICAL_BASE, PHYSICAL_SIZE, RODATA_SIZE, RWDATA_SIZE,
-            TEXT_SIZE,
+            FREE_SIZE, INIT_SIZE, KERNEL_IMAGE_START, PHYSICAL_BASE, PHYSICAL_SIZE, RODATA_SIZE,
+            RWDATA_SIZE, TEXT_SIZE,
         },
         MEMORY_SIZE,
     },
     read_reg,
+    task::executor::set_boot_scheduler,
     trap::early_trap,
 };
 
@@ -47,6 +54,16 @@ extern "C" {
     /// End of BSS section.
     static __bss_end: Symbol;
 
+    /// Boot-only code.
+    static __init_text: Symbol;
+    /// End of boot-only code.
+    static __init_text_end: Symbol;
+
+    /// Boot-only data, including the stack.
+    static __init_data: Symbol;
+    /// End of boot-only data.
+    static __init_end: Symbol;
+
     /// Read-write region to be used as a stack during boot.
     static __tmp_stack_top: Symbol;
 
@@ -72,7 +89,8 @@ unsafe extern "C" fn entry() -> ! {
 
 /// Initialize the root page-table and map the kernel
 #[no_mangle]
-unsafe extern "C" fn enable_paging(_hart_id: usize, _device_tree: *const u8) -> ! {
+#[link_section = ".init.text"]
+unsafe extern "C" fn enable_paging(_hart_id: usize, device_tree: *const u8) -> ! {
     riscv::register::stvec::write(
         early_trap as usize,
         riscv::register::stvec::TrapMode::Direct,
@@ -80,10 +98,31 @@ unsafe extern "C" fn enable_paging(_hart_id: usize, _device_tree: *const u8) ->
 
     early_println(BANNER);
 
+    // The address-space layout depends on the paging mode, so pick it first.
+    early_println(match probe_mode() {
+        Mode::Sv39 => "\nPaging mode: Sv39",
+        Mode::Sv48 => "\nPaging mode: Sv48",
+        Mode::Sv57 => "\nPaging mode: Sv57",
+    });
+
+    // Map 64 KiB runs with single TLB entries if the harts support it. The
+    // device-tree is only read here, before its memory is handed out as frames.
+    let fdt = Fdt::from_ptr(device_tree);
+    if fdt.map_or(false, |fdt| has_extension(&fdt, "svnapot")) {
+        early_println("Svnapot: map 64 KiB pages");
+        set_svnapot(true);
+    }
+
+    // Keep the scheduler chosen on the command line, e.g. `sched=fair`, for
+    // when the executor starts.
+    if let Some(name) = fdt.and_then(|fdt| boot_arg(bootargs(&fdt)?, "sched")) {
+        set_boot_scheduler(name);
+    }
+
     // Calculate and save some constants based on the device-tree (TODO) and linker
     // symbols.
 
-    let virt_offset = KERNEL_SPACE_START.offset(__text.address());
+    let virt_offset = KERNEL_IMAGE_START.offset(__text.address());
 
     PHYSICAL_BASE = __text.address();
     PHYSICAL_SIZE = MEMORY_SIZE;
@@ -91,7 +130,8 @@ unsafe extern "C" fn enable_paging(_hart_id: usize, _device_tree: *const u8) ->
     TEXT_SIZE = __text_end.address() - __text.address();
     RODATA_SIZE = __ro_data_end.address() - __ro_data.address();
     RWDATA_SIZE = __rw_data_end.address() - __rw_data.address();
-    FREE_SIZE = MEMORY_SIZE - TEXT_SIZE - RODATA_SIZE - RWDATA_SIZE;
+    INIT_SIZE = __init_end.address() - __init_text.address();
+    FREE_SIZE = MEMORY_SIZE - TEXT_SIZE - RODATA_SIZE - RWDATA_SIZE - INIT_SIZE;
 
     early_println("\nInitialize frame allocator");
 
@@ -112,6 +152,7 @@ unsafe extern "C" fn enable_paging(_hart_id: usize, _device_tree: *const u8) ->
         Permissions::ReadExecute,
         Scope::Global,
         Privilege::Kernel,
+        KERNEL_ASID,
     )
     .unwrap();
 
@@ -123,6 +164,7 @@ unsafe extern "C" fn enable_paging(_hart_id: usize, _device_tree: *const u8) ->
         Permissions::ReadOnly,
         Scope::Global,
         Privilege::Kernel,
+        KERNEL_ASID,
     )
     .unwrap();
 
@@ -134,17 +176,42 @@ unsafe extern "C" fn enable_paging(_hart_id: usize, _device_tree: *const u8) ->
         Permissions::ReadWrite,
         Scope::Global,
         Privilege::Kernel,
+        KERNEL_ASID,
+    )
+    .unwrap();
+
+    // Map the boot-only code and data. They are released after init.
+    map(
+        Some(__init_text.address().add_offset(virt_offset).as_virt()),
+        Some(__init_text.address()),
+        __init_text_end.address() - __init_text.address(),
+        Permissions::ReadExecute,
+        Scope::Global,
+        Privilege::Kernel,
+        KERNEL_ASID,
+    )
+    .unwrap();
+
+    map(
+        Some(__init_data.address().add_offset(virt_offset).as_virt()),
+        Some(__init_data.address()),
+        __init_end.address() - __init_data.address(),
+        Permissions::ReadWrite,
+        Scope::Global,
+        Privilege::Kernel,
+        KERNEL_ASID,
     )
     .unwrap();
 
     // Map the rest of the physical memory.
     map(
-        Some(__rw_data_end.address().add_offset(virt_offset).as_virt()),
-        Some(__rw_data_end.address()),
+        Some(__free.address().add_offset(virt_offset).as_virt()),
+        Some(__free.address()),
         FREE_SIZE,
         Permissions::ReadWrite,
         Scope::Global,
         Privilege::Kernel,
+        KERNEL_ASID,
     )
     .unwrap();
 
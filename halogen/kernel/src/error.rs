@@ -34,6 +34,15 @@ macro_rules! make_errors {
             }
         }
 
+        impl $ty {
+            /// Get the error that caused this one, if any.
+            pub fn cause(&self) -> Option<&$ty> {
+                match self {
+                    $($ty::$variant(_, _, inner) => inner.as_deref(),)*
+                }
+            }
+        }
+
         impl<T> From<$ty> for Result<T, $ty> {
             fn from(err: $ty) -> Result<T, $ty> {
                 Err(err)
@@ -47,8 +56,12 @@ make_errors! {
         ProcessCreate,
         ThreadCreate,
         NoSuchThread,
+        NoSuchProcess,
+        RealTimeAdmission,
+        NoSuchScheduler,
         ExecutableFormat,
         OutOfVirtualAddresses,
+        InvalidVirtualFree,
         OutOfPhysicalFrames,
         HeapAllocationOutOfSpace,
         HeapInvalidFree,
@@ -56,12 +69,27 @@ make_errors! {
         PageTableAllocation,
         InvalidMapping,
         PageTableCorruption,
+        NoSuchSharedMemory,
+        SharedMemoryExists,
+        OutOfMemory,
+        OutOfSwap,
+        BlockDeviceInit,
+        BlockIo,
+        BlockOutOfRange,
         Sbi,
     }
 }
 
 pub type KernelResult<T> = Result<T, KernelError>;
 
+impl KernelError {
+    /// Returns true if the error was caused by running out of physical frames.
+    pub fn is_out_of_frames(&self) -> bool {
+        matches!(self, KernelError::OutOfPhysicalFrames(..))
+            || self.cause().map_or(false, KernelError::is_out_of_frames)
+    }
+}
+
 /// Construct a new kernel error, optionally with a causing error. This can be
 /// chained to build psuedo-stack-traces.
 ///
@@ -0,0 +1,260 @@
+use alloc::{boxed::Box, collections::VecDeque, sync::Arc, task::Wake, vec::Vec};
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, AtomicU8, Ordering},
+    task::{Context, Poll, Waker},
+};
+
+use halogen_common::mem::alloc::Tag;
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+use crate::{
+    critical_section,
+    error::KernelResult,
+    mem::heap,
+    task::{self, WaitQueue},
+};
+
+/// Waiting to be woken.
+const IDLE: u8 = 0;
+/// In the run queue.
+const QUEUED: u8 = 1;
+/// Being polled by a worker.
+const RUNNING: u8 = 2;
+/// Woken while being polled, so it is queued again afterwards.
+const NOTIFIED: u8 = 3;
+/// Completed, with its future dropped.
+const DONE: u8 = 4;
+
+type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+lazy_static! {
+    /// Tasks ready to be polled, in the order they were woken.
+    static ref RUN_QUEUE: Mutex<VecDeque<Arc<Task>>> = Mutex::new(VecDeque::new());
+}
+
+/// Worker threads waiting for a task to be queued.
+static WORKERS: WaitQueue = WaitQueue::new();
+
+/// A spawned future. Its state makes sure it is queued at most once and
+/// polled by one worker at a time.
+struct Task {
+    state: AtomicU8,
+    future: Mutex<Option<BoxFuture>>,
+}
+
+impl Task {
+    /// Poll the future once. If the task was woken meanwhile, it is queued
+    /// again.
+    fn run(self: Arc<Self>) {
+        self.state.store(RUNNING, Ordering::Release);
+
+        let waker = Waker::from(self.clone());
+        let mut cx = Context::from_waker(&waker);
+        let mut future = self.future.lock();
+        let ready = match future.as_mut() {
+            Some(future) => future.as_mut().poll(&mut cx).is_ready(),
+            None => true,
+        };
+
+        if ready {
+            *future = None;
+            self.state.store(DONE, Ordering::Release);
+            return;
+        }
+        drop(future);
+
+        if self
+            .state
+            .compare_exchange(RUNNING, IDLE, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            self.state.store(QUEUED, Ordering::Release);
+            enqueue(self);
+        }
+    }
+}
+
+impl Wake for Task {
+    fn wake(self: Arc<Self>) {
+        let mut state = self.state.load(Ordering::Acquire);
+        loop {
+            let next = match state {
+                IDLE => QUEUED,
+                RUNNING => NOTIFIED,
+                _ => return,
+            };
+
+            match self
+                .state
+                .compare_exchange_weak(state, next, Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => break,
+                Err(current) => state = current,
+            }
+        }
+
+        // A running task is queued again by its worker.
+        if state == IDLE {
+            enqueue(self);
+        }
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.clone().wake();
+    }
+}
+
+/// Add a task to the run queue and wake a worker to poll it.
+fn enqueue(task: Arc<Task>) {
+    critical_section!({ RUN_QUEUE.lock().push_back(task) });
+    WORKERS.wake_one();
+}
+
+/// Body of a worker thread, which polls queued tasks and blocks while there
+/// are none.
+extern "C" fn worker(_: usize) -> isize {
+    loop {
+        match critical_section!({ RUN_QUEUE.lock().pop_front() }) {
+            Some(task) => task.run(),
+            None => WORKERS.wait_until(|| critical_section!({ !RUN_QUEUE.lock().is_empty() })),
+        }
+    }
+}
+
+/// Start `count` worker threads to poll tasks and return their IDs.
+pub fn start(count: usize) -> KernelResult<Vec<usize>> {
+    (0..count).map(|_| task::spawn(worker, 0)).collect()
+}
+
+/// Spawn a task to run `future` on the worker threads. The returned handle
+/// is a future of its output; dropping it detaches the task.
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static, {
+    let _tag = heap::tag(Tag::Scheduler);
+    let join = Arc::new(Mutex::new(Join {
+        output: None,
+        waker: None,
+    }));
+
+    let task_join = join.clone();
+    let task = Arc::new(Task {
+        state: AtomicU8::new(QUEUED),
+        future: Mutex::new(Some(Box::pin(async move {
+            let output = future.await;
+            let waker = critical_section!({
+                let mut join = task_join.lock();
+                join.output = Some(output);
+                join.waker.take()
+            });
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }))),
+    });
+    enqueue(task);
+
+    JoinHandle { join }
+}
+
+/// The output of a task and the waker of whoever awaits it.
+struct Join<T> {
+    output: Option<T>,
+    waker: Option<Waker>,
+}
+
+/// Handle to a spawned task, which is a future of its output.
+pub struct JoinHandle<T> {
+    join: Arc<Mutex<Join<T>>>,
+}
+
+impl<T> JoinHandle<T> {
+    /// Returns true if the task has completed and its output wasn't taken.
+    pub fn is_finished(&self) -> bool {
+        critical_section!({ self.join.lock().output.is_some() })
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<T> {
+        critical_section!({
+            let mut join = self.join.lock();
+            match join.output.take() {
+                Some(output) => Poll::Ready(output),
+                None => {
+                    join.waker = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        })
+    }
+}
+
+/// Wakes a thread blocked in `block_on`.
+struct ThreadWaker {
+    woken: AtomicBool,
+    queue: WaitQueue,
+}
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.woken.store(true, Ordering::Release);
+        self.queue.wake_one();
+    }
+}
+
+/// Run `future` on the calling thread, blocking it between polls until the
+/// future completes. It must not be called from a task, since it would block
+/// a worker.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = Box::pin(future);
+    let thread = Arc::new(ThreadWaker {
+        woken: AtomicBool::new(false),
+        queue: WaitQueue::new(),
+    });
+    let waker = Waker::from(thread.clone());
+    let mut cx = Context::from_waker(&waker);
+
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        thread
+            .queue
+            .wait_until(|| thread.woken.swap(false, Ordering::AcqRel));
+    }
+}
+
+/// Let other tasks run before the calling task continues.
+pub fn yield_now() -> YieldNow {
+    YieldNow { yielded: false }
+}
+
+/// Future returned by `yield_now`.
+pub struct YieldNow {
+    yielded: bool,
+}
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if self.yielded {
+            return Poll::Ready(());
+        }
+
+        self.yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
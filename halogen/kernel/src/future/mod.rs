@@ -0,0 +1,19 @@
+//! Async tasks for kernel services. Drivers and services can be written as
+//! futures instead of hand-rolled state machines on threads: `spawn` adds a
+//! future to a run queue, and worker threads started by `start` poll it until
+//! it completes. Wakers may be used from interrupt handlers, e.g. in a timer
+//! callback or an ISR.
+//!
+//! A kernel thread can wait for a future with `block_on`, which blocks the
+//! thread rather than a worker.
+
+/// Task run queue and worker threads.
+mod executor;
+/// Notifications that wake waiting tasks.
+mod notify;
+/// Async timers.
+mod time;
+
+pub use executor::{block_on, spawn, start, yield_now, JoinHandle, YieldNow};
+pub use notify::{Notified, Notify};
+pub use time::{sleep, Sleep};
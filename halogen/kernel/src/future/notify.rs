@@ -0,0 +1,87 @@
+use alloc::vec::Vec;
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll, Waker},
+};
+
+use spin::Mutex;
+
+use crate::critical_section;
+
+/// Wakes tasks waiting for an event, such as a driver waiting for its device.
+/// `notify` may be called from an interrupt handler. A notification completes
+/// one wait, and one with no task waiting is kept until a task waits.
+/// Notifications are not counted: any made while one is already pending are
+/// merged into it.
+pub struct Notify {
+    pending: AtomicBool,
+    wakers: Mutex<Vec<Waker>>,
+}
+
+impl Notify {
+    /// Create a notify with no notification pending.
+    pub const fn new() -> Notify {
+        Notify {
+            pending: AtomicBool::new(false),
+            wakers: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Notify a waiting task, or the next task to wait if none does. Every
+    /// waiting task is woken, but only the first to poll completes its wait.
+    pub fn notify(&self) {
+        self.pending.store(true, Ordering::Release);
+
+        let wakers = critical_section!({ core::mem::take(&mut *self.wakers.lock()) });
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    /// Wait for a notification.
+    pub fn notified(&self) -> Notified {
+        Notified { notify: self }
+    }
+
+    /// Take the pending notification, if there is one.
+    fn take(&self) -> bool {
+        self.pending.swap(false, Ordering::AcqRel)
+    }
+}
+
+impl Default for Notify {
+    fn default() -> Notify {
+        Notify::new()
+    }
+}
+
+/// Future returned by `Notify::notified`.
+pub struct Notified<'a> {
+    notify: &'a Notify,
+}
+
+impl Future for Notified<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if self.notify.take() {
+            return Poll::Ready(());
+        }
+
+        critical_section!({
+            let mut wakers = self.notify.wakers.lock();
+            if !wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+                wakers.push(cx.waker().clone());
+            }
+        });
+
+        // A notification may have come before the waker was registered.
+        if self.notify.take() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use halogen_common::sched::TimerId;
+
+use crate::{sbi::timer::now_us, timer};
+
+/// Wait for at least `duration`.
+pub fn sleep(duration: Duration) -> Sleep {
+    Sleep {
+        deadline: timer::deadline(duration),
+        timer: None,
+    }
+}
+
+/// Future returned by `sleep`, which wakes its task from a kernel timer.
+pub struct Sleep {
+    /// Time to wake up, in microseconds since boot.
+    deadline: usize,
+    /// Timer waking the last waker the future was polled with.
+    timer: Option<TimerId>,
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if now_us() >= self.deadline {
+            return Poll::Ready(());
+        }
+
+        // The task may be polled with a different waker, so set a new timer.
+        if let Some(id) = self.timer.take() {
+            timer::cancel(id);
+        }
+        let waker = cx.waker().clone();
+        self.timer = Some(timer::at(self.deadline, move || waker.wake()));
+
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some(id) = self.timer.take() {
+            timer::cancel(id);
+        }
+    }
+}
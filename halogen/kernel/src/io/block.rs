@@ -0,0 +1,82 @@
+//! Block devices read and write fixed-size blocks by index. The swap area is
+//! kept on one of these.
+
+use alloc::{vec, vec::Vec};
+
+use crate::{
+    error::{KernelError, KernelResult},
+    kerror,
+};
+
+/// A device that stores data in fixed-size blocks.
+pub trait BlockDevice: Send {
+    /// Size of a block in bytes.
+    fn block_size(&self) -> usize;
+
+    /// Number of blocks on the device.
+    fn blocks(&self) -> usize;
+
+    /// Read consecutive blocks starting at `block` into `buf`. The length of
+    /// `buf` must be a multiple of the block size.
+    fn read(&mut self, block: usize, buf: &mut [u8]) -> KernelResult<()>;
+
+    /// Write `buf` to consecutive blocks starting at `block`. The length of
+    /// `buf` must be a multiple of the block size.
+    fn write(&mut self, block: usize, buf: &[u8]) -> KernelResult<()>;
+
+    /// Size of the device in bytes.
+    fn size(&self) -> usize {
+        self.blocks() * self.block_size()
+    }
+}
+
+/// Check that a transfer of `len` bytes at `block` fits on a device.
+pub fn check_range(device: &dyn BlockDevice, block: usize, len: usize) -> KernelResult<()> {
+    let blocks = len / device.block_size();
+    if len % device.block_size() != 0 || block + blocks > device.blocks() {
+        kerror!(KernelError::BlockOutOfRange).into()
+    } else {
+        Ok(())
+    }
+}
+
+/// A block device kept in the kernel heap. Useful for testing, since nothing
+/// is stored outside of memory.
+pub struct RamDisk {
+    data: Vec<u8>,
+    block_size: usize,
+}
+
+impl RamDisk {
+    /// Create a zeroed disk of `blocks` blocks.
+    pub fn new(blocks: usize, block_size: usize) -> RamDisk {
+        RamDisk {
+            data: vec![0; blocks * block_size],
+            block_size,
+        }
+    }
+}
+
+impl BlockDevice for RamDisk {
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn blocks(&self) -> usize {
+        self.data.len() / self.block_size
+    }
+
+    fn read(&mut self, block: usize, buf: &mut [u8]) -> KernelResult<()> {
+        check_range(self, block, buf.len())?;
+        let start = block * self.block_size;
+        buf.copy_from_slice(&self.data[start..start + buf.len()]);
+        Ok(())
+    }
+
+    fn write(&mut self, block: usize, buf: &[u8]) -> KernelResult<()> {
+        check_range(self, block, buf.len())?;
+        let start = block * self.block_size;
+        self.data[start..start + buf.len()].copy_from_slice(buf);
+        Ok(())
+    }
+}
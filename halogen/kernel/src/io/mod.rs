@@ -1,5 +1,11 @@
+/// Block device interface.
+pub mod block;
+
 /// Debug console and printing.
 pub mod console;
 
 /// Driver for the NS16550A UART device.
 pub mod uart;
+
+/// Driver for virtio block devices.
+pub mod virtio;
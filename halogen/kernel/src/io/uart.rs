@@ -1,45 +1,116 @@
-use alloc::boxed::Box;
+use alloc::{boxed::Box, collections::VecDeque};
 
-use halogen_common::mem::{Address, VirtualAddress};
+use halogen_common::mem::alloc::Tag;
+use lazy_static::lazy_static;
+use spin::Mutex;
 
 use super::console::register_console;
-use crate::mem::{
-    io::{UART_BASE, UART_SIZE},
-    paging::{map, Permissions, Privilege, Scope},
+use crate::{
+    critical_section,
+    future::Notify,
+    irq::plic,
+    mem::{
+        heap,
+        io::{ioremap, Mmio, ReadOnly, ReadWrite, WriteOnly, UART_BASE, UART_SIZE},
+    },
 };
 
 const UART_IRQ: usize = 10;
+/// Received bytes kept until they are read. Later bytes are dropped.
+const RX_CAPACITY: usize = 1024;
+/// Line status bit set when the receive buffer holds a byte.
+const LINE_DATA_READY: u8 = 0b1;
 
-const DATA_OFFSET: usize = 0;
-const INT_ENABLE_OFFSET: usize = 1;
-const FIFO_OFFSET: usize = 2;
-const LINE_CTL_OFFSET: usize = 3;
-const MODEM_CTL_OFFSET: usize = 4;
-const LINE_STAT_OFFSET: usize = 5;
-const MODEM_STAT_OFFSET: usize = 6;
-const SCRATCH_OFFSET: usize = 7;
+lazy_static! {
+    /// The UART as seen by the receive interrupt handler, sharing the
+    /// console's mapping.
+    static ref RX_UART: Ns16550aUart = unsafe {
+        Ns16550aUart::new(ioremap(UART_BASE, UART_SIZE).expect("failed to map UART"))
+    };
+    /// Bytes received and not read yet.
+    static ref RECEIVED: Mutex<VecDeque<u8>> = Mutex::new(VecDeque::with_capacity(RX_CAPACITY));
+}
+
+/// Notified when bytes are received.
+static RX_READY: Notify = Notify::new();
+
+/// Registers of the NS16550A, with the divisor latch disabled.
+#[repr(C)]
+struct Registers {
+    /// Receive buffer when read, transmit holding when written.
+    data: ReadWrite<u8>,
+    int_enable: ReadWrite<u8>,
+    /// FIFO control; reads return the interrupt status instead.
+    fifo_ctl: WriteOnly<u8>,
+    line_ctl: ReadWrite<u8>,
+    modem_ctl: ReadWrite<u8>,
+    line_stat: ReadOnly<u8>,
+    modem_stat: ReadOnly<u8>,
+    scratch: ReadWrite<u8>,
+}
 
 /// Register the UART device as the main console.
 pub fn use_as_console() {
+    let _tag = heap::tag(Tag::Driver);
     unsafe {
-        register_console(Box::new(Ns16550aUart::new(
-            map(
-                None,
-                Some(UART_BASE),
-                UART_SIZE,
-                Permissions::ReadWrite,
-                Scope::Global,
-                Privilege::Kernel,
-            )
-            .unwrap(),
-        )));
+        let mut uart = Ns16550aUart::new(ioremap(UART_BASE, UART_SIZE).unwrap());
+        uart.init();
+        register_console(Box::new(uart));
     }
+
+    // Map the UART for the handler before it can run.
+    lazy_static::initialize(&RX_UART);
+    plic::register_isr(UART_IRQ, handle_rx);
+    plic::set_priority(UART_IRQ, 1);
+    plic::set_enabled(UART_IRQ, true);
+}
+
+/// Receive interrupt handler: move the received bytes into the buffer and
+/// wake readers.
+fn handle_rx() -> usize {
+    critical_section!({
+        let mut received = RECEIVED.lock();
+        while let Some(byte) = RX_UART.receive() {
+            if received.len() < RX_CAPACITY {
+                received.push_back(byte);
+            }
+        }
+    });
+
+    RX_READY.notify();
+    0
+}
+
+/// Read received bytes into `buf`, waiting until there is at least one.
+/// Returns the number of bytes read.
+pub async fn read(buf: &mut [u8]) -> usize {
+    loop {
+        let count = critical_section!({
+            let mut received = RECEIVED.lock();
+            let count = buf.len().min(received.len());
+            for (dst, src) in buf.iter_mut().zip(received.drain(..count)) {
+                *dst = src;
+            }
+            count
+        });
+
+        if count > 0 || buf.is_empty() {
+            return count;
+        }
+        RX_READY.notified().await;
+    }
+}
+
+/// Read a received byte, waiting until there is one.
+pub async fn read_byte() -> u8 {
+    let mut byte = [0];
+    read(&mut byte).await;
+    byte[0]
 }
 
 /// Driver for the NS16550A UART device.
-#[derive(Clone, Copy)]
 pub struct Ns16550aUart {
-    base: VirtualAddress,
+    mmio: Mmio,
 }
 
 impl Ns16550aUart {
@@ -47,27 +118,39 @@ impl Ns16550aUart {
     ///
     /// # Safety
     ///
-    /// - `base` must be mapped to a NS16550A device.
-    pub const unsafe fn new(base: VirtualAddress) -> Ns16550aUart {
-        Ns16550aUart { base }
+    /// - `mmio` must map the registers of a NS16550A device.
+    pub unsafe fn new(mmio: Mmio) -> Ns16550aUart {
+        Ns16550aUart { mmio }
+    }
+
+    fn regs(&self) -> &Registers {
+        unsafe { self.mmio.block(0) }
     }
 
     /// Initialize the UART module registers.
     pub fn init(&mut self) {
-        unsafe {
-            ((self.base + FIFO_OFFSET).as_mut_ptr() as *mut u8).write_volatile(0b1);
-            ((self.base + LINE_CTL_OFFSET).as_mut_ptr() as *mut u8).write_volatile(0b11);
-            ((self.base + INT_ENABLE_OFFSET).as_mut_ptr() as *mut u8).write_volatile(0b1);
+        let regs = self.regs();
+        regs.fifo_ctl.write(0b1);
+        regs.line_ctl.write(0b11);
+        regs.int_enable.write(0b1);
+    }
+
+    /// Take a byte from the receive buffer, if there is one.
+    fn receive(&self) -> Option<u8> {
+        let regs = self.regs();
+        if regs.line_stat.read() & LINE_DATA_READY != 0 {
+            Some(regs.data.read())
+        } else {
+            None
         }
     }
 }
 
 impl core::fmt::Write for Ns16550aUart {
     fn write_str(&mut self, str: &str) -> core::fmt::Result {
+        let regs = self.regs();
         for b in str.bytes() {
-            unsafe {
-                ((self.base + DATA_OFFSET).as_mut_ptr() as *mut u8).write_volatile(b);
-            }
+            regs.data.write(b);
         }
         Ok(())
     }
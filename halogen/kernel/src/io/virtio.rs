@@ -0,0 +1,402 @@
+//! Driver for virtio block devices on the MMIO transport, like the ones QEMU
+//! adds to the `virt` machine with `-device virtio-blk-device`. Both the legacy
+//! (version 1) and modern (version 2) register layouts are supported.
+//!
+//! The driver keeps a single virtqueue in one frame and makes one request at a
+//! time, polling for its completion, so it does not need interrupts. Data is
+//! copied through a bounce frame, so callers can pass any kernel buffer.
+
+use core::sync::atomic::{fence, Ordering};
+
+use halogen_common::mem::{Address, PhysicalAddress, VirtualAddress};
+
+use super::block::{check_range, BlockDevice};
+use crate::{
+    error::{KernelError, KernelResult},
+    kerror,
+    log::*,
+    mem::{
+        io::{ioremap, Mmio, VIRTIO_BASE, VIRTIO_COUNT, VIRTIO_STRIDE},
+        paging::PAGE_SIZE,
+        phys,
+    },
+};
+
+const MAGIC: u32 = 0x7472_6976;
+const DEVICE_BLOCK: u32 = 2;
+
+const MAGIC_OFFSET: usize = 0x000;
+const VERSION_OFFSET: usize = 0x004;
+const DEVICE_ID_OFFSET: usize = 0x008;
+const DEVICE_FEATURES_OFFSET: usize = 0x010;
+const DEVICE_FEATURES_SEL_OFFSET: usize = 0x014;
+const DRIVER_FEATURES_OFFSET: usize = 0x020;
+const DRIVER_FEATURES_SEL_OFFSET: usize = 0x024;
+const GUEST_PAGE_SIZE_OFFSET: usize = 0x028;
+const QUEUE_SEL_OFFSET: usize = 0x030;
+const QUEUE_NUM_MAX_OFFSET: usize = 0x034;
+const QUEUE_NUM_OFFSET: usize = 0x038;
+const QUEUE_ALIGN_OFFSET: usize = 0x03c;
+const QUEUE_PFN_OFFSET: usize = 0x040;
+const QUEUE_READY_OFFSET: usize = 0x044;
+const QUEUE_NOTIFY_OFFSET: usize = 0x050;
+const INTERRUPT_STATUS_OFFSET: usize = 0x060;
+const INTERRUPT_ACK_OFFSET: usize = 0x064;
+const STATUS_OFFSET: usize = 0x070;
+const QUEUE_DESC_OFFSET: usize = 0x080;
+const QUEUE_DRIVER_OFFSET: usize = 0x090;
+const QUEUE_DEVICE_OFFSET: usize = 0x0a0;
+const CONFIG_OFFSET: usize = 0x100;
+
+const STATUS_ACKNOWLEDGE: u32 = 1;
+const STATUS_DRIVER: u32 = 2;
+const STATUS_DRIVER_OK: u32 = 4;
+const STATUS_FEATURES_OK: u32 = 8;
+
+/// The device is read-only.
+const FEATURE_RO: u32 = 1 << 5;
+/// The device is not legacy; bit 32 of the features.
+const FEATURE_VERSION_1: u32 = 1 << 0;
+
+const DESC_NEXT: u16 = 1;
+const DESC_WRITE: u16 = 2;
+
+const REQUEST_IN: u32 = 0;
+const REQUEST_OUT: u32 = 1;
+
+/// Size of a sector, in which capacity and request offsets are given.
+const SECTOR_SIZE: usize = 512;
+
+/// Descriptors in the queue. Each request uses three.
+const QUEUE_SIZE: usize = 8;
+/// Alignment of the used ring in the legacy layout.
+const USED_ALIGN: usize = 256;
+
+// Layout of the queue frame.
+const AVAIL_OFFSET: usize = QUEUE_SIZE * core::mem::size_of::<Descriptor>();
+const USED_OFFSET: usize = USED_ALIGN;
+const HEADER_OFFSET: usize = 2 * USED_ALIGN;
+const STATUS_BYTE_OFFSET: usize = HEADER_OFFSET + core::mem::size_of::<RequestHeader>();
+
+// Layouts shared with the device.
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+struct Available {
+    flags: u16,
+    idx: u16,
+    ring: [u16; QUEUE_SIZE],
+    used_event: u16,
+}
+
+#[repr(C)]
+struct UsedElement {
+    id: u32,
+    len: u32,
+}
+
+#[repr(C)]
+struct Used {
+    flags: u16,
+    idx: u16,
+    ring: [UsedElement; QUEUE_SIZE],
+    avail_event: u16,
+}
+
+#[repr(C)]
+struct RequestHeader {
+    kind: u32,
+    reserved: u32,
+    sector: u64,
+}
+
+/// Find the first virtio block device and initialize it. The transports of
+/// other devices are unmapped again.
+pub fn probe() -> Option<VirtioBlock> {
+    (0..VIRTIO_COUNT).find_map(|n| {
+        let regs = match unsafe { ioremap(VIRTIO_BASE + n * VIRTIO_STRIDE, VIRTIO_STRIDE) } {
+            Ok(regs) => regs,
+            Err(why) => {
+                warn!("Failed to map virtio transport {}: {:?}", n, why);
+                return None;
+            }
+        };
+
+        match unsafe { VirtioBlock::new(regs) } {
+            Ok(Some(device)) => {
+                info!(
+                    "Found virtio block device {} with {} KiB",
+                    n,
+                    device.size() / 1024
+                );
+                Some(device)
+            }
+            Ok(None) => None,
+            Err(why) => {
+                warn!("Failed to initialize virtio block device {}: {:?}", n, why);
+                None
+            }
+        }
+    })
+}
+
+/// A virtio block device.
+pub struct VirtioBlock {
+    regs: Mmio,
+    queue: VirtualAddress,
+    buffer: VirtualAddress,
+    buffer_phys: PhysicalAddress,
+    queue_phys: PhysicalAddress,
+    /// Capacity in sectors.
+    capacity: usize,
+    /// Index of the next request in the available ring.
+    avail_idx: u16,
+    /// Index of the next completion expected in the used ring.
+    used_idx: u16,
+}
+
+impl VirtioBlock {
+    /// Initialize the device whose registers are mapped by `regs`. Returns
+    /// `None` if it is not a block device.
+    ///
+    /// # Safety
+    ///
+    /// - `regs` must map a virtio MMIO transport.
+    unsafe fn new(regs: Mmio) -> KernelResult<Option<VirtioBlock>> {
+        let base = regs.base();
+        let read = |offset: usize| (base + offset).as_ptr::<u32>().read_volatile();
+        let write =
+            |offset: usize, value: u32| (base + offset).as_mut_ptr::<u32>().write_volatile(value);
+
+        let version = read(VERSION_OFFSET);
+        if read(MAGIC_OFFSET) != MAGIC
+            || read(DEVICE_ID_OFFSET) != DEVICE_BLOCK
+            || !(1..=2).contains(&version)
+        {
+            return Ok(None);
+        }
+
+        // Reset and tell the device we found it and can drive it.
+        write(STATUS_OFFSET, 0);
+        let mut status = STATUS_ACKNOWLEDGE | STATUS_DRIVER;
+        write(STATUS_OFFSET, status);
+
+        write(DEVICE_FEATURES_SEL_OFFSET, 0);
+        if read(DEVICE_FEATURES_OFFSET) & FEATURE_RO != 0 {
+            return kerror!(KernelError::BlockDeviceInit).into();
+        }
+
+        // No optional features are used.
+        write(DRIVER_FEATURES_SEL_OFFSET, 0);
+        write(DRIVER_FEATURES_OFFSET, 0);
+        if version == 2 {
+            write(DRIVER_FEATURES_SEL_OFFSET, 1);
+            write(DRIVER_FEATURES_OFFSET, FEATURE_VERSION_1);
+
+            status |= STATUS_FEATURES_OK;
+            write(STATUS_OFFSET, status);
+            if read(STATUS_OFFSET) & STATUS_FEATURES_OK == 0 {
+                return kerror!(KernelError::BlockDeviceInit).into();
+            }
+        } else {
+            write(GUEST_PAGE_SIZE_OFFSET, PAGE_SIZE as u32);
+        }
+
+        write(QUEUE_SEL_OFFSET, 0);
+        if (read(QUEUE_NUM_MAX_OFFSET) as usize) < QUEUE_SIZE {
+            return kerror!(KernelError::BlockDeviceInit).into();
+        }
+        write(QUEUE_NUM_OFFSET, QUEUE_SIZE as u32);
+
+        let (queue, queue_phys) = phys::alloc_zeroed().ok_or_else(|| {
+            kerror!(
+                KernelError::BlockDeviceInit,
+                kerror!(KernelError::OutOfPhysicalFrames)
+            )
+        })?;
+        let (buffer, buffer_phys) = match phys::alloc() {
+            Some(frame) => frame,
+            None => {
+                phys::free(queue_phys);
+                return kerror!(
+                    KernelError::BlockDeviceInit,
+                    kerror!(KernelError::OutOfPhysicalFrames)
+                )
+                .into();
+            }
+        };
+
+        let queue_phys_addr: usize = queue_phys.into();
+        if version == 2 {
+            let write_addr = |offset: usize, addr: usize| {
+                write(offset, addr as u32);
+                write(offset + 4, (addr >> 32) as u32);
+            };
+            write_addr(QUEUE_DESC_OFFSET, queue_phys_addr);
+            write_addr(QUEUE_DRIVER_OFFSET, queue_phys_addr + AVAIL_OFFSET);
+            write_addr(QUEUE_DEVICE_OFFSET, queue_phys_addr + USED_OFFSET);
+            write(QUEUE_READY_OFFSET, 1);
+        } else {
+            write(QUEUE_ALIGN_OFFSET, USED_ALIGN as u32);
+            write(QUEUE_PFN_OFFSET, (queue_phys_addr / PAGE_SIZE) as u32);
+        }
+
+        write(STATUS_OFFSET, status | STATUS_DRIVER_OK);
+
+        let capacity = read(CONFIG_OFFSET) as usize | (read(CONFIG_OFFSET + 4) as usize) << 32;
+
+        Ok(Some(VirtioBlock {
+            regs,
+            queue,
+            buffer,
+            buffer_phys,
+            queue_phys,
+            capacity,
+            avail_idx: 0,
+            used_idx: 0,
+        }))
+    }
+
+    fn read_reg(&self, offset: usize) -> u32 {
+        unsafe { (self.regs.base() + offset).as_ptr::<u32>().read_volatile() }
+    }
+
+    fn write_reg(&self, offset: usize, value: u32) {
+        unsafe {
+            (self.regs.base() + offset)
+                .as_mut_ptr::<u32>()
+                .write_volatile(value)
+        }
+    }
+
+    /// Transfer `len` bytes between the bounce frame and the device at
+    /// `sector`, then wait for the device to finish.
+    fn request(&mut self, kind: u32, sector: usize, len: usize) -> KernelResult<()> {
+        debug_assert!(len <= PAGE_SIZE);
+        let queue_phys: usize = self.queue_phys.into();
+
+        unsafe {
+            (self.queue + HEADER_OFFSET)
+                .as_mut_ptr::<RequestHeader>()
+                .write_volatile(RequestHeader {
+                    kind,
+                    reserved: 0,
+                    sector: sector as u64,
+                });
+            let status = (self.queue + STATUS_BYTE_OFFSET).as_mut_ptr::<u8>();
+            status.write_volatile(0xff);
+
+            // Header, data, then status.
+            let descriptors = self.queue.as_mut_ptr::<Descriptor>();
+            descriptors.write_volatile(Descriptor {
+                addr: (queue_phys + HEADER_OFFSET) as u64,
+                len: core::mem::size_of::<RequestHeader>() as u32,
+                flags: DESC_NEXT,
+                next: 1,
+            });
+            descriptors.add(1).write_volatile(Descriptor {
+                addr: usize::from(self.buffer_phys) as u64,
+                len: len as u32,
+                flags: DESC_NEXT | if kind == REQUEST_IN { DESC_WRITE } else { 0 },
+                next: 2,
+            });
+            descriptors.add(2).write_volatile(Descriptor {
+                addr: (queue_phys + STATUS_BYTE_OFFSET) as u64,
+                len: 1,
+                flags: DESC_WRITE,
+                next: 0,
+            });
+
+            let avail = &mut *(self.queue + AVAIL_OFFSET).as_mut_ptr::<Available>();
+            core::ptr::addr_of_mut!(avail.ring[self.avail_idx as usize % QUEUE_SIZE])
+                .write_volatile(0);
+            fence(Ordering::SeqCst);
+            self.avail_idx = self.avail_idx.wrapping_add(1);
+            core::ptr::addr_of_mut!(avail.idx).write_volatile(self.avail_idx);
+            fence(Ordering::SeqCst);
+
+            self.write_reg(QUEUE_NOTIFY_OFFSET, 0);
+
+            let used = &*(self.queue + USED_OFFSET).as_ptr::<Used>();
+            while core::ptr::addr_of!(used.idx).read_volatile() == self.used_idx {
+                core::hint::spin_loop();
+            }
+            fence(Ordering::SeqCst);
+            self.used_idx = self.used_idx.wrapping_add(1);
+
+            self.write_reg(INTERRUPT_ACK_OFFSET, self.read_reg(INTERRUPT_STATUS_OFFSET));
+
+            match status.read_volatile() {
+                0 => Ok(()),
+                _ => kerror!(KernelError::BlockIo).into(),
+            }
+        }
+    }
+}
+
+impl Drop for VirtioBlock {
+    /// Reset the device so it no longer uses the queue, then free the queue and
+    /// bounce frames. The registers are unmapped with `regs`.
+    fn drop(&mut self) {
+        self.write_reg(STATUS_OFFSET, 0);
+        // The reset is done once the device reads back a status of 0.
+        while self.read_reg(STATUS_OFFSET) != 0 {
+            core::hint::spin_loop();
+        }
+
+        unsafe {
+            phys::free(self.queue_phys);
+            phys::free(self.buffer_phys);
+        }
+    }
+}
+
+impl BlockDevice for VirtioBlock {
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn blocks(&self) -> usize {
+        self.capacity
+    }
+
+    fn read(&mut self, block: usize, buf: &mut [u8]) -> KernelResult<()> {
+        check_range(self, block, buf.len())?;
+
+        for (n, chunk) in buf.chunks_mut(PAGE_SIZE).enumerate() {
+            let sector = block + n * PAGE_SIZE / SECTOR_SIZE;
+            self.request(REQUEST_IN, sector, chunk.len())?;
+            unsafe {
+                chunk.copy_from_slice(core::slice::from_raw_parts(
+                    self.buffer.as_ptr::<u8>(),
+                    chunk.len(),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write(&mut self, block: usize, buf: &[u8]) -> KernelResult<()> {
+        check_range(self, block, buf.len())?;
+
+        for (n, chunk) in buf.chunks(PAGE_SIZE).enumerate() {
+            let sector = block + n * PAGE_SIZE / SECTOR_SIZE;
+            unsafe {
+                core::slice::from_raw_parts_mut(self.buffer.as_mut_ptr::<u8>(), chunk.len())
+                    .copy_from_slice(chunk);
+            }
+            self.request(REQUEST_OUT, sector, chunk.len())?;
+        }
+
+        Ok(())
+    }
+}
@@ -35,3 +35,28 @@ pub fn enable() {
         riscv::register::sstatus::set_sie();
     }
 }
+
+/// Interrupts disabled until the guard is dropped, when they are restored to
+/// how they were before.
+pub struct InterruptGuard {
+    enabled: bool,
+}
+
+impl InterruptGuard {
+    /// Disable interrupts, saving whether they were enabled.
+    #[inline]
+    pub fn disable() -> InterruptGuard {
+        let enabled = riscv::register::sstatus::read().sie();
+        disable();
+        InterruptGuard { enabled }
+    }
+}
+
+impl Drop for InterruptGuard {
+    #[inline]
+    fn drop(&mut self) {
+        if self.enabled {
+            enable();
+        }
+    }
+}
@@ -7,16 +7,14 @@
 //! on that IRQ, handle the request, mark it as completed, and finally return
 //! from the trap handler.
 
-use halogen_common::mem::{Address, PhysicalAddress, VirtualAddress, KIB};
+use halogen_common::mem::{PhysicalAddress, KIB};
 use lazy_static::lazy_static;
 use spin::Mutex;
 
 use crate::{
     critical_section,
-    mem::{
-        io::PLIC_BASE,
-        paging::{map, Permissions, Privilege, Scope},
-    },
+    log::*,
+    mem::io::{ioremap, Mmio, ReadOnly, ReadWrite, PLIC_BASE},
 };
 
 const INT_SOURCE_COUNT: usize = 1024;
@@ -58,158 +56,129 @@ pub fn set_threshold(threshold: u32) {
 /// Handle the next pending external interrupt mark it as complete. Returns
 /// `None` if there are no interrupts pending.
 pub fn handle_next() -> Option<usize> {
-    None
+    let irq = critical_section!({ PLIC.lock().claim(0) })?;
+
+    match unsafe { ISRS.get(irq as usize).copied().flatten() } {
+        Some(isr) => {
+            let status = isr();
+            if status != 0 {
+                warn!("ISR for IRQ {} failed ({})", irq, status);
+            }
+        }
+        None => warn!("No ISR for IRQ {}", irq),
+    }
+
+    critical_section!({ PLIC.lock().complete(0, irq) });
+    Some(irq as usize)
 }
 
 // One word per interrupt source.
-const PRIORITIES_OFFSET: isize = 0x0;
+const PRIORITIES_OFFSET: usize = 0x0;
 const PRIORITIES_LEN: usize = INT_SOURCE_COUNT * 4;
 
 // One bit per interrupt source.
-const PENDING_OFFSET: isize = 0x1000;
+const PENDING_OFFSET: usize = 0x1000;
 const PENDING_LEN: usize = INT_SOURCE_COUNT / 8;
 
 // One bit per interrupt source per hart context.
-const ENABLES_OFFSET: isize = 0x2000;
+const ENABLES_OFFSET: usize = 0x2000;
 const ENABLES_LEN: usize = (INT_SOURCE_COUNT / 8) * CONTEXT_COUNT;
 
 // 4K per context.
-const CONTEXT_OFFSET: isize = 0x200000;
+const CONTEXT_OFFSET: usize = 0x200000;
 const CONTEXT_LEN: usize = 4 * KIB * CONTEXT_COUNT;
 
 /// A function that can be called as an ISR. Accepts no arguments and returns 0
 /// on success.
 pub type InterruptRoutine = fn() -> usize;
 
-/// Platform-level interrupt controller per the RISC-V PLIC spec. The fields are
-/// contiguous in physical memory, but this does not necessarily hold after the
-/// struct is created and the fields are mapped.
-struct Plic {
-    priorities: *mut u8,
-    pending: *mut u8,
-    enables: *mut u8,
-    contexts: *mut u8,
+/// Bits for each interrupt source, packed into words.
+type SourceBits<R> = [R; INT_SOURCE_COUNT / 32];
+
+/// Registers of a hart context.
+#[repr(C)]
+struct Context {
+    threshold: ReadWrite<u32>,
+    /// Claims an interrupt when read, and completes one when written.
+    claim: ReadWrite<u32>,
+    _reserved: [u8; 4 * KIB - 8],
 }
 
-unsafe impl Sync for Plic {}
-unsafe impl Send for Plic {}
+/// Platform-level interrupt controller per the RISC-V PLIC spec. The sections
+/// are contiguous in physical memory, but each is mapped on its own.
+struct Plic {
+    priorities: Mmio,
+    pending: Mmio,
+    enables: Mmio,
+    contexts: Mmio,
+}
 
 impl Plic {
     /// Map the PLIC at the base physical address.
     unsafe fn from_phys(base: PhysicalAddress) -> Plic {
-        // For each section (priorites, enables, etc.) map the MMIO address and create a
-        // slice
-        let priorities = map(
-            None,
-            Some(base.add_offset(PRIORITIES_OFFSET)),
-            PRIORITIES_LEN,
-            Permissions::ReadWrite,
-            Scope::Global,
-            Privilege::Kernel,
-        )
-        .expect("failed to map PLIC priorities");
-
-        let pending = map(
-            None,
-            Some(base.add_offset(PENDING_OFFSET)),
-            PENDING_LEN,
-            Permissions::ReadOnly,
-            Scope::Global,
-            Privilege::Kernel,
-        )
-        .expect("failed to map PLIC pending");
-
-        let enables = map(
-            None,
-            Some(base.add_offset(ENABLES_OFFSET)),
-            ENABLES_LEN,
-            Permissions::ReadWrite,
-            Scope::Global,
-            Privilege::Kernel,
-        )
-        .expect("failed to map PLIC enables");
-
-        let contexts = map(
-            None,
-            Some(base.add_offset(CONTEXT_OFFSET)),
-            CONTEXT_LEN,
-            Permissions::ReadWrite,
-            Scope::Global,
-            Privilege::Kernel,
-        )
-        .expect("failed to map PLIC enables");
-
-        Plic::from_virt(priorities, pending, enables, contexts)
+        Plic {
+            priorities: ioremap(base + PRIORITIES_OFFSET, PRIORITIES_LEN)
+                .expect("failed to map PLIC priorities"),
+            pending: ioremap(base + PENDING_OFFSET, PENDING_LEN)
+                .expect("failed to map PLIC pending"),
+            enables: ioremap(base + ENABLES_OFFSET, ENABLES_LEN)
+                .expect("failed to map PLIC enables"),
+            contexts: ioremap(base + CONTEXT_OFFSET, CONTEXT_LEN)
+                .expect("failed to map PLIC contexts"),
+        }
     }
 
-    /// Construct a PLIC from mapped regions.
-    unsafe fn from_virt(
-        priorities: VirtualAddress,
-        pending: VirtualAddress,
-        enables: VirtualAddress,
-        contexts: VirtualAddress,
-    ) -> Plic {
-        Plic {
-            priorities: priorities.as_mut_ptr(),
-            pending: pending.as_mut_ptr(),
-            enables: enables.as_mut_ptr(),
-            contexts: contexts.as_mut_ptr(),
+    fn priorities(&self) -> &[ReadWrite<u32>; INT_SOURCE_COUNT] {
+        unsafe { self.priorities.block(0) }
+    }
+
+    fn pending(&self) -> &SourceBits<ReadOnly<u32>> {
+        unsafe { self.pending.block(0) }
+    }
+
+    fn enables(&self, hart: usize) -> &SourceBits<ReadWrite<u32>> {
+        unsafe {
+            &self
+                .enables
+                .block::<[SourceBits<ReadWrite<u32>>; CONTEXT_COUNT]>(0)[hart]
         }
     }
 
+    fn context(&self, hart: usize) -> &Context {
+        unsafe { &self.contexts.block::<[Context; CONTEXT_COUNT]>(0)[hart] }
+    }
+
     /// Enable an interrupt source on hart.
     fn set_enabled(&self, hart: usize, irq: usize, enabled: bool) {
-        // Two
-        let index = (irq & 0xFFFF_FF00) >> 8;
-        let offset = irq & 0xFF;
-
-        unsafe {
-            let entry = ((self.enables as *mut [u32; INT_SOURCE_COUNT / 8]).add(hart) as *mut u8)
-                .add(index);
+        let bit = 1 << (irq % 32);
+        self.enables(hart)[irq / 32].modify(|word| if enabled { word | bit } else { word & !bit });
+    }
 
-            if enabled {
-                entry.write_volatile(entry.read_volatile() | (1 << offset));
-            } else {
-                entry.write_volatile(entry.read_volatile() & !(1 << offset));
-            }
-        }
+    /// Returns true if an interrupt source is pending.
+    fn is_pending(&self, irq: usize) -> bool {
+        self.pending()[irq / 32].read() & (1 << (irq % 32)) != 0
     }
 
     /// Set the priority for an interrupt source.
     fn set_priority(&self, irq: usize, priority: u32) {
-        unsafe {
-            (self.priorities as *mut u32)
-                .add(irq)
-                .write_volatile(priority);
-        }
+        self.priorities()[irq].write(priority);
     }
 
     /// Set the priority threshold for a hart.
     fn set_threshold(&self, hart: usize, threshold: u32) {
-        unsafe {
-            ((self.contexts as *mut [u8; 4 * KIB]).add(hart) as *mut u32).write_volatile(threshold);
-        }
+        self.context(hart).threshold.write(threshold);
     }
 
     /// Claim an interrupt for a hart.
     fn claim(&self, hart: usize) -> Option<u32> {
-        unsafe {
-            match ((self.contexts as *mut [u8; 4 * KIB]).add(hart) as *mut u32)
-                .add(1)
-                .read_volatile()
-            {
-                0 => None,
-                id => Some(id),
-            }
+        match self.context(hart).claim.read() {
+            0 => None,
+            id => Some(id),
         }
     }
 
     /// Complete an interrupt on a hart.
     fn complete(&self, hart: usize, isr: u32) {
-        unsafe {
-            ((self.contexts as *mut [u8; 4 * KIB]).add(hart) as *mut u32)
-                .add(1)
-                .write_volatile(isr)
-        }
+        self.context(hart).claim.write(isr)
     }
 }
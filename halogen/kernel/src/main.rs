@@ -22,7 +22,7 @@
 extern crate alloc;
 
 #[cfg(not(target_arch = "riscv64"))]
-core::compile_error!("rv64gc+sv39 is the only supported platform");
+core::compile_error!("rv64gc is the only supported platform");
 
 /// Unit and integration tests.
 #[cfg(test)]
@@ -37,6 +37,8 @@ mod panic;
 pub mod arch;
 /// Kernel error type.
 pub mod error;
+/// Async tasks for kernel services.
+pub mod future;
 /// I/O devices.
 pub mod io;
 /// Interrupt request configuration.
@@ -47,10 +49,14 @@ pub mod log;
 pub mod mem;
 /// Interfacing with OpenSBI.
 pub mod sbi;
+/// Blocking synchronization primitives.
+pub mod sync;
 /// System call definitions.
 pub mod syscall;
 /// Processes and threads.
 pub mod task;
+/// Kernel timers.
+pub mod timer;
 /// Trap handler.
 pub mod trap;
 
@@ -63,6 +69,7 @@ const LOG_LEVEL: log::Level = log::Level::Trace;
 /// - This is only called once by the bootstrap code.
 #[allow(named_asm_labels)]
 #[repr(align(4))]
+#[link_section = ".init.text"]
 pub unsafe extern "C" fn kinit() -> ! {
     mem::heap::init();
 
@@ -73,6 +80,9 @@ pub unsafe extern "C" fn kinit() -> ! {
     // Until now, we've been using SBI calls to print.
     io::uart::use_as_console();
 
+    // Swap to a block device if there is one.
+    mem::swap::init();
+
     // Setup and enable trap handler.
     irq::enable();
 
@@ -82,6 +92,12 @@ pub unsafe extern "C" fn kinit() -> ! {
 
 /// Main thread for the kernel.
 extern "C" fn kmain(_: usize) -> isize {
+    // Boot is over and its stack abandoned, so lock down the kernel image.
+    unsafe { mem::protect::finish_boot() };
+
+    // Poll async tasks of kernel services.
+    future::start(1).expect("Failed to start async workers");
+
     #[cfg(test)]
     crate::test_harness();
 
@@ -1,10 +1,54 @@
-use crate::mem::paging::PageTable;
+use core::ptr::write_bytes;
+
+use halogen_common::mem::{
+    Address, FrameKind, MemoryAccount, MemoryLimits, PhysicalAddress, Resource, Segment,
+    VirtualAddress, MIB,
+};
+
+use crate::{
+    error::{KernelError, KernelResult},
+    kerror,
+    mem::{
+        paging::{
+            mode, svnapot, Level, PageTable, Permissions, Privilege, Scope, NAPOT_SIZE, PAGE_SIZE,
+        },
+        phys,
+        regions::Region,
+        swap,
+    },
+};
+
+/// Limits given to each new address-space.
+pub const DEFAULT_MEMORY_LIMITS: MemoryLimits = MemoryLimits {
+    resident: 64 * MIB,
+    page_tables: MIB,
+    heap: MIB,
+};
+
+/// Region of each user address-space in which the kernel picks addresses for
+/// anonymous mappings.
+pub const ANON_REGION: Segment<VirtualAddress> = Segment::new(
+    VirtualAddress(0x20_0000_0000),
+    VirtualAddress(0x30_0000_0000),
+);
 
 /// A virtual address space isolated to a single process.
-#[derive(Debug, Clone, Copy, Default)]
+#[derive(Debug, Clone, Copy)]
 pub struct AddressSpace {
     pub id: usize,
     pub root: PageTable,
+    /// Memory charged to the address-space.
+    pub account: MemoryAccount,
+}
+
+impl Default for AddressSpace {
+    fn default() -> AddressSpace {
+        AddressSpace {
+            id: 0,
+            root: PageTable::default(),
+            account: MemoryAccount::new(DEFAULT_MEMORY_LIMITS),
+        }
+    }
 }
 
 impl AddressSpace {
@@ -13,6 +57,280 @@ impl AddressSpace {
         AddressSpace {
             id,
             root: PageTable::from_kernel_root(),
+            account: MemoryAccount::new(DEFAULT_MEMORY_LIMITS),
+        }
+    }
+
+    /// Map a user page to a frame, charging the page and any page tables
+    /// allocated for it. Fails without mapping if either is over its limit.
+    pub fn map(
+        &mut self,
+        virt_addr: VirtualAddress,
+        phys_addr: PhysicalAddress,
+        perms: Permissions,
+    ) -> KernelResult<()> {
+        self.map_charged(PAGE_SIZE, |root, owner| {
+            root.map(
+                virt_addr,
+                phys_addr,
+                Level::PAGE,
+                perms,
+                Scope::Local,
+                Privilege::User,
+                owner,
+            )
+        })
+    }
+
+    /// Map a 64 KiB run of user pages to a run of frames as one Svnapot page,
+    /// charging like `map`. Both addresses must be aligned to `NAPOT_SIZE`.
+    pub fn map_napot(
+        &mut self,
+        virt_addr: VirtualAddress,
+        phys_addr: PhysicalAddress,
+        perms: Permissions,
+    ) -> KernelResult<()> {
+        self.map_charged(NAPOT_SIZE, |root, owner| {
+            root.map_napot(
+                virt_addr,
+                phys_addr,
+                perms,
+                Scope::Local,
+                Privilege::User,
+                owner,
+            )
+        })
+    }
+
+    /// Charge `size` bytes of resident memory and the page tables `f` might
+    /// allocate, then call `f` to do the mapping. `f` returns the number of
+    /// tables it allocated.
+    fn map_charged(
+        &mut self,
+        size: usize,
+        f: impl FnOnce(&mut PageTable, u16) -> KernelResult<usize>,
+    ) -> KernelResult<()> {
+        // At most one table is needed for each level below the root. All of
+        // them are charged up front, and what isn't allocated is returned.
+        let tables = mode().levels() - 1;
+        if !self
+            .account
+            .try_charge(Resource::PageTables, tables * PAGE_SIZE)
+        {
+            return kerror!(KernelError::OutOfMemory).into();
+        }
+        if !self.account.try_charge(Resource::Resident, size) {
+            self.account
+                .uncharge(Resource::PageTables, tables * PAGE_SIZE);
+            return kerror!(KernelError::OutOfMemory).into();
+        }
+
+        match f(&mut self.root, self.id as u16) {
+            Ok(allocated) => {
+                self.account
+                    .uncharge(Resource::PageTables, (tables - allocated) * PAGE_SIZE);
+                Ok(())
+            }
+            Err(why) => {
+                self.account.uncharge(Resource::Resident, size);
+                self.account
+                    .uncharge(Resource::PageTables, tables * PAGE_SIZE);
+                kerror!(KernelError::InvalidMapping, why).into()
+            }
+        }
+    }
+
+    /// Remove the mapping of a user page and return the frame it mapped to.
+    /// The frame is not freed.
+    pub fn unmap(&mut self, virt_addr: VirtualAddress) -> Option<PhysicalAddress> {
+        let phys_addr = self.root.unmap(virt_addr)?;
+        self.account.uncharge(Resource::Resident, PAGE_SIZE);
+        Some(phys_addr)
+    }
+
+    /// Allocate a zeroed frame and map it at a user page. Returns the kernel
+    /// address of the frame.
+    pub fn alloc(
+        &mut self,
+        virt_addr: VirtualAddress,
+        perms: Permissions,
+    ) -> KernelResult<VirtualAddress> {
+        if !self.account.can_charge(Resource::Resident, PAGE_SIZE) {
+            return kerror!(KernelError::OutOfMemory).into();
+        }
+
+        let (frame_virt, frame_phys) = phys::alloc_zeroed_for(FrameKind::User, self.id as u16)
+            .ok_or_else(|| kerror!(KernelError::OutOfPhysicalFrames))?;
+
+        if let Err(why) = self.map(virt_addr, frame_phys, perms) {
+            unsafe { phys::free(frame_phys) };
+            return Err(why);
+        }
+
+        Ok(frame_virt)
+    }
+
+    /// Allocate zeroed frames and map them at user pages from `virt_addr`, for
+    /// up to `size` bytes. If Svnapot is enabled and `virt_addr` starts an
+    /// aligned 64 KiB run within `size`, the run is backed by contiguous frames
+    /// and mapped as one page where the allocator has them. Otherwise, a single
+    /// page is mapped. Returns the kernel address of the frames and the number
+    /// of bytes mapped.
+    pub fn alloc_run(
+        &mut self,
+        virt_addr: VirtualAddress,
+        size: usize,
+        perms: Permissions,
+    ) -> KernelResult<(VirtualAddress, usize)> {
+        if svnapot()
+            && size >= NAPOT_SIZE
+            && virt_addr.is_aligned_to(NAPOT_SIZE)
+            && self.account.can_charge(Resource::Resident, NAPOT_SIZE)
+        {
+            if let Some((run_virt, run)) = phys::alloc_contiguous_for(
+                NAPOT_SIZE / PAGE_SIZE,
+                NAPOT_SIZE,
+                FrameKind::User,
+                self.id as u16,
+            ) {
+                unsafe { write_bytes(run_virt.as_mut_ptr::<u8>(), 0, NAPOT_SIZE) };
+
+                if let Err(why) = self.map_napot(virt_addr, run, perms) {
+                    for offset in (0..NAPOT_SIZE).step_by(PAGE_SIZE) {
+                        unsafe { phys::free(run + offset) };
+                    }
+                    return Err(why);
+                }
+                return Ok((run_virt, NAPOT_SIZE));
+            }
+        }
+
+        Ok((self.alloc(virt_addr, perms)?, PAGE_SIZE))
+    }
+
+    /// Unmap a user page and drop its reference to the frame, or release its
+    /// swap slot if it is swapped out.
+    pub fn free(&mut self, virt_addr: VirtualAddress) {
+        if let Some(frame) = self.unmap(virt_addr) {
+            unsafe { phys::free(frame) };
+        } else if let Some(slot) = self
+            .root
+            .leaf_entry(virt_addr)
+            .and_then(|entry| entry.take_swap_slot())
+        {
+            swap::release(slot);
+        }
+    }
+
+    /// Returns true if a user page is mapped or swapped out.
+    pub fn is_mapped(&self, virt_addr: VirtualAddress) -> bool {
+        self.root.translate(virt_addr).is_some()
+            || matches!(self.root.leaf_entry(virt_addr), Some(entry) if entry.swap_slot().is_some())
+    }
+
+    /// Allocate and map zeroed frames for `size` bytes. If no address is
+    /// provided, one is chosen from `ANON_REGION`. Returns the base of the
+    /// mapping.
+    pub fn map_anonymous(
+        &mut self,
+        addr: Option<VirtualAddress>,
+        size: usize,
+        perms: Permissions,
+    ) -> KernelResult<VirtualAddress> {
+        if !self.account.can_charge(Resource::Resident, size) {
+            return kerror!(KernelError::OutOfMemory).into();
+        }
+
+        let base = self.placement(ANON_REGION, addr, size)?;
+
+        let mut offset = 0;
+        while offset < size {
+            match self.alloc_run(base + offset, size - offset, perms) {
+                Ok((_, mapped)) => offset += mapped,
+                Err(why) => {
+                    // Roll back the pages mapped so far.
+                    self.unmap_anonymous(base, offset);
+                    return Err(why);
+                }
+            }
+        }
+
+        Ok(base)
+    }
+
+    /// Unmap and free `size` bytes of anonymous memory at `base`.
+    pub fn unmap_anonymous(&mut self, base: VirtualAddress, size: usize) {
+        for offset in (0..size).step_by(PAGE_SIZE) {
+            self.free(base + offset);
+        }
+    }
+
+    /// Unmap everything in the user half, dropping references to the mapped
+    /// frames and freeing the page tables.
+    pub fn release(&mut self) {
+        let (frames, tables) = unsafe { self.root.release_user() };
+        self.account
+            .uncharge(Resource::Resident, frames * PAGE_SIZE);
+        self.account
+            .uncharge(Resource::PageTables, tables * PAGE_SIZE);
+    }
+
+    /// Returns true if `size` bytes at `base` are page aligned, in the user
+    /// half, and not yet mapped. An empty range is never available.
+    pub fn is_available(&self, base: VirtualAddress, size: usize) -> bool {
+        let last = match size
+            .checked_sub(1)
+            .and_then(|offset| usize::from(base).checked_add(offset))
+        {
+            Some(last) => VirtualAddress(last),
+            None => return false,
+        };
+
+        base.is_aligned_to(PAGE_SIZE)
+            && matches!(Region::from(base), Region::User)
+            && matches!(Region::from(last), Region::User)
+            && (0..size)
+                .step_by(PAGE_SIZE)
+                .all(|offset| !self.is_mapped(base + offset))
+    }
+
+    /// Find the lowest unmapped range of `size` bytes in a region.
+    pub fn find_free(
+        &self,
+        region: Segment<VirtualAddress>,
+        size: usize,
+    ) -> Option<VirtualAddress> {
+        let mut base = region.start;
+
+        while base + size <= region.end {
+            match (0..size)
+                .step_by(PAGE_SIZE)
+                .find(|&offset| self.is_mapped(base + offset))
+            {
+                // Skip past the mapped page.
+                Some(offset) => base = base + offset + PAGE_SIZE,
+                None => return Some(base),
+            }
+        }
+
+        None
+    }
+
+    /// Choose and validate the address of a new mapping, picking one from
+    /// `region` if none is provided.
+    pub fn placement(
+        &self,
+        region: Segment<VirtualAddress>,
+        addr: Option<VirtualAddress>,
+        size: usize,
+    ) -> KernelResult<VirtualAddress> {
+        match addr {
+            Some(addr) if self.is_available(addr, size) => Ok(addr),
+            Some(_) => kerror!(KernelError::InvalidMapping).into(),
+            None => {
+                self.find_free(region, size)
+                    .ok_or_else(|| kerror!(KernelError::OutOfVirtualAddresses))
+            }
         }
     }
 }
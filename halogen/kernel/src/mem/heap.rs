@@ -1,15 +1,24 @@
 use alloc::alloc::{GlobalAlloc, Layout};
+use core::sync::atomic::{AtomicU8, Ordering};
 
+#[cfg(feature = "heap-track")]
+use halogen_common::mem::alloc::AllocationTracker;
+#[cfg(not(feature = "debug-heap"))]
+use halogen_common::mem::alloc::FreeListAllocator;
+#[cfg(feature = "debug-heap")]
+use halogen_common::mem::alloc::{DebugAllocator, TRACE_DEPTH};
 use halogen_common::mem::{
-    alloc::{AllocatorStats, FreeListAllocator},
+    alloc::{AllocatorStats, Snapshot, Tag},
     Segment, VirtualAddress, MIB,
 };
 use spin::Mutex;
 
+#[cfg(any(feature = "debug-heap", feature = "heap-track"))]
+use crate::arch::backtrace;
 use crate::{
-    fwprintln, kprintln,
+    critical_section, fwprintln, kprintln,
     mem::{
-        paging::{map, Permissions, Privilege, Scope},
+        paging::{map, Permissions, Privilege, Scope, KERNEL_ASID},
         regions::HEAP,
     },
 };
@@ -17,12 +26,36 @@ use crate::{
 const START_SIZE: usize = 32 * MIB;
 const MIN_ALLOC: usize = 64;
 
-/// The heap allocates space for dynamic data structures using a linked-list
-/// allocator. This is a thin wrapper around the `FreeListAllocator` intended to
-/// act the `GlobalAlloc` for the `alloc` crate.
+#[cfg(not(feature = "debug-heap"))]
+type Allocator = FreeListAllocator<'static, MIN_ALLOC>;
+/// With the `debug-heap` feature, allocations are guarded by red zones and
+/// freed memory is poisoned.
+#[cfg(feature = "debug-heap")]
+type Allocator = DebugAllocator<'static, MIN_ALLOC>;
+
+/// Number of live allocations that can be tracked.
+#[cfg(feature = "heap-track")]
+const TRACK_CAPACITY: usize = 4096;
+/// Return addresses walked to find the caller of an allocation. The first
+/// frames belong to the allocation shims of the `alloc` crate.
+#[cfg(feature = "heap-track")]
+const CALLER_DEPTH: usize = 3;
+
+/// Tag given to new allocations. It belongs to the running thread: the executor
+/// saves it with a thread's context and restores it when switching back.
+static CURRENT_TAG: AtomicU8 = AtomicU8::new(Tag::Untagged as u8);
+
+/// The heap allocates space for dynamic data structures using a segregated-fit
+/// allocator, which resizes allocations in place when it can. This is a thin
+/// wrapper around the `FreeListAllocator` intended to act the `GlobalAlloc` for
+/// the `alloc` crate. Interrupts are disabled while it is locked, so interrupt
+/// handlers can allocate and free, e.g. when waking a task.
 #[derive(Debug)]
 struct HeapAllocator {
-    allocator: Mutex<Option<FreeListAllocator<'static, MIN_ALLOC>>>,
+    allocator: Mutex<Option<Allocator>>,
+    /// With the `heap-track` feature, every live allocation is recorded.
+    #[cfg(feature = "heap-track")]
+    tracker: Mutex<AllocationTracker<TRACK_CAPACITY>>,
 }
 
 impl HeapAllocator {
@@ -30,6 +63,8 @@ impl HeapAllocator {
     const fn new_uninit() -> HeapAllocator {
         HeapAllocator {
             allocator: Mutex::new(None),
+            #[cfg(feature = "heap-track")]
+            tracker: Mutex::new(AllocationTracker::new()),
         }
     }
 
@@ -44,9 +79,58 @@ impl HeapAllocator {
             Permissions::ReadWrite,
             Scope::Global,
             Privilege::Kernel,
+            KERNEL_ASID,
         )
         .unwrap();
-        self.allocator = Mutex::new(Some(FreeListAllocator::new(segment.as_mut_slice())));
+        self.allocator = Mutex::new(Some(Allocator::new(segment.as_mut_slice())));
+    }
+
+    /// Record a new allocation with the current tag.
+    #[inline(always)]
+    fn track_alloc(&self, ptr: *mut u8, layout: Layout) {
+        #[cfg(feature = "heap-track")]
+        if !ptr.is_null() {
+            let caller = backtrace::<CALLER_DEPTH>()
+                .iter()
+                .rev()
+                .copied()
+                .find(|&addr| addr != 0)
+                .unwrap_or(0);
+            let tag = current_tag();
+
+            self.tracker
+                .lock()
+                .insert(ptr as usize, layout, caller, tag);
+        }
+
+        #[cfg(not(feature = "heap-track"))]
+        let _ = (ptr, layout);
+    }
+
+    /// Move the record of a resized allocation, keeping its tag and caller.
+    #[inline(always)]
+    fn track_realloc(&self, ptr: *mut u8, new_ptr: *mut u8, new_size: usize) {
+        #[cfg(feature = "heap-track")]
+        {
+            let mut tracker = self.tracker.lock();
+            if let Some(alloc) = tracker.remove(ptr as usize) {
+                let layout = Layout::from_size_align(new_size, alloc.layout.align()).unwrap();
+                tracker.insert(new_ptr as usize, layout, alloc.caller, alloc.tag);
+            }
+        }
+
+        #[cfg(not(feature = "heap-track"))]
+        let _ = (ptr, new_ptr, new_size);
+    }
+
+    /// Remove the record of a freed allocation.
+    #[inline(always)]
+    fn track_dealloc(&self, ptr: *mut u8) {
+        #[cfg(feature = "heap-track")]
+        self.tracker.lock().remove(ptr as usize);
+
+        #[cfg(not(feature = "heap-track"))]
+        let _ = ptr;
     }
 }
 
@@ -59,17 +143,68 @@ impl core::fmt::Display for HeapAllocator {
     }
 }
 
+#[cfg(not(feature = "debug-heap"))]
 unsafe impl GlobalAlloc for HeapAllocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        match self.allocator.lock().as_mut() {
-            Some(allocator) => allocator.alloc(layout),
-            None => core::ptr::null_mut(),
-        }
+        critical_section!({
+            let ptr = match self.allocator.lock().as_mut() {
+                Some(allocator) => allocator.alloc(layout),
+                None => core::ptr::null_mut(),
+            };
+            self.track_alloc(ptr, layout);
+            ptr
+        })
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        if let Some(allocator) = self.allocator.lock().as_mut() {
-            allocator.dealloc(ptr, layout)
+        critical_section!({
+            self.track_dealloc(ptr);
+            if let Some(allocator) = self.allocator.lock().as_mut() {
+                allocator.dealloc(ptr, layout)
+            }
+        })
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        critical_section!({
+            let new_ptr = match self.allocator.lock().as_mut() {
+                Some(allocator) => allocator.realloc(ptr, layout, new_size),
+                None => core::ptr::null_mut(),
+            };
+            if !new_ptr.is_null() {
+                self.track_realloc(ptr, new_ptr, new_size);
+            }
+            new_ptr
+        })
+    }
+}
+
+#[cfg(feature = "debug-heap")]
+unsafe impl GlobalAlloc for HeapAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        // Record the call site before taking the lock.
+        let trace = backtrace::<TRACE_DEPTH>();
+        critical_section!({
+            let ptr = match self.allocator.lock().as_mut() {
+                Some(allocator) => allocator.alloc(layout, trace),
+                None => core::ptr::null_mut(),
+            };
+            self.track_alloc(ptr, layout);
+            ptr
+        })
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let result = critical_section!({
+            self.track_dealloc(ptr);
+            match self.allocator.lock().as_mut() {
+                Some(allocator) => allocator.dealloc(ptr, layout),
+                None => Ok(()),
+            }
+        });
+
+        if let Err(bad) = result {
+            panic!("Heap corruption: {}", bad);
         }
     }
 }
@@ -105,6 +240,73 @@ pub fn dump() {
     }
 }
 
+/// Restores the previous allocation tag when dropped.
+#[must_use = "allocations are only tagged while the guard is alive"]
+pub struct TagGuard(u8);
+
+impl Drop for TagGuard {
+    fn drop(&mut self) {
+        CURRENT_TAG.store(self.0, Ordering::Relaxed);
+    }
+}
+
+/// Tag allocations made by the calling thread until the returned guard is
+/// dropped. Tags only matter with the `heap-track` feature.
+pub fn tag(tag: Tag) -> TagGuard {
+    TagGuard(CURRENT_TAG.swap(tag as u8, Ordering::Relaxed))
+}
+
+/// Get the tag of the running thread's allocations.
+pub fn current_tag() -> Tag {
+    Tag::from_index(CURRENT_TAG.load(Ordering::Relaxed) as usize)
+}
+
+/// Set the tag of the running thread's allocations, when switching threads.
+pub fn set_current_tag(tag: Tag) {
+    CURRENT_TAG.store(tag as u8, Ordering::Relaxed);
+}
+
+/// Get the usage of the heap grouped by tag.
+#[cfg(feature = "heap-track")]
+pub fn snapshot() -> Option<Snapshot> {
+    unsafe { Some(GLOBAL_ALLOCATOR.tracker.lock().snapshot()) }
+}
+
+/// Heap usage is not tracked without the `heap-track` feature.
+#[cfg(not(feature = "heap-track"))]
+pub fn snapshot() -> Option<Snapshot> {
+    None
+}
+
+/// Print the usage of the heap grouped by tag to the console.
+pub fn summary() {
+    match snapshot() {
+        Some(snapshot) => kprintln!("Heap usage by tag:\n{}", snapshot),
+        None => kprintln!("Heap tracking is disabled"),
+    }
+}
+
+/// Print the allocations made since `earlier` that are still live, then the
+/// change in usage of each tag.
+pub fn report_since(earlier: &Snapshot) {
+    #[cfg(feature = "heap-track")]
+    unsafe {
+        for alloc in GLOBAL_ALLOCATOR.tracker.lock().since(earlier) {
+            kprintln!(
+                "  {:#x}: {} bytes ({}) from {:#x}",
+                alloc.ptr,
+                alloc.layout.size(),
+                alloc.tag.name(),
+                alloc.caller
+            );
+        }
+    }
+
+    if let Some(now) = snapshot() {
+        kprintln!("Heap usage since snapshot:\n{}", now.diff(earlier));
+    }
+}
+
 /// Initialize the heap allocator.
 ///
 /// # Safety
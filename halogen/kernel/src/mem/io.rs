@@ -1,11 +1,294 @@
-//! Physical addresses of memory-mapped I/O.
+//! Memory-mapped I/O. Device registers are mapped into the kernel with
+//! `ioremap`, which returns an `Mmio` that keeps the mapping alive. Mappings
+//! are shared: remapping registers that are already mapped reuses the existing
+//! virtual range, registers that partly overlap a mapping extend it, and the
+//! mapping is unmapped when its last `Mmio` is dropped.
+//!
+//! Drivers declare their registers as `#[repr(C)]` structs of `ReadOnly`,
+//! `WriteOnly`, and `ReadWrite` fields, and get a reference to one with
+//! `Mmio::block`. Every access to a register is volatile.
 
-use halogen_common::mem::PhysicalAddress;
+use alloc::vec::Vec;
+use core::cell::UnsafeCell;
+
+use halogen_common::{
+    align_up,
+    mem::{alloc::Tag, Address, PhysicalAddress, Segment, VirtualAddress},
+};
+use spin::Mutex;
+
+use crate::{
+    error::KernelResult,
+    log::*,
+    mem::{
+        heap,
+        paging::{
+            flush_tlb, map, unmap, Permissions, Privilege, Scope, KERNEL_ASID, PAGE_MASK, PAGE_SIZE,
+        },
+        virt_alloc::virt_addr_free,
+    },
+};
 
 /// Base address of the UART device.
 pub const UART_BASE: PhysicalAddress = PhysicalAddress(0x1000_0000);
-/// Size in pages of the UART memory map.
-pub const UART_SIZE: usize = 1;
+/// Size in bytes of the UART registers.
+pub const UART_SIZE: usize = 8;
 
 /// Base PLIC device.
 pub const PLIC_BASE: PhysicalAddress = PhysicalAddress(0x0C00_0000);
+
+/// Base address of the first virtio MMIO transport.
+pub const VIRTIO_BASE: PhysicalAddress = PhysicalAddress(0x1000_1000);
+/// Number of virtio MMIO transports.
+pub const VIRTIO_COUNT: usize = 8;
+/// Distance between consecutive virtio MMIO transports.
+pub const VIRTIO_STRIDE: usize = 0x1000;
+
+static MAPPINGS: Mutex<Vec<Mapping>> = Mutex::new(Vec::new());
+
+/// Pages of device memory mapped into the kernel. No two mappings overlap.
+struct Mapping {
+    phys: Segment<PhysicalAddress>,
+    virt: VirtualAddress,
+    /// Virtual ranges of the mappings this one was extended from. They stay
+    /// mapped for the `Mmio` in them, and are unmapped with this one.
+    retired: Vec<Segment<VirtualAddress>>,
+    /// Number of `Mmio` in the mapping.
+    users: usize,
+}
+
+impl Mapping {
+    /// Get the virtual address of `phys_addr` in the mapping.
+    fn to_virt(&self, phys_addr: PhysicalAddress) -> VirtualAddress {
+        self.virt + (phys_addr - self.phys.start)
+    }
+
+    /// Returns true if the mapping shares a page with `pages`.
+    fn overlaps(&self, pages: Segment<PhysicalAddress>) -> bool {
+        self.phys.start < pages.end && pages.start < self.phys.end
+    }
+
+    /// Get every virtual range of the mapping, the current one first.
+    fn windows(&self) -> impl Iterator<Item = Segment<VirtualAddress>> + '_ {
+        core::iter::once(Segment::from_size(self.virt, self.phys.size()))
+            .chain(self.retired.iter().copied())
+    }
+
+    /// Map the pages at the current virtual range.
+    unsafe fn map(&self) -> KernelResult<()> {
+        map(
+            Some(self.virt),
+            Some(self.phys.start),
+            self.phys.size(),
+            Permissions::ReadWrite,
+            Scope::Global,
+            Privilege::Kernel,
+            KERNEL_ASID,
+        )?;
+        Ok(())
+    }
+
+    /// Unmap every virtual range of the mapping.
+    unsafe fn unmap(&self) -> KernelResult<()> {
+        let result = self.windows().try_for_each(|window| unmap(window));
+        flush_tlb();
+        result
+    }
+}
+
+/// Map `len` bytes of device registers at `phys_addr` into the kernel. If they
+/// are already in a mapping, it is shared. If they are partly in one or more
+/// mappings, those are replaced by one that covers them all.
+///
+/// # Safety
+///
+/// - The physical range must be device memory, not RAM that might be used by
+///   the kernel.
+pub unsafe fn ioremap(phys_addr: PhysicalAddress, len: usize) -> KernelResult<Mmio> {
+    let start = usize::from(phys_addr);
+    let pages: Segment<PhysicalAddress> =
+        Segment::from(start & PAGE_MASK..align_up!(start + len, PAGE_SIZE));
+
+    let _tag = heap::tag(Tag::Driver);
+    let mut mappings = MAPPINGS.lock();
+
+    if let Some(mapping) = mappings.iter_mut().find(|m| m.phys.encapsulates(pages)) {
+        // A mapping that failed to unmap may have lost some of its pages.
+        if mapping.users == 0 {
+            mapping.map()?;
+        }
+        mapping.users += 1;
+
+        return Ok(Mmio {
+            phys: phys_addr,
+            virt: mapping.to_virt(phys_addr),
+            len,
+        });
+    }
+
+    let phys = mappings
+        .iter()
+        .filter(|m| m.overlaps(pages))
+        .fold(pages, |phys, m| {
+            Segment::new(phys.start.min(m.phys.start), phys.end.max(m.phys.end))
+        });
+    let virt_base = map(
+        None,
+        Some(phys.start),
+        phys.size(),
+        Permissions::ReadWrite,
+        Scope::Global,
+        Privilege::Kernel,
+        KERNEL_ASID,
+    )?;
+
+    // The `Mmio` in the mappings that were extended keep their addresses, but
+    // count towards the new mapping.
+    let (extended, kept): (Vec<_>, Vec<_>) = mappings.drain(..).partition(|m| m.overlaps(pages));
+    *mappings = kept;
+
+    let mut mapping = Mapping {
+        phys,
+        virt: virt_base,
+        retired: Vec::new(),
+        users: 1,
+    };
+    for old in extended {
+        mapping.retired.extend(old.windows());
+        mapping.users += old.users;
+    }
+
+    let virt = mapping.to_virt(phys_addr);
+    mappings.push(mapping);
+
+    Ok(Mmio {
+        phys: phys_addr,
+        virt,
+        len,
+    })
+}
+
+/// Device registers mapped into the kernel by `ioremap`. The mapping is
+/// released when the last `Mmio` in it is dropped.
+#[derive(Debug)]
+pub struct Mmio {
+    phys: PhysicalAddress,
+    virt: VirtualAddress,
+    len: usize,
+}
+
+impl Mmio {
+    /// Get the physical address of the registers.
+    pub fn phys(&self) -> PhysicalAddress {
+        self.phys
+    }
+
+    /// Get the virtual address of the registers.
+    pub fn base(&self) -> VirtualAddress {
+        self.virt
+    }
+
+    /// Get the size of the registers in bytes.
+    pub fn size(&self) -> usize {
+        self.len
+    }
+
+    /// Get the registers at `offset` as a register block.
+    ///
+    /// # Safety
+    ///
+    /// - `T` must describe the layout of the registers at `offset`, e.g. a
+    ///   `#[repr(C)]` struct of `ReadOnly`, `WriteOnly`, and `ReadWrite`.
+    pub unsafe fn block<T>(&self, offset: usize) -> &T {
+        let end = offset.checked_add(core::mem::size_of::<T>());
+        assert!(matches!(end, Some(end) if end <= self.len));
+
+        let addr = self.virt + offset;
+        assert!(addr.is_aligned_to(core::mem::align_of::<T>()));
+
+        &*addr.as_ptr::<T>()
+    }
+}
+
+impl Drop for Mmio {
+    fn drop(&mut self) {
+        let mut mappings = MAPPINGS.lock();
+        let index = match mappings.iter().position(|m| m.phys.contains(self.phys)) {
+            Some(index) => index,
+            None => {
+                error!("Drop of MMIO at {:?} outside any mapping", self.phys);
+                return;
+            }
+        };
+
+        mappings[index].users -= 1;
+        if mappings[index].users > 0 {
+            return;
+        }
+
+        let mapping = mappings.swap_remove(index);
+        if let Err(why) = unsafe { mapping.unmap() } {
+            warn!(
+                "Failed to unmap MMIO at {:?}: {:?}",
+                mapping.phys.start, why
+            );
+            // Keep it, unused, so the registers aren't mapped a second time.
+            mappings.push(mapping);
+            return;
+        }
+
+        for window in mapping.windows() {
+            if let Err(why) = virt_addr_free(window.start) {
+                warn!("Failed to free MMIO mapping: {:?}", why);
+            }
+        }
+    }
+}
+
+/// A device register that can only be read.
+#[repr(transparent)]
+pub struct ReadOnly<T: Copy>(UnsafeCell<T>);
+
+impl<T: Copy> ReadOnly<T> {
+    /// Read the register.
+    #[inline]
+    pub fn read(&self) -> T {
+        unsafe { self.0.get().read_volatile() }
+    }
+}
+
+/// A device register that can only be written.
+#[repr(transparent)]
+pub struct WriteOnly<T: Copy>(UnsafeCell<T>);
+
+impl<T: Copy> WriteOnly<T> {
+    /// Write the register.
+    #[inline]
+    pub fn write(&self, value: T) {
+        unsafe { self.0.get().write_volatile(value) }
+    }
+}
+
+/// A device register that can be read and written.
+#[repr(transparent)]
+pub struct ReadWrite<T: Copy>(UnsafeCell<T>);
+
+impl<T: Copy> ReadWrite<T> {
+    /// Read the register.
+    #[inline]
+    pub fn read(&self) -> T {
+        unsafe { self.0.get().read_volatile() }
+    }
+
+    /// Write the register.
+    #[inline]
+    pub fn write(&self, value: T) {
+        unsafe { self.0.get().write_volatile(value) }
+    }
+
+    /// Read the register, then write back the result of `f`.
+    #[inline]
+    pub fn modify(&self, f: impl FnOnce(T) -> T) {
+        self.write(f(self.read()))
+    }
+}
@@ -2,14 +2,20 @@ use halogen_common::mem::MIB;
 
 /// Heap management.
 pub mod heap;
-/// Addresses and metadata for memory-mapped I/O.
+/// Memory-mapped I/O.
 pub mod io;
-/// Sv39 implementation.
+/// Sv39/Sv48/Sv57 implementation.
 pub mod paging;
 /// Physical frame allocation.
 pub mod phys;
+/// Protection of the kernel image after boot.
+pub mod protect;
 /// Kernel address-space layout.
 pub mod regions;
+/// Memory shared between processes.
+pub mod shm;
+/// Swapping user pages out to a block device.
+pub mod swap;
 /// Allocation of unused virtual addresses.
 pub mod virt_alloc;
 
@@ -22,4 +28,9 @@ mod stack;
 pub use stack::*;
 
 /// The size of physical memory available to the kernel.
+#[cfg(not(feature = "small-memory"))]
 pub const MEMORY_SIZE: usize = 256 * MIB;
+/// With the `small-memory` feature, the kernel runs with little memory, e.g.
+/// so that tests can run out of frames.
+#[cfg(feature = "small-memory")]
+pub const MEMORY_SIZE: usize = 64 * MIB;
@@ -1,4 +1,6 @@
-//! This module implements paging and memory mapping for Sv39. The main
+//! This module implements paging and memory mapping for Sv39, Sv48 and Sv57.
+//! The modes only differ in the number of levels, so the page-table code walks
+//! as many levels as the mode chosen at boot (see `probe_mode`). The main
 //! interface is the `map()` function, which provides the ability to allocate
 //! virtual memory, back virtual regions with physical frames, or map
 //! physical regions into virtual space. The exact function depends on which, if
@@ -6,20 +8,21 @@
 
 use halogen_common::{
     align_up, mask_range,
-    mem::{Address, PhysicalAddress, Segment, VirtualAddress, GIB, KIB, MIB},
+    mem::{Address, FrameKind, PhysicalAddress, Segment, VirtualAddress, GIB, KIB, MIB},
 };
 use spin::Mutex;
 
-use super::{phys, regions::virtual_offset};
+use super::{
+    phys,
+    regions::{virtual_offset, Region},
+    swap,
+};
 use crate::{
     error::{KernelError, KernelResult},
     kerror,
-    mem::virt_alloc::virt_addr_alloc,
+    mem::virt_alloc::{virt_addr_alloc, virt_addr_alloc_aligned},
 };
 
-/// Sv39 requires satp.mode = 8.
-pub const SATP_MODE: usize = 8;
-
 /// Address-space ID for the kernel space.
 pub const KERNEL_ASID: u16 = 0;
 
@@ -27,6 +30,10 @@ pub const KERNEL_ASID: u16 = 0;
 pub const PAGE_SIZE: usize = 4 * KIB;
 pub const PAGE_MASK: usize = usize::MAX & !(PAGE_SIZE - 1);
 
+/// Svnapot page = 64K, mapped by 16 level 0 entries.
+pub const NAPOT_SIZE: usize = 64 * KIB;
+pub const NAPOT_MASK: usize = usize::MAX & !(NAPOT_SIZE - 1);
+
 /// Level 1 page = 2M.
 pub const MEGAPAGE_SIZE: usize = 2 * MIB;
 pub const MEGAPAGE_MASK: usize = usize::MAX & !(MEGAPAGE_SIZE - 1);
@@ -40,12 +47,24 @@ const PT_LENGTH: usize = 512;
 
 const FIELD_VPN: usize = 0x1FF;
 
+/// Number of entries that map one Svnapot page.
+const NAPOT_ENTRIES: usize = NAPOT_SIZE / PAGE_SIZE;
+/// Low bits of the PPN of a 64 KiB Svnapot entry.
+const NAPOT_PPN: usize = 0b1000;
+
 /// Some language features rely on position-dependent code. In practice, this
 /// means nothing that uses or calls something that uses dynamic-dispatch. For
 /// this kernel, `Mutex` and `print!` are the most notable cases. This flag
 /// determines if those features can be used.
 pub static mut PAGING_ENABLED: bool = false;
 
+/// Paging mode in use, set by `probe_mode` before paging is enabled.
+static mut MODE: Mode = Mode::Sv39;
+
+/// Map 64 KiB runs with Svnapot entries. Set during boot if every hart has the
+/// extension.
+static mut SVNAPOT: bool = false;
+
 static ROOT_PAGE_TABLE_MUTEX: Mutex<()> = Mutex::new(());
 static mut ROOT_PAGE_TABLE: PageTable = PageTable([PageTableEntry(0); PT_LENGTH]);
 
@@ -66,6 +85,126 @@ mod flags {
     pub const DIRTY: usize = 0b1000_0000;
     /// For use by the kernel.
     pub const ACCESSED: usize = 0b0100_0000;
+    /// Reserved for software: the entry is not valid because the page is in
+    /// swap, and the PPN field holds the swap slot.
+    pub const SWAPPED: usize = 0b1_0000_0000;
+    /// Svnapot: the leaf is one of the entries that map a naturally aligned
+    /// run of pages.
+    pub const NAPOT: usize = 1 << 63;
+}
+
+/// Virtual-memory schemes supported by the page-table code.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Mode {
+    Sv39,
+    Sv48,
+    Sv57,
+}
+
+impl Mode {
+    /// Modes from the largest address-space to the smallest.
+    pub const ALL: [Mode; 3] = [Mode::Sv57, Mode::Sv48, Mode::Sv39];
+
+    /// Value of satp.mode that selects this mode.
+    pub fn satp(self) -> usize {
+        match self {
+            Mode::Sv39 => 8,
+            Mode::Sv48 => 9,
+            Mode::Sv57 => 10,
+        }
+    }
+
+    /// Number of page-table levels.
+    pub fn levels(self) -> usize {
+        match self {
+            Mode::Sv39 => 3,
+            Mode::Sv48 => 4,
+            Mode::Sv57 => 5,
+        }
+    }
+
+    /// Number of significant bits in a virtual address. The bits above must
+    /// match the highest of them.
+    pub fn va_bits(self) -> usize {
+        12 + 9 * self.levels()
+    }
+}
+
+/// Get the paging mode in use.
+#[inline]
+pub fn mode() -> Mode {
+    unsafe { MODE }
+}
+
+/// Pick the largest mode supported by the hart and use it from now on. A write
+/// to satp with an unsupported mode has no effect, so each mode is tried by
+/// writing it and reading it back. While a mode is tried, the first entry of a
+/// temporary root table identity-maps the low 512 GiB (Sv48) or 256 TiB (Sv57)
+/// as one leaf, so the kernel keeps running from its physical addresses. Sv39
+/// is assumed to be supported.
+///
+/// # Safety
+///
+/// - Must be called before paging is enabled, and before anything depends on
+///   the mode (e.g. the layout in `regions`).
+/// - Physical memory must be below 512 GiB.
+#[link_section = ".init.text"]
+pub unsafe fn probe_mode() -> Mode {
+    #[link_section = ".init.data"]
+    static mut PROBE_TABLE: PageTable = PageTable([PageTableEntry(0); PT_LENGTH]);
+
+    PROBE_TABLE.0[0] = PageTableEntry(
+        flags::VALID | flags::READ | flags::WRITE | flags::EXECUTE | flags::ACCESSED | flags::DIRTY,
+    );
+    let root = usize::from(PhysicalAddress::from_ref(&PROBE_TABLE)) >> 12;
+
+    for mode in Mode::ALL {
+        if mode == Mode::Sv39 {
+            MODE = mode;
+            break;
+        }
+
+        let satp: usize;
+        core::arch::asm!(
+            "csrw satp, {}",
+            "sfence.vma zero, zero",
+            "csrr {}, satp",
+            "csrw satp, zero",
+            "sfence.vma zero, zero",
+            in(reg) (mode.satp() << 60) | root,
+            out(reg) satp,
+        );
+
+        if satp >> 60 == mode.satp() {
+            MODE = mode;
+            break;
+        }
+    }
+
+    MODE
+}
+
+/// Returns true if `map` uses 64 KiB Svnapot pages where it can.
+#[inline]
+pub fn svnapot() -> bool {
+    unsafe { SVNAPOT }
+}
+
+/// Choose whether `map` uses 64 KiB Svnapot pages, and return the previous
+/// setting. Existing mappings are not changed.
+///
+/// # Safety
+///
+/// - The hart must support Svnapot to enable it.
+pub unsafe fn set_svnapot(enabled: bool) -> bool {
+    core::mem::replace(&mut SVNAPOT, enabled)
+}
+
+/// Flush every address translation cached by the hart, e.g. after permissions
+/// of a kernel mapping were reduced.
+#[inline]
+pub fn flush_tlb() {
+    unsafe { core::arch::asm!("sfence.vma zero, zero") };
 }
 
 pub fn get_root_satp() -> usize {
@@ -83,7 +222,7 @@ pub fn get_satp(asid: u16, root: &PageTable) -> usize {
         PhysicalAddress::from_ref(root)
     };
 
-    (SATP_MODE << 60) | ((asid as usize) << 44) | (usize::from(phys_addr) >> 12)
+    (mode().satp() << 60) | ((asid as usize) << 44) | (usize::from(phys_addr) >> 12)
 }
 
 /// Describes in which address spaces a mapping is accessible. Global mappings
@@ -195,32 +334,44 @@ impl From<usize> for Permissions {
     }
 }
 
-/// Mapping levels of Sv39. Gigapage = 1 GiB, MegaPage = 2 MiB, Page = 4 KiB.
+/// A level of the page-table hierarchy, counting up from the leaf level. A
+/// leaf at level `n` maps a page of `4 KiB * 512^n`, so `PAGE` = 4 KiB,
+/// `MEGAPAGE` = 2 MiB and `GIGAPAGE` = 1 GiB. The root is at `levels() - 1` of
+/// the current mode.
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
-pub enum Level {
-    GigaPage,
-    MegaPage,
-    Page,
-}
+pub struct Level(usize);
 
 impl Level {
+    pub const GIGAPAGE: Level = Level(2);
+    pub const MEGAPAGE: Level = Level(1);
+    pub const PAGE: Level = Level(0);
+
+    /// The level of the root table in the current mode.
+    pub fn root() -> Level {
+        Level(mode().levels() - 1)
+    }
+
     /// Return the next level down, if any.
     pub fn next(&self) -> Option<Level> {
-        match &self {
-            Level::GigaPage => Some(Level::MegaPage),
-            Level::MegaPage => Some(Level::Page),
-            Level::Page => None,
-        }
+        self.0.checked_sub(1).map(Level)
+    }
+
+    /// Position of the lowest virtual address bit indexed at this level.
+    #[inline]
+    pub fn shift(&self) -> usize {
+        12 + 9 * self.0
+    }
+
+    /// Size of the page mapped by a leaf at this level.
+    #[inline]
+    pub fn page_size(&self) -> usize {
+        1 << self.shift()
     }
 }
 
 impl From<Level> for usize {
     fn from(level: Level) -> usize {
-        match level {
-            Level::GigaPage => 0,
-            Level::MegaPage => 1,
-            Level::Page => 2,
-        }
+        level.0
     }
 }
 
@@ -228,11 +379,7 @@ impl From<Level> for usize {
 #[inline]
 fn ppn(virt_addr: VirtualAddress, level: Level) -> usize {
     let virt_addr: usize = virt_addr.into();
-    match level {
-        Level::GigaPage => (virt_addr & mask_range!(55, 30)) >> 30,
-        Level::MegaPage => (virt_addr & mask_range!(29, 21)) >> 21,
-        Level::Page => (virt_addr & mask_range!(20, 12)) >> 12,
-    }
+    (virt_addr & mask_range!(55, level.shift())) >> level.shift()
 }
 
 /// Extract the offset bits from a physical address.
@@ -242,30 +389,21 @@ fn offset(phys_addr: PhysicalAddress) -> usize {
     phys_addr & mask_range!(12, 0)
 }
 
-/// Extract the level-based offset as a mask to add to the address.
+/// Extract the offset into a page of `size` bytes as a mask to add to the
+/// address.
 #[inline]
-fn offset_mask(virt_addr: VirtualAddress, level: Level) -> usize {
-    let virt_addr: usize = virt_addr.into();
-    match level {
-        Level::GigaPage => virt_addr & mask_range!(29, 0),
-        Level::MegaPage => virt_addr & mask_range!(20, 0),
-        Level::Page => virt_addr & mask_range!(11, 0),
-    }
+fn offset_mask(virt_addr: VirtualAddress, size: usize) -> usize {
+    usize::from(virt_addr) & (size - 1)
 }
 
 /// Extract the VPN from a virtual address.
 #[inline]
 fn vpn(virt_addr: VirtualAddress, level: Level) -> usize {
-    let virt_addr: usize = virt_addr.into();
-    match level {
-        Level::GigaPage => (virt_addr >> 30) & FIELD_VPN,
-        Level::MegaPage => (virt_addr >> 21) & FIELD_VPN,
-        Level::Page => (virt_addr >> 12) & FIELD_VPN,
-    }
+    (usize::from(virt_addr) >> level.shift()) & FIELD_VPN
 }
 
-/// A 64-bit Sv39 page table entry. Points to the next-level page table or a
-/// physical address.
+/// A 64-bit page table entry (the format is the same in every mode). Points to
+/// the next-level page table or a physical address.
 #[repr(C, packed)]
 #[derive(Clone, Copy, Debug, Default)]
 pub struct PageTableEntry(usize);
@@ -313,14 +451,114 @@ impl PageTableEntry {
         self.0 & (flags::READ | flags::WRITE | flags::EXECUTE) != 0
     }
 
+    /// Returns true if the leaf allows writes.
+    pub fn is_writable(&self) -> bool {
+        self.0 & flags::WRITE != 0
+    }
+
+    /// Returns true if the leaf allows instruction fetches.
+    pub fn is_executable(&self) -> bool {
+        self.0 & flags::EXECUTE != 0
+    }
+
+    /// Remove write access from a leaf. The TLB must be flushed for this to
+    /// take effect.
+    pub fn clear_writable(&mut self) {
+        self.0 &= !flags::WRITE;
+    }
+
+    /// Returns true if the entry is valid and maps a physical address.
+    pub fn is_mapped(&self) -> bool {
+        self.is_valid() && self.is_leaf()
+    }
+
+    /// Get the frame mapped by a page-level leaf.
+    pub fn frame(&self) -> PhysicalAddress {
+        PhysicalAddress(self.page_number(Level::PAGE))
+    }
+
+    /// Returns true if the leaf is one of the entries of a 64 KiB Svnapot page.
+    pub fn is_napot(&self) -> bool {
+        self.0 & flags::NAPOT != 0
+    }
+
+    /// Turn the page-level leaf for the start of a 64 KiB aligned run into a
+    /// Svnapot leaf for the whole run.
+    fn set_napot(&mut self) {
+        self.0 |= flags::NAPOT | (NAPOT_PPN << 10);
+    }
+
+    /// Size of the page mapped by a leaf at `level`.
+    fn span(&self, level: Level) -> usize {
+        if self.is_napot() {
+            NAPOT_SIZE
+        } else {
+            level.page_size()
+        }
+    }
+
+    /// Returns true if the page has been accessed since the bit was cleared.
+    pub fn is_accessed(&self) -> bool {
+        self.0 & flags::ACCESSED != 0
+    }
+
+    /// Clear the accessed bit. The TLB is flushed on every return from a trap,
+    /// so the hardware sets it again on the next access.
+    pub fn clear_accessed(&mut self) {
+        self.0 &= !flags::ACCESSED;
+    }
+
+    /// Set the accessed bit, and the dirty bit for a write, for hardware that
+    /// faults rather than updating them. Returns false if the leaf does not
+    /// allow the access or the bits were already set, i.e. the fault was real.
+    pub fn mark_accessed(&mut self, write: bool) -> bool {
+        if !self.is_mapped() || (write && self.0 & flags::WRITE == 0) {
+            return false;
+        }
+
+        let bits = flags::ACCESSED | if write { flags::DIRTY } else { 0 };
+        if self.0 & bits == bits {
+            false
+        } else {
+            self.0 |= bits;
+            true
+        }
+    }
+
+    /// Get the swap slot of a page that has been swapped out.
+    pub fn swap_slot(&self) -> Option<usize> {
+        if !self.is_valid() && self.0 & flags::SWAPPED != 0 {
+            Some(self.0 >> 10)
+        } else {
+            None
+        }
+    }
+
+    /// Replace a user leaf with an invalid entry that records the swap slot the
+    /// page was written to. The permissions are kept for `swap_in`.
+    pub fn set_swapped(&mut self, slot: usize) {
+        let perms = self.0 & (flags::READ | flags::WRITE | flags::EXECUTE);
+        self.0 = (slot << 10) | flags::SWAPPED | perms;
+    }
+
+    /// Map a swapped-out user page to the frame it was read back into.
+    pub fn swap_in(&mut self, frame: PhysicalAddress) {
+        let meta = Translation::Leaf(Scope::Local, Privilege::User, self.permissions());
+        self.set_translation(frame, meta);
+    }
+
+    /// Clear a swapped-out entry and return its slot. Other entries are left
+    /// alone.
+    pub fn take_swap_slot(&mut self) -> Option<usize> {
+        let slot = self.swap_slot()?;
+        *self = PageTableEntry(0);
+        Some(slot)
+    }
+
     /// Extract the physical page numbers from a PTE as a mask to
     /// combine w/ the VPN portion of the translated address.
     fn page_number(&self, level: Level) -> usize {
-        (match level {
-            Level::GigaPage => self.0 & mask_range!(53, 28),
-            Level::MegaPage => self.0 & mask_range!(53, 19),
-            Level::Page => self.0 & mask_range!(53, 10),
-        } << 2) as usize
+        ((self.0 & mask_range!(53, 10)) << 2) & !(self.span(level) - 1)
     }
 
     /// Get a reference to the next level page table.
@@ -359,16 +597,17 @@ impl PageTable {
         unsafe { ROOT_PAGE_TABLE }
     }
 
-    /// Allocate a physical page on the kernel heap and clear it for use as
-    /// a page table.
-    pub fn new_static() -> KernelResult<(&'static mut PageTable, PhysicalAddress)> {
+    /// Allocate a zeroed physical page for use as a page table of the
+    /// address-space with ID `owner`.
+    pub fn new_static(owner: u16) -> KernelResult<(&'static mut PageTable, PhysicalAddress)> {
         unsafe {
-            let (virt_addr, phys_addr) = phys::alloc().ok_or_else(|| {
-                kerror!(
-                    KernelError::PageTableAllocation,
-                    kerror!(KernelError::OutOfPhysicalFrames)
-                )
-            })?;
+            let (virt_addr, phys_addr) = phys::alloc_zeroed_for(FrameKind::PageTable, owner)
+                .ok_or_else(|| {
+                    kerror!(
+                        KernelError::PageTableAllocation,
+                        kerror!(KernelError::OutOfPhysicalFrames)
+                    )
+                })?;
 
             let pt = (if PAGING_ENABLED {
                 virt_addr.as_mut_ptr()
@@ -378,7 +617,6 @@ impl PageTable {
                 .as_mut()
                 .expect("Physical frame is null pointer");
 
-            pt.clear();
             Ok((pt, phys_addr))
         }
     }
@@ -396,11 +634,16 @@ impl PageTable {
         }
     }
 
-    /// Get or create the next level page table.
-    pub fn get_create_next(&self, n: usize, scope: Scope) -> KernelResult<&'static mut PageTable> {
+    /// Get or create the next level page table, issuing a new one to `owner`.
+    pub fn get_create_next(
+        &self,
+        n: usize,
+        scope: Scope,
+        owner: u16,
+    ) -> KernelResult<&'static mut PageTable> {
         let entry = self.get(n);
         if !entry.is_valid() {
-            match PageTable::new_static() {
+            match PageTable::new_static(owner) {
                 Ok((pt, phys_addr)) => {
                     entry.set_translation(phys_addr, Translation::Directory(scope));
                     Ok(pt)
@@ -415,7 +658,10 @@ impl PageTable {
         }
     }
 
-    /// Map a virtual address to a physical address.
+    /// Map a virtual address to a physical address. Page tables allocated to
+    /// do so are issued to the address-space with ID `owner`. Returns the
+    /// number of page tables that were allocated.
+    #[allow(clippy::too_many_arguments)]
     pub fn map(
         &mut self,
         virt_addr: VirtualAddress,
@@ -424,9 +670,11 @@ impl PageTable {
         perms: Permissions,
         scope: Scope,
         prv: Privilege,
-    ) -> KernelResult<()> {
-        let mut curr_level = Level::GigaPage;
+        owner: u16,
+    ) -> KernelResult<usize> {
+        let mut curr_level = Level::root();
         let mut pt = self;
+        let mut tables = 0;
 
         loop {
             // Should we continue descending?
@@ -434,17 +682,25 @@ impl PageTable {
                 // Current level does not match the desired level and there are still more levels.
                 (false, Some(next_level)) => {
                     // We can, so get the next level PT.
-                    pt = pt.get_create_next(vpn(virt_addr, curr_level), scope)?;
+                    let n = vpn(virt_addr, curr_level);
+                    if !pt.get(n).is_valid() {
+                        tables += 1;
+                    }
+                    pt = pt.get_create_next(n, scope, owner)?;
 
                     curr_level = next_level
                 }
                 // Current level matches the desired level or there are no more levels.
                 (true, _) | (_, None) => {
                     // We are at the leaf PT; get the entry and set the address.
-                    pt.get(vpn(virt_addr, curr_level))
+                    let n = vpn(virt_addr, curr_level);
+                    if pt.get(n).is_napot() {
+                        pt.demote_napot(n);
+                    }
+                    pt.get(n)
                         .set_translation(phys_addr, Translation::Leaf(scope, prv, perms));
 
-                    return Ok(());
+                    return Ok(tables);
                 }
             }
         }
@@ -456,7 +712,7 @@ impl PageTable {
         virt_addr: VirtualAddress,
     ) -> Option<(PhysicalAddress, Scope, Privilege, Permissions)> {
         let mut pt = self;
-        let mut level = Level::GigaPage;
+        let mut level = Level::root();
 
         loop {
             let entry = pt.get(vpn(virt_addr, level));
@@ -464,7 +720,9 @@ impl PageTable {
             if entry.is_valid() {
                 if entry.is_leaf() {
                     return Some((
-                        PhysicalAddress(entry.page_number(level) | offset_mask(virt_addr, level)),
+                        PhysicalAddress(
+                            entry.page_number(level) | offset_mask(virt_addr, entry.span(level)),
+                        ),
                         entry.scope(),
                         entry.privilege(),
                         entry.permissions(),
@@ -479,6 +737,222 @@ impl PageTable {
         }
     }
 
+    /// Get the page-level entry for a virtual address, whether or not it is
+    /// valid, without creating tables. Returns `None` if a table on the way is
+    /// missing or the address is mapped by a larger page.
+    pub fn leaf_entry(&self, virt_addr: VirtualAddress) -> Option<&'static mut PageTableEntry> {
+        self.leaf_table(virt_addr)
+            .map(|table| table.get(vpn(virt_addr, Level::PAGE)))
+    }
+
+    /// Get the page-level table for a virtual address without creating tables.
+    pub fn leaf_table(&self, virt_addr: VirtualAddress) -> Option<&'static mut PageTable> {
+        let mut level = Level::root();
+        let mut entry = self.get(vpn(virt_addr, level));
+
+        loop {
+            if !entry.is_valid() || entry.is_leaf() {
+                return None;
+            }
+
+            let table = entry.next_level()?;
+            level = level.next()?;
+            if level == Level::PAGE {
+                return Some(table);
+            }
+            entry = table.get(vpn(virt_addr, level));
+        }
+    }
+
+    /// Map a 64 KiB run as one Svnapot page: the same leaf is written to each
+    /// of its 16 page-level entries. Both addresses must be aligned to
+    /// `NAPOT_SIZE`. Returns the number of page tables that were allocated.
+    pub fn map_napot(
+        &mut self,
+        virt_addr: VirtualAddress,
+        phys_addr: PhysicalAddress,
+        perms: Permissions,
+        scope: Scope,
+        prv: Privilege,
+        owner: u16,
+    ) -> KernelResult<usize> {
+        debug_assert!(virt_addr.is_aligned_to(NAPOT_SIZE) && phys_addr.is_aligned_to(NAPOT_SIZE));
+
+        let tables = self.map(virt_addr, phys_addr, Level::PAGE, perms, scope, prv, owner)?;
+        let table = self
+            .leaf_table(virt_addr)
+            .ok_or_else(|| kerror!(KernelError::PageTableCorruption))?;
+
+        let first = vpn(virt_addr, Level::PAGE);
+        table.get(first).set_napot();
+        let leaf = *table.get(first);
+        for n in first + 1..first + NAPOT_ENTRIES {
+            *table.get(n) = leaf;
+        }
+
+        Ok(tables)
+    }
+
+    /// Split the Svnapot page mapping a virtual address, if there is one, into
+    /// 16 ordinary leaves. Returns true if it was split.
+    pub fn demote(&self, virt_addr: VirtualAddress) -> bool {
+        match self.leaf_table(virt_addr) {
+            Some(table) if table.get(vpn(virt_addr, Level::PAGE)).is_napot() => {
+                table.demote_napot(vpn(virt_addr, Level::PAGE));
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Split the Svnapot page that entry `n` of a page-level table belongs to
+    /// into 16 ordinary leaves, so they can be changed one at a time.
+    fn demote_napot(&self, n: usize) {
+        let first = n & !(NAPOT_ENTRIES - 1);
+        let leaf = *self.get(first);
+        let base = leaf.page_number(Level::PAGE);
+
+        for i in 0..NAPOT_ENTRIES {
+            *self.get(first + i) = PageTableEntry(((base + i * PAGE_SIZE) >> 2) | leaf.flags());
+        }
+    }
+
+    /// Find the first mapped page at or after `from` in the lower (user) half.
+    /// Only pages mapped at the page level are found.
+    pub fn next_user_page(
+        &self,
+        from: VirtualAddress,
+    ) -> Option<(VirtualAddress, &'static mut PageTableEntry)> {
+        if usize::from(from) >> (mode().va_bits() - 1) != 0 {
+            return None;
+        }
+
+        self.next_page_in(Level::root(), 0, PT_LENGTH / 2, usize::from(from))
+    }
+
+    /// Search the first `limit` entries of a table at `level` that maps
+    /// addresses from `base` for a mapped page at or after `from`.
+    fn next_page_in(
+        &self,
+        level: Level,
+        base: usize,
+        limit: usize,
+        from: usize,
+    ) -> Option<(VirtualAddress, &'static mut PageTableEntry)> {
+        let first = if from > base {
+            vpn(VirtualAddress(from), level)
+        } else {
+            0
+        };
+
+        for i in first..limit {
+            let entry = self.get(i);
+            let addr = base | (i << level.shift());
+
+            match level.next() {
+                None if entry.is_mapped() => return Some((VirtualAddress(addr), entry)),
+                Some(next) if entry.is_valid() && !entry.is_leaf() => {
+                    let found = entry
+                        .next_level()?
+                        .next_page_in(next, addr, PT_LENGTH, from);
+                    if found.is_some() {
+                        return found;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        None
+    }
+
+    /// Call `f` with the virtual address, physical address, size, and entry of
+    /// every valid leaf in the upper (kernel) half of the table. Each entry of
+    /// a Svnapot page is visited on its own.
+    pub fn for_each_kernel_leaf(
+        &self,
+        mut f: impl FnMut(VirtualAddress, PhysicalAddress, usize, &'static mut PageTableEntry),
+    ) {
+        let root = Level::root();
+        for i in PT_LENGTH / 2..PT_LENGTH {
+            // Addresses in the upper half are sign-extended.
+            let addr = (usize::MAX << (mode().va_bits() - 1)) | (i << root.shift());
+            PageTable::leaves_in(self.get(i), root, addr, &mut f);
+        }
+    }
+
+    /// Visit the leaves under `entry`, which maps `addr` at `level`.
+    fn leaves_in(
+        entry: &'static mut PageTableEntry,
+        level: Level,
+        addr: usize,
+        f: &mut impl FnMut(VirtualAddress, PhysicalAddress, usize, &'static mut PageTableEntry),
+    ) {
+        if !entry.is_valid() {
+            return;
+        }
+
+        if entry.is_leaf() {
+            // A Svnapot entry only maps its own page of the run.
+            let phys_addr =
+                entry.page_number(level) | offset_mask(VirtualAddress(addr), entry.span(level));
+            f(
+                VirtualAddress(addr),
+                PhysicalAddress(phys_addr),
+                level.page_size(),
+                entry,
+            );
+        } else if let (Some(table), Some(next)) = (entry.next_level(), level.next()) {
+            for n in 0..PT_LENGTH {
+                PageTable::leaves_in(table.get(n), next, addr | (n << next.shift()), f);
+            }
+        }
+    }
+
+    /// Remove the leaf mapping of a virtual address and return the physical
+    /// address it translated to, if it was mapped. Sub-tables are not freed.
+    pub fn unmap(&mut self, virt_addr: VirtualAddress) -> Option<PhysicalAddress> {
+        let mut pt: &PageTable = self;
+        let mut level = Level::root();
+
+        loop {
+            let n = vpn(virt_addr, level);
+            let entry = pt.get(n);
+
+            if !entry.is_valid() {
+                return None;
+            } else if entry.is_leaf() {
+                // Keep the rest of a 64 KiB page mapped.
+                if entry.is_napot() {
+                    pt.demote_napot(n);
+                }
+                let phys_addr = PhysicalAddress(
+                    entry.page_number(level) | offset_mask(virt_addr, entry.span(level)),
+                );
+                *entry = PageTableEntry(0);
+                return Some(phys_addr);
+            } else {
+                pt = entry.next_level()?;
+                level = level.next()?;
+            }
+        }
+    }
+
+    /// Remove every mapping in the lower (user) half of the table, dropping a
+    /// reference to each mapped frame, releasing swapped-out pages, and freeing
+    /// the sub-tables. Returns the number of frames unmapped and tables freed.
+    ///
+    /// # Safety
+    ///
+    /// - Sub-tables of the lower half must only be referred to by this table.
+    pub unsafe fn release_user(&mut self) -> (usize, usize) {
+        let mut counts = (0, 0);
+        for entry in self.0[..PT_LENGTH / 2].iter_mut() {
+            release_entry(entry, Level::root(), &mut counts);
+        }
+        counts
+    }
+
     /// Free a table, but do not free any sub-tables.
     ///
     /// # Safety
@@ -511,10 +985,55 @@ impl PageTable {
     }
 }
 
+/// Invalidate an entry, dropping the reference to the frame it maps or
+/// recursively releasing the table it points to.
+unsafe fn release_entry(entry: &mut PageTableEntry, level: Level, counts: &mut (usize, usize)) {
+    if !entry.is_valid() {
+        if let Some(slot) = entry.take_swap_slot() {
+            swap::release(slot);
+        }
+        return;
+    }
+
+    let phys_addr = PhysicalAddress(entry.page_number(Level::PAGE));
+
+    if entry.is_leaf() {
+        phys::free(phys_addr);
+        counts.0 += 1;
+    } else if let (Some(table), Some(next)) = (entry.next_level(), level.next()) {
+        for n in 0..PT_LENGTH {
+            // Each entry of a Svnapot page must drop its own frame.
+            if table.get(n).is_napot() {
+                table.demote_napot(n);
+            }
+            release_entry(table.get(n), next, counts);
+        }
+        phys::free(phys_addr);
+        counts.1 += 1;
+    }
+
+    *entry = PageTableEntry(0);
+}
+
+/// Infer what a frame is used for from the region it is mapped into.
+fn frame_kind(virt_addr: VirtualAddress) -> FrameKind {
+    match Region::from(virt_addr) {
+        Region::Heap => FrameKind::Heap,
+        Region::Stack => FrameKind::Stack,
+        Region::User => FrameKind::User,
+        _ => FrameKind::Kernel,
+    }
+}
+
 /// Map a virtual address to a physical address. If no virtual address is
-/// provided, one is chosen by the kernel. If no physical address is provided, a
-/// frame is allocated. Frames are not guaranteed to be contiguous in physical
-/// memory On success, returns the mapped virtual address.
+/// provided, one is chosen by the kernel. If no physical address is provided,
+/// frames are allocated. Frames are not guaranteed to be contiguous in physical
+/// memory On success, returns the mapped virtual address. If Svnapot is
+/// enabled, a physical run is mapped with 64 KiB pages where both addresses are
+/// aligned, and allocated memory is backed by aligned runs of 16 frames where
+/// the allocator has them. Allocated frames are issued to the address-space
+/// with ID `owner`, which is `KERNEL_ASID` for anything outside the user
+/// region.
 ///
 /// This can be used to assign virtual addresses to devices, or as a
 /// page-grained `vmalloc` implementation.
@@ -531,13 +1050,29 @@ pub unsafe fn map(
     perms: Permissions,
     scope: Scope,
     prv: Privilege,
+    owner: u16,
 ) -> KernelResult<VirtualAddress> {
     let size = align_up!(size, PAGE_SIZE);
 
+    // A physical run can use 64 KiB pages wherever both addresses are aligned,
+    // and allocated frames can always be, so pick a virtual address with the
+    // same alignment.
+    let napot = svnapot()
+        && size >= NAPOT_SIZE
+        && match phys_base {
+            Some(phys_addr) => phys_addr.is_aligned_to(NAPOT_SIZE),
+            None => true,
+        };
+
     let virt_base = match virt_base {
         Some(addr) => addr,
         None => {
-            virt_addr_alloc(PAGE_SIZE).ok_or_else(|| kerror!(KernelError::OutOfVirtualAddresses))?
+            if napot {
+                virt_addr_alloc_aligned(size, NAPOT_SIZE)
+            } else {
+                virt_addr_alloc(size)
+            }
+            .ok_or_else(|| kerror!(KernelError::OutOfVirtualAddresses))?
         }
     };
 
@@ -546,18 +1081,50 @@ pub unsafe fn map(
         _lock = ROOT_PAGE_TABLE_MUTEX.lock();
     }
 
-    for offset in (0..size).step_by(PAGE_SIZE) {
+    let mut offset = 0;
+    while offset < size {
         let virt_addr = virt_base + offset;
-        let phys_addr = match phys_base {
-            Some(phys_addr) => phys_addr + offset,
-            None => {
-                let (_, phys_frame) =
-                    phys::alloc().ok_or_else(|| kerror!(KernelError::OutOfPhysicalFrames))?;
-                phys_frame
+
+        let run = if svnapot() && size - offset >= NAPOT_SIZE && virt_addr.is_aligned_to(NAPOT_SIZE)
+        {
+            match phys_base {
+                Some(phys_addr) => {
+                    Some(phys_addr + offset).filter(|run| run.is_aligned_to(NAPOT_SIZE))
+                }
+                // Fall back to single frames once there are no runs left.
+                None => {
+                    phys::alloc_contiguous_for(
+                        NAPOT_ENTRIES,
+                        NAPOT_SIZE,
+                        frame_kind(virt_addr),
+                        owner,
+                    )
+                    .map(|(_, run)| run)
+                }
             }
+        } else {
+            None
         };
 
-        ROOT_PAGE_TABLE.map(virt_addr, phys_addr, Level::Page, perms, scope, prv)?;
+        match run {
+            Some(run) => {
+                ROOT_PAGE_TABLE.map_napot(virt_addr, run, perms, scope, prv, owner)?;
+                offset += NAPOT_SIZE;
+            }
+            None => {
+                let phys_addr = match phys_base {
+                    Some(phys_addr) => phys_addr + offset,
+                    None => {
+                        let (_, phys_frame) = phys::alloc_for(frame_kind(virt_addr), owner)
+                            .ok_or_else(|| kerror!(KernelError::OutOfPhysicalFrames))?;
+                        phys_frame
+                    }
+                };
+
+                ROOT_PAGE_TABLE.map(virt_addr, phys_addr, Level::PAGE, perms, scope, prv, owner)?;
+                offset += PAGE_SIZE;
+            }
+        }
     }
 
     Ok(virt_base)
@@ -578,10 +1145,11 @@ pub unsafe fn unmap(segment: Segment<VirtualAddress>) -> KernelResult<()> {
         ROOT_PAGE_TABLE.map(
             virt_addr.into(),
             PhysicalAddress::null(),
-            Level::Page,
+            Level::PAGE,
             Permissions::Invalid,
             Scope::Local,
             Privilege::Kernel,
+            KERNEL_ASID,
         )?;
     }
 
@@ -600,3 +1168,18 @@ pub fn translate(
         ROOT_PAGE_TABLE.translate(virt_addr)
     }
 }
+
+/// Call `f` with every valid leaf mapping of the kernel half, see
+/// `PageTable::for_each_kernel_leaf`. The root page table is locked, so `f`
+/// must not map or unmap memory.
+pub fn for_each_kernel_leaf(
+    f: impl FnMut(VirtualAddress, PhysicalAddress, usize, &'static mut PageTableEntry),
+) {
+    unsafe {
+        let _lock;
+        if PAGING_ENABLED {
+            _lock = ROOT_PAGE_TABLE_MUTEX.lock();
+        }
+        ROOT_PAGE_TABLE.for_each_kernel_leaf(f)
+    }
+}
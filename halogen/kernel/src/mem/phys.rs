@@ -11,23 +11,54 @@
 //! This is the only region guaranteed to be contiguous virtually and
 //! physically, so translation can be done with just a single offset saved
 //! during bootstrap, rather than walking the page-table.
+//!
+//! Every frame of physical memory also has a descriptor in a table indexed by
+//! PFN. `alloc` and `free` keep it up to date, so the kernel can tell who owns
+//! a frame, what it is used for, and how many references to it exist.
+//!
+//! A small pool of frames known to be zero is kept apart from the allocator,
+//! so `alloc_zeroed` doesn't have to clear a frame on the critical path. The
+//! executor's idle thread refills the pool when the kernel has nothing better
+//! to do. Frames in the pool are free, but flagged as `ZEROED`.
 
-use core::slice::from_raw_parts_mut;
+use core::{ptr::write_bytes, slice::from_raw_parts_mut};
 
 use halogen_common::mem::{
-    alloc::FrameAllocator, Address, PhysicalAddress, Segment, VirtualAddress,
+    alloc::FrameAllocator, Address, FrameDescriptor, FrameKind, FrameStats, FrameTable,
+    PhysicalAddress, Segment, VirtualAddress,
 };
 use spin::Mutex;
 
 use super::regions::PHYSICAL_BASE;
-use crate::mem::{
-    paging::{PAGE_SIZE, PAGING_ENABLED as DO_LOCK},
-    regions::{virtual_offset, KERNEL_SPACE_START},
+use crate::{
+    critical_section, kprintln,
+    log::*,
+    mem::{
+        paging::{KERNEL_ASID, PAGE_SIZE, PAGING_ENABLED as DO_LOCK},
+        regions::{virtual_offset, KERNEL_IMAGE_START},
+        MEMORY_SIZE,
+    },
 };
 
+/// Number of frames described by the frame table.
+const FRAME_COUNT: usize = MEMORY_SIZE / PAGE_SIZE;
+
 static mut FRAME_ALLOCATOR_MUTEX: Mutex<()> = Mutex::new(());
 static mut FRAME_ALLOCATOR: FrameAllocator<PAGE_SIZE> = FrameAllocator::new_uninit();
 
+static mut FRAME_DESCRIPTORS: [FrameDescriptor; FRAME_COUNT] =
+    [FrameDescriptor::new(); FRAME_COUNT];
+static mut FRAME_TABLE: FrameTable<PAGE_SIZE> = FrameTable::new_uninit();
+
+/// Number of zeroed frames kept ready for `alloc_zeroed`.
+pub const ZERO_POOL_SIZE: usize = 64;
+/// Number of frames the idle thread zeroes between checks for interrupts.
+pub const ZERO_BATCH: usize = 4;
+
+/// Frames known to be zero, guarded by the frame allocator's lock.
+static mut ZERO_POOL: [PhysicalAddress; ZERO_POOL_SIZE] = [PhysicalAddress(0); ZERO_POOL_SIZE];
+static mut ZERO_POOL_LEN: usize = 0;
+
 /// Intitialize the frame allocator for use in bare-paging mode.
 ///
 /// # Safety
@@ -40,6 +71,10 @@ pub unsafe fn init(segment: Segment<PhysicalAddress>) {
     let slice: &'static mut [[u8; PAGE_SIZE]] =
         from_raw_parts_mut(segment.start.as_mut_ptr(), segment.size() / PAGE_SIZE);
     FRAME_ALLOCATOR.init(slice, 0);
+
+    // Everything below the managed segment is the kernel image.
+    FRAME_TABLE.init(PHYSICAL_BASE, &mut FRAME_DESCRIPTORS);
+    FRAME_TABLE.reserve(Segment::new(PHYSICAL_BASE, segment.start));
 }
 
 /// Rebase the frame allocator to its virtual location. This assumes no physical
@@ -55,42 +90,258 @@ pub unsafe fn rebase_virt() {
     // Page allocator's arena's offset into physical memory
     let (frames_used, free_start) = FRAME_ALLOCATOR.boundary().unwrap();
     let free_offset = free_start - PHYSICAL_BASE;
-    let free_base_virt = KERNEL_SPACE_START + free_offset;
+    let free_base_virt = KERNEL_IMAGE_START + free_offset;
     let slice: &'static mut [[u8; PAGE_SIZE]] = from_raw_parts_mut(
         free_base_virt.as_mut_ptr(),
         (FRAME_ALLOCATOR.size()) / PAGE_SIZE - frames_used,
     );
     FRAME_ALLOCATOR.init(slice, virtual_offset());
+
+    // The descriptors live in the kernel image, so they move with it.
+    FRAME_TABLE.rebase(virtual_offset());
 }
 
-/// Allocate a physical frame.
+/// Hand a frame that was reserved at boot back to the allocator, e.g. part of
+/// the `.init` section once boot is done. Returns false, leaving the frame
+/// reserved, if it isn't reserved or the allocator can't take it.
+///
+/// # Safety
+///
+/// - The frame must be unused, and linear-mapped read-write at
+///   `halogen::mem::regions::virtual_offset()`.
+pub unsafe fn release_reserved(frame: PhysicalAddress) -> bool {
+    let _lock;
+    if DO_LOCK {
+        _lock = FRAME_ALLOCATOR_MUTEX.lock();
+    }
+
+    if !FRAME_ALLOCATOR.can_donate(frame) {
+        warn!("Too many ranges donated to release frame {}", frame);
+        return false;
+    }
+    FRAME_TABLE.unreserve(frame) && FRAME_ALLOCATOR.donate(frame)
+}
+
+/// Allocate a physical frame for general kernel use.
 pub fn alloc() -> Option<(VirtualAddress, PhysicalAddress)> {
+    alloc_for(FrameKind::Kernel, KERNEL_ASID)
+}
+
+/// Allocate a physical frame and record its use and the ASID of the
+/// address-space that owns it. Frames are taken from the zeroed pool only when
+/// there are no others left.
+pub fn alloc_for(kind: FrameKind, owner: u16) -> Option<(VirtualAddress, PhysicalAddress)> {
+    unsafe {
+        let _lock;
+        if DO_LOCK {
+            _lock = FRAME_ALLOCATOR_MUTEX.lock();
+        }
+        FRAME_ALLOCATOR
+            .alloc()
+            .or_else(|| pop_zeroed())
+            .map(|phys_addr| issue(phys_addr, kind, owner))
+    }
+}
+
+/// Allocate `count` physically contiguous frames, the first aligned to
+/// `align`, and record their use and owner like `alloc_for`. Each frame is
+/// freed on its own. Returns `None` if no such run is left, even if enough
+/// single frames are.
+pub fn alloc_contiguous_for(
+    count: usize,
+    align: usize,
+    kind: FrameKind,
+    owner: u16,
+) -> Option<(VirtualAddress, PhysicalAddress)> {
+    unsafe {
+        let _lock;
+        if DO_LOCK {
+            _lock = FRAME_ALLOCATOR_MUTEX.lock();
+        }
+        let run = FRAME_ALLOCATOR.alloc_contiguous(count, align)?;
+        for n in 0..count {
+            issue(run + n * PAGE_SIZE, kind, owner);
+        }
+        Some((to_virt(run), run))
+    }
+}
+
+/// Allocate a zeroed physical frame for general kernel use.
+pub fn alloc_zeroed() -> Option<(VirtualAddress, PhysicalAddress)> {
+    alloc_zeroed_for(FrameKind::Kernel, KERNEL_ASID)
+}
+
+/// Allocate a zeroed physical frame and record its use and the ASID of the
+/// address-space that owns it. The frame is taken from the zeroed pool if
+/// possible, otherwise it is cleared here.
+pub fn alloc_zeroed_for(kind: FrameKind, owner: u16) -> Option<(VirtualAddress, PhysicalAddress)> {
+    let ((virt_addr, phys_addr), zeroed) = unsafe {
+        let _lock;
+        if DO_LOCK {
+            _lock = FRAME_ALLOCATOR_MUTEX.lock();
+        }
+        match pop_zeroed() {
+            Some(phys_addr) => (issue(phys_addr, kind, owner), true),
+            None => (issue(FRAME_ALLOCATOR.alloc()?, kind, owner), false),
+        }
+    };
+
+    if !zeroed {
+        unsafe { write_bytes(virt_addr.as_mut_ptr::<u8>(), 0, PAGE_SIZE) };
+    }
+
+    Some((virt_addr, phys_addr))
+}
+
+/// Record a frame as issued and get its kernel address.
+///
+/// # Safety
+///
+/// - The frame allocator's lock must be held.
+unsafe fn issue(
+    phys_addr: PhysicalAddress,
+    kind: FrameKind,
+    owner: u16,
+) -> (VirtualAddress, PhysicalAddress) {
+    FRAME_TABLE.issue(phys_addr, kind, owner, 0);
+    (to_virt(phys_addr), phys_addr)
+}
+
+/// Get the address of a frame in the kernel's linear map of physical memory.
+pub fn to_virt(frame: PhysicalAddress) -> VirtualAddress {
+    unsafe { frame.add_offset(FRAME_ALLOCATOR.virt_offset()).as_virt() }
+}
+
+/// Take a frame from the zeroed pool.
+///
+/// # Safety
+///
+/// - The frame allocator's lock must be held.
+unsafe fn pop_zeroed() -> Option<PhysicalAddress> {
+    if ZERO_POOL_LEN == 0 {
+        return None;
+    }
+    ZERO_POOL_LEN -= 1;
+    Some(ZERO_POOL[ZERO_POOL_LEN])
+}
+
+/// Zero up to `count` free frames and add them to the zeroed pool. The frames
+/// are cleared without holding the lock, and the lock is held with interrupts
+/// disabled, since the idle thread calls this and is never scheduled while
+/// another thread waits for the lock. Returns the number of frames added.
+pub fn refill_zero_pool(count: usize) -> usize {
+    let mut added = 0;
+
+    while added < count {
+        let frame = critical_section!({
+            let _lock;
+            if DO_LOCK {
+                _lock = unsafe { FRAME_ALLOCATOR_MUTEX.lock() };
+            }
+            if unsafe { ZERO_POOL_LEN } == ZERO_POOL_SIZE {
+                break;
+            }
+            match unsafe { FRAME_ALLOCATOR.alloc() } {
+                Some(frame) => frame,
+                None => break,
+            }
+        });
+
+        unsafe { write_bytes(to_virt(frame).as_mut_ptr::<u8>(), 0, PAGE_SIZE) };
+
+        let added_frame = critical_section!({
+            let _lock;
+            if DO_LOCK {
+                _lock = unsafe { FRAME_ALLOCATOR_MUTEX.lock() };
+            }
+            unsafe {
+                if ZERO_POOL_LEN == ZERO_POOL_SIZE {
+                    FRAME_ALLOCATOR.free(frame);
+                    false
+                } else {
+                    if let Some(desc) = FRAME_TABLE.get_mut(frame) {
+                        desc.set_flags(FrameDescriptor::ZEROED);
+                    }
+                    ZERO_POOL[ZERO_POOL_LEN] = frame;
+                    ZERO_POOL_LEN += 1;
+                    true
+                }
+            }
+        });
+        if !added_frame {
+            break;
+        }
+
+        added += 1;
+    }
+
+    added
+}
+
+/// Number of frames in the zeroed pool.
+pub fn zero_pool_len() -> usize {
+    unsafe {
+        let _lock;
+        if DO_LOCK {
+            _lock = FRAME_ALLOCATOR_MUTEX.lock();
+        }
+        ZERO_POOL_LEN
+    }
+}
+
+/// Add a reference to an allocated frame, e.g. when mapping it a second time.
+/// Returns the new reference count.
+pub fn get_ref(frame: PhysicalAddress) -> usize {
     unsafe {
         let _lock;
         if DO_LOCK {
             _lock = FRAME_ALLOCATOR_MUTEX.lock();
         }
-        FRAME_ALLOCATOR.alloc().map(|phys_addr| {
-            (
-                phys_addr
-                    .add_offset(FRAME_ALLOCATOR.virt_offset())
-                    .as_virt(),
-                phys_addr,
-            )
-        })
+        FRAME_TABLE.get_ref(frame)
     }
 }
 
-/// Free a physical frame.
+/// Drop a reference to a physical frame. The frame is returned to the
+/// allocator once the last reference is dropped.
 ///
 /// # Safety
 ///
 /// - `frame` must be aligned.
-/// - `frame` must be unused/unmapped.
+/// - `frame` must be unused/unmapped by the caller.
 pub unsafe fn free(frame: PhysicalAddress) {
     let _lock;
     if DO_LOCK {
         _lock = FRAME_ALLOCATOR_MUTEX.lock();
     }
-    FRAME_ALLOCATOR.free(frame)
+    if FRAME_TABLE.put_ref(frame) == 0 {
+        FRAME_ALLOCATOR.free(frame)
+    }
+}
+
+/// Get a copy of the descriptor for the frame containing a physical address.
+pub fn descriptor(addr: PhysicalAddress) -> Option<FrameDescriptor> {
+    unsafe {
+        let _lock;
+        if DO_LOCK {
+            _lock = FRAME_ALLOCATOR_MUTEX.lock();
+        }
+        FRAME_TABLE.get(addr).copied()
+    }
+}
+
+/// Count the physical frames by use.
+pub fn stats() -> FrameStats {
+    unsafe {
+        let _lock;
+        if DO_LOCK {
+            _lock = FRAME_ALLOCATOR_MUTEX.lock();
+        }
+        FRAME_TABLE.stats()
+    }
+}
+
+/// Print a summary of physical memory usage to the console.
+pub fn dump() {
+    kprintln!("Physical frames:\n{}", stats());
+    kprintln!("Zeroed pool: {}/{}", zero_pool_len(), ZERO_POOL_SIZE);
 }
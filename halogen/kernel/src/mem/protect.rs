@@ -0,0 +1,154 @@
+//! Once boot is over, the kernel image is locked down. The boot-only `.init`
+//! section is unmapped and its frames are given to `phys`. Then
+//! every mapping in the kernel half is checked against these rules:
+//!
+//! - No page is both writable and executable.
+//! - The kernel text is never writable, including through an alias elsewhere in
+//!   the kernel half (e.g. a mapping of its frames made with `map`).
+//! - Nothing but the kernel text is executable, so released boot code can't run
+//!   again.
+
+use core::ptr::write_bytes;
+
+use halogen_common::mem::{Address, PhysicalAddress, Segment, VirtualAddress};
+
+use crate::{
+    error::KernelResult,
+    log::*,
+    mem::{
+        paging::{
+            flush_tlb, for_each_kernel_leaf, map, unmap, Permissions, Privilege, Scope,
+            KERNEL_ASID, PAGE_SIZE,
+        },
+        phys,
+        regions::{virtual_offset, IMAGE_INIT, IMAGE_TEXT, PHYSICAL_BASE, TEXT_SIZE},
+    },
+};
+
+/// A kernel mapping that breaks one of the rules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Violation {
+    /// The page is writable and executable.
+    WriteExecute(VirtualAddress),
+    /// The page is a writable alias of the kernel text.
+    WritableText(VirtualAddress),
+    /// The page is executable, but not part of the kernel text.
+    StrayExecute(VirtualAddress),
+}
+
+/// Returns true if `size` bytes at `phys_addr` overlap the frames of the
+/// kernel text.
+fn maps_text(phys_addr: PhysicalAddress, size: usize) -> bool {
+    let text = unsafe { Segment::from_size(PHYSICAL_BASE, TEXT_SIZE) };
+    phys_addr < text.end && text.start < phys_addr + size
+}
+
+/// Release the `.init` section: unmap it, so its code and data are gone, and
+/// give its frames to `phys`. Frames are reached through the linear map, so
+/// each released frame is mapped again, read-write and zeroed, just before it
+/// is handed over; the rest stay unmapped. Returns the number of frames
+/// released.
+///
+/// # Safety
+///
+/// - Nothing in the section may be used again, i.e. boot must be over and the
+///   boot stack abandoned.
+pub unsafe fn release_init() -> KernelResult<usize> {
+    let init = *IMAGE_INIT;
+    unmap(init)?;
+    flush_tlb();
+
+    let mut count = 0;
+    for virt_addr in init.iter().step_by(PAGE_SIZE) {
+        let virt_addr = VirtualAddress(virt_addr);
+        let frame = virt_addr.add_offset(-virtual_offset()).as_phys();
+
+        map(
+            Some(virt_addr),
+            Some(frame),
+            PAGE_SIZE,
+            Permissions::ReadWrite,
+            Scope::Global,
+            Privilege::Kernel,
+            KERNEL_ASID,
+        )?;
+        write_bytes(virt_addr.as_mut_ptr::<u8>(), 0, PAGE_SIZE);
+
+        if phys::release_reserved(frame) {
+            count += 1;
+        } else {
+            unmap(Segment::from_size(virt_addr, PAGE_SIZE))?;
+        }
+    }
+    flush_tlb();
+
+    Ok(count)
+}
+
+/// Remove write access from every kernel mapping of the kernel text. Returns
+/// the number of pages changed.
+pub fn protect_text() -> usize {
+    let mut count = 0;
+
+    for_each_kernel_leaf(|_, phys_addr, size, entry| {
+        if entry.is_writable() && maps_text(phys_addr, size) {
+            entry.clear_writable();
+            count += 1;
+        }
+    });
+
+    if count > 0 {
+        flush_tlb();
+    }
+    count
+}
+
+/// Call `f` with every kernel mapping that breaks a rule. Nothing is allocated,
+/// but the kernel root table is locked, so `f` must not map memory.
+pub fn audit(mut f: impl FnMut(Violation)) {
+    let text = *IMAGE_TEXT;
+
+    for_each_kernel_leaf(|virt_addr, phys_addr, size, entry| {
+        if entry.is_writable() && entry.is_executable() {
+            f(Violation::WriteExecute(virt_addr));
+        } else if entry.is_writable() && maps_text(phys_addr, size) {
+            f(Violation::WritableText(virt_addr));
+        } else if entry.is_executable() && !text.contains(virt_addr) {
+            f(Violation::StrayExecute(virt_addr));
+        }
+    });
+}
+
+/// Assert that no kernel mapping breaks a rule, logging each that does.
+pub fn check() {
+    let mut count = 0;
+    audit(|violation| {
+        error!("Kernel mapping violates W^X: {:?}", violation);
+        count += 1;
+    });
+
+    assert_eq!(0, count, "{} kernel mappings violate W^X", count);
+}
+
+/// Lock down the kernel image after boot: release `.init`, remove writable
+/// aliases of the text, then check the rules.
+///
+/// # Safety
+///
+/// - Boot must be over, see `release_init`.
+pub unsafe fn finish_boot() {
+    match release_init() {
+        Ok(frames) => info!("Released {} frames of boot-only memory", frames),
+        Err(why) => warn!("Failed to release boot-only memory: {:?}", why),
+    }
+
+    let aliases = protect_text();
+    if aliases > 0 {
+        warn!(
+            "Removed write access from {} aliases of kernel text",
+            aliases
+        );
+    }
+
+    check();
+}
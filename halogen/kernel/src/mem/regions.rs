@@ -1,21 +1,40 @@
 //! This module describes the kernel address-space layout. They are calculated
-//! based on the constants and static variables (set during boot).
+//! based on the constants, the paging mode, and static variables (set during
+//! boot).
 
 use halogen_common::mem::{Address, PhysicalAddress, Segment, VirtualAddress, GIB};
 
 pub use super::addr_space::AddressSpace;
-use super::paging::PAGE_SIZE;
+use super::paging::{mode, PAGE_SIZE};
 
-/// Upper region of a 39-bit address-space. This is calculated as:
+/// The kernel image is linked at the upper half of a 39-bit address-space (see
+/// `link.ld`). This is calculated as:
 ///
 /// - `2^38` = `0x40_0000_0000` is the halfway point of the address-space.
 /// - Bits 63-39 must match bit 38, so set these bits to 1.
 /// - `0x40_0000_0000 + 0xFFFF_FF80_0000_0000`.
-pub const KERNEL_SPACE_START: VirtualAddress = VirtualAddress(0xFFFF_FFC0_0000_0000);
+///
+/// These are the top 256 GiB of every mode, so the image stays here when a
+/// larger mode is used.
+pub const KERNEL_IMAGE_START: VirtualAddress = VirtualAddress(0xFFFF_FFC0_0000_0000);
+
+/// Start of the upper (kernel) half of the address-space in the current mode.
+/// Bits above the highest virtual address bit must match it, so these are all
+/// set.
+pub fn kernel_space_start() -> VirtualAddress {
+    VirtualAddress(usize::MAX << (mode().va_bits() - 1))
+}
+
+/// The size of the address-space available to the kernel (upper-half of the
+/// current mode).
+pub fn kernel_space_size() -> usize {
+    1 << (mode().va_bits() - 1)
+}
 
-/// The size of the address-space available to the kernel (upper-half of
-/// 39-bits).
-pub const KERNEL_SPACE_SIZE: usize = 1 << 38;
+/// End of the lower (user) half of the address-space in the current mode.
+pub fn user_space_end() -> VirtualAddress {
+    VirtualAddress(1 << (mode().va_bits() - 1))
+}
 
 /// Each thread (kernel or user) has a kernel stack with at most 8 MiB to map.
 pub const STACK_SIZE: usize = 8 * GIB;
@@ -38,12 +57,14 @@ pub static mut TEXT_SIZE: usize = 0;
 pub static mut RODATA_SIZE: usize = 0;
 /// Size of the read-write section of the kernel image (set during bootstrap)
 pub static mut RWDATA_SIZE: usize = 0;
+/// Size of the boot-only section of the kernel image (set during bootstrap)
+pub static mut INIT_SIZE: usize = 0;
 
 /// Get the virtual offset from physical memory. If the virtual base is greater
 /// than the physical base (almost always the case), this is positive.
 #[inline]
 pub fn virtual_offset() -> isize {
-    KERNEL_SPACE_START.offset(unsafe { PHYSICAL_BASE })
+    KERNEL_IMAGE_START.offset(unsafe { PHYSICAL_BASE })
 }
 
 /// Provides an easier way to declare region constants.
@@ -56,29 +77,46 @@ macro_rules! region {
 }
 
 // The bounds of these regions are calculated on first access (`lazy_static`)
-// based on the constants above set during bootstrap.
+// based on the constants above set during bootstrap, and on the paging mode.
 //
 // TODO: This could be done nicely with a TT-muncher macro.
 
-// These four regions create a linear mapping of the physical memory.
-region!(IMAGE_TEXT, KERNEL_SPACE_START, unsafe { TEXT_SIZE });
+// These five regions create a linear mapping of the physical memory.
+region!(IMAGE_TEXT, KERNEL_IMAGE_START, unsafe { TEXT_SIZE });
 region!(IMAGE_RO, IMAGE_TEXT.end, unsafe { RODATA_SIZE });
 region!(IMAGE_RW, IMAGE_RO.end, unsafe { RWDATA_SIZE });
-region!(FRAME_ALLOC, IMAGE_RW.end, unsafe { FREE_SIZE });
+region!(IMAGE_INIT, IMAGE_RW.end, unsafe { INIT_SIZE });
+region!(FRAME_ALLOC, IMAGE_INIT.end, unsafe { FREE_SIZE });
 
 // The rest of the regions are not necessarily backed by physical memory.
 region!(STACK, FRAME_ALLOC.end, STACK_SIZE);
 region!(HEAP, STACK.end, HEAP_SIZE);
-region!(VIRT_SPACE, HEAP.end, VirtualAddress(usize::MAX) - HEAP.end);
+
+lazy_static::lazy_static! {
+    /// Dynamic mappings use the largest unused part of the kernel half: the
+    /// space below the image in modes larger than Sv39, or else the space
+    /// above the heap.
+    pub static ref VIRT_SPACE: Segment<VirtualAddress> =
+        if usize::from(kernel_space_start()) < usize::from(KERNEL_IMAGE_START) {
+            Segment::new(kernel_space_start(), KERNEL_IMAGE_START)
+        } else {
+            Segment::from_size(HEAP.end, VirtualAddress(usize::MAX) - HEAP.end)
+        };
+}
 
 /// Regions of the kernel address-space.
 #[derive(Debug, Copy, Clone)]
 pub enum Region {
     Zero,
     User,
+    /// Non-canonical addresses between the two halves.
+    Hole,
     ImageText,
     ImageRo,
     ImageRw,
+    /// The boot-only section, which is unmapped after init, except for the
+    /// frames given to `phys`.
+    ImageInit,
     Heap,
     Stack,
     Physical,
@@ -90,14 +128,18 @@ impl From<VirtualAddress> for Region {
         let virt_addr_i: usize = virt_addr.into();
         if virt_addr_i <= PAGE_SIZE {
             Region::Zero
-        } else if virt_addr_i < KERNEL_SPACE_START.into() {
+        } else if virt_addr_i < user_space_end().into() {
             Region::User
+        } else if virt_addr_i < kernel_space_start().into() {
+            Region::Hole
         } else if IMAGE_TEXT.contains(virt_addr) {
             Region::ImageText
         } else if IMAGE_RO.contains(virt_addr) {
             Region::ImageRo
         } else if IMAGE_RW.contains(virt_addr) {
             Region::ImageRw
+        } else if IMAGE_INIT.contains(virt_addr) {
+            Region::ImageInit
         } else if HEAP.contains(virt_addr) {
             Region::Heap
         } else if STACK.contains(virt_addr) {
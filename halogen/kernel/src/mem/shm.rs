@@ -0,0 +1,219 @@
+//! Shared memory objects let processes exchange data without going through the
+//! kernel. An object is a set of physical frames identified by a key chosen by
+//! its creator. Any process that knows the key can map the object into its own
+//! address-space, either at an address of its choosing or one picked by the
+//! kernel.
+//!
+//! Each mapping holds a reference to every frame of the object. The object is
+//! destroyed and its frames returned to the frame allocator when its last
+//! mapping is removed.
+
+use alloc::{collections::BTreeMap, vec::Vec};
+
+use halogen_common::{
+    align_up,
+    mem::{
+        alloc::Tag, Address, FrameKind, PhysicalAddress, Resource, Segment, VirtualAddress, GIB,
+    },
+};
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+use crate::{
+    error::{KernelError, KernelResult},
+    kerror,
+    log::*,
+    mem::{
+        heap,
+        paging::{Permissions, PAGE_SIZE},
+        phys, AddressSpace,
+    },
+};
+
+/// Region of each user address-space in which the kernel picks addresses for
+/// shared memory mappings.
+pub const SHM_REGION: Segment<VirtualAddress> = Segment::new(
+    VirtualAddress(0x10_0000_0000),
+    VirtualAddress(0x20_0000_0000),
+);
+
+/// Largest shared memory object that can be created.
+pub const SHM_MAX_SIZE: usize = GIB;
+
+lazy_static! {
+    static ref OBJECTS: Mutex<BTreeMap<usize, SharedMemory>> = Mutex::new(BTreeMap::new());
+}
+
+/// A set of frames that can be mapped into multiple address-spaces.
+struct SharedMemory {
+    frames: Vec<PhysicalAddress>,
+    mappings: usize,
+}
+
+impl SharedMemory {
+    /// Allocate and zero the frames for a new object on behalf of the
+    /// address-space with ID `owner`.
+    fn try_new(size: usize, owner: u16) -> KernelResult<SharedMemory> {
+        let mut shm = SharedMemory {
+            frames: Vec::with_capacity(size / PAGE_SIZE),
+            mappings: 0,
+        };
+
+        for _ in 0..(size / PAGE_SIZE) {
+            match phys::alloc_zeroed_for(FrameKind::User, owner) {
+                Some((_, phys_addr)) => shm.frames.push(phys_addr),
+                None => {
+                    shm.release();
+                    return kerror!(KernelError::OutOfPhysicalFrames).into();
+                }
+            }
+        }
+
+        Ok(shm)
+    }
+
+    /// The size of the object in bytes.
+    fn size(&self) -> usize {
+        self.frames.len() * PAGE_SIZE
+    }
+
+    /// Map every frame into an address-space starting at `base`, adding a
+    /// reference to each.
+    fn map_into(&mut self, space: &mut AddressSpace, base: VirtualAddress) -> KernelResult<()> {
+        for (i, &frame) in self.frames.iter().enumerate() {
+            let virt_addr = base + i * PAGE_SIZE;
+            if let Err(why) = space.map(virt_addr, frame, Permissions::ReadWrite) {
+                // Roll back the pages mapped so far.
+                self.unmap_from(space, base, i);
+                return Err(why);
+            }
+            phys::get_ref(frame);
+        }
+
+        self.mappings += 1;
+        Ok(())
+    }
+
+    /// Unmap the first `count` frames from an address-space starting at `base`
+    /// and drop their references.
+    fn unmap_from(&self, space: &mut AddressSpace, base: VirtualAddress, count: usize) {
+        for i in 0..count.min(self.frames.len()) {
+            if let Some(frame) = space.unmap(base + i * PAGE_SIZE) {
+                unsafe { phys::free(frame) };
+            }
+        }
+    }
+
+    /// Drop the object's own reference to each of its frames.
+    fn release(&mut self) {
+        for frame in self.frames.drain(..) {
+            unsafe { phys::free(frame) };
+        }
+    }
+}
+
+/// Create a shared memory object of at least `size` bytes identified by `key`
+/// and map it into `space`. If no address is provided, one is chosen by the
+/// kernel. Returns the address of the mapping.
+pub fn create(
+    key: usize,
+    size: usize,
+    space: &mut AddressSpace,
+    addr: Option<VirtualAddress>,
+) -> KernelResult<VirtualAddress> {
+    let _tag = heap::tag(Tag::SharedMemory);
+    let size = align_up!(size.max(1), PAGE_SIZE);
+    if size > SHM_MAX_SIZE {
+        return kerror!(KernelError::OutOfPhysicalFrames).into();
+    }
+
+    let mut objects = OBJECTS.lock();
+    if objects.contains_key(&key) {
+        return kerror!(KernelError::SharedMemoryExists).into();
+    }
+
+    if !space.account.can_charge(Resource::Resident, size) {
+        return kerror!(KernelError::OutOfMemory).into();
+    }
+
+    let base = space.placement(SHM_REGION, addr, size)?;
+    let mut shm = SharedMemory::try_new(size, space.id as u16)?;
+
+    if let Err(why) = shm.map_into(space, base) {
+        shm.release();
+        return Err(why);
+    }
+
+    info!(
+        "Create shared memory {} ({} bytes) at {:?} in address-space {}",
+        key, size, base, space.id
+    );
+
+    objects.insert(key, shm);
+    Ok(base)
+}
+
+/// Map an existing shared memory object into `space`. If no address is
+/// provided, one is chosen by the kernel. Returns the address of the mapping.
+pub fn map(
+    key: usize,
+    space: &mut AddressSpace,
+    addr: Option<VirtualAddress>,
+) -> KernelResult<VirtualAddress> {
+    let mut objects = OBJECTS.lock();
+    let shm = objects
+        .get_mut(&key)
+        .ok_or_else(|| kerror!(KernelError::NoSuchSharedMemory))?;
+
+    let base = space.placement(SHM_REGION, addr, shm.size())?;
+    shm.map_into(space, base)?;
+
+    trace!(
+        "Map shared memory {} at {:?} in address-space {}",
+        key,
+        base,
+        space.id
+    );
+
+    Ok(base)
+}
+
+/// Remove the mapping of a shared memory object at `base` from `space`. The
+/// object is destroyed when its last mapping is removed.
+pub fn unmap(key: usize, space: &mut AddressSpace, base: VirtualAddress) -> KernelResult<()> {
+    let mut objects = OBJECTS.lock();
+    let shm = objects
+        .get_mut(&key)
+        .ok_or_else(|| kerror!(KernelError::NoSuchSharedMemory))?;
+
+    // Make sure the object is actually mapped here before tearing it down.
+    match space.root.translate(base) {
+        Some((frame, _, _, _)) if shm.frames.first() == Some(&frame) => {}
+        _ => return kerror!(KernelError::InvalidMapping).into(),
+    }
+
+    let count = shm.frames.len();
+    shm.unmap_from(space, base, count);
+    shm.mappings -= 1;
+
+    trace!(
+        "Unmap shared memory {} at {:?} in address-space {}",
+        key,
+        base,
+        space.id
+    );
+
+    if shm.mappings == 0 {
+        info!("Destroy shared memory {}", key);
+        if let Some(mut shm) = objects.remove(&key) {
+            shm.release();
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns true if a shared memory object exists for a key.
+pub fn exists(key: usize) -> bool {
+    OBJECTS.lock().contains_key(&key)
+}
@@ -8,9 +8,12 @@ use super::paging::Privilege;
 use crate::{
     error::{KernelError, KernelResult},
     kerror,
+    log::*,
     mem::{
-        paging::{map, Permissions, Scope, PAGE_SIZE},
+        paging::{map, translate, unmap, Permissions, Scope, KERNEL_ASID, PAGE_SIZE},
+        phys,
         regions::STACK,
+        AddressSpace,
     },
 };
 
@@ -22,7 +25,12 @@ lazy_static! {
 }
 
 /// Kernel stack.
-pub struct Stack(Segment<VirtualAddress>);
+pub struct Stack {
+    segment: Segment<VirtualAddress>,
+    /// Base of the range taken from the stack allocator, including the guard
+    /// pages, if the stack is in kernel space.
+    guard_base: Option<VirtualAddress>,
+}
 
 unsafe impl Sync for Stack {}
 unsafe impl Send for Stack {}
@@ -35,44 +43,99 @@ impl Stack {
             .alloc(size + 2 * PAGE_SIZE)
             .ok_or_else(|| kerror!(KernelError::OutOfVirtualAddresses))?;
 
-        unsafe {
+        let segment = unsafe {
             Stack::new(
                 guard_base.add_offset(PAGE_SIZE as isize),
                 size,
                 Scope::Global,
                 Privilege::Kernel,
             )
+        };
+
+        match segment {
+            Ok(segment) => {
+                Ok(Stack {
+                    segment,
+                    guard_base: Some(guard_base),
+                })
+            }
+            Err(why) => {
+                let _ = STACK_ALLOCATOR.lock().free(guard_base);
+                Err(why)
+            }
         }
     }
 
-    /// Allocate a new stack for use in userspace.
+    /// Allocate a new stack for use in userspace, mapping the top `init_size`
+    /// bytes of `segment` into `space`. Fails with `OutOfMemory` if the
+    /// address-space cannot be charged for it.
     ///
     /// TODO: Support demand paging.
-    ///
-    /// # Safety
-    ///
-    /// - The region described by `base` and `size` must not conflict with any
-    ///   existing kernel mappings.
-    pub unsafe fn try_new_user(
+    pub fn try_new_user(
+        space: &mut AddressSpace,
         segment: Segment<VirtualAddress>,
         init_size: usize,
     ) -> KernelResult<Stack> {
-        Stack::new(segment.start, init_size, Scope::Local, Privilege::User)
+        let init_size = init_size.min(segment.size());
+        space.map_anonymous(
+            Some(segment.end - init_size),
+            init_size,
+            Permissions::ReadWrite,
+        )?;
+        Ok(Stack {
+            segment,
+            guard_base: None,
+        })
     }
 
-    /// Create a new stack.
+    /// Map a new kernel stack.
     unsafe fn new(
         base: VirtualAddress,
         size: usize,
         scope: Scope,
         prv: Privilege,
-    ) -> KernelResult<Stack> {
-        map(Some(base), None, size, Permissions::ReadWrite, scope, prv)?;
-        Ok(Stack(Segment::from_size(base, size)))
+    ) -> KernelResult<Segment<VirtualAddress>> {
+        map(
+            Some(base),
+            None,
+            size,
+            Permissions::ReadWrite,
+            scope,
+            prv,
+            KERNEL_ASID,
+        )?;
+        Ok(Segment::from_size(base, size))
     }
 
     /// Get a pointer to the top of the stack.
     pub fn top(&self) -> *mut u8 {
-        self.0.end.as_mut_ptr()
+        self.segment.end.as_mut_ptr()
+    }
+}
+
+impl Drop for Stack {
+    /// Free the frames and addresses of a kernel stack. User stacks are freed
+    /// with the address-space they are mapped in.
+    fn drop(&mut self) {
+        let guard_base = match self.guard_base {
+            Some(base) => base,
+            None => return,
+        };
+
+        unsafe {
+            for addr in self.segment.iter().step_by(PAGE_SIZE) {
+                if let Some((frame, ..)) = translate(VirtualAddress(addr)) {
+                    phys::free(frame);
+                }
+            }
+
+            if let Err(why) = unmap(self.segment) {
+                error!("Failed to unmap stack {}: {:?}", self.segment, why);
+            }
+        }
+
+        if let Err(why) = STACK_ALLOCATOR.lock().free(guard_base) {
+            error!("Failed to free stack {}: {:?}", self.segment, why);
+        }
     }
 }
@@ -0,0 +1,227 @@
+//! Anonymous user pages can be swapped out to a swap area on a block device
+//! when physical frames run low. The swap area is divided into page-sized
+//! slots. A swapped-out page is left with an invalid PTE that records its slot
+//! (see `PageTableEntry::set_swapped`), so the next access faults and `fault`
+//! reads it back into a new frame.
+//!
+//! Only frames mapped once into a single user address-space are swapped. Frames
+//! that are shared or pinned stay resident. Cold pages are chosen by the clock
+//! in `task::oom`.
+
+use alloc::boxed::Box;
+use core::slice::{from_raw_parts, from_raw_parts_mut};
+
+use halogen_common::mem::{Address, FrameDescriptor, FrameKind, Resource, SwapMap, VirtualAddress};
+use spin::Mutex;
+
+use crate::{
+    error::{KernelError, KernelResult},
+    io::{block::BlockDevice, virtio},
+    kerror, kprintln,
+    log::*,
+    mem::{
+        paging::{PAGE_MASK, PAGE_SIZE},
+        phys, AddressSpace,
+    },
+};
+
+static SWAP: Mutex<Option<SwapArea>> = Mutex::new(None);
+
+/// A block device divided into page-sized slots.
+struct SwapArea {
+    device: Box<dyn BlockDevice>,
+    slots: SwapMap,
+    blocks_per_slot: usize,
+}
+
+impl SwapArea {
+    fn new(device: Box<dyn BlockDevice>) -> SwapArea {
+        let blocks_per_slot = PAGE_SIZE / device.block_size();
+        SwapArea {
+            slots: SwapMap::new(device.blocks() / blocks_per_slot),
+            device,
+            blocks_per_slot,
+        }
+    }
+}
+
+/// Use the first virtio block device as the swap area, if there is one.
+pub fn init() {
+    match virtio::probe() {
+        Some(device) => {
+            if let Err(why) = set_device(Box::new(device)) {
+                warn!("Failed to enable swap: {:?}", why);
+            }
+        }
+        None => info!("No swap device; swap is disabled"),
+    }
+}
+
+/// Use a block device as the swap area. Fails if pages are swapped out to the
+/// current one.
+pub fn set_device(device: Box<dyn BlockDevice>) -> KernelResult<()> {
+    let mut swap = SWAP.lock();
+    if matches!(swap.as_ref(), Some(area) if area.slots.used() > 0) {
+        return kerror!(KernelError::BlockDeviceInit).into();
+    }
+
+    let area = SwapArea::new(device);
+    info!("Enable swap: {}", area.slots);
+    *swap = Some(area);
+    Ok(())
+}
+
+/// Stop swapping and return the device used for the swap area. Fails if pages
+/// are swapped out to it.
+pub fn remove_device() -> KernelResult<Option<Box<dyn BlockDevice>>> {
+    let mut swap = SWAP.lock();
+    if matches!(swap.as_ref(), Some(area) if area.slots.used() > 0) {
+        return kerror!(KernelError::BlockDeviceInit).into();
+    }
+
+    Ok(swap.take().map(|area| area.device))
+}
+
+/// Returns true if there is a swap area.
+pub fn is_enabled() -> bool {
+    SWAP.lock().is_some()
+}
+
+/// Get the number of slots in use and the total number of slots.
+pub fn usage() -> Option<(usize, usize)> {
+    SWAP.lock()
+        .as_ref()
+        .map(|area| (area.slots.used(), area.slots.len()))
+}
+
+/// Returns true if the frame can be swapped out: it is user memory mapped
+/// exactly once and not pinned.
+fn is_swappable(desc: &FrameDescriptor) -> bool {
+    matches!(desc.kind(), FrameKind::User)
+        && desc.refcount() == 1
+        && !desc.has_flags(FrameDescriptor::PINNED)
+}
+
+/// Write the page at `virt_addr` to the swap area and free its frame. Returns
+/// false if the page is not mapped or its frame can't be swapped out.
+pub fn evict(space: &mut AddressSpace, virt_addr: VirtualAddress) -> KernelResult<bool> {
+    let entry = match space.root.leaf_entry(virt_addr) {
+        Some(entry) if entry.is_mapped() => entry,
+        _ => return Ok(false),
+    };
+
+    // A page of a 64 KiB run has its own frame within the run.
+    let frame = match space.root.translate(virt_addr) {
+        Some((frame, _, _, _)) => frame,
+        None => return Ok(false),
+    };
+    match phys::descriptor(frame) {
+        Some(desc) if is_swappable(&desc) => {}
+        _ => return Ok(false),
+    }
+
+    let mut swap = SWAP.lock();
+    let area = swap
+        .as_mut()
+        .ok_or_else(|| kerror!(KernelError::OutOfSwap))?;
+    let slot = area
+        .slots
+        .alloc()
+        .ok_or_else(|| kerror!(KernelError::OutOfSwap))?;
+
+    let page = unsafe { from_raw_parts(phys::to_virt(frame).as_ptr::<u8>(), PAGE_SIZE) };
+    if let Err(why) = area.device.write(slot * area.blocks_per_slot, page) {
+        area.slots.free(slot);
+        return Err(why);
+    }
+
+    // The rest of the run stays mapped as ordinary pages.
+    space.root.demote(virt_addr);
+    entry.set_swapped(slot);
+    space.account.uncharge(Resource::Resident, PAGE_SIZE);
+    unsafe { phys::free(frame) };
+
+    Ok(true)
+}
+
+/// Handle a page-fault at `virt_addr` in a user address-space. A swapped-out
+/// page is read back into a new frame. Returns false if the fault can't be
+/// resolved, i.e. the access was invalid.
+pub fn fault(
+    space: &mut AddressSpace,
+    virt_addr: VirtualAddress,
+    write: bool,
+) -> KernelResult<bool> {
+    let page = VirtualAddress(usize::from(virt_addr) & PAGE_MASK);
+    let entry = match space.root.leaf_entry(page) {
+        Some(entry) => entry,
+        None => return Ok(false),
+    };
+
+    if entry.is_mapped() {
+        return Ok(entry.mark_accessed(write));
+    }
+
+    let slot = match entry.swap_slot() {
+        Some(slot) => slot,
+        None => return Ok(false),
+    };
+
+    // The page stays swapped out if it would take the space over its limit.
+    if !space.account.try_charge(Resource::Resident, PAGE_SIZE) {
+        return kerror!(KernelError::OutOfMemory).into();
+    }
+
+    let (frame_virt, frame) = match phys::alloc_for(FrameKind::User, space.id as u16) {
+        Some(frame) => frame,
+        None => {
+            space.account.uncharge(Resource::Resident, PAGE_SIZE);
+            return kerror!(KernelError::OutOfPhysicalFrames).into();
+        }
+    };
+
+    let mut swap = SWAP.lock();
+    let area = swap.as_mut().expect("page swapped out without a swap area");
+
+    let buf = unsafe { from_raw_parts_mut(frame_virt.as_mut_ptr::<u8>(), PAGE_SIZE) };
+    if let Err(why) = area.device.read(slot * area.blocks_per_slot, buf) {
+        unsafe { phys::free(frame) };
+        space.account.uncharge(Resource::Resident, PAGE_SIZE);
+        return Err(why);
+    }
+
+    area.slots.free(slot);
+    entry.swap_in(frame);
+
+    Ok(true)
+}
+
+/// Read back every swapped-out page of `size` bytes at `base`, so the kernel
+/// can access them directly.
+pub fn fault_in(space: &mut AddressSpace, base: VirtualAddress, size: usize) -> KernelResult<()> {
+    let first = usize::from(base) & PAGE_MASK;
+    let end = usize::from(base) + size;
+
+    for page in (first..end).step_by(PAGE_SIZE) {
+        fault(space, VirtualAddress(page), false)?;
+    }
+
+    Ok(())
+}
+
+/// Free the slot of a swapped-out page that is being unmapped.
+pub fn release(slot: usize) {
+    if let Some(area) = SWAP.lock().as_mut() {
+        if !area.slots.free(slot) {
+            warn!("Release of free swap slot {}", slot);
+        }
+    }
+}
+
+/// Print the state of the swap area to the console.
+pub fn dump() {
+    match SWAP.lock().as_ref() {
+        Some(area) => kprintln!("Swap: {}", area.slots),
+        None => kprintln!("Swap is disabled"),
+    }
+}
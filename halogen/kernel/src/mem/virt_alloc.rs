@@ -1,9 +1,17 @@
-use halogen_common::mem::{alloc::SegmentAllocator, VirtualAddress};
+use halogen_common::mem::{
+    alloc::{SegmentAllocator, Tag},
+    VirtualAddress,
+};
 use lazy_static::lazy_static;
 use spin::Mutex;
 
 use super::regions::VIRT_SPACE;
-use crate::{log::*, mem::paging::PAGE_SIZE};
+use crate::{
+    error::{KernelError, KernelResult},
+    kerror,
+    log::*,
+    mem::{heap, paging::PAGE_SIZE},
+};
 
 lazy_static! {
     static ref VIRTUAL_ALLOCATOR: Mutex<SegmentAllocator<VirtualAddress>> = {
@@ -14,10 +22,23 @@ lazy_static! {
 
 /// Allocate an unused virtual address.
 pub fn virt_addr_alloc(size: usize) -> Option<VirtualAddress> {
+    let _tag = heap::tag(Tag::Paging);
     VIRTUAL_ALLOCATOR.lock().alloc(size)
 }
 
-/// Free an unmapped virtual address.
-pub fn virt_addr_free(addr: VirtualAddress) {
-    VIRTUAL_ALLOCATOR.lock().free(addr)
+/// Allocate an unused virtual address aligned to `align`.
+pub fn virt_addr_alloc_aligned(size: usize, align: usize) -> Option<VirtualAddress> {
+    let _tag = heap::tag(Tag::Paging);
+    VIRTUAL_ALLOCATOR.lock().alloc_aligned(size, align)
+}
+
+/// Free an unmapped virtual address. It must be the start of an allocation.
+pub fn virt_addr_free(addr: VirtualAddress) -> KernelResult<()> {
+    match VIRTUAL_ALLOCATOR.lock().free(addr) {
+        Ok(_) => Ok(()),
+        Err(why) => {
+            error!("Invalid free of virtual address {:?}: {:?}", addr, why);
+            kerror!(KernelError::InvalidVirtualFree).into()
+        }
+    }
 }
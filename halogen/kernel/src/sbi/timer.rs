@@ -4,8 +4,22 @@ use crate::{arch::TIMER_FREQ_HZ, log::*};
 const TIMER_EXT_ID: usize = 0x54494D45;
 const SET_TIMER_FUNC_ID: usize = 0;
 
+/// Convert microseconds to timer cycles. Times too far off to count in cycles
+/// saturate to `usize::MAX`, which never comes.
 fn us_to_cycles(us: usize) -> usize {
-    us * TIMER_FREQ_HZ / 1_000_000
+    match us.checked_mul(TIMER_FREQ_HZ) {
+        Some(scaled) => scaled / 1_000_000,
+        None => usize::MAX,
+    }
+}
+
+fn cycles_to_us(cycles: usize) -> usize {
+    cycles / (TIMER_FREQ_HZ / 1_000_000)
+}
+
+/// Get the time since boot in microseconds.
+pub fn now_us() -> usize {
+    cycles_to_us(riscv::register::time::read())
 }
 
 /// Set the timer such that it will trigger an interrupt in `delay_us`
@@ -17,10 +31,18 @@ pub fn set(delay_us: usize) {
             if delay_us > 0 {
                 trace!("Set timer +{} us", delay_us);
             }
-            riscv::register::time::read().wrapping_add(us_to_cycles(delay_us))
+            riscv::register::time::read().saturating_add(us_to_cycles(delay_us))
         }
     };
 
     let args = [time, 0, 0, 0, 0, 0];
     sbi_ecall(TIMER_EXT_ID, SET_TIMER_FUNC_ID, args);
 }
+
+/// Set the timer such that it will trigger an interrupt at `time_us`
+/// microseconds since boot. A time of `usize::MAX`, or any too far off to
+/// count in cycles, will disable the timer.
+pub fn set_at(time_us: usize) {
+    let args = [us_to_cycles(time_us), 0, 0, 0, 0, 0];
+    sbi_ecall(TIMER_EXT_ID, SET_TIMER_FUNC_ID, args);
+}
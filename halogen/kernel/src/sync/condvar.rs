@@ -0,0 +1,67 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::MutexGuard;
+use crate::task::WaitQueue;
+
+/// Condition variable, for threads to wait with a `Mutex` unlocked until
+/// another thread notifies them. Like any condition variable, a waiter may
+/// wake without the condition it waits for holding, so it should check it
+/// again, e.g. with `wait_while`.
+pub struct Condvar {
+    /// Number of notifications, so a notification between unlocking the mutex
+    /// and blocking isn't lost.
+    notified: AtomicUsize,
+    waiters: WaitQueue,
+}
+
+impl Condvar {
+    /// Create a condition variable.
+    pub const fn new() -> Condvar {
+        Condvar {
+            notified: AtomicUsize::new(0),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Unlock the mutex of `guard` and block until notified, then lock it
+    /// again.
+    pub fn wait<'a, T: ?Sized>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = guard.mutex();
+        let notified = self.notified.load(Ordering::Acquire);
+        drop(guard);
+
+        self.waiters
+            .wait_until(|| self.notified.load(Ordering::Acquire) != notified);
+        mutex.lock()
+    }
+
+    /// Wait while `cond` holds for the data of the mutex.
+    pub fn wait_while<'a, T: ?Sized>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut cond: impl FnMut(&mut T) -> bool,
+    ) -> MutexGuard<'a, T> {
+        while cond(&mut *guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    /// Wake the thread that has waited the longest.
+    pub fn notify_one(&self) {
+        self.notified.fetch_add(1, Ordering::Release);
+        self.waiters.wake_one();
+    }
+
+    /// Wake every waiting thread.
+    pub fn notify_all(&self) {
+        self.notified.fetch_add(1, Ordering::Release);
+        self.waiters.wake_all();
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Condvar {
+        Condvar::new()
+    }
+}
@@ -0,0 +1,72 @@
+//! Synchronization primitives that block waiting threads on a `WaitQueue`
+//! instead of spinning, so contention doesn't burn whole quanta. They need a
+//! running thread to block; before the executor runs one, they spin.
+//!
+//! In debug builds, a thread that takes a `Mutex` or a write lock of an
+//! `RwLock` it already holds panics rather than blocking forever.
+
+#[cfg(debug_assertions)]
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+#[cfg(debug_assertions)]
+use crate::task::executor::try_tid;
+
+/// Condition variables.
+mod condvar;
+/// Sleeping mutual exclusion lock.
+mod mutex;
+/// Sleeping reader-writer lock.
+mod rwlock;
+/// Counting semaphores.
+mod semaphore;
+
+pub use condvar::Condvar;
+pub use mutex::{Mutex, MutexGuard};
+pub use rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use semaphore::Semaphore;
+
+/// The thread holding a lock, tracked in debug builds to detect recursion.
+struct Owner {
+    /// The thread ID plus one, or 0 if no thread holds the lock.
+    #[cfg(debug_assertions)]
+    tid: AtomicUsize,
+}
+
+impl Owner {
+    const fn new() -> Owner {
+        Owner {
+            #[cfg(debug_assertions)]
+            tid: AtomicUsize::new(0),
+        }
+    }
+
+    /// Panic if the calling thread holds the lock.
+    #[inline]
+    #[allow(unused_variables)]
+    fn check_recursion(&self, lock: &str) {
+        #[cfg(debug_assertions)]
+        if let Some(tid) = try_tid() {
+            assert!(
+                self.tid.load(Ordering::Relaxed) != tid + 1,
+                "thread {} locked a {} it already holds",
+                tid,
+                lock
+            );
+        }
+    }
+
+    /// Record the calling thread as the owner.
+    #[inline]
+    fn set(&self) {
+        #[cfg(debug_assertions)]
+        self.tid
+            .store(try_tid().map_or(0, |tid| tid + 1), Ordering::Relaxed);
+    }
+
+    /// Record that no thread holds the lock.
+    #[inline]
+    fn clear(&self) {
+        #[cfg(debug_assertions)]
+        self.tid.store(0, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,159 @@
+use core::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use super::Owner;
+use crate::task::WaitQueue;
+
+/// State of a lock held by a writer; otherwise it is the number of readers.
+const WRITER: usize = usize::MAX;
+
+/// Reader-writer lock that blocks waiting threads instead of spinning. Any
+/// number of readers or a single writer hold it at a time. Readers are not held
+/// back for a waiting writer, so a steady stream of them can starve writers.
+pub struct RwLock<T: ?Sized> {
+    state: AtomicUsize,
+    writer: Owner,
+    waiters: WaitQueue,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    /// Create an unlocked reader-writer lock.
+    pub const fn new(data: T) -> RwLock<T> {
+        RwLock {
+            state: AtomicUsize::new(0),
+            writer: Owner::new(),
+            waiters: WaitQueue::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Consume the lock and return the data.
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    fn acquire_read(&self) -> bool {
+        self.state
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |state| {
+                match state.checked_add(1) {
+                    Some(WRITER) | None => None,
+                    readers => readers,
+                }
+            })
+            .is_ok()
+    }
+
+    fn acquire_write(&self) -> bool {
+        self.state
+            .compare_exchange(0, WRITER, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    /// Lock for reading, blocking while a writer holds the lock.
+    pub fn read(&self) -> RwLockReadGuard<T> {
+        self.waiters.wait_until(|| self.acquire_read());
+        RwLockReadGuard { lock: self }
+    }
+
+    /// Lock for reading if no writer holds the lock.
+    pub fn try_read(&self) -> Option<RwLockReadGuard<T>> {
+        self.acquire_read().then(|| RwLockReadGuard { lock: self })
+    }
+
+    /// Lock for writing, blocking while any thread holds the lock.
+    pub fn write(&self) -> RwLockWriteGuard<T> {
+        self.writer.check_recursion("write lock");
+        self.waiters.wait_until(|| self.acquire_write());
+        self.writer.set();
+
+        RwLockWriteGuard { lock: self }
+    }
+
+    /// Lock for writing if no thread holds the lock.
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<T>> {
+        if self.acquire_write() {
+            self.writer.set();
+            Some(RwLockWriteGuard { lock: self })
+        } else {
+            None
+        }
+    }
+
+    /// Get a mutable reference to the data, which needs no locking since the
+    /// lock is borrowed mutably.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    fn unlock_read(&self) {
+        // The last reader lets waiting writers in.
+        if self.state.fetch_sub(1, Ordering::Release) == 1 {
+            self.waiters.wake_all();
+        }
+    }
+
+    fn unlock_write(&self) {
+        self.writer.clear();
+        self.state.store(0, Ordering::Release);
+        self.waiters.wake_all();
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    fn default() -> RwLock<T> {
+        RwLock::new(T::default())
+    }
+}
+
+/// Shared access to the data of an `RwLock`, which is unlocked on drop.
+pub struct RwLockReadGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T: ?Sized> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.unlock_read();
+    }
+}
+
+/// Exclusive access to the data of an `RwLock`, which is unlocked on drop.
+pub struct RwLockWriteGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T: ?Sized> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.unlock_write();
+    }
+}
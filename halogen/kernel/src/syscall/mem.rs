@@ -0,0 +1,57 @@
+use halogen_common::mem::{Resource, VirtualAddress};
+
+use crate::{
+    log::*,
+    task::executor::{with_current_process, with_current_process_reclaim},
+};
+
+pub(super) fn syscall_mmap(size: usize, addr: usize) -> isize {
+    let addr = match addr {
+        0 => None,
+        addr => Some(VirtualAddress(addr)),
+    };
+
+    match with_current_process_reclaim(|proc| proc.mmap(size, addr)) {
+        Some(Ok(base)) => usize::from(base) as isize,
+        Some(Err(why)) => {
+            warn!("Failed to map {} bytes: {:?}", size, why);
+            -1
+        }
+        None => -1,
+    }
+}
+
+pub(super) fn syscall_munmap(addr: usize) -> isize {
+    match with_current_process(|proc| proc.munmap(VirtualAddress(addr))) {
+        Some(Ok(())) => 0,
+        Some(Err(why)) => {
+            warn!("Failed to unmap {:p}: {:?}", addr as *const u8, why);
+            -1
+        }
+        None => -1,
+    }
+}
+
+/// Get the bytes of a resource charged to the calling process, or its limit if
+/// `limit` is non-zero. Resources are numbered resident (0), page tables (1),
+/// and kernel heap (2).
+pub(super) fn syscall_mem_usage(resource: usize, limit: usize) -> isize {
+    let resource = match resource {
+        0 => Resource::Resident,
+        1 => Resource::PageTables,
+        2 => Resource::Heap,
+        _ => return -1,
+    };
+
+    let bytes = with_current_process(|proc| {
+        match limit {
+            0 => proc.usage().get(resource),
+            _ => proc.limits().get(resource),
+        }
+    });
+
+    match bytes {
+        Some(bytes) => bytes.min(isize::MAX as usize) as isize,
+        None => -1,
+    }
+}
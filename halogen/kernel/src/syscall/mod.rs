@@ -1,12 +1,20 @@
 use crate::{arch::Context, log::*};
 
+mod mem;
 mod print;
+mod shm;
 mod task;
 
 #[derive(Clone, Copy, Debug)]
 pub enum Function {
     Exit,
     Print,
+    ShmCreate,
+    ShmMap,
+    ShmUnmap,
+    Mmap,
+    Munmap,
+    MemUsage,
     Invalid,
 }
 
@@ -15,6 +23,12 @@ impl From<usize> for Function {
         match n {
             0 => Function::Exit,
             1 => Function::Print,
+            2 => Function::ShmCreate,
+            3 => Function::ShmMap,
+            4 => Function::ShmUnmap,
+            5 => Function::Mmap,
+            6 => Function::Munmap,
+            7 => Function::MemUsage,
             _ => Function::Invalid,
         }
     }
@@ -45,6 +59,12 @@ pub unsafe extern "C" fn handle_syscall(ctx: &mut Context) {
     let ret = match syscall_fn {
         Function::Exit => task::syscall_exit(a1 as isize),
         Function::Print => print::syscall_print(a1 as *const u8, a2),
+        Function::ShmCreate => shm::syscall_shm_create(a1, a2, a3),
+        Function::ShmMap => shm::syscall_shm_map(a1, a2),
+        Function::ShmUnmap => shm::syscall_shm_unmap(a1),
+        Function::Mmap => mem::syscall_mmap(a1, a2),
+        Function::Munmap => mem::syscall_munmap(a1),
+        Function::MemUsage => mem::syscall_mem_usage(a1, a2),
         Function::Invalid => -1,
     };
 
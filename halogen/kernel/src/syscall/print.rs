@@ -1,8 +1,18 @@
 use core::{slice::from_raw_parts, str::from_utf8};
 
-use crate::fwprint;
+use halogen_common::mem::VirtualAddress;
+
+use crate::{fwprint, mem::swap, task::executor::with_current_process_reclaim};
 
 pub fn syscall_print(msg: *const u8, n: usize) -> isize {
+    // The message may be in pages that are swapped out.
+    let base = VirtualAddress(msg as usize);
+    if let Some(Err(_)) =
+        with_current_process_reclaim(|proc| swap::fault_in(&mut proc.space, base, n))
+    {
+        return 1;
+    }
+
     // Set SUM to read user memory.
     unsafe {
         riscv::register::sstatus::set_sum();
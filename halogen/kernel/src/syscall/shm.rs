@@ -0,0 +1,51 @@
+use halogen_common::mem::VirtualAddress;
+
+use crate::{
+    log::*,
+    task::executor::{with_current_process, with_current_process_reclaim},
+};
+
+/// Convert a user-provided address to an optional address; zero lets the
+/// kernel choose.
+fn hint(addr: usize) -> Option<VirtualAddress> {
+    match addr {
+        0 => None,
+        addr => Some(VirtualAddress(addr)),
+    }
+}
+
+pub(super) fn syscall_shm_create(key: usize, size: usize, addr: usize) -> isize {
+    match with_current_process_reclaim(|proc| proc.shm_create(key, size, hint(addr))) {
+        Some(Ok(base)) => usize::from(base) as isize,
+        Some(Err(why)) => {
+            warn!("Failed to create shared memory {}: {:?}", key, why);
+            -1
+        }
+        None => -1,
+    }
+}
+
+pub(super) fn syscall_shm_map(key: usize, addr: usize) -> isize {
+    match with_current_process_reclaim(|proc| proc.shm_map(key, hint(addr))) {
+        Some(Ok(base)) => usize::from(base) as isize,
+        Some(Err(why)) => {
+            warn!("Failed to map shared memory {}: {:?}", key, why);
+            -1
+        }
+        None => -1,
+    }
+}
+
+pub(super) fn syscall_shm_unmap(addr: usize) -> isize {
+    match with_current_process(|proc| proc.shm_unmap(VirtualAddress(addr))) {
+        Some(Ok(())) => 0,
+        Some(Err(why)) => {
+            warn!(
+                "Failed to unmap shared memory at {:p}: {:?}",
+                addr as *const u8, why
+            );
+            -1
+        }
+        None => -1,
+    }
+}
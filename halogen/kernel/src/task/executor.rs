@@ -1,10 +1,21 @@
-use alloc::{boxed::Box, collections::BTreeMap};
-
-use halogen_common::sched::{RoundRobinScheduler, TaskScheduler};
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    vec::Vec,
+};
+use core::time::Duration;
+
+use halogen_common::{
+    mem::{alloc::Tag, MemoryLimits, MemoryUsage},
+    sched::{
+        create_scheduler, scheduler_name, BoxedScheduler, EdfScheduler, RealTimeParams,
+        TaskScheduler, DEFAULT_SCHEDULER,
+    },
+};
 use lazy_static::lazy_static;
 use spin::Mutex;
 
 use super::{
+    oom::{self, Reclaimed, OOM_KILLED},
     process::Process,
     thread::{KernelThread, Thread, ThreadFunction, ThreadState},
 };
@@ -12,9 +23,9 @@ use crate::{
     arch::Context,
     critical_section,
     error::{KernelError, KernelResult},
-    irq, kerror,
+    irq, kerror, kprintln,
     log::*,
-    mem::paging::KERNEL_ASID,
+    mem::{heap, paging::KERNEL_ASID, phys},
     sbi::timer,
 };
 
@@ -22,6 +33,12 @@ use crate::{
 pub const DEFAULT_QUANTA_LIMIT: usize = 4;
 /// Length of a single time slice.
 pub const DEFAULT_QUANTUM_US: usize = 250_000;
+/// Longest scheduler name that can be given in the boot arguments.
+const BOOT_SCHEDULER_LEN: usize = 32;
+
+/// Name of the scheduler given by the `sched=` boot argument, if any. It is
+/// copied out of the device-tree at boot, since the device-tree isn't kept.
+static mut BOOT_SCHEDULER: ([u8; BOOT_SCHEDULER_LEN], usize) = ([0; BOOT_SCHEDULER_LEN], 0);
 
 lazy_static! {
     static ref EXECUTOR: Mutex<Executor> = Mutex::new(Executor::default());
@@ -29,6 +46,7 @@ lazy_static! {
 
 /// Add a kernel thread to the executor pool.
 pub fn spawn(entry: ThreadFunction, arg: usize) -> KernelResult<usize> {
+    let _tag = heap::tag(Tag::Scheduler);
     critical_section!({
         match EXECUTOR.lock().spawn_kernel(entry, arg) {
             Err(why) => {
@@ -60,44 +78,119 @@ pub fn exit(status: isize) {
 
 /// Spawn a process and return the PID and main thread's TID.
 pub fn exec(elf: &[u8]) -> KernelResult<(usize, usize)> {
-    let (pid, tid) = critical_section!({
+    let _tag = heap::tag(Tag::Scheduler);
+    let (pid, main_tid) = critical_section!({
         let mut executor = EXECUTOR.lock();
-        let pid = executor.get_pid();
-        let mut proc = Process::try_from_elf(pid, elf)?;
-
-        let main_tid = executor.get_tid();
-        let main = proc.create_main(main_tid)?;
+        (executor.get_pid(), executor.get_tid())
+    });
+
+    // The executable is loaded with interrupts enabled, and the executor is
+    // only locked to reclaim memory.
+    let mut proc = reclaim_while(pid, || Process::try_from_elf(pid, elf))?;
+    let main = match reclaim_while(pid, || proc.create_main(main_tid)) {
+        Ok(main) => main,
+        Err(why) => {
+            proc.release();
+            return Err(why);
+        }
+    };
 
+    critical_section!({
+        let mut executor = EXECUTOR.lock();
         executor.add_thread(main_tid, Thread::User(main));
         executor.processes.insert(pid, proc);
+    });
 
-        Ok((pid, main_tid))
-    })?;
-
-    trace!("Create process {} with main thread {}", pid, tid);
+    trace!("Create process {} with main thread {}", pid, main_tid);
 
     yld();
 
-    Ok((pid, tid))
+    Ok((pid, main_tid))
+}
+
+/// Call `f` on behalf of a process that is not running yet. While it fails for
+/// lack of physical frames, reclaim memory and retry.
+fn reclaim_while<R>(pid: usize, mut f: impl FnMut() -> KernelResult<R>) -> KernelResult<R> {
+    loop {
+        match f() {
+            Err(why) if why.is_out_of_frames() => {
+                if critical_section!({ EXECUTOR.lock().reclaim(pid) }).is_none() {
+                    return Err(why);
+                }
+            }
+            result => return result,
+        }
+    }
 }
 
-/// Wait for a thread to complete and return its result.
+/// Wait for a thread to complete and return its result. The caller is blocked
+/// until the thread exits.
 pub fn join(tid: usize) -> KernelResult<isize> {
+    let exists = critical_section!({ EXECUTOR.lock().threads.contains_key(&tid) });
+    if !exists {
+        return kerror!(KernelError::NoSuchThread).into();
+    }
+
+    wait_until(Event::Exit(tid), |executor| executor.is_complete(tid));
+
+    critical_section!({
+        EXECUTOR
+            .lock()
+            .reap(tid)
+            .map(|opt| opt.expect("complete thread has no exit status"))
+    })
+}
+
+/// Block the calling thread for at least `duration`.
+pub fn sleep(duration: Duration) {
+    let tid = tid();
+    let deadline = crate::timer::deadline(duration);
+
+    crate::timer::after(duration, move || {
+        wake(Event::Sleep(tid), 1);
+    });
+    wait_until(Event::Sleep(tid), |_| timer::now_us() >= deadline);
+}
+
+/// Something threads can wait for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(super) enum Event {
+    /// A wake-up of a wait queue, by ID.
+    Queue(usize),
+    /// The exit of a thread, by ID.
+    Exit(usize),
+    /// The end of a thread's sleep, by ID.
+    Sleep(usize),
+}
+
+/// Block the calling thread on an event until `cond` holds. The condition is
+/// checked with the executor locked, so a wake-up can't be lost between the
+/// check and blocking; it must not call into the executor itself. Before the
+/// executor runs a thread, e.g. during boot, it spins instead.
+pub(super) fn wait_until(event: Event, mut cond: impl FnMut(&Executor) -> bool) {
     loop {
-        let completed = critical_section!({ EXECUTOR.lock().is_complete(tid) });
-        if completed {
-            return critical_section!({
-                EXECUTOR
-                    .lock()
-                    .reap(tid)
-                    .map(|opt| opt.expect("complete thread has no exit status"))
-            });
+        let blocked = critical_section!({
+            let mut executor = EXECUTOR.lock();
+            if cond(&executor) {
+                return;
+            }
+            executor.block_current(event)
+        });
+
+        if blocked {
+            yld();
         } else {
-            yld()
+            core::hint::spin_loop();
         }
     }
 }
 
+/// Wake up to `count` threads blocked on an event, in the order they blocked.
+/// Returns the number of threads woken.
+pub(super) fn wake(event: Event, count: usize) -> usize {
+    critical_section!({ EXECUTOR.lock().wake(event, count) })
+}
+
 /// Save the context for the current thread and return the next context.
 ///
 /// # Safety
@@ -113,6 +206,7 @@ pub unsafe fn resume(saved_ctx: &Context) -> *const Context {
 pub fn handoff(entry: ThreadFunction, arg: usize) -> ! {
     info!("Handing off control to thread executor");
     spawn(entry, arg).expect("failed to spawn handoff thread");
+    critical_section!({ EXECUTOR.lock().spawn_idle() }).expect("failed to spawn idle thread");
 
     irq::enable_timer();
     timer::set(0);
@@ -120,45 +214,293 @@ pub fn handoff(entry: ThreadFunction, arg: usize) -> ! {
     panic!("returned from executor handoff")
 }
 
-/// Register a timer event.
+/// Body of the idle thread, which runs when no other thread is runnable. It
+/// refills the pool of zeroed frames, then waits for an interrupt.
+extern "C" fn idle(_: usize) -> isize {
+    loop {
+        if phys::refill_zero_pool(phys::ZERO_BATCH) == 0 {
+            unsafe { riscv::asm::wfi() };
+        }
+    }
+}
+
+/// Register a timer event: run the expired timers, and count a quantum if the
+/// scheduling tick is due. Other timers may fire between ticks.
 pub fn timer_event() {
-    let mut executor = EXECUTOR.lock();
-    executor.register_quantum();
-    timer::set(executor.quantum_len);
+    crate::timer::expire();
+
+    if crate::timer::tick_due() {
+        let mut executor = EXECUTOR.lock();
+        executor.register_quantum();
+        crate::timer::set_tick(executor.timer_delay());
+    }
 }
 
 /// Get the ID of the calling thread.
 pub fn tid() -> usize {
-    EXECUTOR
-        .lock()
-        .scheduler
-        .current()
-        .expect("no thread running")
+    critical_section!({ EXECUTOR.lock().current_tid() }).expect("no thread running")
+}
+
+/// Get the ID of the calling thread, or `None` if the executor isn't running
+/// one yet.
+pub fn try_tid() -> Option<usize> {
+    critical_section!({ EXECUTOR.lock().current_tid() })
+}
+
+/// Set the priority of a thread. It is ignored if the scheduler has none.
+pub fn set_priority(tid: usize, priority: isize) -> KernelResult<()> {
+    critical_section!({
+        let mut executor = EXECUTOR.lock();
+        if !executor.threads.contains_key(&tid) {
+            return kerror!(KernelError::NoSuchThread).into();
+        }
+
+        executor.scheduler.set_priority(tid, priority);
+        executor.priorities.insert(tid, priority);
+        Ok(())
+    })
+}
+
+/// Get the number of times the idle thread was switched to, and the number of
+/// times the scheduling tick was stopped while idle.
+pub fn idle_stats() -> (usize, usize) {
+    critical_section!({
+        let executor = EXECUTOR.lock();
+        (executor.idle_entries, executor.tick_stops)
+    })
+}
+
+/// Choose the scheduler the executor starts with, e.g. from the boot
+/// arguments. The name is checked when the executor is created, which falls
+/// back to `DEFAULT_SCHEDULER` if there is no such scheduler.
+///
+/// # Safety
+///
+/// - Only call during boot, before the executor is used.
+pub unsafe fn set_boot_scheduler(name: &str) {
+    let len = name.len().min(BOOT_SCHEDULER_LEN);
+    BOOT_SCHEDULER.0[..len].copy_from_slice(&name.as_bytes()[..len]);
+    BOOT_SCHEDULER.1 = len;
+}
+
+/// Get the registered name of the scheduler chosen at boot, or the default if
+/// none was chosen. An unknown name is logged and ignored.
+fn boot_scheduler() -> &'static str {
+    let (name, len) = unsafe { BOOT_SCHEDULER };
+    let name = match core::str::from_utf8(&name[..len]) {
+        Ok("") => return DEFAULT_SCHEDULER,
+        Ok(name) => name,
+        Err(_) => "<invalid>",
+    };
+
+    scheduler_name(name).unwrap_or_else(|| {
+        warn!(
+            "Unknown scheduler {:?} in boot arguments, using {}",
+            name, DEFAULT_SCHEDULER
+        );
+        DEFAULT_SCHEDULER
+    })
+}
+
+/// Get the name of the scheduler in use.
+pub fn scheduler() -> &'static str {
+    critical_section!({ EXECUTOR.lock().scheduler_name })
+}
+
+/// Replace the scheduler with the one named `name` in the registry (see
+/// `halogen_common::sched::SCHEDULERS`). At the next scheduling decision, which
+/// the caller yields to, the threads of the old scheduler move to the new one
+/// in the order they would have run.
+pub fn set_scheduler(name: &str) -> KernelResult<()> {
+    let (name, scheduler) = match (scheduler_name(name), create_scheduler(name)) {
+        (Some(name), Some(scheduler)) => (name, scheduler),
+        _ => return kerror!(KernelError::NoSuchScheduler).into(),
+    };
+
+    critical_section!({
+        let _tag = heap::tag(Tag::Scheduler);
+        EXECUTOR.lock().pending_scheduler = Some((name, scheduler));
+    });
+    yld();
+
+    Ok(())
+}
+
+/// Move a thread to the real-time class with the given timing. It then runs
+/// ahead of every normal thread while it has runtime left in its period, and
+/// yielding ends its work for the period. Fails if admitting it would raise the
+/// total density of real-time threads over 100%.
+pub fn set_realtime(tid: usize, params: RealTimeParams) -> KernelResult<()> {
+    critical_section!({
+        let mut executor = EXECUTOR.lock();
+        if !executor.threads.contains_key(&tid) || executor.is_complete(tid) {
+            return kerror!(KernelError::NoSuchThread).into();
+        }
+
+        let running = executor.current_tid() == Some(tid);
+        if let Err(why) = executor.rt.admit(tid, params, timer::now_us()) {
+            warn!("Reject real-time thread {} ({:?}): {:?}", tid, params, why);
+            return kerror!(KernelError::RealTimeAdmission).into();
+        }
+
+        // The running thread charges its time so far to the normal class.
+        if running {
+            executor.account_current();
+            executor.rt.start(tid);
+        } else if matches!(executor.threads[&tid].state(), ThreadState::Blocked) {
+            executor.rt.block(tid);
+        }
+        executor.scheduler.complete(tid);
+
+        info!("Thread {} is real-time with {:?}", tid, params);
+        Ok(())
+    })
+}
+
+/// Call a function with the process that owns the calling thread. Returns
+/// `None` if the caller is a kernel thread.
+pub fn with_current_process<R>(f: impl FnOnce(&mut Process) -> R) -> Option<R> {
+    critical_section!({
+        let mut executor = EXECUTOR.lock();
+        let pid = executor.get_current()?.pid()?;
+        executor.processes.get_mut(&pid).map(f)
+    })
+}
+
+/// Call a function with the process that owns the calling thread to serve an
+/// allocation. While it fails for lack of physical frames, reclaim memory and
+/// retry. Returns `None` if the caller is a kernel thread.
+pub fn with_current_process_reclaim<R>(
+    f: impl FnMut(&mut Process) -> KernelResult<R>,
+) -> Option<KernelResult<R>> {
+    critical_section!({
+        let mut executor = EXECUTOR.lock();
+        let pid = executor.get_current()?.pid()?;
+        let Executor {
+            processes,
+            threads,
+            scheduler,
+            rt,
+            ..
+        } = &mut *executor;
+
+        let result = oom::serve(processes, pid, f, |victim| {
+            kill_threads(threads, scheduler.as_mut(), rt, victim)
+        });
+        executor.wake_exited();
+
+        Some(result)
+    })
+}
+
+/// Finish every thread of a process killed to reclaim memory. Their joiners
+/// are woken by `Executor::wake_exited`.
+fn kill_threads(
+    threads: &mut BTreeMap<usize, Thread>,
+    scheduler: &mut dyn TaskScheduler<Handle = usize>,
+    rt: &mut EdfScheduler,
+    proc: &Process,
+) {
+    for tid in proc.tids.iter() {
+        if let Some(thread) = threads.get_mut(tid) {
+            if !matches!(thread.state(), ThreadState::Finished) {
+                info!("Kill thread {}", thread);
+                thread.exit(OOM_KILLED);
+                scheduler.complete(*tid);
+                rt.remove(*tid);
+            }
+        }
+    }
+}
+
+/// Get the memory charged to a process.
+pub fn usage(pid: usize) -> Option<MemoryUsage> {
+    critical_section!({ EXECUTOR.lock().processes.get(&pid).map(Process::usage) })
+}
+
+/// Replace the memory limits of a process.
+pub fn set_limits(pid: usize, limits: MemoryLimits) -> KernelResult<()> {
+    critical_section!({
+        match EXECUTOR.lock().processes.get_mut(&pid) {
+            Some(proc) => {
+                proc.set_limits(limits);
+                Ok(())
+            }
+            None => kerror!(KernelError::NoSuchProcess).into(),
+        }
+    })
+}
+
+/// Print each process with its threads and memory usage.
+pub fn dump() {
+    critical_section!({
+        let executor = EXECUTOR.lock();
+        kprintln!("{} processes:", executor.processes.len());
+        for proc in executor.processes.values() {
+            kprintln!(
+                "  PID={} threads={:?} {} (limit: {})",
+                proc.pid,
+                proc.tids,
+                proc.usage(),
+                proc.limits()
+            );
+        }
+    })
 }
 
 /// Coordinates execution and scheduling of processes and kernel threads.
-struct Executor {
+pub(super) struct Executor {
     tid_counter: usize,
-    scheduler: Box<dyn TaskScheduler<Handle = usize>>,
+    scheduler: BoxedScheduler,
+    scheduler_name: &'static str,
+    /// Scheduler to swap in at the next scheduling decision.
+    pending_scheduler: Option<(&'static str, BoxedScheduler)>,
+    /// Priorities set for threads, to carry over to a new scheduler.
+    priorities: BTreeMap<usize, isize>,
+    /// Blocked threads by the event they wait for, in the order they blocked.
+    waiters: BTreeMap<Event, VecDeque<usize>>,
+    /// Real-time threads, which run ahead of those in `scheduler`.
+    rt: EdfScheduler,
     threads: BTreeMap<usize, Thread>,
     quanta_limit: usize,
     quanta: BTreeMap<usize, usize>,
     quantum_len: usize,
+    /// Time the current thread started running, in microseconds.
+    switched_at: usize,
+    /// Thread of the hart that runs when no other thread is runnable.
+    idle: Option<usize>,
+    /// The idle thread is running.
+    idling: bool,
+    /// Number of times the idle thread was switched to.
+    idle_entries: usize,
+    /// Number of times the scheduling tick was stopped while idle.
+    tick_stops: usize,
     processes: BTreeMap<usize, Process>,
     pid_counter: usize,
 }
 
 impl Default for Executor {
     fn default() -> Executor {
+        let scheduler = boot_scheduler();
         Executor {
             tid_counter: 0,
             pid_counter: KERNEL_ASID as usize + 1,
             quanta_limit: DEFAULT_QUANTA_LIMIT,
             quantum_len: DEFAULT_QUANTUM_US,
+            switched_at: 0,
+            idle: None,
+            idling: false,
+            idle_entries: 0,
+            tick_stops: 0,
             threads: BTreeMap::default(),
             processes: BTreeMap::default(),
             quanta: BTreeMap::default(),
-            scheduler: Box::new(RoundRobinScheduler::default()),
+            scheduler: create_scheduler(scheduler).expect("registered scheduler can be created"),
+            scheduler_name: scheduler,
+            pending_scheduler: None,
+            priorities: BTreeMap::default(),
+            waiters: BTreeMap::default(),
+            rt: EdfScheduler::default(),
         }
     }
 }
@@ -178,6 +520,20 @@ impl Executor {
         Ok(tid)
     }
 
+    /// Create the idle thread. It isn't in the scheduler, and runs only when
+    /// no other thread is runnable.
+    fn spawn_idle(&mut self) -> KernelResult<usize> {
+        let tid = self.get_tid();
+        let thread = Thread::Kernel(KernelThread::try_new(tid, idle, 0)?);
+
+        self.threads.insert(tid, thread);
+        self.quanta.insert(tid, 0);
+        self.idle = Some(tid);
+
+        info!("Spawn idle thread {}", tid);
+        Ok(tid)
+    }
+
     fn get_tid(&mut self) -> usize {
         let tid = self.tid_counter;
         self.tid_counter += 1;
@@ -190,10 +546,32 @@ impl Executor {
         pid
     }
 
+    /// Reclaim memory for a process that is not running yet, waking the
+    /// joiners of any threads killed to do so.
+    fn reclaim(&mut self, pid: usize) -> Option<Reclaimed> {
+        let Executor {
+            processes,
+            threads,
+            scheduler,
+            rt,
+            ..
+        } = &mut *self;
+
+        let reclaimed = oom::reclaim(processes, Some(pid), |victim| {
+            kill_threads(threads, scheduler.as_mut(), rt, victim)
+        });
+        self.wake_exited();
+        reclaimed
+    }
+
     /// Yield the caller's remaining time.
     fn yld(&mut self) {
-        if let Some(tid) = self.scheduler.current() {
-            self.scheduler.yld(tid);
+        if let Some(tid) = self.current_tid() {
+            if self.rt.contains(tid) {
+                self.rt.finish(tid);
+            } else {
+                self.scheduler.yld(tid);
+            }
             self.quanta.insert(tid, self.quanta_limit);
         }
     }
@@ -230,12 +608,15 @@ impl Executor {
 
                     if thread.tid() == main_tid {
                         info!("Clean up process {}", pid);
-                        self.processes.remove(&pid);
+                        if let Some(mut proc) = self.processes.remove(&pid) {
+                            proc.release();
+                        }
                     }
                 }
 
                 self.threads.remove(&tid);
                 self.quanta.remove(&tid);
+                self.priorities.remove(&tid);
 
                 Ok(ret)
             }
@@ -248,7 +629,8 @@ impl Executor {
             // Coming from a running thread
             Some(current) => {
                 let state = current.state();
-                let time_reached = self.time_reached();
+                // The idle thread gives way as soon as a thread may be ready.
+                let time_reached = self.idling || self.time_reached() || self.preempt();
 
                 match (state, time_reached) {
                     // Thread is running but out of quanta
@@ -258,6 +640,7 @@ impl Executor {
                         let thread = self.current_mut().unwrap();
                         thread.set_state(ThreadState::Ready);
                         thread.save_context(saved_ctx);
+                        self.account_current();
 
                         // Move to the next thread
                         let next = self.update_and_get_next();
@@ -269,6 +652,23 @@ impl Executor {
                         trace!("Resuming thread");
                         saved_ctx
                     }
+                    // Thread blocked on an event
+                    (ThreadState::Blocked, _) => {
+                        let thread = self.current_mut().unwrap();
+                        thread.save_context(saved_ctx);
+                        self.account_current();
+                        self.deschedule_current();
+
+                        let next = self.update_and_get_next();
+                        trace!("Swap to thread {}", next.tid());
+                        next.context()
+                    }
+                    // Thread preempted by a real-time thread that just finished
+                    (ThreadState::Ready, _) => {
+                        let next = self.update_and_get_next();
+                        trace!("Swap to thread {}", next.tid());
+                        next.context()
+                    }
                     // Invalid state
                     (state, _) => {
                         panic!("{:?} thread cannot run", state)
@@ -291,24 +691,91 @@ impl Executor {
         self.scheduler.add_new(tid);
     }
 
+    /// Get the ID of the running thread: the real-time one, if any, or else the
+    /// one chosen by the scheduler, or else the idle thread.
+    fn current_tid(&self) -> Option<usize> {
+        self.rt
+            .current()
+            .or_else(|| self.scheduler.current())
+            .or_else(|| self.idle.filter(|_| self.idling))
+    }
+
     /// Returns whether the current thread has reached its quanta limit, false
     /// if the limit is not reached or no thread is running.
     fn time_reached(&self) -> bool {
-        match self.scheduler.current() {
+        match self.current_tid() {
             Some(tid) => {
-                *self
+                let quanta_reached = *self
                     .quanta
                     .get(&tid)
                     .unwrap_or_else(|| panic!("no quanta for thread {}", tid))
-                    >= self.quanta_limit
+                    >= self.quanta_limit;
+
+                // A yield uses up the quanta, even with a time slice.
+                match self.time_slice_us(tid) {
+                    Some(slice) => quanta_reached || timer::now_us() - self.switched_at >= slice,
+                    None => quanta_reached,
+                }
             }
             None => false,
         }
     }
 
+    /// Returns true if a ready thread should preempt the current one: a
+    /// real-time thread with an earlier deadline than the current thread, or a
+    /// thread the scheduler prefers to the current normal one.
+    fn preempt(&mut self) -> bool {
+        self.rt.preempt(timer::now_us())
+            || (self.rt.current().is_none() && self.scheduler.preempt())
+    }
+
+    /// Get the time slice of a thread: the runtime left in its period for a
+    /// real-time thread, or else the one set by the scheduler, if any.
+    fn time_slice_us(&self, tid: usize) -> Option<usize> {
+        match self.rt.remaining(tid) {
+            Some(remaining) => Some(remaining),
+            None => self.scheduler.time_slice_us(tid),
+        }
+    }
+
+    /// Get the delay until the next timer event: the rest of the current
+    /// thread's time slice if it has one, or else a quantum. It is cut short by
+    /// the next release of a real-time thread, which may preempt. While idle,
+    /// there is no tick, so only a release or another timer ends the wait.
+    fn timer_delay(&self) -> usize {
+        let now = timer::now_us();
+        let delay = if self.idling {
+            usize::MAX
+        } else {
+            match self.current_tid().and_then(|tid| self.time_slice_us(tid)) {
+                Some(slice) => slice.saturating_sub(now - self.switched_at),
+                None => self.quantum_len,
+            }
+        };
+
+        match self.rt.next_release() {
+            Some(release) => delay.min(release.saturating_sub(now)),
+            None => delay,
+        }
+    }
+
+    /// Report the time the current thread ran to its scheduling class.
+    fn account_current(&mut self) {
+        if let Some(tid) = self.current_tid() {
+            let now = timer::now_us();
+            if self.rt.contains(tid) {
+                self.rt.account(tid, now - self.switched_at);
+            } else if !self.idling {
+                self.scheduler.account(tid, now - self.switched_at);
+            }
+            self.switched_at = now;
+        }
+    }
+
     /// Call once per timer event to increment the current thread's quanta.
     fn register_quantum(&mut self) {
-        if let Some(tid) = self.scheduler.current() {
+        // Real-time threads run by their runtime rather than by quanta.
+        if let Some(tid) = self.current_tid().filter(|&tid| !self.rt.contains(tid)) {
             *self
                 .quanta
                 .get_mut(&tid)
@@ -318,9 +785,7 @@ impl Executor {
 
     /// Returns a reference to the currently running thread.
     fn get_current(&self) -> Option<&Thread> {
-        self.scheduler
-            .current()
-            .and_then(|tid| self.threads.get(&tid))
+        self.current_tid().and_then(|tid| self.threads.get(&tid))
     }
 
     /// Get a mutable reference to a thread with an ID.
@@ -330,36 +795,176 @@ impl Executor {
 
     /// Returns a mutable reference to the currently running thread.
     fn current_mut(&mut self) -> Option<&mut Thread> {
-        self.scheduler
-            .current()
-            .and_then(move |tid| self.get_mut(tid))
+        self.current_tid().and_then(move |tid| self.get_mut(tid))
     }
 
-    /// Get the next thread from the scheduler and update its state.
+    /// Swap in the pending scheduler, if any. Threads move to it in the order
+    /// they would have run, with the priorities set for them.
+    fn swap_scheduler(&mut self) {
+        let (name, mut scheduler) = match self.pending_scheduler.take() {
+            Some(pending) => pending,
+            None => return,
+        };
+
+        for tid in self.scheduler.drain() {
+            let priority = self.priorities.get(&tid).copied().unwrap_or(0);
+            scheduler.add_with_priority(tid, priority);
+        }
+
+        info!("Swap scheduler {} for {}", self.scheduler_name, name);
+        self.scheduler = scheduler;
+        self.scheduler_name = name;
+    }
+
+    /// Get the next thread, real-time ones first, and update its state.
     fn update_and_get_next(&mut self) -> &mut Thread {
-        let next_tid = self
-            .scheduler
-            .next()
-            .expect("scheduler returned no next thread");
+        // No thread is running, so the scheduler can be swapped.
+        self.swap_scheduler();
+
+        let next_tid = match self
+            .rt
+            .next(timer::now_us())
+            .or_else(|| self.scheduler.next())
+        {
+            Some(tid) => {
+                self.idling = false;
+                tid
+            }
+            None => {
+                if !self.idling {
+                    self.idle_entries += 1;
+                }
+                self.idling = true;
+                self.idle
+                    .expect("no thread is runnable and there is no idle thread")
+            }
+        };
 
-        let thread = self
+        let next = self
             .threads
             .get_mut(&next_tid)
             .unwrap_or_else(|| panic!("no such thread {}", next_tid));
+        next.set_state(ThreadState::Running);
+        heap::set_current_tag(next.heap_tag());
+        self.quanta.insert(next_tid, 0);
+        self.switched_at = timer::now_us();
+
+        // The tick may be set for the last thread, or stopped while idle.
+        let delay = self.timer_delay();
+        if delay == usize::MAX {
+            self.tick_stops += 1;
+        }
+        crate::timer::set_tick(delay);
+
+        self.threads.get_mut(&next_tid).unwrap()
+    }
+
+    /// Block the running thread on an event. It keeps running until the next
+    /// scheduling decision, which the caller should yield to. Returns false if
+    /// no thread is running.
+    fn block_current(&mut self, event: Event) -> bool {
+        let tid = match self.current_tid() {
+            Some(tid) => tid,
+            None => return false,
+        };
+
+        self.threads
+            .get_mut(&tid)
+            .unwrap_or_else(|| panic!("no such thread {}", tid))
+            .set_state(ThreadState::Blocked);
+        self.waiters.entry(event).or_default().push_back(tid);
+
+        true
+    }
+
+    /// Take the running thread, which just blocked, out of its scheduling
+    /// class.
+    fn deschedule_current(&mut self) {
+        if let Some(tid) = self.current_tid() {
+            if self.rt.contains(tid) {
+                self.rt.block(tid);
+            } else {
+                self.scheduler.block(tid);
+            }
+        }
+    }
+
+    /// Make a blocked thread ready and return it to its scheduling class.
+    /// Returns false if the thread is not blocked, e.g. because it was killed
+    /// while it waited.
+    fn wake_thread(&mut self, tid: usize) -> bool {
+        let running = self.current_tid() == Some(tid);
+        let thread = match self.threads.get_mut(&tid) {
+            Some(thread) if matches!(thread.state(), ThreadState::Blocked) => thread,
+            _ => return false,
+        };
+
+        // A thread that blocked but hasn't stopped yet keeps running.
+        if running {
+            thread.set_state(ThreadState::Running);
+        } else {
+            thread.set_state(ThreadState::Ready);
+            if self.rt.contains(tid) {
+                self.rt.wake(tid, timer::now_us());
+            } else {
+                let priority = self.priorities.get(&tid).copied().unwrap_or(0);
+                self.scheduler.wake(tid, priority);
+            }
+        }
 
-        thread.set_state(ThreadState::Running);
-        thread
+        trace!("Wake thread {}", tid);
+        true
+    }
+
+    /// Wake up to `count` threads blocked on an event, in the order they
+    /// blocked. Returns the number of threads woken.
+    fn wake(&mut self, event: Event, count: usize) -> usize {
+        let mut queue = match self.waiters.remove(&event) {
+            Some(queue) => queue,
+            None => return 0,
+        };
+
+        let mut woken = 0;
+        while woken < count {
+            match queue.pop_front() {
+                Some(tid) => {
+                    if self.wake_thread(tid) {
+                        woken += 1;
+                    }
+                }
+                None => break,
+            }
+        }
+
+        if !queue.is_empty() {
+            self.waiters.insert(event, queue);
+        }
+        woken
+    }
+
+    /// Wake the joiners of every thread that exited.
+    fn wake_exited(&mut self) {
+        let exited: Vec<Event> = self
+            .waiters
+            .keys()
+            .copied()
+            .filter(|event| matches!(event, Event::Exit(tid) if self.is_complete(*tid)))
+            .collect();
+
+        for event in exited {
+            self.wake(event, usize::MAX);
+        }
     }
 
     /// Register a thread as having returned and save its return value; keep it
     /// around until it is joined and reaped.
     fn exit(&mut self, status: isize) {
         let curr_tid = self
-            .scheduler
-            .current()
+            .current_tid()
             .expect("scheduler returned no current thread");
 
         self.scheduler.complete(curr_tid);
+        self.rt.remove(curr_tid);
 
         let curr = self
             .threads
@@ -369,5 +974,6 @@ impl Executor {
         info!("Exit thread {} with status {}", curr, status);
 
         curr.exit(status);
+        self.wake(Event::Exit(curr_tid), usize::MAX);
     }
 }
@@ -1,16 +1,15 @@
 use goblin::elf::Elf;
-use halogen_common::mem::{Address, VirtualAddress};
+use halogen_common::mem::{alloc::Tag, Address, VirtualAddress};
 
 use crate::{
     error::{KernelError, KernelResult},
     kerror,
-    mem::{
-        paging::{Level, Permissions, Privilege, Scope, PAGE_SIZE},
-        phys, AddressSpace,
-    },
+    mem::{heap, paging::Permissions, AddressSpace},
 };
 
 pub fn load_elf(space: &mut AddressSpace, elf_bytes: &[u8]) -> KernelResult<()> {
+    let _tag = heap::tag(Tag::Loader);
+
     let elf = match Elf::parse(elf_bytes) {
         Ok(elf) => elf,
         Err(_) => return kerror!(KernelError::ExecutableFormat).into(),
@@ -33,28 +32,24 @@ pub fn load_elf(space: &mut AddressSpace, elf_bytes: &[u8]) -> KernelResult<()>
             _ => return kerror!(KernelError::ExecutableFormat).into(),
         };
 
-        // For each page in that section...
-        for (offset, virt_addr) in phdr.vm_range().into_iter().enumerate().step_by(PAGE_SIZE) {
-            // Allocate a page.
-            let (virt_page, phys_page) =
-                phys::alloc().ok_or_else(|| kerror!(KernelError::OutOfPhysicalFrames))?;
+        // For each page, or 64 KiB run of pages, in that section...
+        let range = phdr.vm_range();
+        let mut offset = 0;
+        while offset < range.len() {
+            // Allocate frames and map them into the address space.
+            let (virt_pages, size) = space.alloc_run(
+                VirtualAddress(range.start + offset),
+                range.len() - offset,
+                perms,
+            )?;
 
             // Get virtual pointers to the source and destination.
             let src_ptr: *const u8 = unsafe { elf_bytes.as_ptr().add(section_start + offset) };
-            let dest_ptr: *mut u8 = virt_page.as_mut_ptr();
-
-            // Copy from the source to the new page and map it into the address space.
-            unsafe {
-                core::ptr::copy_nonoverlapping(src_ptr, dest_ptr, PAGE_SIZE);
-                space.root.map(
-                    VirtualAddress(virt_addr),
-                    phys_page,
-                    Level::Page,
-                    perms,
-                    Scope::Local,
-                    Privilege::User,
-                )?;
-            }
+            let dest_ptr: *mut u8 = virt_pages.as_mut_ptr();
+
+            // Copy from the source to the new pages.
+            unsafe { core::ptr::copy_nonoverlapping(src_ptr, dest_ptr, size) };
+            offset += size;
         }
     }
 
@@ -4,10 +4,20 @@ pub mod executor;
 /// Userspace processes and threads.
 pub mod process;
 
+/// Reclaim memory when physical frames run out.
+pub mod oom;
+
 /// Kernel thread structure.
 mod thread;
 
+/// Block threads until an event.
+pub mod wait;
+
 /// Load ELF binaries.
 mod loader;
 
-pub use executor::{exec, exit, join, resume, spawn, tid, yld};
+pub use executor::{
+    exec, exit, join, resume, scheduler, set_priority, set_realtime, set_scheduler, sleep, spawn,
+    tid, yld,
+};
+pub use wait::WaitQueue;
@@ -1,7 +1,58 @@
 use alloc::vec::Vec;
 
-use super::{loader::load_elf, thread::UserThread};
-use crate::{error::KernelResult, mem::AddressSpace};
+use halogen_common::{
+    align_up,
+    mem::{alloc::Tag, MemoryAccount, MemoryLimits, MemoryUsage, Resource, VirtualAddress},
+};
+
+use super::{
+    loader::load_elf,
+    thread::{Thread, UserThread},
+};
+use crate::{
+    error::{KernelError, KernelResult},
+    kerror,
+    log::*,
+    mem::{
+        heap,
+        paging::{Permissions, PAGE_SIZE},
+        shm, AddressSpace,
+    },
+};
+
+/// Fewest entries allocated for a list of the process, so that the first few
+/// mappings don't each grow its buffer.
+const MIN_CAPACITY: usize = 4;
+
+/// Make room for one more entry in `list`, charging the growth of its buffer to
+/// the kernel heap of the process first.
+fn grow<T>(account: &mut MemoryAccount, list: &mut Vec<T>) -> KernelResult<()> {
+    if list.len() < list.capacity() {
+        return Ok(());
+    }
+
+    let additional = list.capacity().max(MIN_CAPACITY);
+    if !account.try_charge(Resource::Heap, additional * core::mem::size_of::<T>()) {
+        return kerror!(KernelError::OutOfMemory).into();
+    }
+    list.reserve_exact(additional);
+    Ok(())
+}
+
+/// Shrink the buffer of `list` once it is at most a quarter full, and uncharge
+/// the bytes freed.
+fn shrink<T>(account: &mut MemoryAccount, list: &mut Vec<T>) {
+    let capacity = list.capacity();
+    if list.len() > capacity / 4 {
+        return;
+    }
+
+    list.shrink_to(list.len() * 2);
+    account.uncharge(
+        Resource::Heap,
+        (capacity - list.capacity()) * core::mem::size_of::<T>(),
+    );
+}
 
 #[derive(Debug, Clone, Default)]
 pub struct Process {
@@ -9,24 +60,188 @@ pub struct Process {
     pub space: AddressSpace,
     pub main_tid: usize,
     pub tids: Vec<usize>,
+    /// Key and base address of each shared memory mapping.
+    pub shared: Vec<(usize, VirtualAddress)>,
+    /// Base address and size of each anonymous mapping.
+    pub anonymous: Vec<(VirtualAddress, usize)>,
 }
 
 impl Process {
     pub fn try_from_elf(pid: usize, elf: &[u8]) -> KernelResult<Process> {
         let mut space = AddressSpace::new(pid);
-        load_elf(&mut space, elf)?;
+        if !space
+            .account
+            .try_charge(Resource::Heap, core::mem::size_of::<Process>())
+        {
+            return kerror!(KernelError::OutOfMemory).into();
+        }
+        if let Err(why) = load_elf(&mut space, elf) {
+            // Free whatever was loaded before the failure.
+            space.release();
+            return Err(why);
+        }
 
         Ok(Process {
             pid,
             space,
             main_tid: 0,
             tids: Vec::default(),
+            shared: Vec::default(),
+            anonymous: Vec::default(),
         })
     }
 
     pub fn create_main(&mut self, tid: usize) -> KernelResult<UserThread> {
-        self.main_tid = tid;
-        let thread = UserThread::try_new(tid, self)?;
-        Ok(thread)
+        let _tag = heap::tag(Tag::Process);
+        grow(&mut self.space.account, &mut self.tids)?;
+        // The executor keeps the thread by value.
+        self.charge_heap(core::mem::size_of::<Thread>())?;
+        match UserThread::try_new(tid, self) {
+            Ok(thread) => {
+                self.main_tid = tid;
+                self.tids.push(tid);
+                Ok(thread)
+            }
+            Err(why) => {
+                self.space
+                    .account
+                    .uncharge(Resource::Heap, core::mem::size_of::<Thread>());
+                Err(why)
+            }
+        }
+    }
+
+    /// Get the memory charged to the process.
+    pub fn usage(&self) -> MemoryUsage {
+        self.space.account.usage()
+    }
+
+    /// Get the memory limits of the process.
+    pub fn limits(&self) -> MemoryLimits {
+        self.space.account.limits()
+    }
+
+    /// Replace the memory limits of the process.
+    pub fn set_limits(&mut self, limits: MemoryLimits) {
+        self.space.account.set_limits(limits);
+    }
+
+    /// Charge kernel heap allocated on behalf of the process.
+    fn charge_heap(&mut self, bytes: usize) -> KernelResult<()> {
+        if self.space.account.try_charge(Resource::Heap, bytes) {
+            Ok(())
+        } else {
+            kerror!(KernelError::OutOfMemory).into()
+        }
+    }
+
+    /// Map zeroed memory into the process.
+    pub fn mmap(
+        &mut self,
+        size: usize,
+        addr: Option<VirtualAddress>,
+    ) -> KernelResult<VirtualAddress> {
+        let _tag = heap::tag(Tag::Process);
+        let size = align_up!(size.max(1), PAGE_SIZE);
+        grow(&mut self.space.account, &mut self.anonymous)?;
+
+        match self.space.map_anonymous(addr, size, Permissions::ReadWrite) {
+            Ok(base) => {
+                self.anonymous.push((base, size));
+                Ok(base)
+            }
+            Err(why) => {
+                shrink(&mut self.space.account, &mut self.anonymous);
+                Err(why)
+            }
+        }
+    }
+
+    /// Remove the anonymous mapping at `base` from the process.
+    pub fn munmap(&mut self, base: VirtualAddress) -> KernelResult<()> {
+        let i = self
+            .anonymous
+            .iter()
+            .position(|&(addr, _)| addr == base)
+            .ok_or_else(|| kerror!(KernelError::InvalidMapping))?;
+
+        let (base, size) = self.anonymous.remove(i);
+        shrink(&mut self.space.account, &mut self.anonymous);
+        self.space.unmap_anonymous(base, size);
+        Ok(())
+    }
+
+    /// Create a shared memory object and map it into the process.
+    pub fn shm_create(
+        &mut self,
+        key: usize,
+        size: usize,
+        addr: Option<VirtualAddress>,
+    ) -> KernelResult<VirtualAddress> {
+        grow(&mut self.space.account, &mut self.shared)?;
+        match shm::create(key, size, &mut self.space, addr) {
+            Ok(base) => {
+                self.shared.push((key, base));
+                Ok(base)
+            }
+            Err(why) => {
+                shrink(&mut self.space.account, &mut self.shared);
+                Err(why)
+            }
+        }
+    }
+
+    /// Map an existing shared memory object into the process.
+    pub fn shm_map(
+        &mut self,
+        key: usize,
+        addr: Option<VirtualAddress>,
+    ) -> KernelResult<VirtualAddress> {
+        grow(&mut self.space.account, &mut self.shared)?;
+        match shm::map(key, &mut self.space, addr) {
+            Ok(base) => {
+                self.shared.push((key, base));
+                Ok(base)
+            }
+            Err(why) => {
+                shrink(&mut self.space.account, &mut self.shared);
+                Err(why)
+            }
+        }
+    }
+
+    /// Remove the shared memory mapping at `base` from the process.
+    pub fn shm_unmap(&mut self, base: VirtualAddress) -> KernelResult<()> {
+        let i = self
+            .shared
+            .iter()
+            .position(|&(_, addr)| addr == base)
+            .ok_or_else(|| kerror!(KernelError::InvalidMapping))?;
+
+        // Keep the mapping on record if it can't be unmapped, so `release`
+        // still tries again.
+        let (key, base) = self.shared[i];
+        shm::unmap(key, &mut self.space, base)?;
+        self.shared.remove(i);
+        shrink(&mut self.space.account, &mut self.shared);
+        Ok(())
+    }
+
+    /// Release resources that are shared with other processes and free the
+    /// address-space. Call before dropping the process.
+    pub fn release(&mut self) {
+        while let Some((key, base)) = self.shared.pop() {
+            if let Err(why) = shm::unmap(key, &mut self.space, base) {
+                warn!("Failed to unmap shared memory {}: {:?}", key, why);
+            }
+        }
+
+        while let Some((base, size)) = self.anonymous.pop() {
+            self.space.unmap_anonymous(base, size);
+        }
+        shrink(&mut self.space.account, &mut self.shared);
+        shrink(&mut self.space.account, &mut self.anonymous);
+
+        self.space.release();
     }
 }
@@ -1,10 +1,10 @@
-use halogen_common::mem::{Segment, VirtualAddress, KIB, MIB};
+use halogen_common::mem::{alloc::Tag, Segment, VirtualAddress, KIB, MIB};
 
 use super::{process::Process, yld};
 use crate::{
     arch::{Context, Privilege},
     error::KernelResult,
-    mem::{paging::get_satp, Stack},
+    mem::{heap, paging::get_satp, Stack},
     task::executor::exit,
 };
 
@@ -61,10 +61,14 @@ pub struct KernelThread {
     pub arg: usize,
     pub state: ThreadState,
     pub exit: Option<isize>,
+    /// Tag of the thread's heap allocations while it is switched out.
+    heap_tag: Tag,
     stack: Stack,
 }
 
 impl Thread {
+    /// Save the context of the thread, and its heap allocation tag, when
+    /// switching away from it.
     pub fn save_context(&mut self, ctx: &Context) {
         unsafe {
             match self {
@@ -72,6 +76,19 @@ impl Thread {
                 Thread::Kernel(kt) => kt.context = core::mem::transmute_copy(ctx),
             };
         }
+
+        match self {
+            Thread::User(ut) => ut.heap_tag = heap::current_tag(),
+            Thread::Kernel(kt) => kt.heap_tag = heap::current_tag(),
+        };
+    }
+
+    /// Get the tag the thread's heap allocations are made with.
+    pub fn heap_tag(&self) -> Tag {
+        match self {
+            Thread::User(ut) => ut.heap_tag,
+            Thread::Kernel(kt) => kt.heap_tag,
+        }
     }
 
     pub fn context(&self) -> &Context {
@@ -141,6 +158,7 @@ impl KernelThread {
             arg,
             stack,
             exit: None,
+            heap_tag: Tag::Untagged,
             state: ThreadState::default(),
             context: Context::new_kernel(),
         };
@@ -165,17 +183,18 @@ pub struct UserThread {
     pub state: ThreadState,
     pub context: Context,
     pub exit: Option<isize>,
+    /// Tag of the thread's heap allocations while it is switched out.
+    heap_tag: Tag,
     stack: Stack,
 }
 
 impl UserThread {
-    pub fn try_new(tid: usize, parent: &Process) -> KernelResult<UserThread> {
-        let stack = unsafe {
-            Stack::try_new_user(
-                Segment::from_size(USER_STACK_TOP - USER_STACK_SIZE, USER_STACK_SIZE),
-                USER_STACK_SIZE,
-            )?
-        };
+    pub fn try_new(tid: usize, parent: &mut Process) -> KernelResult<UserThread> {
+        let stack = Stack::try_new_user(
+            &mut parent.space,
+            Segment::from_size(USER_STACK_TOP - USER_STACK_SIZE, USER_STACK_SIZE),
+            USER_STACK_SIZE,
+        )?;
 
         let ctx = Context {
             pc: USER_START.into(),
@@ -191,6 +210,7 @@ impl UserThread {
             context: ctx,
             stack,
             exit: None,
+            heap_tag: Tag::Untagged,
         })
     }
 }
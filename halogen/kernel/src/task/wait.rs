@@ -0,0 +1,83 @@
+//! Wait queues. A thread waits on a queue by blocking: it is taken out of the
+//! scheduler until another thread wakes it up. Device drivers and IPC wait for
+//! events this way rather than by spinning.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::executor::{self, Event};
+
+/// Source of wait queue IDs. 0 means a queue doesn't have one yet.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+
+/// A queue of threads blocked until they are woken up.
+pub struct WaitQueue {
+    /// The ID of the queue, assigned when it is first used so that queues can
+    /// be created in a `static`.
+    id: AtomicUsize,
+}
+
+impl WaitQueue {
+    /// Create an empty wait queue.
+    pub const fn new() -> WaitQueue {
+        WaitQueue {
+            id: AtomicUsize::new(0),
+        }
+    }
+
+    fn event(&self) -> Event {
+        let id = match self.id.load(Ordering::Acquire) {
+            0 => {
+                let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+                match self
+                    .id
+                    .compare_exchange(0, id, Ordering::AcqRel, Ordering::Acquire)
+                {
+                    Ok(_) => id,
+                    Err(current) => current,
+                }
+            }
+            id => id,
+        };
+
+        Event::Queue(id)
+    }
+
+    /// Block the calling thread until it is woken up.
+    pub fn wait(&self) {
+        let mut waited = false;
+        executor::wait_until(self.event(), |_| core::mem::replace(&mut waited, true));
+    }
+
+    /// Block the calling thread until `cond` holds, checking it again each time
+    /// the thread is woken. The check and blocking are atomic with respect to
+    /// wake-ups, so `cond` must not call into the executor.
+    pub fn wait_until(&self, mut cond: impl FnMut() -> bool) {
+        executor::wait_until(self.event(), |_| cond());
+    }
+
+    /// Wake the thread that has waited the longest. Returns false if no thread
+    /// waits.
+    pub fn wake_one(&self) -> bool {
+        executor::wake(self.event(), 1) > 0
+    }
+
+    /// Wake every waiting thread and return how many there were.
+    pub fn wake_all(&self) -> usize {
+        executor::wake(self.event(), usize::MAX)
+    }
+}
+
+impl Default for WaitQueue {
+    fn default() -> WaitQueue {
+        WaitQueue::new()
+    }
+}
+
+impl Drop for WaitQueue {
+    fn drop(&mut self) {
+        // Threads blocked on a dropped queue could never be woken.
+        if *self.id.get_mut() != 0 {
+            self.wake_all();
+        }
+    }
+}
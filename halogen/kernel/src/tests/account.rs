@@ -0,0 +1,78 @@
+use halogen_common::mem::{MemoryLimits, Resource};
+
+use crate::{
+    error::KernelError,
+    mem::{paging::PAGE_SIZE, AddressSpace},
+    task::process::Process,
+};
+
+fn process(pid: usize) -> Process {
+    Process {
+        pid,
+        space: AddressSpace::new(pid),
+        ..Default::default()
+    }
+}
+
+#[test_case]
+fn charge_mmap() {
+    let mut proc = process(110);
+
+    let base = proc.mmap(4 * PAGE_SIZE, None).unwrap();
+    let usage = proc.usage();
+    assert_eq!(4 * PAGE_SIZE, usage.resident);
+    assert!(usage.page_tables > 0);
+    assert!(usage.heap > 0);
+
+    // The list of mappings already has room for another.
+    let second = proc.mmap(PAGE_SIZE, None).unwrap();
+    assert_eq!(usage.heap, proc.usage().heap);
+
+    proc.munmap(second).unwrap();
+    proc.munmap(base).unwrap();
+    assert_eq!(0, proc.usage().resident);
+    assert_eq!(0, proc.usage().heap);
+}
+
+#[test_case]
+fn resident_limit() {
+    let mut limited = process(111);
+    let mut other = process(112);
+
+    limited.set_limits(MemoryLimits {
+        resident: 2 * PAGE_SIZE,
+        ..MemoryLimits::unlimited()
+    });
+
+    let base = limited.mmap(2 * PAGE_SIZE, None).unwrap();
+    assert!(matches!(
+        limited.mmap(PAGE_SIZE, None),
+        Err(KernelError::OutOfMemory(..))
+    ));
+    assert_eq!(2 * PAGE_SIZE, limited.usage().resident);
+
+    // Other processes are not affected.
+    let other_base = other.mmap(4 * PAGE_SIZE, None).unwrap();
+
+    limited.munmap(base).unwrap();
+    limited.mmap(PAGE_SIZE, None).unwrap();
+
+    limited.release();
+    other.munmap(other_base).unwrap();
+}
+
+#[test_case]
+fn page_table_limit() {
+    let mut proc = process(113);
+
+    proc.set_limits(MemoryLimits {
+        page_tables: 0,
+        ..MemoryLimits::unlimited()
+    });
+
+    assert!(matches!(
+        proc.mmap(PAGE_SIZE, None),
+        Err(KernelError::OutOfMemory(..))
+    ));
+    assert_eq!(0, proc.usage().get(Resource::Resident));
+}
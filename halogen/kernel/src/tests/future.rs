@@ -0,0 +1,107 @@
+use alloc::vec::Vec;
+use core::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use spin::Mutex;
+
+use crate::{future, future::Notify, sbi::timer::now_us, task, timer};
+
+#[test_case]
+fn spawn() {
+    let task = future::spawn(async { 1 + 2 });
+    assert_eq!(3, future::block_on(task));
+
+    // Tasks can await each other.
+    let inner = future::spawn(async { 4 });
+    let outer = future::spawn(async move { inner.await * 2 });
+    assert_eq!(8, future::block_on(outer));
+}
+
+#[test_case]
+fn sleep() {
+    let slept = future::block_on(async {
+        let start = now_us();
+        future::sleep(Duration::from_millis(10)).await;
+        now_us() - start
+    });
+    assert!(slept >= 10_000);
+}
+
+#[test_case]
+fn concurrent_sleeps() {
+    static ORDER: Mutex<Vec<u64>> = Mutex::new(Vec::new());
+    ORDER.lock().clear();
+
+    // Both sleep at once on one worker, so they finish in deadline order.
+    let start = now_us();
+    let tasks: Vec<_> = [30, 10, 20]
+        .iter()
+        .map(|&ms| {
+            future::spawn(async move {
+                future::sleep(Duration::from_millis(ms)).await;
+                ORDER.lock().push(ms);
+            })
+        })
+        .collect();
+
+    for task in tasks {
+        future::block_on(task);
+    }
+    assert_eq!(&[10, 20, 30], ORDER.lock().as_slice());
+    assert!(now_us() - start >= 30_000);
+}
+
+#[test_case]
+fn yield_now() {
+    static STEPS: AtomicUsize = AtomicUsize::new(0);
+    STEPS.store(0, Ordering::SeqCst);
+
+    let counter = |steps: usize| {
+        future::spawn(async move {
+            for _ in 0..steps {
+                STEPS.fetch_add(1, Ordering::SeqCst);
+                future::yield_now().await;
+            }
+        })
+    };
+    let first = counter(5);
+    let second = counter(5);
+
+    future::block_on(first);
+    future::block_on(second);
+    assert_eq!(10, STEPS.load(Ordering::SeqCst));
+}
+
+#[test_case]
+fn notify_from_interrupt() {
+    static NOTIFY: Notify = Notify::new();
+
+    // The timer callback runs in the timer interrupt.
+    let waiter = future::spawn(async {
+        NOTIFY.notified().await;
+        now_us()
+    });
+    let start = now_us();
+    timer::after(Duration::from_millis(5), || NOTIFY.notify());
+
+    assert!(future::block_on(waiter) - start >= 5_000);
+}
+
+#[test_case]
+fn notify_before_wait() {
+    static NOTIFY: Notify = Notify::new();
+
+    // A notification with no waiter is kept for the next wait, but only one.
+    NOTIFY.notify();
+    NOTIFY.notify();
+    future::block_on(NOTIFY.notified());
+
+    let waiter = future::spawn(async { NOTIFY.notified().await });
+    task::sleep(Duration::from_millis(5));
+    assert!(!waiter.is_finished());
+
+    NOTIFY.notify();
+    future::block_on(waiter);
+}
@@ -1,36 +1,189 @@
-use owo_colors::{colors, OwoColorize};
+//! When physical frames run out while serving an allocation for a user
+//! process, the kernel reclaims memory and retries. Cold user pages are swapped
+//! out first, if there is a swap area. Otherwise, the user process with the
+//! most resident memory is killed and its address-space freed. The process
+//! being served is never killed, since its address-space is in use, e.g. by
+//! the page fault being handled; its allocation fails instead.
+//!
+//! Cold pages are found with the clock algorithm. The hand sweeps over the user
+//! pages of every process in order. A page that has been accessed since the
+//! hand last passed it gets its accessed bit cleared and a second chance; a
+//! page that has not is swapped out.
 
+use alloc::collections::BTreeMap;
+
+use halogen_common::mem::VirtualAddress;
+use spin::Mutex;
+
+use super::process::Process;
 use crate::{
-    kprintln,
-    sbi::reset::{shutdown, Reason},
+    error::{KernelError, KernelResult},
+    kerror,
+    log::*,
+    mem::{paging::PAGE_SIZE, swap},
 };
 
-pub trait TestCase {
-    fn run(&self);
+/// Exit status of threads killed to reclaim memory.
+pub const OOM_KILLED: isize = -9;
+
+/// Number of pages swapped out each time memory is reclaimed.
+pub const SWAP_BATCH: usize = 16;
+
+/// Full sweeps the clock hand makes looking for cold pages. The first may only
+/// clear accessed bits.
+const CLOCK_SWEEPS: usize = 2;
+
+/// The PID and user address at which the clock hand stopped.
+static CLOCK_HAND: Mutex<(usize, VirtualAddress)> = Mutex::new((0, VirtualAddress(0)));
+
+/// How memory was reclaimed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reclaimed {
+    /// This many pages were swapped out.
+    Swapped(usize),
+    /// The process with this PID was killed.
+    Killed(usize),
 }
 
-impl<T> TestCase for T
-where
-    T: Fn(),
-{
-    fn run(&self) {
-        kprintln!("---");
-        kprintln!("{}", core::any::type_name::<T>().fg::<colors::Cyan>());
+/// Swap out up to `count` cold user pages with the clock algorithm. Returns the
+/// number of pages swapped out.
+pub fn swap_out(processes: &mut BTreeMap<usize, Process>, count: usize) -> usize {
+    if !swap::is_enabled() {
+        return 0;
+    }
+
+    let mut hand = CLOCK_HAND.lock();
+    let (mut pid, mut addr) = *hand;
+    let mut wraps = 0;
+    let mut swapped = 0;
+
+    while swapped < count {
+        let proc = match processes.range_mut(pid..).next() {
+            Some((&next, proc)) => {
+                if next != pid {
+                    pid = next;
+                    addr = VirtualAddress(0);
+                }
+                proc
+            }
+            None => {
+                // Wrap around to the first process.
+                wraps += 1;
+                if wraps > CLOCK_SWEEPS {
+                    break;
+                }
+                pid = 0;
+                addr = VirtualAddress(0);
+                continue;
+            }
+        };
 
-        self();
+        let (page, entry) = match proc.space.root.next_user_page(addr) {
+            Some(next) => next,
+            None => {
+                pid += 1;
+                addr = VirtualAddress(0);
+                continue;
+            }
+        };
 
-        kprintln!("{}", "Ok".fg::<colors::Green>());
+        addr = page + PAGE_SIZE;
+        if entry.is_accessed() {
+            entry.clear_accessed();
+            continue;
+        }
+
+        match swap::evict(&mut proc.space, page) {
+            Ok(true) => swapped += 1,
+            Ok(false) => {}
+            Err(why) => {
+                warn!(
+                    "Failed to swap out {:?} of process {}: {:?}",
+                    page, pid, why
+                );
+                break;
+            }
+        }
     }
+
+    *hand = (pid, addr);
+
+    if swapped > 0 {
+        trace!("Swapped out {} pages", swapped);
+    }
+    swapped
 }
 
-pub fn run_tests(tests: &[&dyn TestCase]) -> ! {
-    kprintln!("\nRunning {} tests\n", tests.len());
+/// Choose the process to kill to reclaim frames: the one with the most resident
+/// memory, other than `spare`. Processes with nothing resident are never
+/// chosen.
+pub fn victim(processes: &BTreeMap<usize, Process>, spare: Option<usize>) -> Option<usize> {
+    processes
+        .values()
+        .filter(|proc| Some(proc.pid) != spare && proc.usage().resident > 0)
+        .max_by_key(|proc| proc.usage().resident)
+        .map(|proc| proc.pid)
+}
 
-    for test in tests {
-        test.run();
+/// Reclaim memory on behalf of `requester`. Swap out cold pages if possible.
+/// Otherwise, kill a victim other than the requester by calling `kill` on it,
+/// then free its memory.
+pub fn reclaim(
+    processes: &mut BTreeMap<usize, Process>,
+    requester: Option<usize>,
+    mut kill: impl FnMut(&Process),
+) -> Option<Reclaimed> {
+    match swap_out(processes, SWAP_BATCH) {
+        0 => {}
+        swapped => return Some(Reclaimed::Swapped(swapped)),
     }
 
-    kprintln!("---\n\nAll tests passed");
+    let pid = match victim(processes, requester) {
+        Some(pid) => pid,
+        None => {
+            error!(
+                "Out of memory serving process {:?}; no process to kill",
+                requester
+            );
+            return None;
+        }
+    };
+
+    let proc = processes.get_mut(&pid)?;
+    warn!(
+        "Out of memory serving process {:?}; kill process {} ({})",
+        requester,
+        pid,
+        proc.usage()
+    );
+
+    kill(proc);
+    proc.release();
 
-    shutdown(Reason::None);
+    Some(Reclaimed::Killed(pid))
+}
+
+/// Call `f` with the process `pid` to serve an allocation. While it fails for
+/// lack of physical frames, reclaim memory and retry. Gives up when nothing can
+/// be reclaimed.
+pub fn serve<R>(
+    processes: &mut BTreeMap<usize, Process>,
+    pid: usize,
+    mut f: impl FnMut(&mut Process) -> KernelResult<R>,
+    mut kill: impl FnMut(&Process),
+) -> KernelResult<R> {
+    loop {
+        let proc = processes
+            .get_mut(&pid)
+            .ok_or_else(|| kerror!(KernelError::NoSuchProcess))?;
+
+        let why = match f(proc) {
+            Err(why) if why.is_out_of_frames() => why,
+            result => return result,
+        };
+
+        if reclaim(processes, Some(pid), &mut kill).is_none() {
+            return Err(why);
+        }
+    }
 }
@@ -1,8 +1,19 @@
-use alloc::{vec, vec::Vec};
-
+use alloc::{
+    alloc::{alloc, dealloc, realloc, Layout},
+    vec,
+    vec::Vec,
+};
+
+#[cfg(feature = "heap-track")]
+use halogen_common::mem::alloc::{Snapshot, Tag, TagDelta};
 use halogen_common::mem::KIB;
 
 use crate::mem::heap;
+#[cfg(feature = "heap-track")]
+use crate::{
+    mem::{paging::PAGE_SIZE, AddressSpace},
+    task::{self, process::Process},
+};
 
 #[test_case]
 fn independence() {
@@ -64,3 +75,93 @@ fn stress() {
         assert_eq!(free_before, stats.bytes_free);
     }
 }
+
+#[test_case]
+fn aligned() {
+    for align in [8, 64, 4 * KIB] {
+        let layout = Layout::from_size_align(100, align).unwrap();
+        unsafe {
+            let ptr = alloc(layout);
+            assert!(!ptr.is_null());
+            assert_eq!(0, ptr as usize % align);
+            dealloc(ptr, layout);
+        }
+    }
+}
+
+#[test_case]
+fn grow_and_shrink() {
+    let mut layout = Layout::from_size_align(64, 8).unwrap();
+
+    unsafe {
+        let mut ptr = alloc(layout);
+        ptr.write_bytes(0x5a, layout.size());
+
+        for size in [128, 4 * KIB, 100, 16] {
+            ptr = realloc(ptr, layout, size);
+            assert!(!ptr.is_null());
+
+            let kept = layout.size().min(size);
+            assert!((0..kept).all(|i| *ptr.add(i) == 0x5a));
+
+            layout = Layout::from_size_align(size, 8).unwrap();
+            ptr.write_bytes(0x5a, size);
+        }
+
+        dealloc(ptr, layout);
+    }
+}
+
+#[cfg(feature = "heap-track")]
+extern "C" fn nop(arg: usize) -> isize {
+    arg as isize
+}
+
+/// Assert that nothing allocated with `tags` since `before` is still live.
+/// Other tags are left out, since background threads allocate meanwhile.
+#[cfg(feature = "heap-track")]
+fn assert_no_leaks(before: &Snapshot, tags: &[Tag]) {
+    let diff = heap::snapshot().unwrap().diff(before);
+    let leaked = tags
+        .iter()
+        .any(|&tag| diff.delta(tag) != TagDelta::default());
+    if leaked {
+        heap::report_since(before);
+    }
+    assert!(!leaked);
+}
+
+#[cfg(feature = "heap-track")]
+#[test_case]
+fn spawn_join_leaks_nothing() {
+    // Let the executor grow its tables before taking the snapshot.
+    task::join(task::spawn(nop, 0).unwrap()).unwrap();
+
+    let before = heap::snapshot().unwrap();
+
+    for i in 0..8 {
+        let tid = task::spawn(nop, i).unwrap();
+        assert_eq!(i as isize, task::join(tid).unwrap());
+    }
+
+    assert_no_leaks(&before, &[Tag::Scheduler]);
+}
+
+#[cfg(feature = "heap-track")]
+#[test_case]
+fn process_leaks_nothing() {
+    let before = heap::snapshot().unwrap();
+
+    let mut proc = Process {
+        pid: 130,
+        space: AddressSpace::new(130),
+        ..Default::default()
+    };
+    let base = proc.mmap(4 * PAGE_SIZE, None).unwrap();
+    proc.mmap(PAGE_SIZE, None).unwrap();
+    proc.munmap(base).unwrap();
+    proc.release();
+    drop(proc);
+
+    assert_no_leaks(&before, &[Tag::Process, Tag::Paging, Tag::Untagged]);
+}
@@ -0,0 +1,71 @@
+use halogen_common::mem::PhysicalAddress;
+
+use crate::mem::{
+    io::{ioremap, ReadOnly, UART_BASE, UART_SIZE},
+    paging::{translate, Permissions, PAGE_SIZE},
+};
+
+/// Boot ROM of the QEMU `virt` machine, which the kernel never maps.
+const ROM_BASE: PhysicalAddress = PhysicalAddress(0x1000);
+
+#[test_case]
+fn ioremap_shares_mappings() {
+    let rom = unsafe { ioremap(ROM_BASE, 16).unwrap() };
+    let (phys_addr, _, _, perms) = translate(rom.base()).unwrap();
+    assert_eq!(ROM_BASE, phys_addr);
+    assert_eq!(Permissions::ReadWrite, perms);
+
+    // Registers in the same page reuse the mapping.
+    let word = unsafe { ioremap(ROM_BASE + 8, 4).unwrap() };
+    assert_eq!(rom.base() + 8, word.base());
+
+    drop(rom);
+    assert!(translate(word.base()).is_some());
+
+    // The last user unmaps it.
+    let virt_addr = word.base();
+    drop(word);
+    assert!(translate(virt_addr).is_none());
+}
+
+#[test_case]
+fn ioremap_extends_mappings() {
+    let rom = unsafe { ioremap(ROM_BASE, 16).unwrap() };
+    let old_base = rom.base();
+
+    // Registers that cross into the next page extend the mapping, and the old
+    // range stays mapped for the registers in it.
+    let cross = unsafe { ioremap(ROM_BASE + PAGE_SIZE - 8, 16).unwrap() };
+    assert_eq!(ROM_BASE + PAGE_SIZE - 8, translate(cross.base()).unwrap().0);
+    assert_eq!(ROM_BASE, translate(old_base).unwrap().0);
+
+    // Later users share the extended mapping.
+    let word = unsafe { ioremap(ROM_BASE, 4).unwrap() };
+    assert_eq!(cross.base() - (PAGE_SIZE - 8), word.base());
+
+    let new_base = word.base();
+    drop(rom);
+    drop(cross);
+    assert!(translate(old_base).is_some());
+
+    // Every range is unmapped with the last user.
+    drop(word);
+    assert!(translate(old_base).is_none());
+    assert!(translate(new_base).is_none());
+}
+
+#[test_case]
+fn register_block() {
+    #[repr(C)]
+    struct Registers {
+        _data: [u8; 5],
+        line_stat: ReadOnly<u8>,
+    }
+
+    // Shares the mapping of the console.
+    let uart = unsafe { ioremap(UART_BASE, UART_SIZE).unwrap() };
+    let regs = unsafe { uart.block::<Registers>(0) };
+
+    // The transmitter is always empty in QEMU.
+    assert_ne!(0, regs.line_stat.read() & (1 << 5));
+}
@@ -1,6 +1,20 @@
 pub mod harness;
 pub use harness::run_tests;
 
+mod account;
+mod future;
 mod heap;
+mod mmio;
+// User allocations only run out of frames for real with little memory. Run
+// these with `just test-oom`.
+#[cfg(feature = "small-memory")]
+mod oom;
 mod paging;
+mod phys;
+mod protect;
+mod shm;
+mod swap;
+mod sync;
 mod thread;
+mod timer;
+mod wait;
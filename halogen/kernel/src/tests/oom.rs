@@ -0,0 +1,96 @@
+use alloc::{boxed::Box, collections::BTreeMap, vec, vec::Vec};
+
+use crate::{
+    io::block::BlockDevice,
+    mem::{paging::PAGE_SIZE, swap, AddressSpace},
+    task::{oom, process::Process},
+};
+
+/// Pages mapped by each allocation of the process hogging memory.
+const HOG_CHUNK: usize = 64;
+
+/// Create processes with anonymous mappings of the given number of pages.
+fn processes(pages: &[(usize, usize)]) -> BTreeMap<usize, Process> {
+    pages
+        .iter()
+        .map(|&(pid, pages)| {
+            let mut proc = Process {
+                pid,
+                space: AddressSpace::new(pid),
+                ..Default::default()
+            };
+            proc.mmap(pages * PAGE_SIZE, None).unwrap();
+            (pid, proc)
+        })
+        .collect()
+}
+
+/// Run `f` without a swap area, so memory can only be reclaimed by killing a
+/// process.
+fn without_swap(f: impl FnOnce()) {
+    let device: Option<Box<dyn BlockDevice>> = swap::remove_device().unwrap();
+    f();
+    if let Some(device) = device {
+        swap::set_device(device).unwrap();
+    }
+}
+
+/// Map memory into the process `hog` until a process is killed or an
+/// allocation fails. Returns true if a process was killed.
+fn hog(procs: &mut BTreeMap<usize, Process>, hog: usize, killed: &mut Vec<usize>) -> bool {
+    loop {
+        let result = oom::serve(
+            procs,
+            hog,
+            |proc| proc.mmap(HOG_CHUNK * PAGE_SIZE, None),
+            |victim| killed.push(victim.pid),
+        );
+
+        match result {
+            Ok(_) if killed.is_empty() => continue,
+            Ok(_) => return true,
+            Err(why) => {
+                assert!(why.is_out_of_frames());
+                return false;
+            }
+        }
+    }
+}
+
+#[test_case]
+fn kill_largest() {
+    without_swap(|| {
+        let mut procs = processes(&[(120, 4), (121, 16), (122, 1)]);
+        let mut killed = Vec::new();
+
+        // The hog soon has the most memory, but it is never the victim.
+        assert!(hog(&mut procs, 122, &mut killed));
+
+        assert_eq!(vec![121], killed);
+        assert_eq!(0, procs[&121].usage().resident);
+        assert_eq!(0, procs[&121].usage().page_tables);
+        assert_eq!(4 * PAGE_SIZE, procs[&120].usage().resident);
+
+        procs.values_mut().for_each(Process::release);
+    });
+}
+
+#[test_case]
+fn spare_requester() {
+    without_swap(|| {
+        let mut procs = processes(&[(123, 2), (124, 1)]);
+        let mut killed = Vec::new();
+
+        assert!(hog(&mut procs, 124, &mut killed));
+        assert_eq!(vec![123], killed);
+
+        // Only the hog has memory left, so its allocations fail instead of it
+        // being killed.
+        killed.clear();
+        assert!(!hog(&mut procs, 124, &mut killed));
+        assert!(killed.is_empty());
+        assert!(procs[&124].usage().resident > 0);
+
+        procs.values_mut().for_each(Process::release);
+    });
+}
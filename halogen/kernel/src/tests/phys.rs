@@ -0,0 +1,103 @@
+use alloc::{boxed::Box, vec::Vec};
+use core::time::Duration;
+
+use halogen_common::mem::{Address, FrameKind, VirtualAddress};
+
+use crate::{
+    mem::{
+        paging::{translate, PAGE_SIZE},
+        phys,
+    },
+    task,
+};
+
+#[test_case]
+fn descriptor_tracks_alloc() {
+    let free_before = phys::stats().free;
+
+    let (_, frame) = phys::alloc().unwrap();
+    let desc = phys::descriptor(frame).unwrap();
+
+    assert_eq!(FrameKind::Kernel, desc.kind());
+    assert_eq!(1, desc.refcount());
+    assert_eq!(free_before - 1, phys::stats().free);
+
+    unsafe {
+        phys::free(frame);
+    }
+
+    assert!(phys::descriptor(frame).unwrap().is_free());
+    assert_eq!(free_before, phys::stats().free);
+}
+
+#[test_case]
+fn descriptor_refcount() {
+    let shared_before = phys::stats().shared;
+    let (_, frame) = phys::alloc_for(FrameKind::User, 1).unwrap();
+
+    assert_eq!(2, phys::get_ref(frame));
+    assert_eq!(shared_before + 1, phys::stats().shared);
+
+    unsafe {
+        phys::free(frame);
+    }
+
+    let desc = phys::descriptor(frame).unwrap();
+    assert_eq!(1, desc.refcount());
+    assert_eq!(1, desc.owner());
+
+    unsafe {
+        phys::free(frame);
+    }
+
+    assert!(phys::descriptor(frame).unwrap().is_free());
+}
+
+#[test_case]
+fn heap_frames_tagged() {
+    let boxed = Box::new(0_usize);
+    let (frame, _, _, _) = translate(VirtualAddress::from_ref(&*boxed)).unwrap();
+
+    assert_eq!(FrameKind::Heap, phys::descriptor(frame).unwrap().kind());
+}
+
+#[test_case]
+fn alloc_zeroed() {
+    // Dirty a frame and give it back, so the pool has to clear it.
+    let (virt_addr, frame) = phys::alloc().unwrap();
+    unsafe {
+        core::ptr::write_bytes(virt_addr.as_mut_ptr::<u8>(), 0xa5, PAGE_SIZE);
+        phys::free(frame);
+    }
+
+    phys::refill_zero_pool(phys::ZERO_POOL_SIZE);
+    assert_eq!(phys::ZERO_POOL_SIZE, phys::zero_pool_len());
+
+    let (virt_addr, frame) = phys::alloc_zeroed().unwrap();
+    let bytes = unsafe { core::slice::from_raw_parts(virt_addr.as_ptr::<u8>(), PAGE_SIZE) };
+    assert!(bytes.iter().all(|&b| b == 0));
+    assert_eq!(phys::ZERO_POOL_SIZE - 1, phys::zero_pool_len());
+    assert_eq!(0, phys::descriptor(frame).unwrap().flags());
+
+    unsafe {
+        phys::free(frame);
+    }
+}
+
+#[test_case]
+fn idle_refills_zero_pool() {
+    phys::refill_zero_pool(phys::ZERO_POOL_SIZE);
+    let frames: Vec<_> = (0..phys::ZERO_POOL_SIZE)
+        .map(|_| phys::alloc_zeroed().unwrap().1)
+        .collect();
+    assert_eq!(0, phys::zero_pool_len());
+
+    // Nothing else is runnable while this thread sleeps, so the idle thread
+    // refills the pool.
+    task::sleep(Duration::from_millis(20));
+    assert_eq!(phys::ZERO_POOL_SIZE, phys::zero_pool_len());
+
+    for frame in frames {
+        unsafe { phys::free(frame) };
+    }
+}
@@ -0,0 +1,60 @@
+use halogen_common::mem::{Address, FrameKind, Segment, VirtualAddress};
+
+use crate::mem::{
+    paging::{map, translate, unmap, Permissions, Privilege, Scope, KERNEL_ASID, PAGE_SIZE},
+    phys,
+    protect::{audit, protect_text, Violation},
+    regions::{virtual_offset, IMAGE_INIT, PHYSICAL_BASE},
+    virt_alloc::virt_addr_free,
+};
+
+#[test_case]
+fn no_violations_after_boot() {
+    let mut count = 0;
+    audit(|_| count += 1);
+    assert_eq!(0, count);
+}
+
+#[test_case]
+fn init_released() {
+    // The boot code can't run: each page is unmapped, or a frame that belongs
+    // to the allocator.
+    for virt_addr in IMAGE_INIT.iter().step_by(PAGE_SIZE) {
+        let virt_addr = VirtualAddress(virt_addr);
+        if let Some((phys_addr, _, _, perms)) = translate(virt_addr) {
+            assert_eq!(Permissions::ReadWrite, perms);
+            assert_eq!(virt_addr.add_offset(-virtual_offset()).as_phys(), phys_addr);
+            assert_ne!(
+                FrameKind::Reserved,
+                phys::descriptor(phys_addr).unwrap().kind()
+            );
+        }
+    }
+}
+
+#[test_case]
+fn writable_text_alias() {
+    let virt_addr = unsafe {
+        map(
+            None,
+            Some(PHYSICAL_BASE),
+            PAGE_SIZE,
+            Permissions::ReadWrite,
+            Scope::Global,
+            Privilege::Kernel,
+            KERNEL_ASID,
+        )
+        .unwrap()
+    };
+
+    let mut found = false;
+    audit(|violation| found |= violation == Violation::WritableText(virt_addr));
+    assert!(found);
+
+    assert!(protect_text() > 0);
+    audit(|violation| assert_ne!(Violation::WritableText(virt_addr), violation));
+    assert_eq!(Permissions::ReadOnly, translate(virt_addr).unwrap().3);
+
+    unsafe { unmap(Segment::from_size(virt_addr, PAGE_SIZE)).unwrap() };
+    virt_addr_free(virt_addr).unwrap();
+}
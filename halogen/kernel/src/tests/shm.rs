@@ -0,0 +1,115 @@
+use halogen_common::mem::{Address, VirtualAddress};
+
+use crate::{
+    mem::{
+        paging::{Permissions, PAGE_SIZE},
+        phys,
+        regions::virtual_offset,
+        shm::{self, SHM_REGION},
+        AddressSpace,
+    },
+    task::process::Process,
+};
+
+/// Get a kernel pointer to the byte a user address maps to in an address-space.
+fn kernel_ptr(space: &AddressSpace, addr: VirtualAddress) -> *mut u8 {
+    let (phys_addr, _, _, perms) = space.root.translate(addr).expect("address not mapped");
+    assert_eq!(Permissions::ReadWrite, perms);
+    phys_addr.add_offset(virtual_offset()).as_mut_ptr()
+}
+
+#[test_case]
+fn exchange_data() {
+    let mut p1 = Process {
+        pid: 100,
+        space: AddressSpace::new(100),
+        ..Default::default()
+    };
+    let mut p2 = Process {
+        pid: 101,
+        space: AddressSpace::new(101),
+        ..Default::default()
+    };
+
+    let size = 2 * PAGE_SIZE;
+    let a1 = p1.shm_create(0xCAFE, size, None).unwrap();
+    let a2 = p2
+        .shm_map(0xCAFE, Some(SHM_REGION.start + 16 * PAGE_SIZE))
+        .unwrap();
+
+    assert_eq!(SHM_REGION.start, a1);
+    assert_eq!(SHM_REGION.start + 16 * PAGE_SIZE, a2);
+
+    // Each process writes to one page and reads the other's.
+    for i in 0..PAGE_SIZE {
+        unsafe {
+            kernel_ptr(&p1.space, a1 + i).write_volatile(1);
+            kernel_ptr(&p2.space, a2 + PAGE_SIZE + i).write_volatile(2);
+        }
+    }
+    for i in 0..PAGE_SIZE {
+        unsafe {
+            assert_eq!(1, kernel_ptr(&p2.space, a2 + i).read_volatile());
+            assert_eq!(2, kernel_ptr(&p1.space, a1 + PAGE_SIZE + i).read_volatile());
+        }
+    }
+
+    // Both mappings and the object hold a reference.
+    let (frame, _, _, _) = p1.space.root.translate(a1).unwrap();
+    assert_eq!(3, phys::descriptor(frame).unwrap().refcount());
+
+    p1.release();
+    assert!(p1.space.root.translate(a1).is_none());
+    assert!(shm::exists(0xCAFE));
+
+    p2.shm_unmap(a2).unwrap();
+    assert!(!shm::exists(0xCAFE));
+    assert!(phys::descriptor(frame).unwrap().is_free());
+    p2.release();
+}
+
+#[test_case]
+fn duplicate_key() {
+    let mut space = AddressSpace::new(102);
+
+    let addr = shm::create(0xBEEF, PAGE_SIZE, &mut space, None).unwrap();
+    assert!(shm::create(0xBEEF, PAGE_SIZE, &mut space, None).is_err());
+
+    shm::unmap(0xBEEF, &mut space, addr).unwrap();
+    assert!(!shm::exists(0xBEEF));
+    space.release();
+}
+
+#[test_case]
+fn overlapping_mapping() {
+    let mut space = AddressSpace::new(103);
+
+    let addr = shm::create(0xF00D, PAGE_SIZE, &mut space, None).unwrap();
+    assert!(shm::map(0xF00D, &mut space, Some(addr)).is_err());
+    assert!(shm::map(0xF00D, &mut space, Some(addr + 1)).is_err());
+
+    shm::unmap(0xF00D, &mut space, addr).unwrap();
+    space.release();
+}
+
+#[test_case]
+fn failed_unmap_kept() {
+    let mut proc = Process {
+        pid: 104,
+        space: AddressSpace::new(104),
+        ..Default::default()
+    };
+    let addr = proc.shm_create(0xD00D, PAGE_SIZE, None).unwrap();
+
+    // With the page gone from under it, the mapping can't be torn down.
+    let frame = proc.space.unmap(addr).unwrap();
+    assert!(proc.shm_unmap(addr).is_err());
+    assert!(shm::exists(0xD00D));
+
+    // The process still knows about it, so it can be unmapped once fixed.
+    proc.space.map(addr, frame, Permissions::ReadWrite).unwrap();
+    proc.shm_unmap(addr).unwrap();
+    assert!(!shm::exists(0xD00D));
+
+    proc.release();
+}
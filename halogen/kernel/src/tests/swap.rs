@@ -0,0 +1,382 @@
+use core::slice::from_raw_parts;
+
+use halogen_common::mem::{Address, FrameKind, PhysicalAddress, Segment, VirtualAddress};
+
+use crate::{
+    critical_section,
+    mem::{
+        paging::{
+            get_root_satp, get_satp, map, mode, svnapot, translate, unmap, Level, PageTable,
+            Permissions, Privilege, Scope, KERNEL_ASID, NAPOT_SIZE, PAGE_SIZE,
+        },
+        phys,
+        regions::{
+            kernel_space_size, kernel_space_start, user_space_end, Region, KERNEL_IMAGE_START,
+            PHYSICAL_BASE,
+        },
+        virt_alloc::virt_addr_free,
+        AddressSpace, ANON_REGION,
+    },
+};
+
+/// Where the 64 KiB test runs are mapped in a private table.
+const RUN: VirtualAddress = VirtualAddress(0x10_0000);
+
+/// Run `f` on the hart switched to an address-space by its ASID, with user
+/// pages accessible. Nothing is flushed on the way in or out.
+fn in_space<T>(space: &AddressSpace, f: impl FnOnce() -> T) -> T {
+    critical_section!({
+        unsafe {
+            core::arch::asm!("csrw satp, {}", in(reg) get_satp(space.id as u16, &space.root));
+            riscv::register::sstatus::set_sum();
+        }
+        let result = f();
+        unsafe {
+            riscv::register::sstatus::clear_sum();
+            core::arch::asm!("csrw satp, {}", in(reg) get_root_satp());
+        }
+        result
+    })
+}
+
+#[test_case]
+fn address_translation() {
+    let (phys_addr, _, _, _) = translate(KERNEL_IMAGE_START).unwrap();
+    assert_eq!(unsafe { PHYSICAL_BASE }, phys_addr);
+}
+
+#[test_case]
+fn scope_translation() {
+    let (_, scope, _, _) = translate(KERNEL_IMAGE_START).unwrap();
+    assert_eq!(Scope::Global, scope);
+}
+
+#[test_case]
+fn privilege_translation() {
+    let (_, _, prv, _) = translate(KERNEL_IMAGE_START).unwrap();
+    assert_eq!(Privilege::Kernel, prv);
+}
+
+#[test_case]
+fn permissions_translation() {
+    let (_, _, _, perms) = translate(KERNEL_IMAGE_START).unwrap();
+    assert_eq!(Permissions::ReadExecute, perms);
+}
+
+#[test_case]
+fn probed_mode_in_use() {
+    let satp = riscv::register::satp::read().bits();
+    assert_eq!(mode().satp(), satp >> 60);
+}
+
+#[test_case]
+fn layout_follows_mode() {
+    let half = 1 << (mode().va_bits() - 1);
+    assert_eq!(half, kernel_space_size());
+    assert_eq!(half, usize::from(user_space_end()));
+    assert_eq!(0, usize::from(kernel_space_start()).wrapping_add(half));
+    assert!(usize::from(KERNEL_IMAGE_START) >= usize::from(kernel_space_start()));
+
+    assert!(matches!(
+        Region::from(user_space_end() - PAGE_SIZE),
+        Region::User
+    ));
+    assert!(matches!(Region::from(user_space_end()), Region::Hole));
+    assert!(matches!(
+        Region::from(kernel_space_start()),
+        Region::Dynamic | Region::ImageText
+    ));
+}
+
+#[test_case]
+fn map_top_of_user_half() {
+    let mut space = AddressSpace::new(140);
+    let base = user_space_end() - 2 * PAGE_SIZE;
+
+    space
+        .map_anonymous(Some(base), 2 * PAGE_SIZE, Permissions::ReadWrite)
+        .unwrap();
+    assert!(space.root.translate(base + PAGE_SIZE).is_some());
+
+    // The walk reaches the page through every level of the mode.
+    let (found, _) = space.root.next_user_page(VirtualAddress(0)).unwrap();
+    assert_eq!(base, found);
+    let (found, _) = space.root.next_user_page(base + PAGE_SIZE).unwrap();
+    assert_eq!(base + PAGE_SIZE, found);
+    assert!(space.root.next_user_page(user_space_end()).is_none());
+
+    space.release();
+    assert_eq!(0, space.account.usage().resident);
+    assert_eq!(0, space.account.usage().page_tables);
+}
+
+#[test_case]
+fn user_frames_owned_by_space() {
+    let mut space = AddressSpace::new(141);
+    let base = space
+        .map_anonymous(None, PAGE_SIZE, Permissions::ReadWrite)
+        .unwrap();
+
+    let (frame, ..) = space.root.translate(base).unwrap();
+    let desc = phys::descriptor(frame).unwrap();
+    assert_eq!(FrameKind::User, desc.kind());
+    assert_eq!(141, desc.owner());
+
+    // The tables allocated for the mapping belong to the space too.
+    let table = space.root.leaf_table(base).unwrap();
+    let (table_frame, ..) = translate(VirtualAddress::from_ref(table)).unwrap();
+    let desc = phys::descriptor(table_frame).unwrap();
+    assert_eq!(FrameKind::PageTable, desc.kind());
+    assert_eq!(141, desc.owner());
+
+    space.release();
+}
+
+#[test_case]
+fn same_address_per_asid() {
+    let mut spaces = [AddressSpace::new(142), AddressSpace::new(143)];
+    let addr = ANON_REGION.start;
+
+    for (value, space) in spaces.iter_mut().enumerate() {
+        let frame = space.alloc(addr, Permissions::ReadWrite).unwrap();
+        unsafe { frame.as_mut_ptr::<usize>().write_volatile(value) };
+        space.root.leaf_entry(addr).unwrap().mark_accessed(true);
+        // Drop whatever an earlier space with the same ID left in the TLB.
+        unsafe { core::arch::asm!("sfence.vma zero, {}", in(reg) space.id) };
+    }
+
+    // Switching back and forth, each space reads its own frame at the address.
+    for _ in 0..2 {
+        for (value, space) in spaces.iter().enumerate() {
+            let read = in_space(space, || unsafe { addr.as_ptr::<usize>().read_volatile() });
+            assert_eq!(value, read);
+        }
+    }
+
+    // Writes through the address only reach the space that made them.
+    for (value, space) in spaces.iter().enumerate() {
+        in_space(space, || unsafe {
+            addr.as_mut_ptr::<usize>().write_volatile(value + 10)
+        });
+    }
+    for (value, space) in spaces.iter().enumerate() {
+        let read = in_space(space, || unsafe { addr.as_ptr::<usize>().read_volatile() });
+        assert_eq!(value + 10, read);
+    }
+
+    for space in spaces.iter_mut() {
+        space.release();
+    }
+}
+
+/// Map the start of the kernel image at `RUN` in a new table, either as one
+/// Svnapot page or as 16 ordinary pages. The table is never used by the MMU, so
+/// this works without the extension.
+fn run_table(napot: bool) -> (&'static mut PageTable, PhysicalAddress) {
+    let (table, table_phys) = PageTable::new_static(KERNEL_ASID).unwrap();
+    let phys_addr = unsafe { PHYSICAL_BASE };
+
+    if napot {
+        table
+            .map_napot(
+                RUN,
+                phys_addr,
+                Permissions::ReadOnly,
+                Scope::Local,
+                Privilege::Kernel,
+                KERNEL_ASID,
+            )
+            .unwrap();
+    } else {
+        for offset in (0..NAPOT_SIZE).step_by(PAGE_SIZE) {
+            table
+                .map(
+                    RUN + offset,
+                    phys_addr + offset,
+                    Level::PAGE,
+                    Permissions::ReadOnly,
+                    Scope::Local,
+                    Privilege::Kernel,
+                    KERNEL_ASID,
+                )
+                .unwrap();
+        }
+    }
+
+    (table, table_phys)
+}
+
+/// Unmap the run without dropping the image frames, then free the tables.
+fn free_run_table(table: &mut PageTable, table_phys: PhysicalAddress) {
+    for offset in (0..NAPOT_SIZE).step_by(PAGE_SIZE) {
+        table.unmap(RUN + offset);
+    }
+
+    unsafe {
+        table.release_user();
+        phys::free(table_phys);
+    }
+}
+
+#[test_case]
+fn napot_translation() {
+    let (plain, plain_phys) = run_table(false);
+    let (napot, napot_phys) = run_table(true);
+
+    assert!(napot.leaf_entry(RUN + PAGE_SIZE).unwrap().is_napot());
+    assert!(!plain.leaf_entry(RUN + PAGE_SIZE).unwrap().is_napot());
+
+    // Both give the same results, including past the end of the run.
+    for offset in (0..NAPOT_SIZE + PAGE_SIZE).step_by(PAGE_SIZE / 2) {
+        assert_eq!(plain.translate(RUN + offset), napot.translate(RUN + offset));
+    }
+
+    free_run_table(plain, plain_phys);
+    free_run_table(napot, napot_phys);
+}
+
+#[test_case]
+fn napot_unmap_page() {
+    let (table, table_phys) = run_table(true);
+    let phys_base = unsafe { PHYSICAL_BASE };
+
+    assert_eq!(
+        Some(phys_base + 3 * PAGE_SIZE),
+        table.unmap(RUN + 3 * PAGE_SIZE)
+    );
+    assert!(table.translate(RUN + 3 * PAGE_SIZE).is_none());
+
+    // The rest of the run is split into ordinary pages that still translate.
+    for offset in (0..NAPOT_SIZE).step_by(PAGE_SIZE) {
+        if offset != 3 * PAGE_SIZE {
+            let (phys_addr, _, _, _) = table.translate(RUN + offset + 8).unwrap();
+            assert_eq!(phys_base + offset + 8, phys_addr);
+            assert!(!table.leaf_entry(RUN + offset).unwrap().is_napot());
+        }
+    }
+
+    free_run_table(table, table_phys);
+}
+
+#[test_case]
+fn napot_kernel_map() {
+    if !svnapot() {
+        return;
+    }
+
+    let size = NAPOT_SIZE + PAGE_SIZE;
+    let virt_addr = unsafe {
+        map(
+            None,
+            Some(PHYSICAL_BASE),
+            size,
+            Permissions::ReadOnly,
+            Scope::Global,
+            Privilege::Kernel,
+            KERNEL_ASID,
+        )
+        .unwrap()
+    };
+
+    let root = PageTable::from_kernel_root();
+    assert!(root.leaf_entry(virt_addr).unwrap().is_napot());
+    assert!(!root.leaf_entry(virt_addr + NAPOT_SIZE).unwrap().is_napot());
+
+    // The MMU sees the same memory as through the linear mapping.
+    let (mapped, image) = unsafe {
+        (
+            from_raw_parts(virt_addr.as_ptr::<u8>(), size),
+            from_raw_parts(KERNEL_IMAGE_START.as_ptr::<u8>(), size),
+        )
+    };
+    assert_eq!(image, mapped);
+
+    unsafe { unmap(Segment::from_size(virt_addr, size)).unwrap() };
+    virt_addr_free(virt_addr).unwrap();
+}
+
+#[test_case]
+fn napot_kernel_alloc() {
+    if !svnapot() {
+        return;
+    }
+
+    let virt_addr = unsafe {
+        map(
+            None,
+            None,
+            NAPOT_SIZE,
+            Permissions::ReadWrite,
+            Scope::Global,
+            Privilege::Kernel,
+            KERNEL_ASID,
+        )
+        .unwrap()
+    };
+
+    // Allocated frames are mapped as one 64 KiB page of contiguous frames.
+    let root = PageTable::from_kernel_root();
+    assert!(root.leaf_entry(virt_addr).unwrap().is_napot());
+    let (run, ..) = translate(virt_addr).unwrap();
+    assert!(run.is_aligned_to(NAPOT_SIZE));
+
+    let mut frames = [PhysicalAddress::null(); NAPOT_SIZE / PAGE_SIZE];
+    for (n, frame) in frames.iter_mut().enumerate() {
+        (*frame, ..) = translate(virt_addr + n * PAGE_SIZE).unwrap();
+        assert_eq!(run + n * PAGE_SIZE, *frame);
+        assert_eq!(FrameKind::Kernel, phys::descriptor(*frame).unwrap().kind());
+    }
+
+    unsafe {
+        virt_addr
+            .as_mut_ptr::<usize>()
+            .add(NAPOT_SIZE / size_of::<usize>() - 1)
+            .write_volatile(0x5a5a);
+        assert_eq!(
+            0x5a5a,
+            phys::to_virt(run + NAPOT_SIZE - size_of::<usize>())
+                .as_ptr::<usize>()
+                .read_volatile()
+        );
+
+        unmap(Segment::from_size(virt_addr, NAPOT_SIZE)).unwrap();
+        for frame in frames {
+            phys::free(frame);
+        }
+    }
+    virt_addr_free(virt_addr).unwrap();
+}
+
+#[test_case]
+fn napot_user_alloc() {
+    let mut space = AddressSpace::new(144);
+    let size = NAPOT_SIZE + PAGE_SIZE;
+    let base = space
+        .map_anonymous(Some(ANON_REGION.start), size, Permissions::ReadWrite)
+        .unwrap();
+
+    assert_eq!(svnapot(), space.root.leaf_entry(base).unwrap().is_napot());
+    assert!(!space.root.leaf_entry(base + NAPOT_SIZE).unwrap().is_napot());
+    assert_eq!(size, space.account.usage().resident);
+
+    // Every page is zeroed and has its own frame owned by the space.
+    for offset in (0..size).step_by(PAGE_SIZE) {
+        let (frame, ..) = space.root.translate(base + offset).unwrap();
+        let desc = phys::descriptor(frame).unwrap();
+        assert_eq!(FrameKind::User, desc.kind());
+        assert_eq!(144, desc.owner());
+        assert_eq!(1, desc.refcount());
+
+        let page = unsafe { from_raw_parts(phys::to_virt(frame).as_ptr::<u8>(), PAGE_SIZE) };
+        assert!(page.iter().all(|&b| b == 0));
+    }
+
+    // Unmapping a page keeps the rest of the run.
+    space.unmap_anonymous(base + PAGE_SIZE, PAGE_SIZE);
+    assert!(!space.is_mapped(base + PAGE_SIZE));
+    assert!(space.is_mapped(base) && space.is_mapped(base + 2 * PAGE_SIZE));
+    assert_eq!(size - PAGE_SIZE, space.account.usage().resident);
+
+    space.release();
+    assert_eq!(0, space.account.usage().resident);
+}
@@ -0,0 +1,174 @@
+use alloc::vec::Vec;
+
+use crate::{
+    sync::{Condvar, Mutex, RwLock, Semaphore},
+    task,
+};
+
+const THREADS: usize = 4;
+const ITEMS: usize = 64;
+const CAPACITY: usize = 8;
+
+/// Sum of every item produced.
+const TOTAL: isize = (THREADS * ITEMS * (THREADS * ITEMS - 1) / 2) as isize;
+
+/// Run `THREADS` producers and consumers, and return the sum of the items
+/// the consumers took.
+fn produce_and_consume(
+    producer: extern "C" fn(usize) -> isize,
+    consumer: extern "C" fn(usize) -> isize,
+) -> isize {
+    let producers: Vec<usize> = (0..THREADS)
+        .map(|n| task::spawn(producer, n).unwrap())
+        .collect();
+    let consumers: Vec<usize> = (0..THREADS)
+        .map(|n| task::spawn(consumer, n).unwrap())
+        .collect();
+
+    for tid in producers {
+        task::join(tid).unwrap();
+    }
+    consumers
+        .into_iter()
+        .map(|tid| task::join(tid).unwrap())
+        .sum()
+}
+
+static COUNTER: Mutex<usize> = Mutex::new(0);
+
+/// Increment the counter, yielding while holding the lock.
+extern "C" fn increment(n: usize) -> isize {
+    for _ in 0..n {
+        let mut counter = COUNTER.lock();
+        let value = *counter;
+        task::yld();
+        *counter = value + 1;
+    }
+    0
+}
+
+#[test_case]
+fn mutex_exclusion() {
+    *COUNTER.lock() = 0;
+    let tids: Vec<usize> = (0..THREADS)
+        .map(|_| task::spawn(increment, ITEMS).unwrap())
+        .collect();
+    for tid in tids {
+        task::join(tid).unwrap();
+    }
+
+    assert_eq!(THREADS * ITEMS, *COUNTER.lock());
+}
+
+#[test_case]
+fn mutex_try_lock() {
+    let mutex = Mutex::new(1);
+    let guard = mutex.lock();
+    assert!(mutex.is_locked());
+    assert!(mutex.try_lock().is_none());
+    drop(guard);
+
+    *mutex.try_lock().unwrap() += 1;
+    assert_eq!(2, mutex.into_inner());
+}
+
+static BUFFER: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+static NOT_EMPTY: Condvar = Condvar::new();
+static NOT_FULL: Condvar = Condvar::new();
+
+extern "C" fn condvar_producer(n: usize) -> isize {
+    for item in n * ITEMS..(n + 1) * ITEMS {
+        let mut buffer = NOT_FULL.wait_while(BUFFER.lock(), |buffer| buffer.len() >= CAPACITY);
+        buffer.push(item);
+        drop(buffer);
+        NOT_EMPTY.notify_one();
+    }
+    0
+}
+
+extern "C" fn condvar_consumer(_: usize) -> isize {
+    let mut sum = 0;
+    for _ in 0..ITEMS {
+        let mut buffer = NOT_EMPTY.wait_while(BUFFER.lock(), |buffer| buffer.is_empty());
+        sum += buffer.pop().unwrap();
+        drop(buffer);
+        NOT_FULL.notify_one();
+    }
+    sum as isize
+}
+
+#[test_case]
+fn condvar_producers_consumers() {
+    assert_eq!(
+        TOTAL,
+        produce_and_consume(condvar_producer, condvar_consumer)
+    );
+    assert!(BUFFER.lock().is_empty());
+}
+
+static SLOTS: Semaphore = Semaphore::new(CAPACITY);
+static FILLED: Semaphore = Semaphore::new(0);
+
+extern "C" fn semaphore_producer(n: usize) -> isize {
+    for item in n * ITEMS..(n + 1) * ITEMS {
+        SLOTS.acquire();
+        BUFFER.lock().push(item);
+        FILLED.release();
+    }
+    0
+}
+
+extern "C" fn semaphore_consumer(_: usize) -> isize {
+    let mut sum = 0;
+    for _ in 0..ITEMS {
+        FILLED.acquire();
+        sum += BUFFER.lock().pop().unwrap();
+        SLOTS.release();
+    }
+    sum as isize
+}
+
+#[test_case]
+fn semaphore_producers_consumers() {
+    assert_eq!(
+        TOTAL,
+        produce_and_consume(semaphore_producer, semaphore_consumer)
+    );
+    assert_eq!(CAPACITY, SLOTS.permits());
+    assert!(!FILLED.try_acquire());
+}
+
+static PAIR: RwLock<(usize, usize)> = RwLock::new((0, 0));
+
+/// Update both halves of the pair, yielding in between.
+extern "C" fn writer(_: usize) -> isize {
+    for _ in 0..ITEMS {
+        let mut pair = PAIR.write();
+        pair.0 += 1;
+        task::yld();
+        pair.1 += 1;
+    }
+    0
+}
+
+/// Check that no writer is seen halfway, yielding while reading.
+extern "C" fn reader(_: usize) -> isize {
+    for _ in 0..ITEMS {
+        let pair = PAIR.read();
+        let first = pair.0;
+        task::yld();
+        assert_eq!(first, pair.1);
+        assert!(PAIR.try_write().is_none());
+    }
+    0
+}
+
+#[test_case]
+fn rwlock_readers_writers() {
+    *PAIR.write() = (0, 0);
+    assert_eq!(0, produce_and_consume(writer, reader));
+
+    let pair = PAIR.read();
+    assert!(PAIR.try_read().is_some());
+    assert_eq!((THREADS * ITEMS, THREADS * ITEMS), *pair);
+}
@@ -1,3 +1,5 @@
+use halogen_common::sched::{RealTimeParams, SCHEDULERS};
+
 use crate::task;
 
 #[test_case]
@@ -5,6 +7,42 @@ fn get_tid() {
     assert_eq!(0, task::tid());
 }
 
+#[test_case]
+fn set_priority() {
+    assert!(task::set_priority(task::tid(), 1).is_ok());
+    assert!(task::set_priority(usize::MAX, 1).is_err());
+    task::set_priority(task::tid(), 0).unwrap();
+}
+
+/// Do `n` periods of work as a real-time thread.
+extern "C" fn periodic(n: usize) -> isize {
+    for _ in 0..n {
+        task::yld();
+    }
+    n as isize
+}
+
+#[test_case]
+fn set_realtime() {
+    let params = RealTimeParams {
+        runtime_us: 1_000,
+        period_us: 10_000,
+        deadline_us: 10_000,
+    };
+    let tid = task::spawn(periodic, 4).unwrap();
+    task::set_realtime(tid, params).unwrap();
+
+    // Admitting a whole CPU more would overload it.
+    let full = RealTimeParams {
+        runtime_us: 10_000,
+        ..params
+    };
+    assert!(task::set_realtime(task::tid(), full).is_err());
+    assert!(task::set_realtime(usize::MAX, params).is_err());
+
+    assert_eq!(4, task::join(tid).unwrap());
+}
+
 /// Exponentially multithreaded Fibonacci implementation
 extern "C" fn fib(n: usize) -> isize {
     match n {
@@ -72,3 +110,18 @@ fn fib_multithread_4() {
 fn fib_multithread_8() {
     fib_test(8)
 }
+
+#[test_case]
+fn set_scheduler() {
+    let boot = task::scheduler();
+    assert!(task::set_scheduler("lottery").is_err());
+
+    // Every scheduler runs the same workload.
+    for (name, _) in SCHEDULERS.iter() {
+        task::set_scheduler(name).unwrap();
+        fib_test(4);
+        assert_eq!(*name, task::scheduler());
+    }
+
+    task::set_scheduler(boot).unwrap();
+}
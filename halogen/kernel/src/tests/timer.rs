@@ -0,0 +1,101 @@
+use core::{
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use crate::{
+    sbi::timer::now_us,
+    task::{self, executor},
+    timer,
+};
+
+static FIRED: AtomicUsize = AtomicUsize::new(0);
+
+/// Yield until `done` holds, so some thread stays runnable.
+fn yield_until(done: impl Fn() -> bool) {
+    while !done() {
+        task::yld();
+    }
+}
+
+#[test_case]
+fn after() {
+    FIRED.store(0, Ordering::SeqCst);
+    let start = now_us();
+
+    timer::after(Duration::from_millis(20), || {
+        FIRED.fetch_add(1, Ordering::SeqCst);
+    });
+    timer::after(Duration::from_millis(10), || {
+        FIRED.fetch_add(1, Ordering::SeqCst);
+    });
+
+    yield_until(|| FIRED.load(Ordering::SeqCst) == 2);
+    assert!(now_us() - start >= 20_000);
+}
+
+#[test_case]
+fn cancel() {
+    static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+    let id = timer::after(Duration::from_millis(5), || {
+        CANCELLED.store(true, Ordering::SeqCst);
+    });
+    assert!(timer::cancel(id));
+    assert!(!timer::cancel(id));
+
+    let end = timer::deadline(Duration::from_millis(10));
+    yield_until(|| now_us() >= end);
+    assert!(!CANCELLED.load(Ordering::SeqCst));
+}
+
+#[test_case]
+fn far_deadline() {
+    // A deadline too far off to count in timer cycles is never reached.
+    let id = timer::after(Duration::from_secs(30 * 86400), || {
+        panic!("far timer fired");
+    });
+    task::yld();
+    assert!(timer::cancel(id));
+}
+
+static AWAKE: AtomicUsize = AtomicUsize::new(0);
+
+/// Sleep for `ms` milliseconds and return how long it took in microseconds.
+extern "C" fn sleeper(ms: usize) -> isize {
+    let start = now_us();
+    task::sleep(Duration::from_millis(ms as u64));
+    AWAKE.fetch_add(1, Ordering::SeqCst);
+    (now_us() - start) as isize
+}
+
+#[test_case]
+fn sleep() {
+    AWAKE.store(0, Ordering::SeqCst);
+    let long = task::spawn(sleeper, 30).unwrap();
+    let short = task::spawn(sleeper, 10).unwrap();
+
+    // Neither wakes early, and the caller keeps running meanwhile.
+    yield_until(|| AWAKE.load(Ordering::SeqCst) == 2);
+
+    assert!(task::join(short).unwrap() >= 10_000);
+    assert!(task::join(long).unwrap() >= 30_000);
+}
+
+#[test_case]
+fn sleep_while_idle() {
+    let (entries, stops) = executor::idle_stats();
+
+    // Nothing else is runnable, so the idle thread runs meanwhile, without a
+    // tick.
+    let start = now_us();
+    task::sleep(Duration::from_millis(10));
+    assert!(now_us() - start >= 10_000);
+
+    let (entries_after, stops_after) = executor::idle_stats();
+    assert!(entries_after > entries);
+    assert!(stops_after > stops);
+
+    let sleeper = task::spawn(sleeper, 10).unwrap();
+    assert!(task::join(sleeper).unwrap() >= 10_000);
+}
@@ -0,0 +1,43 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::task::{self, WaitQueue};
+
+static QUEUE: WaitQueue = WaitQueue::new();
+static READY: AtomicUsize = AtomicUsize::new(0);
+static WOKEN: AtomicUsize = AtomicUsize::new(0);
+
+/// Wait until `READY` reaches `n`.
+extern "C" fn waiter(n: usize) -> isize {
+    QUEUE.wait_until(|| READY.load(Ordering::SeqCst) >= n);
+    WOKEN.fetch_add(1, Ordering::SeqCst);
+    n as isize
+}
+
+#[test_case]
+fn wake_when_ready() {
+    READY.store(0, Ordering::SeqCst);
+    WOKEN.store(0, Ordering::SeqCst);
+
+    let first = task::spawn(waiter, 1).unwrap();
+    let second = task::spawn(waiter, 2).unwrap();
+
+    // Both block, since neither condition holds.
+    task::yld();
+    assert_eq!(0, WOKEN.load(Ordering::SeqCst));
+
+    // Only the first can go on; the second blocks again.
+    READY.store(1, Ordering::SeqCst);
+    assert_eq!(2, QUEUE.wake_all());
+    assert_eq!(1, task::join(first).unwrap());
+    assert_eq!(1, WOKEN.load(Ordering::SeqCst));
+
+    READY.store(2, Ordering::SeqCst);
+    assert!(QUEUE.wake_one());
+    assert_eq!(2, task::join(second).unwrap());
+    assert!(!QUEUE.wake_one());
+}
+
+#[test_case]
+fn join_missing_thread() {
+    assert!(task::join(usize::MAX).is_err());
+}
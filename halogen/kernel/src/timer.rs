@@ -0,0 +1,94 @@
+//! Kernel timers. Deadlines are kept in a single ordered queue, and the SBI
+//! timer is programmed for the earliest of them and the executor's next
+//! scheduling tick. Callbacks run in the timer interrupt, so they should be
+//! short, e.g. waking a thread.
+
+use alloc::boxed::Box;
+use core::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use halogen_common::{
+    mem::alloc::Tag,
+    sched::{TimerId, TimerQueue},
+};
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+use crate::{critical_section, mem::heap, sbi::timer};
+
+/// Function called when a timer expires.
+type Callback = Box<dyn FnOnce() + Send>;
+
+lazy_static! {
+    static ref TIMERS: Mutex<TimerQueue<Callback>> = Mutex::new(TimerQueue::default());
+}
+
+/// Time of the next scheduling tick, in microseconds since boot.
+static TICK: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// Get the time `delay` from now, in microseconds since boot.
+pub fn deadline(delay: Duration) -> usize {
+    let delay_us = usize::try_from(delay.as_micros()).unwrap_or(usize::MAX);
+    timer::now_us().saturating_add(delay_us)
+}
+
+/// Call `callback` from the timer interrupt once `delay` has passed.
+pub fn after(delay: Duration, callback: impl FnOnce() + Send + 'static) -> TimerId {
+    at(deadline(delay), callback)
+}
+
+/// Call `callback` from the timer interrupt at `deadline`, in microseconds
+/// since boot.
+pub fn at(deadline: usize, callback: impl FnOnce() + Send + 'static) -> TimerId {
+    let _tag = heap::tag(Tag::Scheduler);
+    let callback = Box::new(callback);
+
+    critical_section!({
+        let mut timers = TIMERS.lock();
+        let id = timers.insert(deadline, callback);
+        program(&timers);
+        id
+    })
+}
+
+/// Cancel a timer. Returns false if it already expired.
+pub fn cancel(id: TimerId) -> bool {
+    critical_section!({ TIMERS.lock().cancel(id).is_some() })
+}
+
+/// Set the next scheduling tick `delay_us` from now.
+pub fn set_tick(delay_us: usize) {
+    TICK.store(timer::now_us().saturating_add(delay_us), Ordering::Relaxed);
+    critical_section!({ program(&TIMERS.lock()) });
+}
+
+/// Returns true if the scheduling tick is due.
+pub fn tick_due() -> bool {
+    timer::now_us() >= TICK.load(Ordering::Relaxed)
+}
+
+/// Run the callbacks of every expired timer, then program the SBI timer for
+/// the next deadline. Called from the timer interrupt.
+pub fn expire() {
+    loop {
+        let expired = critical_section!({ TIMERS.lock().pop_expired(timer::now_us()) });
+        match expired {
+            Some((_, callback)) => callback(),
+            None => break,
+        }
+    }
+
+    critical_section!({ program(&TIMERS.lock()) });
+}
+
+/// Program the SBI timer for the earliest of the scheduling tick and the
+/// timers.
+fn program(timers: &TimerQueue<Callback>) {
+    let tick = TICK.load(Ordering::Relaxed);
+    let next = timers
+        .next_deadline()
+        .map_or(tick, |deadline| deadline.min(tick));
+    timer::set_at(next);
+}
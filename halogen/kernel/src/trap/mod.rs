@@ -6,11 +6,14 @@ use crate::{
     io::console::{early_print, early_println},
     irq::plic,
     log::*,
-    mem::{regions::Region, Stack},
+    mem::{regions::Region, swap, Stack},
     read_csr,
     sbi::reset::{shutdown, Reason},
     syscall::handle_syscall,
-    task::{executor::timer_event, resume},
+    task::{
+        executor::{timer_event, with_current_process_reclaim},
+        resume,
+    },
 };
 
 /// Set the trap vector and allocate a stack for context saving
@@ -138,6 +141,15 @@ fn dump_ctx(ctx: &Context, scause: TrapCause, stval: usize) {
     fwprintln!("{}", ctx);
 }
 
+/// Resolve a page-fault of the current process, e.g. by reading back a page
+/// that was swapped out. Returns false if the access was invalid.
+fn handle_page_fault(addr: VirtualAddress, write: bool) -> bool {
+    matches!(
+        with_current_process_reclaim(|proc| swap::fault(&mut proc.space, addr, write)),
+        Some(Ok(true))
+    )
+}
+
 /// Handle the trap/interrupt/exception. Returns a `Context` which contains the
 /// general purpose registers, calling environment, and program counter.
 #[no_mangle]
@@ -150,13 +162,16 @@ unsafe extern "C" fn trap_handler(
     let scause: TrapCause = scause.into();
 
     match scause {
-        TrapCause::SupervisorExternal => {
-            plic::handle_next();
-        }
+        TrapCause::SupervisorExternal => while plic::handle_next().is_some() {},
         TrapCause::SupervisorTimer => {
             timer_event();
         }
         TrapCause::UserCall => handle_syscall(ctx),
+        TrapCause::FetchPageFault | TrapCause::LoadPageFault | TrapCause::StorePageFault
+            if handle_page_fault(
+                VirtualAddress(stval),
+                matches!(scause, TrapCause::StorePageFault),
+            ) => {}
         _ => {
             // TODO: Don't just panic; kill the current thread if it isn't TID=0
             dump_ctx(ctx, scause, stval);